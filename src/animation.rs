@@ -0,0 +1,310 @@
+// Note: skeletal animation
+//
+// glTF skins and their first animation clip, played on loop. The skinned vertices carry up to
+// 4 joints and weights (Vertex::joints, Vertex::weights), the vertex shader blends the joint
+// matrices of the palette (set 0, binding 1) with them: linear blend skinning.
+// The joint matrix of a joint is its animated world transform times its inverse bind matrix.
+// The skins of a file share one palette, the joints of each skin follow the ones of the skins
+// before it (see MeshBuilder::read_gltf).
+// Only the skinned meshes move: the node transforms of the other meshes are baked at load time.
+
+use gltf::animation::{util::ReadOutputs, Interpolation};
+use nalgebra::{Matrix4, Quaternion, UnitQuaternion, Vector3};
+use tracing::{info, warn};
+
+// Size of the joint palette of each frame, the joints after it keep their bind pose
+pub const MAX_JOINTS: usize = 256;
+
+// Column major, as the mat4 of the shader
+pub type JointMatrix = [[f32; 4]; 4];
+
+// Local transform of a node, the animation channels replace its parts
+#[derive(Debug, Clone, Copy)]
+struct NodeTransform {
+    translation: Vector3<f32>,
+    rotation: UnitQuaternion<f32>,
+    scale: Vector3<f32>,
+}
+
+impl NodeTransform {
+    fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::new_translation(&self.translation)
+            * self.rotation.to_homogeneous()
+            * Matrix4::new_nonuniform_scaling(&self.scale)
+    }
+}
+
+#[derive(Debug)]
+enum ChannelValues {
+    Translations(Vec<Vector3<f32>>),
+    Rotations(Vec<UnitQuaternion<f32>>),
+    Scales(Vec<Vector3<f32>>),
+}
+
+// Key frames of one property of one node
+#[derive(Debug)]
+struct Channel {
+    node: usize,
+    times: Vec<f32>, // seconds, increasing
+    values: ChannelValues,
+    step: bool, // hold the value until the next key instead of interpolating
+}
+
+impl Channel {
+    // The keys around `time` and the interpolation factor between them
+    fn keys(&self, time: f32) -> (usize, usize, f32) {
+        let next = self.times.partition_point(|key_time| *key_time <= time);
+        if next == 0 {
+            return (0, 0, 0.0);
+        }
+        if next == self.times.len() {
+            return (next - 1, next - 1, 0.0);
+        }
+
+        let previous = next - 1;
+        let span = self.times[next] - self.times[previous];
+        let factor = if self.step || span <= f32::EPSILON {
+            0.0
+        } else {
+            (time - self.times[previous]) / span
+        };
+        (previous, next, factor)
+    }
+
+    fn apply(&self, time: f32, transform: &mut NodeTransform) {
+        let (previous, next, factor) = self.keys(time);
+        match &self.values {
+            ChannelValues::Translations(values) => {
+                transform.translation = values[previous].lerp(&values[next], factor)
+            }
+            ChannelValues::Scales(values) => {
+                transform.scale = values[previous].lerp(&values[next], factor)
+            }
+            // Normalized lerp along the shortest arc
+            ChannelValues::Rotations(values) => {
+                let (from, to) = (values[previous], values[next]);
+                let to = if from.coords.dot(&to.coords) < 0.0 {
+                    -to.into_inner()
+                } else {
+                    to.into_inner()
+                };
+                transform.rotation =
+                    UnitQuaternion::new_normalize(from.into_inner().lerp(&to, factor));
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct AnimationClip {
+    duration: f32, // seconds, last key of all the channels
+    channels: Vec<Channel>,
+}
+
+pub struct Skeleton {
+    parents: Vec<Option<usize>>, // by node index
+    order: Vec<usize>,           // node indices, every parent before its children
+    rest: Vec<NodeTransform>,    // by node index, the pose without the animation
+    joints: Vec<usize>,          // node index of each joint of the palette
+    inverse_bind_matrices: Vec<Matrix4<f32>>,
+    clip: Option<AnimationClip>,
+}
+
+impl Skeleton {
+    // The skins and the first animation of a glTF file, from the document and buffers already
+    // imported by MeshBuilder::read_gltf. None without skin.
+    pub fn from_gltf(
+        gltf: &gltf::Document,
+        buffers: &[gltf::buffer::Data],
+        path: &str,
+    ) -> Option<Self> {
+        if gltf.skins().len() == 0 {
+            return None;
+        }
+        let get_buffer = |buffer: gltf::Buffer| buffers.get(buffer.index()).map(|data| &data[..]);
+
+        let mut parents = vec![None; gltf.nodes().len()];
+        let mut children = vec![Vec::new(); gltf.nodes().len()];
+        for node in gltf.nodes() {
+            for child in node.children() {
+                parents[child.index()] = Some(node.index());
+                children[node.index()].push(child.index());
+            }
+        }
+
+        // Depth first from the roots
+        let mut order = Vec::with_capacity(parents.len());
+        let mut stack: Vec<usize> = (0..parents.len())
+            .filter(|node| parents[*node].is_none())
+            .collect();
+        while let Some(node) = stack.pop() {
+            order.push(node);
+            stack.extend(&children[node]);
+        }
+
+        let rest = gltf
+            .nodes()
+            .map(|node| {
+                let (translation, [x, y, z, w], scale) = node.transform().decomposed();
+                NodeTransform {
+                    translation: translation.into(),
+                    rotation: UnitQuaternion::new_normalize(Quaternion::new(w, x, y, z)),
+                    scale: scale.into(),
+                }
+            })
+            .collect();
+
+        let mut joints = Vec::new();
+        let mut inverse_bind_matrices = Vec::new();
+        for skin in gltf.skins() {
+            let skin_joints: Vec<usize> = skin.joints().map(|joint| joint.index()).collect();
+            // Identity when the skin has none
+            let mut matrices: Vec<Matrix4<f32>> = skin
+                .reader(get_buffer)
+                .read_inverse_bind_matrices()
+                .map(|matrices| matrices.map(Matrix4::from).collect())
+                .unwrap_or_default();
+            matrices.resize(skin_joints.len(), Matrix4::identity());
+
+            joints.extend(skin_joints);
+            inverse_bind_matrices.extend(matrices);
+        }
+        if joints.len() > MAX_JOINTS {
+            warn!(
+                "glTF {path}: {} joints, the ones after {MAX_JOINTS} are not animated",
+                joints.len()
+            );
+        }
+
+        let clip = gltf.animations().next().map(|animation| {
+            let clip = read_clip(&animation, get_buffer);
+            info!(
+                "glTF {path}: animation {:?}, {} channels, {:.2} s",
+                animation.name().unwrap_or_default(),
+                clip.channels.len(),
+                clip.duration
+            );
+            clip
+        });
+
+        info!("glTF {path}: skeleton of {} joints", joints.len());
+
+        Some(Self {
+            parents,
+            order,
+            rest,
+            joints,
+            inverse_bind_matrices,
+            clip,
+        })
+    }
+
+    pub fn joint_count(&self) -> usize {
+        self.joints.len().min(MAX_JOINTS)
+    }
+
+    // Joint matrices at `time` seconds, the clip loops. The bind pose without a clip.
+    pub fn joint_matrices(&self, time: f32) -> Vec<JointMatrix> {
+        let mut local = self.rest.clone();
+        if let Some(clip) = &self.clip {
+            let time = if clip.duration > 0.0 {
+                time % clip.duration
+            } else {
+                0.0
+            };
+            for channel in &clip.channels {
+                if let Some(transform) = local.get_mut(channel.node) {
+                    channel.apply(time, transform);
+                }
+            }
+        }
+
+        let mut world = vec![Matrix4::identity(); local.len()];
+        for node in &self.order {
+            let parent = self.parents[*node].map_or_else(Matrix4::identity, |parent| world[parent]);
+            world[*node] = parent * local[*node].matrix();
+        }
+
+        self.joints
+            .iter()
+            .zip(&self.inverse_bind_matrices)
+            .take(MAX_JOINTS)
+            .map(|(joint, inverse_bind_matrix)| (world[*joint] * inverse_bind_matrix).into())
+            .collect()
+    }
+}
+
+// The translation, rotation and scale channels. Morph target weights are not supported.
+// Cubic spline keys keep their value and drop their tangents: interpolated linearly.
+fn read_clip<'a, 's>(
+    animation: &gltf::Animation<'a>,
+    get_buffer: impl Clone + Fn(gltf::Buffer<'a>) -> Option<&'s [u8]>,
+) -> AnimationClip {
+    let mut channels = Vec::new();
+    for channel in animation.channels() {
+        let reader = channel.reader(get_buffer.clone());
+        let (Some(inputs), Some(outputs)) = (reader.read_inputs(), reader.read_outputs()) else {
+            continue;
+        };
+        let times: Vec<f32> = inputs.collect();
+
+        let interpolation = channel.sampler().interpolation();
+
+        let values = match outputs {
+            ReadOutputs::Translations(translations) => ChannelValues::Translations(
+                key_values(translations.collect(), interpolation)
+                    .into_iter()
+                    .map(Vector3::from)
+                    .collect(),
+            ),
+            ReadOutputs::Scales(scales) => ChannelValues::Scales(
+                key_values(scales.collect(), interpolation)
+                    .into_iter()
+                    .map(Vector3::from)
+                    .collect(),
+            ),
+            ReadOutputs::Rotations(rotations) => ChannelValues::Rotations(
+                key_values(rotations.into_f32().collect(), interpolation)
+                    .into_iter()
+                    .map(|[x, y, z, w]| UnitQuaternion::new_normalize(Quaternion::new(w, x, y, z)))
+                    .collect(),
+            ),
+            ReadOutputs::MorphTargetWeights(_) => continue,
+        };
+
+        let value_count = match &values {
+            ChannelValues::Translations(values) | ChannelValues::Scales(values) => values.len(),
+            ChannelValues::Rotations(values) => values.len(),
+        };
+        if times.is_empty() || value_count != times.len() {
+            warn!(
+                "animation channel of node {}: {} keys, {value_count} values, skipped",
+                channel.target().node().index(),
+                times.len()
+            );
+            continue;
+        }
+
+        channels.push(Channel {
+            node: channel.target().node().index(),
+            times,
+            values,
+            step: interpolation == Interpolation::Step,
+        });
+    }
+
+    let duration = channels
+        .iter()
+        .filter_map(|channel| channel.times.last())
+        .fold(0.0, |duration: f32, time| duration.max(*time));
+
+    AnimationClip { duration, channels }
+}
+
+// One value per key: a cubic spline key has an in tangent, the value and an out tangent
+fn key_values<T>(values: Vec<T>, interpolation: Interpolation) -> Vec<T> {
+    match interpolation {
+        Interpolation::CubicSpline => values.into_iter().skip(1).step_by(3).collect(),
+        _ => values,
+    }
+}
@@ -1,26 +1,45 @@
 use std::{
-    cell::RefCell, collections::BTreeMap, rc::Rc, sync::{Arc, Mutex}
+    cell::RefCell, collections::BTreeMap, path::Path, rc::Rc, sync::{Arc, Mutex}, time::{Duration, Instant}
 };
 
-use tracing::info;
+use tracing::{error, info, warn};
 use vulkano::image::{ImageUsage, SampleCount};
 use winit::{
-    dpi::PhysicalSize,
-    event::{Event, WindowEvent},
-    event_loop::{EventLoop, EventLoopWindowTarget},
-    window::{Window, WindowBuilder, WindowId},
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::{ElementState, Event, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent},
+    event_loop::{ControlFlow, EventLoop, EventLoopWindowTarget},
+    keyboard::{KeyCode, PhysicalKey},
+    window::{Fullscreen, Window, WindowBuilder, WindowId},
 };
 
+#[cfg(feature = "gamepad")]
+use crate::gamepad::{GamepadInput, Gamepads};
 use crate::{
-    camera::{Camera, CameraController, Mvp},
+    camera::{Camera, CameraController, ControllerMode},
+    capture::CAPTURE_FILE,
+    config::AppConfig,
     error::{self, Result},
+    gui::{GuiInfo, GuiState},
+    lighting::LightPreset,
+    mesh::MeshBuilder,
     utils::load_icon,
     vulkan_context::VulkanContext,
     vulkan_device::VulkanDevice,
     vulkan_instance::VulkanInstance,
-    vulkan_renderer::VulkanRenderer,
+    vulkan_renderer::{FrameStats, VulkanRenderer},
 };
 
+// Viewpoint bookmark saved/restored with F6/F7
+const CAMERA_FILE: &str = "camera.json";
+
+// Mouse drag orbit, radians per pixel
+const ORBIT_SENSITIVITY: f32 = 0.005;
+
+// Scroll zoom, fraction of the distance to the target per wheel line.
+// Touchpads scroll in pixels: PIXELS_PER_LINE of them count as one line.
+const ZOOM_PER_LINE: f32 = 0.1;
+const PIXELS_PER_LINE: f32 = 20.0;
+
 pub struct VisualSystem {
     primary_window_id: WindowId,
     windows: BTreeMap<WindowId, Arc<Window>>,
@@ -28,57 +47,87 @@ pub struct VisualSystem {
     vulkan_instance: Arc<VulkanInstance>,
     vulkan_device: Rc<VulkanDevice>,
     vulkan_renderers: BTreeMap<WindowId, Rc<Mutex<VulkanRenderer>>>,
+    suspended_cameras: BTreeMap<WindowId, Camera>, // kept from suspend to resume
+    camera_window: Option<WindowId>,               // moved by the held camera keys
+    orbit_window: Option<WindowId>,                // left mouse button held in this window
+    cursor_position: Option<PhysicalPosition<f64>>, // last position in orbit_window
+    orbit_delta: (f32, f32),                       // (yaw, pitch) not applied yet
+    zoom_delta: Option<(WindowId, f32)>,           // wheel lines not applied yet
+    light_preset: Option<LightPreset>,             // None: the startup lights, until L
 }
 
 impl VisualSystem {
-    pub fn new<T>(window_target: &EventLoopWindowTarget<T>) -> Result<Self> {
-        let window_icon: Option<winit::window::Icon> = Some(load_icon("./assets/icon.png"));
+    pub fn new<T>(window_target: &EventLoopWindowTarget<T>, config: &AppConfig) -> Result<Self> {
+        // Not worth failing the startup for
+        let window_icon = load_icon(&config.icon_path)
+            .inspect_err(|e| warn!("no window icon {}: {e}", config.icon_path))
+            .ok();
 
         // Support Multi windows
         let primary_window = Arc::new(
             WindowBuilder::new()
                 .with_title("🌋VULKANO ♣")
-                .with_window_icon(window_icon)
+                .with_window_icon(window_icon.clone())
                 .with_visible(false)
                 .build(window_target)?,
         );
         let primary_window_id = primary_window.id();
 
         let vulkan_instance = Arc::new(
-            VulkanInstance::new(Arc::clone(&primary_window))
-                .map_err(|_| error::VisualSystemError::ErrorCreatingVulkanInstance)?,
+            VulkanInstance::new(
+                Arc::clone(&primary_window),
+                config.device_name.as_deref(),
+                config.validation,
+            )
+            .map_err(|e| error::VisualSystemError::ErrorCreatingVulkanInstance(Box::new(e)))?,
         );
 
-        let camera = Arc::new(Mutex::new(Camera::default()));
-
+        // Shared by the windows, each window moves its own camera
         let camera_controller = Arc::new(Mutex::new(CameraController::new(0.2)));
 
-        let mut mvp_uniform = Mvp::new();
-        mvp_uniform.update_view(&camera.lock().unwrap());
-        mvp_uniform.update_projection(&camera.lock().unwrap());
+        let samples = config.samples;
 
-        mvp_uniform.update_model_translate(nalgebra::Vector3::new(0.0, 0.0, -1.0));
-
-        let samples = SampleCount::Sample4;
+        // A format the display offers, falls back to SDR without the requested color space
+        let (color_output, color_format) =
+            vulkan_instance.select_surface_format(config.color_output);
 
         let vulkan_context = Rc::new(RefCell::new(VulkanContext::new(
-            camera,
-            Arc::new(Mutex::new(mvp_uniform)),
             camera_controller,
             samples,
+            color_output,
+            config.texture_quality,
+            config.bloom,
+            config.camera_clamp,
+            config.point_size,
         )?));
 
+        vulkan_context.borrow_mut().set_color_format(color_format);
+        vulkan_context
+            .borrow_mut()
+            .set_depth_format(vulkan_instance.select_depth_format()?);
+        vulkan_context.borrow_mut().default_texture = config.texture_path.clone();
+        vulkan_context.borrow_mut().sampler_config = config.sampler;
+
+        vulkan_context.borrow_mut().present_mode = config.present_mode;
+
         let vulkan_device = Rc::new(
-            VulkanDevice::new(Arc::clone(&vulkan_instance), Rc::clone(&vulkan_context))
-                .map_err(|_| error::VisualSystemError::ErrorCreatingVulkanDevice)?,
+            VulkanDevice::new(
+                Arc::clone(&vulkan_instance),
+                Rc::clone(&vulkan_context),
+                &config.model_path,
+            )
+            .map_err(|e| error::VisualSystemError::ErrorCreatingVulkanDevice(Box::new(e)))?,
         );
 
+        vulkan_device.memory_report();
+
         // Store the windows in a BTreeMap
         let mut windows = BTreeMap::from([(primary_window_id, Arc::clone(&primary_window))]);
 
         let symbol_list = "♔♕♖♗♘♙☚★";
 
-        for idx in 0..1 {
+        // Secondary windows: same device, their own renderer and camera
+        for idx in 0..config.window_count.saturating_sub(1) {
             let char_at_index = symbol_list
                 .chars()
                 .nth(idx % (symbol_list.chars().count()))
@@ -87,12 +136,13 @@ impl VisualSystem {
                 WindowBuilder::new()
                     .with_visible(false)
                     .with_title(format!("🌋VULKANO {char_at_index} {idx}"))
+                    .with_window_icon(window_icon.clone())
                     .build(window_target)?,
             );
             windows.insert(window.id(), window);
         }
 
-        // Each window has its own renderer
+        // Each window has its own renderer and camera
         let mut vulkan_renderers = BTreeMap::new();
 
         for (window_id, window) in &windows {
@@ -102,9 +152,15 @@ impl VisualSystem {
                     VulkanRenderer::new(
                         Rc::clone(&vulkan_device),
                         Arc::clone(window),
-                        ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSFER_DST,
+                        // TRANSFER_SRC: frame capture
+                        ImageUsage::COLOR_ATTACHMENT
+                            | ImageUsage::TRANSFER_DST
+                            | ImageUsage::TRANSFER_SRC,
+                        Camera::default(),
                     )
-                    .map_err(|_| error::VisualSystemError::ErrorCreatingVulkanRenderer)?,
+                    .map_err(|e| {
+                        error::VisualSystemError::ErrorCreatingVulkanRenderer(Box::new(e))
+                    })?,
                 )),
             );
         }
@@ -113,13 +169,27 @@ impl VisualSystem {
             .iter()
             .for_each(|(_, window)| window.set_visible(true)); // visible when ready to avoid seeing garbage in the window during setup
 
-        Ok(Self {
+        let visual_system = Self {
             primary_window_id,
             windows,
             vulkan_instance,
             vulkan_device,
             vulkan_renderers,
-        })
+            suspended_cameras: BTreeMap::new(),
+            camera_window: None,
+            orbit_window: None,
+            cursor_position: None,
+            orbit_delta: (0.0, 0.0),
+            zoom_delta: None,
+            light_preset: None,
+        };
+
+        // Whatever the size of the startup model
+        for window_id in visual_system.windows.keys() {
+            visual_system.fit_camera(*window_id)?;
+        }
+
+        Ok(visual_system)
     }
 
     // Resume create a new renderer. Keep device and window
@@ -132,9 +202,14 @@ impl VisualSystem {
                         // Use Mutex fo interior mutability
                         Rc::clone(&self.vulkan_device),
                         Arc::clone(window),
-                        ImageUsage::COLOR_ATTACHMENT,
+                        ImageUsage::COLOR_ATTACHMENT
+                            | ImageUsage::TRANSFER_DST
+                            | ImageUsage::TRANSFER_SRC,
+                        self.suspended_cameras.remove(window_id).unwrap_or_default(),
                     )
-                    .map_err(|_| error::VisualSystemError::ErrorCreatingVulkanRenderer)?,
+                    .map_err(|e| {
+                        error::VisualSystemError::ErrorCreatingVulkanRenderer(Box::new(e))
+                    })?,
                 )),
             );
         }
@@ -142,90 +217,783 @@ impl VisualSystem {
     }
 
     pub fn suspend(&mut self) {
+        self.suspended_cameras = self
+            .vulkan_renderers
+            .iter()
+            .map(|(window_id, vulkan_renderer)| {
+                let camera = std::mem::take(
+                    &mut vulkan_renderer
+                        .lock()
+                        .expect("failed to get a lock on vulkan renderer")
+                        .camera,
+                );
+                (*window_id, camera)
+            })
+            .collect();
+        self.shutdown();
+    }
+
+    pub fn has_window(&self, window_id: WindowId) -> bool {
+        self.windows.contains_key(&window_id)
+    }
+
+    // Close a secondary window: its renderer is dropped once the GPU is done with it.
+    // The primary window ends the app instead, see App::process_event.
+    pub fn close_window(&mut self, window_id: WindowId) -> Result<()> {
+        if window_id == self.primary_window_id {
+            return Ok(());
+        }
+
+        self.vulkan_device.wait_idle()?;
+
+        self.vulkan_renderers.remove(&window_id);
+        self.suspended_cameras.remove(&window_id);
+        self.windows.remove(&window_id);
+
+        if self.camera_window == Some(window_id) {
+            self.camera_window = None;
+        }
+        if self.orbit_window == Some(window_id) {
+            self.orbit_window = None;
+            self.cursor_position = None;
+        }
+        if self
+            .zoom_delta
+            .is_some_and(|(zoom_window, _)| zoom_window == window_id)
+        {
+            self.zoom_delta = None;
+        }
+
+        info!("window {window_id:?} closed");
+        Ok(())
+    }
+
+    // Drop the renderers once the GPU is done with their frames in flight
+    pub fn shutdown(&mut self) {
+        if let Err(e) = self.vulkan_device.wait_idle() {
+            error!("failed to wait for the device to be idle: {e}");
+        }
+
         self.vulkan_renderers.clear(); // Clear the renderers in the BTreeMap
     }
 
     pub fn resize(&mut self, window_id: WindowId, new_size: PhysicalSize<u32>) -> Result<()> {
         if !(new_size.width == 0 || new_size.height == 0) {
-            self.vulkan_renderers[&window_id]
+            let mut vulkan_renderer = self.vulkan_renderers[&window_id]
                 .lock()
-                .expect("failed to get a lock on vulkan renderer")
-                .recreate()?; // Use Mutex for interior mutability
+                .expect("failed to get a lock on vulkan renderer"); // Use Mutex for interior mutability
 
-            // update camera aspect ratio
-            self.vulkan_device
-                .vulkan_context
-                .borrow()
+            // No swapchain recreation here: once, before the next frame of the window
+            vulkan_renderer.request_recreate();
+
+            // update the aspect ratio of this window's camera only
+            vulkan_renderer
                 .camera
-                .lock()
-                .expect("failed to get a lock on camera ")
                 .update_aspect(new_size.width, new_size.height);
+            vulkan_renderer.update_camera()?;
 
-            self.vulkan_device
-                .vulkan_context
-                .borrow()
-                .mvp_uniform
+            return Ok(());
+        }
+
+        Ok(())
+    }
+
+    // The camera keys move the camera of the window that received them, see update_camera
+    pub fn input(&mut self, window_id: WindowId) {
+        self.camera_window = Some(window_id);
+    }
+
+    // Orbit while the left button is held
+    pub fn mouse_input(&mut self, window_id: WindowId, state: ElementState, button: MouseButton) {
+        if button != MouseButton::Left {
+            return;
+        }
+
+        match state {
+            ElementState::Pressed => self.orbit_window = Some(window_id),
+            ElementState::Released => self.orbit_window = None,
+        }
+        self.cursor_position = None;
+    }
+
+    // Accumulate the drag, applied by update_camera
+    pub fn cursor_moved(&mut self, window_id: WindowId, position: PhysicalPosition<f64>) {
+        if self.orbit_window != Some(window_id) {
+            return;
+        }
+
+        if let Some(previous) = self.cursor_position {
+            // Dragging right turns the model right, dragging down tilts it toward the viewer
+            self.orbit_delta.0 -= (position.x - previous.x) as f32 * ORBIT_SENSITIVITY;
+            self.orbit_delta.1 += (position.y - previous.y) as f32 * ORBIT_SENSITIVITY;
+        }
+        self.cursor_position = Some(position);
+    }
+
+    // Accumulate the wheel lines, applied by update_camera
+    pub fn mouse_wheel(&mut self, window_id: WindowId, delta: MouseScrollDelta) {
+        let lines = match delta {
+            MouseScrollDelta::LineDelta(_, y) => y,
+            MouseScrollDelta::PixelDelta(position) => position.y as f32 / PIXELS_PER_LINE,
+        };
+
+        self.zoom_delta = match self.zoom_delta {
+            Some((zoom_window, zoom_lines)) if zoom_window == window_id => {
+                Some((window_id, zoom_lines + lines))
+            }
+            _ => Some((window_id, lines)),
+        };
+    }
+
+    // Keyboard focus lost: stop moving the camera of the window
+    pub fn unfocus(&mut self, window_id: WindowId) {
+        if self.orbit_window == Some(window_id) {
+            self.orbit_window = None;
+            self.cursor_position = None;
+        }
+
+        if self.camera_window == Some(window_id) {
+            self.camera_window = None;
+            let vulkan_context = self.vulkan_device.vulkan_context.borrow();
+            vulkan_context
+                .camera_controller
                 .lock()
-                .expect("failed to get a lock on camera uniform")
-                .update_projection(
-                    &self
-                        .vulkan_device
-                        .vulkan_context
-                        .borrow()
-                        .camera
-                        .lock()
-                        .unwrap(),
-                );
+                .expect("failed to get a lock on camera controller")
+                .release_keys();
+            vulkan_context
+                .fly_controller
+                .lock()
+                .expect("failed to get a lock on fly controller")
+                .release_keys();
+            vulkan_context
+                .spherical_controller
+                .lock()
+                .expect("failed to get a lock on spherical controller")
+                .release_keys();
+        }
+    }
+
+    // Once per frame, while a camera key is held or the mouse dragged: one step and one upload
+    // of the uniform instead of one per event
+    pub fn update_camera(&mut self) -> Result<()> {
+        let controller_mode = self.vulkan_device.vulkan_context.borrow().controller_mode;
+
+        let (delta_yaw, delta_pitch) = std::mem::take(&mut self.orbit_delta);
+        if let Some(window_id) = self.orbit_window {
+            self.orbit_camera(window_id, delta_yaw, delta_pitch)?;
+        }
+
+        if let Some((window_id, lines)) = self.zoom_delta.take() {
+            self.zoom_camera(window_id, lines)?;
+        }
+
+        let Some(window_id) = self.camera_window else {
+            return Ok(());
+        };
+
+        if controller_mode == ControllerMode::Fly {
+            let fly_controller =
+                Arc::clone(&self.vulkan_device.vulkan_context.borrow().fly_controller);
+            let fly_controller = fly_controller
+                .lock()
+                .expect("failed to get a lock on fly controller");
+            if !fly_controller.is_moving() {
+                return Ok(());
+            }
+
+            return self.move_camera(window_id, |camera| fly_controller.update_camera(camera));
+        }
+
+        if controller_mode == ControllerMode::Spherical {
+            let spherical_controller = Arc::clone(
+                &self
+                    .vulkan_device
+                    .vulkan_context
+                    .borrow()
+                    .spherical_controller,
+            );
+            let mut spherical_controller = spherical_controller
+                .lock()
+                .expect("failed to get a lock on spherical controller");
+            if !spherical_controller.is_moving() {
+                return Ok(());
+            }
+
+            return self.move_camera(window_id, |camera| {
+                spherical_controller.update_camera(camera)
+            });
+        }
+
+        let camera_controller =
+            Arc::clone(&self.vulkan_device.vulkan_context.borrow().camera_controller);
+        let camera_controller = camera_controller
+            .lock()
+            .expect("failed to get a lock on camera controller");
+        if !camera_controller.is_moving() {
+            return Ok(());
+        }
+
+        // update camera via camera controller
+        self.move_camera(window_id, |camera| camera_controller.update_camera(camera))
+    }
+
+    // Orbit, or look around in fly mode (dragging down looks down)
+    fn orbit_camera(&self, window_id: WindowId, delta_yaw: f32, delta_pitch: f32) -> Result<()> {
+        if delta_yaw == 0.0 && delta_pitch == 0.0 {
+            return Ok(());
+        }
+
+        let controller_mode = self.vulkan_device.vulkan_context.borrow().controller_mode;
+        self.move_camera(window_id, |camera| match controller_mode {
+            ControllerMode::Orbit | ControllerMode::Spherical => {
+                camera.orbit(delta_yaw, delta_pitch)
+            }
+            ControllerMode::Fly => camera.look(delta_yaw, -delta_pitch),
+        })
+    }
+
+    // Positive `lines` zoom in. Camera::zoom keeps a fast scroll from crossing the target.
+    // The spherical controller changes its radius.
+    fn zoom_camera(&self, window_id: WindowId, lines: f32) -> Result<()> {
+        if lines == 0.0 {
+            return Ok(());
+        }
+
+        let vulkan_context = self.vulkan_device.vulkan_context.borrow();
+        let controller_mode = vulkan_context.controller_mode;
+        let spherical_controller = Arc::clone(&vulkan_context.spherical_controller);
+        drop(vulkan_context);
+        let mut spherical_controller = spherical_controller
+            .lock()
+            .expect("failed to get a lock on spherical controller");
+
+        self.move_camera(window_id, |camera| {
+            let distance = (camera.target - camera.eye).magnitude();
+            let amount = lines * ZOOM_PER_LINE * distance;
+            match controller_mode {
+                ControllerMode::Spherical => spherical_controller.zoom(camera, amount),
+                _ => camera.zoom(amount),
+            }
+        })
+    }
+
+    // Sticks and triggers of the gamepad, once per frame: the camera of the focused window
+    // (the primary one before any key) takes the same path as the mouse
+    #[cfg(feature = "gamepad")]
+    pub fn gamepad_input(&mut self, input: GamepadInput) -> Result<()> {
+        let window_id = self.camera_window.unwrap_or(self.primary_window_id);
+        if !self.has_window(window_id) {
+            return Ok(());
+        }
 
-            self.vulkan_device.update_uniform_buffer()?;
+        self.orbit_camera(window_id, input.delta_yaw, input.delta_pitch)?;
+        self.zoom_camera(window_id, input.zoom_lines)
+    }
 
+    // Move the camera of the window, keep it out of the model and upload the uniform
+    fn move_camera(&self, window_id: WindowId, update: impl FnOnce(&mut Camera)) -> Result<()> {
+        let Some(vulkan_renderer) = self.vulkan_renderers.get(&window_id) else {
             return Ok(());
+        };
+        let mut vulkan_renderer = vulkan_renderer
+            .lock()
+            .expect("failed to get a lock on vulkan renderer");
+
+        update(&mut vulkan_renderer.camera);
+
+        // Then out of the model
+        let camera_clamp = self.vulkan_device.vulkan_context.borrow().camera_clamp;
+        if camera_clamp.enabled {
+            let boxes = self
+                .vulkan_device
+                .collision_boxes(&vulkan_renderer.mvp_uniform.model_matrix());
+            vulkan_renderer
+                .camera
+                .clamp_eye(&boxes, camera_clamp.margin);
         }
 
+        vulkan_renderer.update_camera()?;
+
         Ok(())
     }
 
-    pub fn input(&mut self) -> Result<()> {
-        // update camera via camera controller
+    // Replace the displayed model. The previous model is kept if loading fails.
+    pub fn load_mesh(&mut self, path: &str) -> Result<()> {
+        self.vulkan_device.load_mesh(path)?;
+        self.vulkan_device.memory_report();
+
+        // The new model may be of any size
+        for window_id in self.windows.keys() {
+            self.fit_camera(*window_id)?;
+        }
+        Ok(())
+    }
+
+    // Frame every instance of the model in the window, see Camera::fit
+    pub fn fit_camera(&self, window_id: WindowId) -> Result<()> {
+        let Some(vulkan_renderer) = self.vulkan_renderers.get(&window_id) else {
+            return Ok(());
+        };
+        let mut vulkan_renderer = vulkan_renderer
+            .lock()
+            .expect("failed to get a lock on vulkan renderer");
+
+        let model = vulkan_renderer.mvp_uniform.model_matrix();
+        let Some((min, max)) = self.vulkan_device.scene_bounds(&model) else {
+            return Ok(());
+        };
+
+        vulkan_renderer.camera.fit(min, max);
+        // The projection changed with znear/zfar
+        vulkan_renderer.update_camera()?;
+
+        Ok(())
+    }
+
+    // Load a model file dropped onto one of the windows
+    pub fn drop_file(&mut self, path: &Path) {
+        if !MeshBuilder::is_supported(path) {
+            warn!("dropped file is not a supported model: {}", path.display());
+            return;
+        }
+
+        if let Err(e) = self.load_mesh(&path.to_string_lossy()) {
+            error!("failed to load {}: {e}. Keeping the previous model", path.display());
+        }
+    }
+
+    pub fn save_camera(&self, window_id: WindowId, path: &str) -> Result<()> {
+        self.vulkan_renderers[&window_id]
+            .lock()
+            .expect("failed to get a lock on vulkan renderer")
+            .camera
+            .save(path)?;
+
+        info!("camera saved to {path}");
+        Ok(())
+    }
+
+    // Save the next frame of the window as a PNG
+    pub fn capture_frame(&self, window_id: WindowId, path: &str) -> Result<()> {
+        self.vulkan_renderers[&window_id]
+            .lock()
+            .expect("failed to get a lock on vulkan renderer")
+            .capture_frame(path)?;
+
+        Ok(())
+    }
+
+    // Borderless fullscreen on the monitor of the window, or back to windowed. The Resized
+    // event that follows updates the camera aspect and has the swapchain recreated, see resize.
+    pub fn toggle_fullscreen(&self, window_id: WindowId) {
+        let window = &self.windows[&window_id];
+        let fullscreen = match window.fullscreen() {
+            Some(_) => None,
+            None => Some(Fullscreen::Borderless(None)),
+        };
+
+        info!("fullscreen: {}", fullscreen.is_some());
+        window.set_fullscreen(fullscreen);
+    }
+
+    // Restore a saved viewpoint in the window
+    pub fn load_camera(&mut self, window_id: WindowId, path: &str) -> Result<()> {
+        self.replace_camera(window_id, Camera::load(path)?)?;
+
+        info!("camera loaded from {path}");
+        Ok(())
+    }
+
+    // Back to the default pose, e.g. after flying far away
+    pub fn reset_camera(&mut self, window_id: WindowId) -> Result<()> {
+        let mut vulkan_renderer = self.vulkan_renderers[&window_id]
+            .lock()
+            .expect("failed to get a lock on vulkan renderer");
+
+        vulkan_renderer.camera.reset();
+        vulkan_renderer.update_camera()?;
+
+        info!("camera reset");
+        Ok(())
+    }
+
+    // Orbit around the target, fly through the scene or orbit in spherical coordinates, for all
+    // the windows
+    pub fn cycle_controller_mode(&mut self) {
+        let mut vulkan_context = self.vulkan_device.vulkan_context.borrow_mut();
+        vulkan_context.cycle_controller_mode();
+
+        info!("camera controller: {:?}", vulkan_context.controller_mode);
+    }
+
+    // Perspective or orthographic, for the window camera
+    pub fn toggle_projection(&mut self, window_id: WindowId) -> Result<()> {
+        let mut vulkan_renderer = self.vulkan_renderers[&window_id]
+            .lock()
+            .expect("failed to get a lock on vulkan renderer");
+
+        vulkan_renderer.camera.toggle_projection();
+        vulkan_renderer.update_camera()?;
+
+        info!("projection: {:?}", vulkan_renderer.camera.projection);
+        Ok(())
+    }
+
+    // Swap the camera of the window and update its uniform.
+    // The current aspect ratio is kept since it belongs to the window.
+    fn replace_camera(&mut self, window_id: WindowId, mut new_camera: Camera) -> Result<()> {
+        let mut vulkan_renderer = self.vulkan_renderers[&window_id]
+            .lock()
+            .expect("failed to get a lock on vulkan renderer");
+
+        new_camera.aspect = vulkan_renderer.camera.aspect;
+        vulkan_renderer.camera = new_camera;
+        vulkan_renderer.update_camera()?;
+
+        Ok(())
+    }
+
+    // Grow or shrink the instance grid by `delta` rows
+    pub fn resize_instance_grid(&mut self, delta: i32) -> Result<()> {
+        let (rows, spacing) = {
+            let vulkan_context = self.vulkan_device.vulkan_context.borrow();
+            (
+                vulkan_context.instances_per_row.saturating_add_signed(delta),
+                vulkan_context.instance_spacing,
+            )
+        };
+
+        self.vulkan_device.set_instance_grid(rows, spacing)
+    }
+
+    pub fn toggle_animate_instances(&mut self) -> Result<()> {
+        let animate_instances = {
+            let mut vulkan_context = self.vulkan_device.vulkan_context.borrow_mut();
+            vulkan_context.toggle_animate_instances();
+            vulkan_context.animate_instances
+        };
+
+        // The compute pass left the spun transforms in the instance buffer: back to the grid
+        if !animate_instances {
+            let instances = self.vulkan_device.instances().clone();
+            self.vulkan_device.update_instances(&instances)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn toggle_spin_model(&mut self) {
         self.vulkan_device
             .vulkan_context
-            .borrow()
-            .camera_controller
-            .lock()
-            .expect("failed to get a lock on camera controller")
-            .update_camera(
-                &mut self
-                    .vulkan_device
-                    .vulkan_context
-                    .borrow()
-                    .camera
-                    .lock()
-                    .expect("failed to get a lock on camera"),
-            );
+            .borrow_mut()
+            .toggle_spin_model();
+    }
+
+    // Compare with the debug text (F1): drawn / total instances of the meshes
+    pub fn toggle_frustum_culling(&mut self) {
+        let mut vulkan_context = self.vulkan_device.vulkan_context.borrow_mut();
+        vulkan_context.toggle_frustum_culling();
+
+        info!("frustum culling: {}", vulkan_context.frustum_culling);
+    }
+
+    pub fn toggle_show_depth(&mut self) {
+        self.vulkan_device
+            .vulkan_context
+            .borrow_mut()
+            .toggle_show_depth();
+    }
+
+    pub fn toggle_show_wireframe(&mut self) {
+        if self.vulkan_device.wireframe_pipeline().is_none() {
+            warn!("wireframe not supported: no fill_mode_non_solid");
+            return;
+        }
+
+        self.vulkan_device
+            .vulkan_context
+            .borrow_mut()
+            .toggle_show_wireframe();
+    }
 
+    pub fn toggle_show_grid(&mut self) {
         self.vulkan_device
             .vulkan_context
-            .borrow()
-            .mvp_uniform
+            .borrow_mut()
+            .toggle_show_grid();
+    }
+
+    pub fn toggle_bloom(&mut self) {
+        self.vulkan_device
+            .vulkan_context
+            .borrow_mut()
+            .toggle_bloom();
+    }
+
+    pub fn cycle_clear_color(&mut self) {
+        let mut vulkan_context = self.vulkan_device.vulkan_context.borrow_mut();
+        vulkan_context.cycle_clear_color();
+        info!("clear color: {:?}", vulkan_context.clear_color);
+    }
+
+    // Cull mode and front face only need new pipelines, the renderers are unchanged
+    pub fn cycle_cull_mode(&mut self) -> Result<()> {
+        self.vulkan_device.cycle_cull_mode()?;
+
+        Ok(())
+    }
+
+    pub fn toggle_front_face(&mut self) -> Result<()> {
+        self.vulkan_device.toggle_front_face()?;
+
+        Ok(())
+    }
+
+    // F5: a broken shader is logged, the current pipelines stay
+    pub fn reload_shaders(&mut self) {
+        if let Err(error) = self.vulkan_device.reload_shaders() {
+            error!("shader reload failed, keeping the current shaders: {error}");
+        }
+    }
+
+    pub fn toggle_camera_clamp(&mut self) {
+        let mut vulkan_context = self.vulkan_device.vulkan_context.borrow_mut();
+        vulkan_context.toggle_camera_clamp();
+        info!("camera clamp: {:?}", vulkan_context.camera_clamp);
+    }
+
+    // Next MSAA sample count. Every window recreates its multisampled images.
+    pub fn cycle_msaa(&mut self) -> Result<()> {
+        self.vulkan_device.cycle_samples()?;
+        self.recreate_renderers()
+    }
+
+    pub fn set_msaa(&mut self, samples: SampleCount) -> Result<()> {
+        self.vulkan_device.set_samples(samples)?;
+        self.recreate_renderers()
+    }
+
+    fn recreate_renderers(&self) -> Result<()> {
+        for vulkan_renderer in self.vulkan_renderers.values() {
+            vulkan_renderer
+                .lock()
+                .expect("failed to get a lock on vulkan renderer")
+                .recreate()?;
+        }
+
+        Ok(())
+    }
+
+    // Ambient and directional lights of a built-in rig, shared by all the windows
+    pub fn set_light_preset(&mut self, preset: LightPreset) -> Result<()> {
+        self.vulkan_device.set_light_preset(preset)?;
+        self.light_preset = Some(preset);
+
+        info!("light preset: {preset:?}");
+
+        Ok(())
+    }
+
+    // Next built-in rig, the first one after the startup lights
+    pub fn cycle_light_preset(&mut self) -> Result<()> {
+        let preset = self
+            .light_preset
+            .map_or(LightPreset::Studio, LightPreset::next);
+
+        self.set_light_preset(preset)
+    }
+
+    pub fn toggle_show_debug_text(&mut self) {
+        self.vulkan_device
+            .vulkan_context
+            .borrow_mut()
+            .toggle_show_debug_text();
+    }
+
+    pub fn toggle_gui(&mut self, window_id: WindowId) -> Result<()> {
+        self.vulkan_renderers[&window_id]
             .lock()
-            .expect("failed to get a lock on camera uniform")
-            .update_view(
-                &self
-                    .vulkan_device
-                    .vulkan_context
-                    .borrow()
-                    .camera
-                    .lock()
-                    .unwrap(),
-            );
+            .expect("failed to get a lock on vulkan renderer")
+            .toggle_gui()
+    }
+
+    // Let the control panel of the window see the event first.
+    // true when egui used it, the rest of the app should then ignore it.
+    pub fn gui_event(&mut self, window_id: WindowId, event: &WindowEvent) -> bool {
+        let Some(vulkan_renderer) = self.vulkan_renderers.get(&window_id) else {
+            return false;
+        };
+        let mut vulkan_renderer = vulkan_renderer
+            .lock()
+            .expect("failed to get a lock on vulkan renderer");
+        let Some(window) = vulkan_renderer.target.window().cloned() else {
+            return false;
+        };
+
+        vulkan_renderer
+            .gui
+            .as_mut()
+            .is_some_and(|gui| gui.on_event(&window, event))
+    }
+
+    // Current values of the settings shown in the control panel
+    pub fn gui_state(&self) -> Result<GuiState> {
+        let vulkan_context = self.vulkan_device.vulkan_context.borrow();
+        let camera_speed = vulkan_context
+            .camera_controller
+            .lock()
+            .expect("failed to get a lock on camera controller")
+            .speed();
 
-        self.vulkan_device.update_uniform_buffer()?;
+        let first_light = self.vulkan_device.directional_lights()?.first().copied();
+        let (shininess, specular_strength) = self.vulkan_device.specular()?;
+
+        Ok(GuiState {
+            light_color: first_light.map_or([1.0; 3], |light| *light.color),
+            light_position: first_light.map_or([0.0; 3], |light| *light.position),
+            ambient_intensity: self.vulkan_device.ambient_light()?.intensity,
+            shininess,
+            specular_strength,
+            camera_speed,
+            samples: vulkan_context.samples,
+            clear_color: vulkan_context.clear_color,
+        })
+    }
+
+    // Apply the settings edited in the control panel through the setters
+    pub fn set_gui_state(&mut self, state: GuiState) -> Result<()> {
+        let current = self.gui_state()?;
+
+        // The panel edits the first light
+        if state.light_color != current.light_color
+            || state.light_position != current.light_position
+        {
+            let mut directional_lights = self.vulkan_device.directional_lights()?;
+            if let Some(light) = directional_lights.first_mut() {
+                light.color = state.light_color.into();
+                light.position = state.light_position.into();
+            }
+            self.vulkan_device
+                .set_directional_lights(&directional_lights)?;
+        }
+
+        if state.ambient_intensity != current.ambient_intensity {
+            let mut ambient_light = self.vulkan_device.ambient_light()?;
+            ambient_light.intensity = state.ambient_intensity;
+            self.vulkan_device.set_ambient_light(ambient_light)?;
+        }
+
+        if state.shininess != current.shininess
+            || state.specular_strength != current.specular_strength
+        {
+            self.vulkan_device
+                .set_specular(state.shininess, state.specular_strength)?;
+        }
+
+        {
+            let mut vulkan_context = self.vulkan_device.vulkan_context.borrow_mut();
+            vulkan_context
+                .camera_controller
+                .lock()
+                .expect("failed to get a lock on camera controller")
+                .set_speed(state.camera_speed);
+            vulkan_context
+                .fly_controller
+                .lock()
+                .expect("failed to get a lock on fly controller")
+                .set_speed(state.camera_speed);
+            vulkan_context.clear_color = state.clear_color;
+        }
+
+        if state.samples != current.samples {
+            self.set_msaa(state.samples)?;
+        }
 
         Ok(())
     }
 
+    // Build the control panel of the window, if shown. The edited settings when they changed.
+    fn run_gui(&self, window_id: WindowId) -> Result<Option<GuiState>> {
+        let mut vulkan_renderer = self.vulkan_renderers[&window_id]
+            .lock()
+            .expect("failed to get a lock on vulkan renderer");
+        let Some(window) = vulkan_renderer.target.window().cloned() else {
+            return Ok(None);
+        };
+
+        if vulkan_renderer.gui.is_none() {
+            return Ok(None);
+        }
+
+        let current = self.gui_state()?;
+        let mut state = current;
+        let supported_samples = self.vulkan_device.supported_sample_counts();
+        let info = GuiInfo {
+            frame_stats: vulkan_renderer.frame_stats(),
+            camera_position: vulkan_renderer.camera.eye.into(),
+        };
+        let Some(gui) = vulkan_renderer.gui.as_mut() else {
+            return Ok(None);
+        };
+        gui.run(&window, |context| {
+            state.show(context, &supported_samples, &info)
+        });
+
+        Ok((state != current).then_some(state))
+    }
+
     pub fn draw(&mut self, window_id: WindowId) -> Result<()> {
-        self.vulkan_renderers[&window_id].lock().unwrap().render()
+        // Redraws still queued after shutdown
+        if !self.vulkan_renderers.contains_key(&window_id) {
+            return Ok(());
+        }
+
+        // Before locking the renderer for the frame: an MSAA change recreates every renderer
+        if let Some(state) = self.run_gui(window_id)? {
+            self.set_gui_state(state)?;
+        }
+
+        let mut vulkan_renderer = self.vulkan_renderers[&window_id].lock().unwrap();
+
+        if self.vulkan_device.vulkan_context.borrow().show_debug_text {
+            let debug_text = self.debug_text(
+                vulkan_renderer.frame_stats(),
+                vulkan_renderer.instance_counts,
+            );
+            vulkan_renderer.draw_text(&debug_text, 8.0, 8.0, 0.75)?;
+        }
+
+        vulkan_renderer.render()
+    }
+
+    // FPS and the current settings, for the text overlay.
+    // `instance_counts`: (drawn, total) mesh instances of the last frame.
+    fn debug_text(&self, frame_stats: FrameStats, instance_counts: (u32, u32)) -> String {
+        let vulkan_context = self.vulkan_device.vulkan_context.borrow();
+        let instances = vulkan_context.instances_per_row.pow(2);
+        let (drawn, total) = instance_counts;
+
+        format!(
+            "{:.0} FPS ({:.1} ms)\nMSAA x{}\n{instances} instances\n{drawn}/{total} drawn{}{}{}",
+            frame_stats.fps,
+            frame_stats.frame_time,
+            vulkan_context.samples as u32,
+            if vulkan_context.frustum_culling {
+                " (culled)"
+            } else {
+                ""
+            },
+            if vulkan_context.bloom.enabled {
+                "\nbloom"
+            } else {
+                ""
+            },
+            if vulkan_context.show_wireframe {
+                "\nwireframe"
+            } else {
+                ""
+            },
+        )
     }
 
     pub fn request_redraw(&mut self) -> Result<()> {
@@ -238,21 +1006,29 @@ impl VisualSystem {
 
 pub struct App {
     is_app_started: bool,
+    config: AppConfig,
     visual_system: Option<VisualSystem>,
+    next_frame_time: Instant, // frame limiter deadline
+    #[cfg(feature = "gamepad")]
+    gamepads: Option<Gamepads>,
 }
 
 impl App {
-    pub fn new<T>(_event_loop: &EventLoop<T>) -> Result<Self> {
+    pub fn new<T>(_event_loop: &EventLoop<T>, config: AppConfig) -> Result<Self> {
         Ok(Self {
             is_app_started: false,
+            config,
             visual_system: None,
+            next_frame_time: Instant::now(),
+            #[cfg(feature = "gamepad")]
+            gamepads: Gamepads::new(),
         })
     }
 
     pub fn start<T>(&mut self, window_target: &EventLoopWindowTarget<T>) -> Result<()> {
         self.visual_system = Some(
-            VisualSystem::new(window_target)
-                .map_err(|_| error::VisualSystemError::ErrorCreatingVisualSystem)?,
+            VisualSystem::new(window_target, &self.config)
+                .map_err(|e| error::VisualSystemError::ErrorCreatingVisualSystem(Box::new(e)))?,
         );
 
         Ok(())
@@ -263,7 +1039,7 @@ impl App {
             .as_mut()
             .expect("no visual system")
             .resume(window_target)
-            .map_err(|_| error::VisualSystemError::ErrorResumingVisualSystem)?;
+            .map_err(|e| error::VisualSystemError::ErrorResumingVisualSystem(Box::new(e)))?;
 
         Ok(())
     }
@@ -275,6 +1051,38 @@ impl App {
             .suspend();
     }
 
+    // Request a redraw every loop, or pace them to `max_fps` by sleeping until the next frame
+    pub fn schedule_redraw<T>(&mut self, window_target: &EventLoopWindowTarget<T>) -> Result<()> {
+        let Some(max_fps) = self.config.max_fps else {
+            return self.visual_system.as_mut().unwrap().request_redraw();
+        };
+
+        let frame_interval = Duration::from_secs_f64(1.0 / max_fps as f64);
+        let now = Instant::now();
+
+        if now >= self.next_frame_time {
+            self.visual_system.as_mut().unwrap().request_redraw()?;
+
+            // Keep a steady cadence, but don't try to catch up after a long stall
+            self.next_frame_time += frame_interval;
+            if self.next_frame_time < now {
+                self.next_frame_time = now + frame_interval;
+            }
+        }
+
+        window_target.set_control_flow(ControlFlow::WaitUntil(self.next_frame_time));
+
+        Ok(())
+    }
+
+    fn redraw(&mut self, window_id: WindowId) -> Result<()> {
+        self.visual_system
+            .as_mut()
+            .unwrap()
+            .draw(window_id)
+            .map_err(|e| error::VisualSystemError::ErrorDrawingVisualSystem(Box::new(e)).into())
+    }
+
     pub fn process_event(
         &mut self,
         event: Event<()>,
@@ -282,6 +1090,21 @@ impl App {
     ) -> Result<()> {
         match event {
             Event::WindowEvent { window_id, event } => {
+                // Late events of a closed secondary window
+                if !self.visual_system.as_ref().unwrap().has_window(window_id) {
+                    return Ok(());
+                }
+
+                // The control panel first, the camera and the shortcuts get what it leaves
+                if self
+                    .visual_system
+                    .as_mut()
+                    .unwrap()
+                    .gui_event(window_id, &event)
+                {
+                    return Ok(());
+                }
+
                 if !self
                     .visual_system
                     .as_mut()
@@ -292,35 +1115,412 @@ impl App {
                     .input(&event)
                 {
                     match event {
-                        WindowEvent::CloseRequested => {
-                            if self.visual_system.as_ref().unwrap().primary_window_id == window_id {
-                                info!("The close button was pressed; stopping \u{2B22}");
-                                window_target.exit()
-                            }
+                        WindowEvent::CloseRequested
+                            if self.visual_system.as_ref().unwrap().primary_window_id
+                                == window_id =>
+                        {
+                            info!("The close button was pressed; stopping \u{2B22}");
+                            self.visual_system.as_mut().unwrap().shutdown();
+                            window_target.exit()
                         }
+                        // Secondary windows close on their own, the app goes on
+                        WindowEvent::CloseRequested => self
+                            .visual_system
+                            .as_mut()
+                            .unwrap()
+                            .close_window(window_id)?,
                         WindowEvent::Resized(new_size) => {
                             self.visual_system
                                 .as_mut()
                                 .unwrap()
                                 .resize(window_id, new_size)
-                                .map_err(|_| error::VisualSystemError::ErrorResizingVisualSystem)?;
+                                .map_err(|e| {
+                                    error::VisualSystemError::ErrorResizingVisualSystem(Box::new(e))
+                                })?;
+                        }
+
+                        WindowEvent::MouseInput { state, button, .. } => self
+                            .visual_system
+                            .as_mut()
+                            .unwrap()
+                            .mouse_input(window_id, state, button),
+                        WindowEvent::CursorMoved { position, .. } => self
+                            .visual_system
+                            .as_mut()
+                            .unwrap()
+                            .cursor_moved(window_id, position),
+
+                        WindowEvent::MouseWheel { delta, .. } => self
+                            .visual_system
+                            .as_mut()
+                            .unwrap()
+                            .mouse_wheel(window_id, delta),
+
+                        WindowEvent::Focused(false) => {
+                            self.visual_system.as_mut().unwrap().unfocus(window_id)
+                        }
+
+                        WindowEvent::DroppedFile(path) => {
+                            self.visual_system.as_mut().unwrap().drop_file(&path)
+                        }
+
+                        // Toggle the compute shader instance animation
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::KeyI),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => self.visual_system.as_mut().unwrap().toggle_animate_instances()?,
+
+                        // Toggle the vertex shader spin of the model
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::KeyR),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => self.visual_system.as_mut().unwrap().toggle_spin_model(),
+
+                        // Toggle the frustum culling of the instances
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::KeyK),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => self
+                            .visual_system
+                            .as_mut()
+                            .unwrap()
+                            .toggle_frustum_culling(),
+
+                        // Grow/shrink the instance grid
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key:
+                                        PhysicalKey::Code(
+                                            keycode @ (KeyCode::Equal | KeyCode::Minus),
+                                        ),
+                                    ..
+                                },
+                            ..
+                        } => self
+                            .visual_system
+                            .as_mut()
+                            .unwrap()
+                            .resize_instance_grid(if keycode == KeyCode::Equal { 1 } else { -1 })?,
+
+                        // Background color presets
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::KeyB),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => self.visual_system.as_mut().unwrap().cycle_clear_color(),
+
+                        // Lighting rigs: studio, outdoor, night
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::KeyL),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => self.visual_system.as_mut().unwrap().cycle_light_preset()?,
+
+                        // Rebuild the pipelines from shaders/*.spv
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::F5),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => self.visual_system.as_mut().unwrap().reload_shaders(),
+
+                        // Debug the winding of a model: cull back, front, nothing
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::KeyU),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => self.visual_system.as_mut().unwrap().cycle_cull_mode()?,
+
+                        // Clockwise or counter clockwise front faces
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::KeyF),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => self.visual_system.as_mut().unwrap().toggle_front_face()?,
+
+                        // Keep the camera out of the model
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::KeyC),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => self.visual_system.as_mut().unwrap().toggle_camera_clamp(),
+
+                        // Cycle the MSAA sample count
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::F3),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => self.visual_system.as_mut().unwrap().cycle_msaa()?,
+
+                        // FPS and settings overlay
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::F1),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => self
+                            .visual_system
+                            .as_mut()
+                            .unwrap()
+                            .toggle_show_debug_text(),
+
+                        // egui control panel
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::F10),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => self.visual_system.as_mut().unwrap().toggle_gui(window_id)?,
+
+                        // Show the depth buffer
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::F4),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => self.visual_system.as_mut().unwrap().toggle_show_depth(),
+
+                        // Wireframe over the shaded meshes
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::F2),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => self.visual_system.as_mut().unwrap().toggle_show_wireframe(),
+
+                        // Reference grid on the XZ plane
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::KeyG),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => self.visual_system.as_mut().unwrap().toggle_show_grid(),
+
+                        // Bloom post-processing
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::F8),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => self.visual_system.as_mut().unwrap().toggle_bloom(),
+
+                        // Save/restore the viewpoint
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::F6),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => {
+                            if let Err(e) = self
+                                .visual_system
+                                .as_ref()
+                                .unwrap()
+                                .save_camera(window_id, CAMERA_FILE)
+                            {
+                                error!("failed to save camera: {e}");
+                            }
+                        }
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::F7),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => {
+                            if let Err(e) = self
+                                .visual_system
+                                .as_mut()
+                                .unwrap()
+                                .load_camera(window_id, CAMERA_FILE)
+                            {
+                                error!("failed to load camera: {e}");
+                            }
                         }
 
-                        WindowEvent::RedrawRequested => self
+                        // Borderless fullscreen for the focused window
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::F11),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => self
+                            .visual_system
+                            .as_ref()
+                            .unwrap()
+                            .toggle_fullscreen(window_id),
+
+                        // Screenshot
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::F12),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => {
+                            if let Err(e) = self
+                                .visual_system
+                                .as_ref()
+                                .unwrap()
+                                .capture_frame(window_id, CAPTURE_FILE)
+                            {
+                                error!("failed to capture the frame: {e}");
+                            }
+                        }
+
+                        // Perspective or orthographic
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::KeyP),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => self
                             .visual_system
                             .as_mut()
                             .unwrap()
-                            .draw(window_id)
-                            .map_err(|_| error::VisualSystemError::ErrorDrawingVisualSystem)?,
+                            .toggle_projection(window_id)?,
+
+                        // Orbit, fly or spherical camera controller
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::KeyV),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => self.visual_system.as_mut().unwrap().cycle_controller_mode(),
+
+                        // Frame the whole model
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::KeyZ),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => self.visual_system.as_ref().unwrap().fit_camera(window_id)?,
+
+                        // Reset the camera
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::Home),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => self
+                            .visual_system
+                            .as_mut()
+                            .unwrap()
+                            .reset_camera(window_id)?,
+
+                        WindowEvent::RedrawRequested => self.redraw(window_id)?,
 
                         _ => {}
                     }
                 } else {
-                    self.visual_system
-                        .as_mut()
-                        .unwrap()
-                        .input()
-                        .map_err(|_| error::VisualSystemError::ErrorInputVisualSystem)?
+                    self.visual_system.as_mut().unwrap().input(window_id)
                 }
             }
 
@@ -338,12 +1538,26 @@ impl App {
                 self.suspend();
             }
 
-            Event::AboutToWait => self
-                .visual_system
-                .as_mut()
-                .unwrap()
-                .request_redraw()
-                .map_err(|_| error::VisualSystemError::ErrorRequestReDrawVisualSystem)?,
+            Event::AboutToWait => {
+                #[cfg(feature = "gamepad")]
+                if let Some(gamepads) = self.gamepads.as_mut() {
+                    self.visual_system
+                        .as_mut()
+                        .unwrap()
+                        .gamepad_input(gamepads.poll())
+                        .map_err(|e| {
+                            error::VisualSystemError::ErrorInputVisualSystem(Box::new(e))
+                        })?;
+                }
+                self.visual_system
+                    .as_mut()
+                    .unwrap()
+                    .update_camera()
+                    .map_err(|e| error::VisualSystemError::ErrorInputVisualSystem(Box::new(e)))?;
+                self.schedule_redraw(window_target).map_err(|e| {
+                    error::VisualSystemError::ErrorRequestReDrawVisualSystem(Box::new(e))
+                })?
+            }
             _ => {}
         }
 
@@ -0,0 +1,113 @@
+// Note: Benchmark
+//
+// `--bench N` renders N frames of the model with the headless renderer (see headless.rs), then
+// prints the frame time stats and exits.
+// No window, no swapchain: the numbers do not depend on a compositor, vsync or the frame limiter.
+//
+// CPU: time of VulkanRenderer::draw_offscreen (recording, submission and the wait for the GPU).
+// GPU: timestamps at the start and the end of the frame command buffer, see GpuTimer.
+// The GPU times arrive one frame late and are missing when timestamps are not supported.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    camera::Camera,
+    config::AppConfig,
+    error::Result,
+    headless::{create_device, HEADLESS_EXTENT},
+    vulkan_instance::VulkanInstance,
+    vulkan_renderer::VulkanRenderer,
+};
+
+// Not counted: pipeline and cache warm-up
+const WARMUP_FRAMES: u32 = 10;
+
+pub struct Bench {
+    frames: u32,
+    warmup: u32,
+    cpu_times: Vec<f32>, // ms
+    gpu_times: Vec<f32>, // ms
+}
+
+impl Bench {
+    pub fn new(frames: u32) -> Self {
+        Self {
+            frames,
+            warmup: WARMUP_FRAMES,
+            cpu_times: Vec::with_capacity(frames as usize),
+            gpu_times: Vec::with_capacity(frames as usize),
+        }
+    }
+
+    pub fn record(&mut self, cpu_time: Duration, gpu_time: Option<f32>) {
+        if self.warmup > 0 {
+            self.warmup -= 1;
+            return;
+        }
+
+        self.cpu_times.push(cpu_time.as_secs_f32() * 1000.0);
+        self.gpu_times.extend(gpu_time);
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.cpu_times.len() >= self.frames as usize
+    }
+
+    pub fn report(&self) {
+        println!("bench: {} frames", self.cpu_times.len());
+        print_stats("CPU", &self.cpu_times);
+        print_stats("GPU", &self.gpu_times);
+    }
+}
+
+// Render `frames` headless frames of the model of `config` and print the stats
+pub fn run_bench(config: &AppConfig, frames: u32) -> Result<()> {
+    let vulkan_instance = Arc::new(VulkanInstance::new_headless(
+        config.device_name.as_deref(),
+        config.validation,
+    )?);
+
+    let vulkan_device = create_device(vulkan_instance, config)?;
+    let mut vulkan_renderer =
+        VulkanRenderer::new_headless(vulkan_device, HEADLESS_EXTENT, Camera::default())?;
+
+    let mut bench = Bench::new(frames);
+    while !bench.is_done() {
+        let start = Instant::now();
+        vulkan_renderer.draw_offscreen()?;
+        bench.record(start.elapsed(), vulkan_renderer.gpu_time);
+    }
+    bench.report();
+
+    Ok(())
+}
+
+fn print_stats(what: &str, times: &[f32]) {
+    if times.is_empty() {
+        println!("{what}: no samples");
+        return;
+    }
+
+    let mut sorted = times.to_vec();
+    sorted.sort_by(f32::total_cmp);
+
+    // Nearest rank
+    let percentile = |p: f32| {
+        sorted[((p / 100.0 * sorted.len() as f32).ceil() as usize).clamp(1, sorted.len()) - 1]
+    };
+    let avg = sorted.iter().sum::<f32>() / sorted.len() as f32;
+
+    println!(
+        "{what} ms: min {:.3} avg {avg:.3} max {:.3} p50 {:.3} p95 {:.3} p99 {:.3} ({} samples, {:.0} FPS avg)",
+        sorted[0],
+        sorted[sorted.len() - 1],
+        percentile(50.0),
+        percentile(95.0),
+        percentile(99.0),
+        sorted.len(),
+        1000.0 / avg,
+    );
+}
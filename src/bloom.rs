@@ -0,0 +1,370 @@
+// Note: Bloom post-processing
+//
+// 1. The main pass resolves into an offscreen scene image instead of the swapchain image.
+// 2. Bright pass: the part of the scene above `threshold` goes into the first (half size) level.
+// 3. Each next level is the previous one downsampled with a 3x3 gaussian blur.
+// 4. Composite: scene + intensity * average of the levels, into the swapchain image.
+//
+// There is no tone mapping yet: the scene image has the swapchain format (LDR, values in [0, 1]),
+// so the threshold has to be below 1. With a UNORM swapchain the scene holds sRGB encoded
+// values and the bloom is added in that space.
+
+use std::sync::Arc;
+
+use vulkano::{
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder,
+        PrimaryAutoCommandBuffer, RenderingAttachmentInfo, RenderingInfo,
+    },
+    descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet},
+    device::Device,
+    format::Format,
+    image::{
+        sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+        view::ImageView,
+        Image, ImageCreateInfo, ImageType, ImageUsage,
+    },
+    memory::allocator::AllocationCreateInfo,
+    pipeline::{
+        graphics::{
+            color_blend::{ColorBlendAttachmentState, ColorBlendState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            subpass::PipelineRenderingCreateInfo,
+            vertex_input::VertexInputState,
+            viewport::{Viewport, ViewportState},
+            GraphicsPipelineCreateInfo,
+        },
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+        DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+    render_pass::{AttachmentLoadOp, AttachmentStoreOp},
+    shader::EntryPoint,
+};
+
+use crate::{
+    config::BloomSettings,
+    error::Result,
+    shader::{bloom_blur_fs, bloom_bright_fs, bloom_composite_fs, fullscreen_vs},
+    vulkan_context::VulkanContext,
+    vulkan_device::VulkanDevice,
+};
+
+// Number of blurred levels, each half the size of the previous one.
+// Must match the bloom samplers of bloom_composite_fs.
+pub const BLOOM_LEVELS: usize = 4;
+
+// Float: the blurred levels keep the precision of the faint tails
+const BLOOM_FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+
+// Shared by all the windows. Created once with the device.
+pub struct BloomPipelines {
+    bright: Arc<GraphicsPipeline>,
+    blur: Arc<GraphicsPipeline>,
+    composite: Arc<GraphicsPipeline>,
+    sampler: Arc<Sampler>, // linear, clamped to the edges
+}
+
+impl BloomPipelines {
+    pub fn new(device: &Arc<Device>, vulkan_context: &VulkanContext) -> Result<Self> {
+        let bright = create_fullscreen_pipeline(
+            device,
+            bloom_bright_fs::load(Arc::clone(device))?
+                .entry_point("main")
+                .unwrap(),
+            BLOOM_FORMAT,
+        )?;
+        let blur = create_fullscreen_pipeline(
+            device,
+            bloom_blur_fs::load(Arc::clone(device))?
+                .entry_point("main")
+                .unwrap(),
+            BLOOM_FORMAT,
+        )?;
+        let composite = create_fullscreen_pipeline(
+            device,
+            bloom_composite_fs::load(Arc::clone(device))?
+                .entry_point("main")
+                .unwrap(),
+            vulkan_context.color_format(),
+        )?;
+
+        let sampler = Sampler::new(
+            Arc::clone(device),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )?;
+
+        Ok(Self {
+            bright,
+            blur,
+            composite,
+            sampler,
+        })
+    }
+}
+
+// Per window images, recreated with the swapchain
+pub struct BloomTargets {
+    pub scene: Arc<ImageView>, // resolve target of the main pass
+    levels: Vec<Arc<ImageView>>,
+    bright_descriptor_set: Arc<PersistentDescriptorSet>,
+    blur_descriptor_sets: Vec<Arc<PersistentDescriptorSet>>, // one per level after the first
+    composite_descriptor_set: Arc<PersistentDescriptorSet>,
+}
+
+impl BloomTargets {
+    pub fn new(vulkan_device: &VulkanDevice, extent: [u32; 2]) -> Result<Self> {
+        let pipelines = vulkan_device.bloom_pipelines();
+        let color_format = vulkan_device.vulkan_context.borrow().color_format();
+
+        let scene = create_target(vulkan_device, color_format, extent)?;
+
+        let levels = (1..=BLOOM_LEVELS as u32)
+            .map(|level| {
+                create_target(
+                    vulkan_device,
+                    BLOOM_FORMAT,
+                    [(extent[0] >> level).max(1), (extent[1] >> level).max(1)],
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let sampled = |binding: u32, image_view: &Arc<ImageView>| {
+            WriteDescriptorSet::image_view_sampler(
+                binding,
+                Arc::clone(image_view),
+                Arc::clone(&pipelines.sampler),
+            )
+        };
+
+        let bright_descriptor_set =
+            create_descriptor_set(vulkan_device, &pipelines.bright, [sampled(0, &scene)])?;
+
+        let blur_descriptor_sets = levels
+            .iter()
+            .take(BLOOM_LEVELS - 1)
+            .map(|source| {
+                create_descriptor_set(vulkan_device, &pipelines.blur, [sampled(0, source)])
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let composite_descriptor_set = create_descriptor_set(
+            vulkan_device,
+            &pipelines.composite,
+            std::iter::once(sampled(0, &scene)).chain(
+                levels
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, level)| sampled(idx as u32 + 1, level)),
+            ),
+        )?;
+
+        Ok(Self {
+            scene,
+            levels,
+            bright_descriptor_set,
+            blur_descriptor_sets,
+            composite_descriptor_set,
+        })
+    }
+
+    // Record the bloom passes, after the main pass has resolved into `scene`
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<
+            PrimaryAutoCommandBuffer<Arc<StandardCommandBufferAllocator>>,
+            Arc<StandardCommandBufferAllocator>,
+        >,
+        pipelines: &BloomPipelines,
+        settings: &BloomSettings,
+        target: Arc<ImageView>,
+    ) -> Result<()> {
+        // Bright pass
+        begin_fullscreen_pass(
+            builder,
+            &pipelines.bright,
+            &self.bright_descriptor_set,
+            &self.levels[0],
+        )?
+        .push_constants(
+            Arc::clone(pipelines.bright.layout()),
+            0,
+            bloom_bright_fs::PushConstantData {
+                threshold: settings.threshold,
+            },
+        )?
+        .draw(3, 1, 0, 0)?
+        .end_rendering()?;
+
+        // Downsample + blur, level by level
+        for (descriptor_set, level) in self.blur_descriptor_sets.iter().zip(&self.levels[1..]) {
+            begin_fullscreen_pass(builder, &pipelines.blur, descriptor_set, level)?
+                .draw(3, 1, 0, 0)?
+                .end_rendering()?;
+        }
+
+        // Composite
+        begin_fullscreen_pass(
+            builder,
+            &pipelines.composite,
+            &self.composite_descriptor_set,
+            &target,
+        )?
+        .push_constants(
+            Arc::clone(pipelines.composite.layout()),
+            0,
+            bloom_composite_fs::PushConstantData {
+                intensity: settings.intensity,
+            },
+        )?
+        .draw(3, 1, 0, 0)?
+        .end_rendering()?;
+
+        Ok(())
+    }
+}
+
+// Begin rendering into `target` with the pipeline and its descriptor set bound.
+// The whole target is overwritten by the fullscreen triangle: nothing to load.
+fn begin_fullscreen_pass<'a>(
+    builder: &'a mut AutoCommandBufferBuilder<
+        PrimaryAutoCommandBuffer<Arc<StandardCommandBufferAllocator>>,
+        Arc<StandardCommandBufferAllocator>,
+    >,
+    pipeline: &Arc<GraphicsPipeline>,
+    descriptor_set: &Arc<PersistentDescriptorSet>,
+    target: &Arc<ImageView>,
+) -> Result<
+    &'a mut AutoCommandBufferBuilder<
+        PrimaryAutoCommandBuffer<Arc<StandardCommandBufferAllocator>>,
+        Arc<StandardCommandBufferAllocator>,
+    >,
+> {
+    let extent = target.image().extent();
+
+    builder
+        .begin_rendering(RenderingInfo {
+            color_attachments: vec![Some(RenderingAttachmentInfo {
+                load_op: AttachmentLoadOp::DontCare,
+                store_op: AttachmentStoreOp::Store,
+                ..RenderingAttachmentInfo::image_view(Arc::clone(target))
+            })],
+            ..Default::default()
+        })?
+        .set_viewport(
+            0,
+            [Viewport {
+                offset: [0.0, 0.0],
+                extent: [extent[0] as f32, extent[1] as f32],
+                depth_range: 0.0..=1.0,
+            }]
+            .into_iter()
+            .collect(),
+        )?
+        .bind_pipeline_graphics(Arc::clone(pipeline))?
+        .bind_descriptor_sets(
+            PipelineBindPoint::Graphics,
+            Arc::clone(pipeline.layout()),
+            0,
+            Arc::clone(descriptor_set),
+        )?;
+
+    Ok(builder)
+}
+
+fn create_target(
+    vulkan_device: &VulkanDevice,
+    format: Format,
+    extent: [u32; 2],
+) -> Result<Arc<ImageView>> {
+    let image_view = ImageView::new_default(Image::new(
+        vulkan_device.memory_allocator.clone(),
+        ImageCreateInfo {
+            image_type: ImageType::Dim2d,
+            format,
+            extent: [extent[0], extent[1], 1],
+            usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED,
+            ..Default::default()
+        },
+        AllocationCreateInfo::default(),
+    )?)?;
+
+    Ok(image_view)
+}
+
+fn create_descriptor_set(
+    vulkan_device: &VulkanDevice,
+    pipeline: &Arc<GraphicsPipeline>,
+    writes: impl IntoIterator<Item = WriteDescriptorSet>,
+) -> Result<Arc<PersistentDescriptorSet>> {
+    let descriptor_set = PersistentDescriptorSet::new(
+        vulkan_device.descriptor_set_allocator(),
+        Arc::clone(
+            pipeline
+                .layout()
+                .set_layouts()
+                .first()
+                .expect("error getting the bloom layout"),
+        ),
+        writes,
+        [],
+    )?;
+
+    Ok(descriptor_set)
+}
+
+// Fullscreen triangle pipeline writing a single sampled color target
+fn create_fullscreen_pipeline(
+    device: &Arc<Device>,
+    fragment_shader: EntryPoint,
+    format: Format,
+) -> Result<Arc<GraphicsPipeline>> {
+    let vertex_shader = fullscreen_vs::load(Arc::clone(device))?
+        .entry_point("main")
+        .unwrap();
+
+    let stages = [
+        PipelineShaderStageCreateInfo::new(vertex_shader),
+        PipelineShaderStageCreateInfo::new(fragment_shader),
+    ];
+
+    let layout = PipelineLayout::new(
+        Arc::clone(device),
+        PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+            .into_pipeline_layout_create_info(Arc::clone(device))?,
+    )?;
+
+    let subpass = PipelineRenderingCreateInfo {
+        color_attachment_formats: vec![Some(format)],
+        ..Default::default()
+    };
+
+    let pipeline = GraphicsPipeline::new(
+        Arc::clone(device),
+        None,
+        GraphicsPipelineCreateInfo {
+            stages: stages.into_iter().collect(),
+            vertex_input_state: Some(VertexInputState::default()),
+            input_assembly_state: Some(InputAssemblyState::default()),
+            viewport_state: Some(ViewportState::default()),
+            rasterization_state: Some(RasterizationState::default()),
+            multisample_state: Some(MultisampleState::default()),
+            color_blend_state: Some(ColorBlendState::with_attachment_states(
+                subpass.color_attachment_formats.len() as u32,
+                ColorBlendAttachmentState::default(),
+            )),
+            dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+            subpass: Some(subpass.into()),
+            ..GraphicsPipelineCreateInfo::layout(layout)
+        },
+    )?;
+
+    Ok(pipeline)
+}
@@ -1,15 +1,22 @@
-use std::f32::consts::FRAC_PI_4;
+use std::{
+    f32::consts::{FRAC_PI_2, FRAC_PI_4, TAU},
+    fs::File,
+    io::{BufReader, BufWriter},
+};
 
 use bytemuck::{Pod, Zeroable};
 
-use nalgebra::Matrix4;
+use nalgebra::{Matrix3, Matrix4, Point3, Rotation3, Unit, Vector3};
+use serde::{Deserialize, Serialize};
 use winit::{
     event::{ElementState, KeyEvent, WindowEvent},
     keyboard::{KeyCode, PhysicalKey},
 };
 
+use crate::error::Result;
+
+// Remap OpenGL clip space depth [-1, 1] to Vulkan (and wgpu) depth [0, 1]: z' = 0.5 * z + 0.5 * w
 #[rustfmt::skip]
-#[allow(unused)]
 pub const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
     0.0, 1.0, 0.0, 0.0,
@@ -24,7 +31,38 @@ pub const GLTF_TO_VULKAN_MATRIX: Matrix4<f32> = Matrix4::new(
 0.,  0.,  0.,  1.,
 );
 
-#[derive(Debug)]
+// Clip space depth convention produced by the projection matrix.
+// nalgebra's Perspective3 targets OpenGL [-1, 1] depth. Vulkan clips everything below 0,
+// so without the remap the front half of the frustum (near the camera) is lost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum DepthRange {
+    // Vulkan native [0, 1]. Applies OPENGL_TO_WGPU_MATRIX
+    #[default]
+    ZeroToOne,
+    // Raw OpenGL [-1, 1]. No remap, for engines expecting the nalgebra projection untouched
+    NegativeOneToOne,
+}
+
+// Projection built by the camera.
+// Orthographic keeps parallel lines parallel: for 2D sprites, UI and technical (CAD-style) views.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum Projection {
+    #[default]
+    Perspective,
+    // `height` is the visible height in world units, the width follows the aspect ratio
+    Orthographic {
+        height: f32,
+    },
+}
+
+// Smallest bounding sphere radius framed by Camera::fit
+const MIN_FIT_RADIUS: f32 = 0.01;
+
+// Orbit pitch limit, just under the poles where the view would flip around `up`
+const MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+
+// Serializable to save/restore viewpoints
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Camera {
     pub eye: nalgebra::Point3<f32>,
     pub target: nalgebra::Point3<f32>,
@@ -33,6 +71,9 @@ pub struct Camera {
     pub fovy: f32,
     pub znear: f32,
     pub zfar: f32,
+    pub depth_range: DepthRange,
+    #[serde(default)] // viewpoints saved before the projection mode existed
+    pub projection: Projection,
 }
 
 impl Camera {
@@ -58,24 +99,62 @@ impl Camera {
             fovy,
             znear,
             zfar,
+            depth_range: DepthRange::default(),
+            projection: Projection::default(),
+        }
+    }
+
+    // Switch between perspective and orthographic. The orthographic height is the one seen by the
+    // perspective at the target distance, so the model keeps about the same size on screen.
+    pub fn toggle_projection(&mut self) {
+        self.projection = match self.projection {
+            Projection::Perspective => Projection::Orthographic {
+                height: 2.0 * (self.target - self.eye).magnitude() * (self.fovy / 2.0).tan(),
+            },
+            Projection::Orthographic { .. } => Projection::Perspective,
+        };
+    }
+
+    fn depth_remap_matrix(&self) -> Matrix4<f32> {
+        match self.depth_range {
+            DepthRange::ZeroToOne => OPENGL_TO_WGPU_MATRIX,
+            DepthRange::NegativeOneToOne => Matrix4::identity(),
+        }
+    }
+
+    // OpenGL style projection matrix, before the depth remap
+    fn projection_matrix(&self) -> Matrix4<f32> {
+        match self.projection {
+            Projection::Perspective => {
+                nalgebra::Perspective3::new(self.aspect, self.fovy, self.znear, self.zfar)
+                    .to_homogeneous()
+            }
+            Projection::Orthographic { height } => {
+                let half_height = height / 2.0;
+                let half_width = half_height * self.aspect;
+                nalgebra::Orthographic3::new(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    self.znear,
+                    self.zfar,
+                )
+                .to_homogeneous()
+            }
         }
     }
 
     pub fn build_view_projection_matrix(&self) -> Matrix4<f32> {
         // 1.
         let view = Matrix4::look_at_rh(&self.eye, &self.target, &self.up);
-        // 2.
-        let projection = nalgebra::Perspective3::new(self.aspect, self.fovy, self.znear, self.zfar);
 
-        // 3.
-        // Convert Perspective3 to Matrix4
-        GLTF_TO_VULKAN_MATRIX * projection.as_matrix() * view
+        // 2.
+        GLTF_TO_VULKAN_MATRIX * self.depth_remap_matrix() * self.projection_matrix() * view
     }
 
     pub fn build_projection_matrix(&self) -> Matrix4<f32> {
-        let projection = nalgebra::Perspective3::new(self.aspect, self.fovy, self.znear, self.zfar);
-
-        GLTF_TO_VULKAN_MATRIX * projection.as_matrix()
+        GLTF_TO_VULKAN_MATRIX * self.depth_remap_matrix() * self.projection_matrix()
     }
 
     pub fn build_view_matrix(&self) -> Matrix4<f32> {
@@ -85,6 +164,142 @@ impl Camera {
     pub fn update_aspect(&mut self, width: u32, height: u32) {
         self.aspect = width as f32 / height.max(1) as f32;
     }
+
+    // Frame the world space (min, max) box: `target` at its center, `eye` back along the current
+    // view direction until the sphere around the box fits both the vertical and the horizontal
+    // field of view. The depth range follows the box size, with room to zoom out.
+    pub fn fit(&mut self, min: [f32; 3], max: [f32; 3]) {
+        let (min, max) = (Point3::from(min), Point3::from(max));
+        let center = nalgebra::center(&min, &max);
+        // A flat or empty box still gets a usable distance
+        let radius = ((max - min).magnitude() / 2.0).max(MIN_FIT_RADIUS);
+
+        let backward = (self.eye - self.target)
+            .try_normalize(f32::EPSILON)
+            .unwrap_or_else(Vector3::z);
+        let half_fovy = self.fovy / 2.0;
+        let half_fovx = (half_fovy.tan() * self.aspect).atan();
+        let distance = radius / half_fovy.min(half_fovx).sin();
+
+        self.target = center;
+        self.eye = center + backward * distance;
+        self.znear = radius * 0.01;
+        self.zfar = (distance + radius) * 10.0;
+
+        if let Projection::Orthographic { height } = &mut self.projection {
+            *height = 2.0 * radius / self.aspect.min(1.0);
+        }
+    }
+
+    // Back to the default viewpoint and projection. The aspect belongs to the window: kept.
+    pub fn reset(&mut self) {
+        *self = Self {
+            aspect: self.aspect,
+            ..Self::default()
+        };
+    }
+
+    // Rotate `eye` around `target` at the same distance: `delta_yaw` around `up`, `delta_pitch`
+    // toward `up`. In radians. The pitch stays within ±MAX_PITCH, `up` is left untouched.
+    pub fn orbit(&mut self, delta_yaw: f32, delta_pitch: f32) {
+        self.eye = rotate_around(self.target, self.eye, self.up, delta_yaw, delta_pitch);
+    }
+
+    // Mouse-look of the fly mode: rotate `target` around `eye`, the reverse of `orbit`.
+    // A positive `delta_pitch` looks up.
+    pub fn look(&mut self, delta_yaw: f32, delta_pitch: f32) {
+        self.target = rotate_around(self.eye, self.target, self.up, delta_yaw, delta_pitch);
+    }
+
+    // Move `eye` by `amount` toward `target` (away when negative). However large the amount,
+    // `eye` stops at `znear` from the target instead of passing through it.
+    // The orthographic view height follows the distance, so zooming works in both projections.
+    pub fn zoom(&mut self, amount: f32) {
+        let forward = self.target - self.eye;
+        let distance = forward.magnitude();
+        if distance <= f32::EPSILON {
+            return;
+        }
+
+        let new_distance = (distance - amount).max(self.znear);
+        self.eye = self.target - forward / distance * new_distance;
+
+        if let Projection::Orthographic { height } = &mut self.projection {
+            *height *= new_distance / distance;
+        }
+    }
+
+    // Push `eye` out of the (min, max) world space boxes, to `margin` from their faces.
+    // Along the axis with the smallest penetration, so the camera slides along the box.
+    pub fn clamp_eye(&mut self, boxes: &[([f32; 3], [f32; 3])], margin: f32) {
+        for (min, max) in boxes {
+            let inside = (0..3).all(|axis| {
+                self.eye[axis] > min[axis] - margin && self.eye[axis] < max[axis] + margin
+            });
+            if !inside {
+                continue;
+            }
+
+            // Distance to get out through each of the 6 faces
+            let (axis, position) = (0..3)
+                .flat_map(|axis| [(axis, min[axis] - margin), (axis, max[axis] + margin)])
+                .min_by(|(a, a_position), (b, b_position)| {
+                    (self.eye[*a] - a_position)
+                        .abs()
+                        .total_cmp(&(self.eye[*b] - b_position).abs())
+                })
+                .expect("a box has faces");
+
+            self.eye[axis] = position;
+        }
+    }
+
+    // Save the viewpoint to a JSON file
+    pub fn save(&self, path: &str) -> Result<()> {
+        serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), self)?;
+        Ok(())
+    }
+
+    // Load a viewpoint saved with `save`
+    pub fn load(path: &str) -> Result<Camera> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+}
+
+// `point` rotated around `center` at the same distance, see Camera::orbit
+fn rotate_around(
+    center: Point3<f32>,
+    point: Point3<f32>,
+    up: Vector3<f32>,
+    delta_yaw: f32,
+    delta_pitch: f32,
+) -> Point3<f32> {
+    let up = Unit::new_normalize(up);
+    let offset = point - center;
+    let radius = offset.magnitude();
+    if radius <= f32::EPSILON {
+        return point;
+    }
+
+    // Split the offset into its height along `up` and its horizontal direction
+    let pitch = (offset.dot(&up) / radius).clamp(-1.0, 1.0).asin();
+    let horizontal = offset - up.into_inner() * offset.dot(&up);
+    let horizontal = if horizontal.magnitude() > f32::EPSILON {
+        horizontal.normalize()
+    } else {
+        // Exactly above or below the center: any horizontal direction
+        let side = if up.x.abs() < 0.9 {
+            Vector3::x()
+        } else {
+            Vector3::z()
+        };
+        up.cross(&side).normalize()
+    };
+
+    let pitch = (pitch + delta_pitch).clamp(-MAX_PITCH, MAX_PITCH);
+    let horizontal = Rotation3::from_axis_angle(&up, delta_yaw) * horizontal;
+
+    center + (horizontal * pitch.cos() + up.into_inner() * pitch.sin()) * radius
 }
 
 impl Default for Camera {
@@ -97,6 +312,8 @@ impl Default for Camera {
             fovy: FRAC_PI_4,
             znear: 0.1,
             zfar: 100.0,
+            depth_range: DepthRange::default(),
+            projection: Projection::default(),
         }
     }
 }
@@ -140,6 +357,30 @@ impl CameraController {
         }
     }
 
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+
+    // A movement key is held: the camera has to be updated this frame
+    pub fn is_moving(&self) -> bool {
+        self.is_forward_pressed
+            || self.is_backward_pressed
+            || self.is_left_pressed
+            || self.is_right_pressed
+    }
+
+    // Forget the held keys, their release goes to another window
+    pub fn release_keys(&mut self) {
+        self.is_forward_pressed = false;
+        self.is_backward_pressed = false;
+        self.is_left_pressed = false;
+        self.is_right_pressed = false;
+    }
+
     pub fn process_events(&mut self, event: &WindowEvent) -> bool {
         match event {
             WindowEvent::KeyboardInput {
@@ -208,6 +449,262 @@ impl CameraController {
     }
 }
 
+// Which controller the camera keys and the mouse drag go to, cycled with `V`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ControllerMode {
+    // CameraController: around a fixed target
+    #[default]
+    Orbit,
+    // FlyController: first person, `eye` and `target` move together
+    Fly,
+    // SphericalController: around the target, from azimuth, elevation and radius
+    Spherical,
+}
+
+impl ControllerMode {
+    pub fn next(self) -> Self {
+        match self {
+            ControllerMode::Orbit => ControllerMode::Fly,
+            ControllerMode::Fly => ControllerMode::Spherical,
+            ControllerMode::Spherical => ControllerMode::Orbit,
+        }
+    }
+}
+
+// Free-fly first person controller: WASD along the view, E/Space up and Q/Ctrl down along `up`.
+// The mouse-look is Camera::look.
+pub struct FlyController {
+    speed: f32,
+    is_forward_pressed: bool,
+    is_backward_pressed: bool,
+    is_left_pressed: bool,
+    is_right_pressed: bool,
+    is_up_pressed: bool,
+    is_down_pressed: bool,
+}
+
+impl FlyController {
+    pub fn new(speed: f32) -> Self {
+        Self {
+            speed,
+            is_forward_pressed: false,
+            is_backward_pressed: false,
+            is_left_pressed: false,
+            is_right_pressed: false,
+            is_up_pressed: false,
+            is_down_pressed: false,
+        }
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.is_forward_pressed
+            || self.is_backward_pressed
+            || self.is_left_pressed
+            || self.is_right_pressed
+            || self.is_up_pressed
+            || self.is_down_pressed
+    }
+
+    pub fn release_keys(&mut self) {
+        *self = Self::new(self.speed);
+    }
+
+    pub fn process_events(&mut self, event: &WindowEvent) -> bool {
+        let WindowEvent::KeyboardInput {
+            event:
+                KeyEvent {
+                    state,
+                    physical_key: PhysicalKey::Code(keycode),
+                    ..
+                },
+            ..
+        } = event
+        else {
+            return false;
+        };
+
+        let is_pressed = *state == ElementState::Pressed;
+        let key = match keycode {
+            KeyCode::KeyW | KeyCode::ArrowUp => &mut self.is_forward_pressed,
+            KeyCode::KeyA | KeyCode::ArrowLeft => &mut self.is_left_pressed,
+            KeyCode::KeyS | KeyCode::ArrowDown => &mut self.is_backward_pressed,
+            KeyCode::KeyD | KeyCode::ArrowRight => &mut self.is_right_pressed,
+            KeyCode::KeyE | KeyCode::Space => &mut self.is_up_pressed,
+            KeyCode::KeyQ | KeyCode::ControlLeft | KeyCode::ControlRight => {
+                &mut self.is_down_pressed
+            }
+            _ => return false,
+        };
+        *key = is_pressed;
+        true
+    }
+
+    // Translate `eye` and `target` together, the view direction is kept
+    pub fn update_camera(&self, camera: &mut Camera) {
+        let forward = camera.target - camera.eye;
+        if forward.magnitude() <= f32::EPSILON {
+            return;
+        }
+        let forward = forward.normalize();
+        let right = forward.cross(&camera.up).normalize();
+        let up = camera.up.normalize();
+
+        let direction = |positive: bool, negative: bool| match (positive, negative) {
+            (true, false) => 1.0,
+            (false, true) => -1.0,
+            _ => 0.0,
+        };
+        let translation = (forward * direction(self.is_forward_pressed, self.is_backward_pressed)
+            + right * direction(self.is_right_pressed, self.is_left_pressed)
+            + up * direction(self.is_up_pressed, self.is_down_pressed))
+            * self.speed;
+
+        camera.eye += translation;
+        camera.target += translation;
+    }
+}
+
+// Orbit controller keeping the spherical coordinates of `eye` around `target`: A/D (left/right)
+// turn the azimuth, W/S (up/down) the elevation and the wheel changes the radius. `eye` is
+// recomputed from them on each update instead of being nudged, so the radius doesn't drift and
+// the elevation is clamped to ±MAX_PITCH.
+pub struct SphericalController {
+    speed: f32,     // radians per frame
+    azimuth: f32,   // around `up`, see spherical_basis
+    elevation: f32, // above the plane perpendicular to `up`
+    radius: f32,
+    // (eye, target) of the last update. Anything else moved the camera (the mouse, a reset,
+    // another window): the coordinates are read again from it.
+    synced: Option<(Point3<f32>, Point3<f32>)>,
+    is_forward_pressed: bool,
+    is_backward_pressed: bool,
+    is_left_pressed: bool,
+    is_right_pressed: bool,
+}
+
+impl SphericalController {
+    pub fn new(speed: f32) -> Self {
+        Self {
+            speed,
+            azimuth: 0.0,
+            elevation: 0.0,
+            radius: 1.0,
+            synced: None,
+            is_forward_pressed: false,
+            is_backward_pressed: false,
+            is_left_pressed: false,
+            is_right_pressed: false,
+        }
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.is_forward_pressed
+            || self.is_backward_pressed
+            || self.is_left_pressed
+            || self.is_right_pressed
+    }
+
+    pub fn release_keys(&mut self) {
+        self.is_forward_pressed = false;
+        self.is_backward_pressed = false;
+        self.is_left_pressed = false;
+        self.is_right_pressed = false;
+    }
+
+    pub fn process_events(&mut self, event: &WindowEvent) -> bool {
+        let WindowEvent::KeyboardInput {
+            event:
+                KeyEvent {
+                    state,
+                    physical_key: PhysicalKey::Code(keycode),
+                    ..
+                },
+            ..
+        } = event
+        else {
+            return false;
+        };
+
+        let is_pressed = *state == ElementState::Pressed;
+        let key = match keycode {
+            KeyCode::KeyW | KeyCode::ArrowUp => &mut self.is_forward_pressed,
+            KeyCode::KeyA | KeyCode::ArrowLeft => &mut self.is_left_pressed,
+            KeyCode::KeyS | KeyCode::ArrowDown => &mut self.is_backward_pressed,
+            KeyCode::KeyD | KeyCode::ArrowRight => &mut self.is_right_pressed,
+            _ => return false,
+        };
+        *key = is_pressed;
+        true
+    }
+
+    // One step of the held keys. W raises the eye, A/D turn it the same way as CameraController.
+    pub fn update_camera(&mut self, camera: &mut Camera) {
+        self.sync(camera);
+
+        let direction = |positive: bool, negative: bool| match (positive, negative) {
+            (true, false) => 1.0,
+            (false, true) => -1.0,
+            _ => 0.0,
+        };
+        self.azimuth += direction(self.is_left_pressed, self.is_right_pressed) * self.speed;
+        self.elevation += direction(self.is_forward_pressed, self.is_backward_pressed) * self.speed;
+
+        self.apply(camera);
+    }
+
+    // Same as Camera::zoom, the radius keeps the new distance
+    pub fn zoom(&mut self, camera: &mut Camera, amount: f32) {
+        self.sync(camera);
+        camera.zoom(amount);
+        self.radius = (camera.target - camera.eye).magnitude();
+        self.apply(camera);
+    }
+
+    // Read the coordinates from the camera, unless it is where the last update left it
+    fn sync(&mut self, camera: &Camera) {
+        if self.synced == Some((camera.eye, camera.target)) {
+            return;
+        }
+
+        let (up, east, north) = spherical_basis(camera.up);
+        let offset = camera.eye - camera.target;
+        self.radius = offset.magnitude().max(camera.znear);
+        self.elevation = (offset.dot(&up) / offset.magnitude().max(f32::EPSILON))
+            .clamp(-1.0, 1.0)
+            .asin();
+        self.azimuth = offset.dot(&north).atan2(offset.dot(&east));
+    }
+
+    fn apply(&mut self, camera: &mut Camera) {
+        self.elevation = self.elevation.clamp(-MAX_PITCH, MAX_PITCH);
+        self.azimuth %= TAU;
+
+        let (up, east, north) = spherical_basis(camera.up);
+        let horizontal = east * self.azimuth.cos() + north * self.azimuth.sin();
+        camera.eye = camera.target
+            + (horizontal * self.elevation.cos() + up * self.elevation.sin()) * self.radius;
+        self.synced = Some((camera.eye, camera.target));
+    }
+}
+
+// Axes of the spherical coordinates: `up` and two horizontal ones, azimuth 0 along `east`
+fn spherical_basis(up: Vector3<f32>) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>) {
+    let up = up.normalize();
+    let side = if up.x.abs() < 0.9 {
+        Vector3::x()
+    } else {
+        Vector3::z()
+    };
+    let east = (side - up * up.dot(&side)).normalize();
+    let north = up.cross(&east);
+
+    (up, east, north)
+}
+
 // MVP (Model-View-Projection)
 #[repr(C)]
 #[derive(Debug, Copy, Clone, Pod, Zeroable)]
@@ -215,6 +712,9 @@ pub struct Mvp {
     model: [[f32; 4]; 4],
     view: [[f32; 4]; 4],
     projection: [[f32; 4]; 4],
+    // Inverse transpose of `model`, for the normals: the lighting is done in world space, so the
+    // view isn't part of it. std140 mat3: 3 columns padded to vec4.
+    normal_matrix: [[f32; 4]; 3],
 }
 
 impl Mvp {
@@ -223,18 +723,179 @@ impl Mvp {
             model: Matrix4::identity().into(),
             view: Matrix4::identity().into(),
             projection: Matrix4::identity().into(),
+            normal_matrix: normal_matrix(&Matrix4::identity()),
         }
     }
 
-    pub fn update_view(&mut self, camera: &Camera) {
+    // After a camera change. The normal matrix is recomputed with them, on the host: the vertex
+    // shader doesn't invert anything.
+    pub fn update_view_proj(&mut self, camera: &Camera) {
         self.view = camera.build_view_matrix().into();
+        self.projection = camera.build_projection_matrix().into();
+        self.normal_matrix = normal_matrix(&self.model_matrix());
     }
 
-    pub fn update_projection(&mut self, camera: &Camera) {
-        self.projection = camera.build_projection_matrix().into();
+    pub fn model_matrix(&self) -> Matrix4<f32> {
+        self.model.into()
     }
 
     pub fn update_model_translate(&mut self, vector: nalgebra::Vector3<f32>) {
-        self.model = nalgebra::Matrix4::new_translation(&vector).into();
+        let model = nalgebra::Matrix4::new_translation(&vector);
+        self.model = model.into();
+        self.normal_matrix = normal_matrix(&model);
+    }
+}
+
+// Keeps the normals perpendicular to the surface under non-uniform scale.
+// Upper 3x3 of the inverse transpose, as 3 columns padded to vec4 (std140 and vertex attributes).
+pub fn normal_matrix(matrix: &Matrix4<f32>) -> [[f32; 4]; 3] {
+    let normal_matrix = matrix
+        .fixed_view::<3, 3>(0, 0)
+        .into_owned()
+        .try_inverse()
+        .unwrap_or_else(Matrix3::identity)
+        .transpose();
+    let column = |index: usize| {
+        let column = normal_matrix.column(index);
+        [column[0], column[1], column[2], 0.0]
+    };
+
+    [column(0), column(1), column(2)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Clip space, after the perspective divide
+    fn project(view_projection: &Matrix4<f32>, point: Point3<f32>) -> Point3<f32> {
+        view_projection.transform_point(&point)
+    }
+
+    // Looking down -z from z = 5
+    fn camera(projection: Projection) -> Camera {
+        Camera {
+            projection,
+            ..Camera::new(
+                Point3::new(0.0, 0.0, 5.0),
+                Point3::origin(),
+                Vector3::y(),
+                1.0,
+                FRAC_PI_4,
+                0.1,
+                100.0,
+            )
+        }
+    }
+
+    #[test]
+    fn nearer_object_has_smaller_depth() {
+        let view_projection = camera(Projection::Perspective).build_view_projection_matrix();
+        // Just past znear: negative OpenGL depth, clipped by Vulkan without the ZeroToOne remap
+        let near = project(&view_projection, Point3::new(0.01, 0.02, 4.85));
+        let far = project(&view_projection, Point3::new(-0.3, 0.2, -20.0));
+
+        assert!((0.0..=1.0).contains(&near.z), "near depth {}", near.z);
+        assert!((0.0..=1.0).contains(&far.z), "far depth {}", far.z);
+        assert!(near.z < far.z);
+    }
+
+    #[test]
+    fn nearer_object_has_smaller_depth_without_remap() {
+        let mut camera = camera(Projection::Perspective);
+        camera.depth_range = DepthRange::NegativeOneToOne;
+        let view_projection = camera.build_view_projection_matrix();
+        let near = project(&view_projection, Point3::new(0.01, 0.02, 4.85));
+        let far = project(&view_projection, Point3::new(-0.3, 0.2, -20.0));
+
+        // The near one lands in the negative half, the one Vulkan would clip
+        assert!((-1.0..0.0).contains(&near.z), "near depth {}", near.z);
+        assert!((-1.0..=1.0).contains(&far.z), "far depth {}", far.z);
+        assert!(near.z < far.z);
+    }
+
+    // The depth view shows the orthographic depth as is, see shader::depth_view_fs
+    #[test]
+    fn orthographic_depth_is_linear() {
+        let camera = camera(Projection::Orthographic { height: 4.0 });
+        let view_projection = camera.build_view_projection_matrix();
+
+        for distance in [0.1, 10.09, 50.05, 77.0] {
+            let depth = project(&view_projection, Point3::new(0.5, -0.5, 5.0 - distance)).z;
+            let expected = (distance - camera.znear) / (camera.zfar - camera.znear);
+            assert!((depth - expected).abs() < 1e-4, "{depth} != {expected}");
+        }
+    }
+
+    // The F6/F7 bookmark, see VisualSystem::save_camera
+    #[test]
+    fn saved_camera_loads_back() {
+        let mut saved = camera(Projection::Orthographic { height: 4.0 });
+        saved.depth_range = DepthRange::NegativeOneToOne;
+        saved.eye = Point3::new(3.0, 4.0, 5.0);
+
+        let path =
+            std::env::temp_dir().join(format!("vulkanox_camera_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        saved.save(path).unwrap();
+        let loaded = Camera::load(path);
+        std::fs::remove_file(path).unwrap();
+        let loaded = loaded.unwrap();
+
+        assert_eq!(loaded.eye, saved.eye);
+        assert_eq!(loaded.target, saved.target);
+        assert_eq!(loaded.up, saved.up);
+        assert_eq!(loaded.aspect, saved.aspect);
+        assert_eq!(loaded.fovy, saved.fovy);
+        assert_eq!(loaded.znear, saved.znear);
+        assert_eq!(loaded.zfar, saved.zfar);
+        assert_eq!(loaded.depth_range, saved.depth_range);
+        assert_eq!(loaded.projection, saved.projection);
+    }
+
+    // 2D cross product of the screen directions of two world space segments, normalized
+    fn screen_cross(camera: &Camera, a: [Point3<f32>; 2], b: [Point3<f32>; 2]) -> f32 {
+        let view_projection = camera.build_view_projection_matrix();
+        let direction = |[start, end]: [Point3<f32>; 2]| {
+            (project(&view_projection, end) - project(&view_projection, start))
+                .xy()
+                .normalize()
+        };
+        let (a, b) = (direction(a), direction(b));
+        a.x * b.y - a.y * b.x
+    }
+
+    #[test]
+    fn orthographic_keeps_parallel_lines_parallel() {
+        let mut camera = camera(Projection::Orthographic { height: 4.0 });
+        camera.eye = Point3::new(3.0, 4.0, 5.0);
+        // Parallel, receding from the camera at different offsets
+        let a = [Point3::new(-1.0, 0.0, 1.0), Point3::new(-1.0, 0.0, -8.0)];
+        let b = [Point3::new(1.5, -0.5, 2.0), Point3::new(1.5, -0.5, -7.0)];
+
+        assert!(screen_cross(&camera, a, b).abs() < 1e-4);
+
+        // The perspective makes them converge
+        camera.projection = Projection::Perspective;
+        assert!(screen_cross(&camera, a, b).abs() > 1e-2);
+    }
+
+    #[test]
+    fn normal_matrix_keeps_normals_perpendicular() {
+        let model = Matrix4::new_nonuniform_scaling(&Vector3::new(4.0, 1.0, 0.5))
+            * Rotation3::from_axis_angle(&Vector3::z_axis(), 0.7).to_homogeneous();
+        let [x, y, z] = normal_matrix(&model);
+        let normal_matrix = Matrix3::from_columns(&[
+            Vector3::new(x[0], x[1], x[2]),
+            Vector3::new(y[0], y[1], y[2]),
+            Vector3::new(z[0], z[1], z[2]),
+        ]);
+        // The diagonal of the unit square and its normal
+        let tangent = model.transform_vector(&Vector3::new(1.0, 1.0, 0.0));
+        let normal = normal_matrix * Vector3::new(1.0, -1.0, 0.0);
+
+        assert!(tangent.dot(&normal).abs() < 1e-5);
+        // The padding column of the std140 mat3
+        assert_eq!([x[3], y[3], z[3]], [0.0; 3]);
     }
 }
@@ -0,0 +1,103 @@
+// Note: frame capture
+//
+// Copy of a rendered color image into a host visible buffer, read (or saved as a PNG) once
+// the GPU is done with the frame. The copy is recorded at the end of the frame command buffer,
+// before the image is presented: a presented image can't be read back.
+// The buffer rows are tightly packed (no row padding): width * 4 bytes per row.
+
+use std::sync::Arc;
+
+use image::RgbaImage;
+use tracing::info;
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, CopyImageToBufferInfo,
+        PrimaryAutoCommandBuffer,
+    },
+    format::Format,
+    image::Image,
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+};
+
+use crate::error::{Result, VisualSystemError};
+
+// Where a frame capture goes, see VulkanRenderer::capture_frame
+pub const CAPTURE_FILE: &str = "capture.png";
+
+pub struct FrameCapture {
+    buffer: Subbuffer<[u8]>,
+    format: Format,
+    extent: [u32; 2],
+}
+
+impl FrameCapture {
+    // Record the copy of the whole image (8 bit RGBA or BGRA formats)
+    pub fn record(
+        builder: &mut AutoCommandBufferBuilder<
+            PrimaryAutoCommandBuffer<Arc<StandardCommandBufferAllocator>>,
+            Arc<StandardCommandBufferAllocator>,
+        >,
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        image: Arc<Image>,
+    ) -> Result<Self> {
+        let format = image.format();
+        if !matches!(
+            format,
+            Format::B8G8R8A8_SRGB
+                | Format::B8G8R8A8_UNORM
+                | Format::R8G8B8A8_SRGB
+                | Format::R8G8B8A8_UNORM
+        ) {
+            return Err(VisualSystemError::UnsupportedCaptureFormat(format!("{format:?}")).into());
+        }
+
+        let [width, height, _] = image.extent();
+
+        let buffer = Buffer::new_slice::<u8>(
+            memory_allocator,
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                ..Default::default()
+            },
+            width as u64 * height as u64 * 4,
+        )?;
+
+        builder.copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(image, buffer.clone()))?;
+
+        Ok(Self {
+            buffer,
+            format,
+            extent: [width, height],
+        })
+    }
+
+    // The pixels, once the command buffer has completed
+    pub fn to_image(&self) -> Result<RgbaImage> {
+        let [width, height] = self.extent;
+        let mut pixels = self.buffer.read()?.to_vec();
+
+        // Swapchains are usually BGRA
+        if matches!(self.format, Format::B8G8R8A8_SRGB | Format::B8G8R8A8_UNORM) {
+            pixels
+                .chunks_exact_mut(4)
+                .for_each(|pixel| pixel.swap(0, 2));
+        }
+
+        Ok(RgbaImage::from_raw(width, height, pixels)
+            .expect("capture buffer smaller than the image"))
+    }
+
+    // Write the PNG, once the command buffer has completed
+    pub fn save(&self, path: &str) -> Result<()> {
+        self.to_image()?.save(path)?;
+
+        info!("frame captured to {path}");
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+// Note: GPU instance transforms
+//
+// Compute pass animating the instances (toggled with `I`): shader::cs reads the initial
+// transforms from the base instance buffer and writes the spinning ones into the instance
+// buffer the graphics pipelines draw. No host round trip, unlike VulkanDevice::update_instances.
+// Recorded before the main pass: the auto command buffer inserts the barrier between the
+// shader write and the vertex input read of the instance buffer.
+
+use std::sync::Arc;
+
+use vulkano::{
+    buffer::Subbuffer,
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder,
+        PrimaryAutoCommandBuffer,
+    },
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, PersistentDescriptorSet, WriteDescriptorSet,
+    },
+    device::Device,
+    pipeline::{
+        compute::ComputePipelineCreateInfo, layout::PipelineDescriptorSetLayoutCreateInfo,
+        ComputePipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+};
+
+use crate::{error::Result, instance_buffer::InstanceRaw, shader::cs};
+
+// local_size_x of shader::cs
+const WORKGROUP_SIZE: u32 = 64;
+
+pub struct InstanceAnimation {
+    pipeline: Arc<ComputePipeline>,
+}
+
+impl InstanceAnimation {
+    pub fn new(device: &Arc<Device>) -> Result<Self> {
+        let compute_shader = cs::load(Arc::clone(device))?.entry_point("main").unwrap();
+        let stage = PipelineShaderStageCreateInfo::new(compute_shader);
+
+        let layout = PipelineLayout::new(
+            Arc::clone(device),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+                .into_pipeline_layout_create_info(Arc::clone(device))?,
+        )?;
+
+        let pipeline = ComputePipeline::new(
+            Arc::clone(device),
+            None,
+            ComputePipelineCreateInfo::stage_layout(stage, layout),
+        )?;
+
+        Ok(Self { pipeline })
+    }
+
+    // The compute shader reads the initial transforms and writes the animated ones.
+    // A new one for each new pair of instance buffers, see VulkanDevice::set_instance_grid.
+    pub fn create_descriptor_set(
+        &self,
+        descriptor_set_allocator: &StandardDescriptorSetAllocator,
+        base_instance_buffer: Subbuffer<[InstanceRaw]>,
+        instance_buffer: Subbuffer<[InstanceRaw]>,
+    ) -> Result<Arc<PersistentDescriptorSet>> {
+        let descriptor_set = PersistentDescriptorSet::new(
+            descriptor_set_allocator,
+            Arc::clone(
+                self.pipeline
+                    .layout()
+                    .set_layouts()
+                    .first()
+                    .expect("error getting the compute layout"),
+            ),
+            [
+                WriteDescriptorSet::buffer(0, base_instance_buffer),
+                WriteDescriptorSet::buffer(1, instance_buffer),
+            ],
+            [],
+        )?;
+
+        Ok(descriptor_set)
+    }
+
+    // Dispatch one invocation per instance, `time` in seconds drives the rotation
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<
+            PrimaryAutoCommandBuffer<Arc<StandardCommandBufferAllocator>>,
+            Arc<StandardCommandBufferAllocator>,
+        >,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+        time: f32,
+        instance_count: u32,
+    ) -> Result<()> {
+        builder
+            .bind_pipeline_compute(Arc::clone(&self.pipeline))?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                Arc::clone(self.pipeline.layout()),
+                0,
+                descriptor_set,
+            )?
+            .push_constants(
+                Arc::clone(self.pipeline.layout()),
+                0,
+                cs::PushConstantData {
+                    time,
+                    count: instance_count,
+                },
+            )?
+            .dispatch([instance_count.div_ceil(WORKGROUP_SIZE), 1, 1])?;
+
+        Ok(())
+    }
+}
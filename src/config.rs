@@ -0,0 +1,455 @@
+use std::env;
+
+use tracing::warn;
+use vulkano::{
+    format::Format,
+    image::{
+        sampler::{Filter, SamplerAddressMode, SamplerMipmapMode, LOD_CLAMP_NONE},
+        SampleCount,
+    },
+    swapchain::{ColorSpace, PresentMode},
+};
+
+// How the final color gets encoded for the display
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorOutput {
+    // sRGB swapchain format. The hardware encodes the linear shader output on write.
+    #[default]
+    Srgb,
+    // UNORM swapchain format (e.g. for capture pipelines). The fragment shader applies
+    // the sRGB curve itself, same as utils::linear_to_srgb.
+    Linear,
+    // HDR: half float swapchain in the extended linear sRGB (scRGB) color space.
+    // The shader output is written as is, 1.0 is SDR white and brighter values are kept.
+    // Needs a display (and a driver) that offers it, see VulkanInstance::supports_surface_format.
+    ExtendedLinear,
+}
+
+impl ColorOutput {
+    // Preferred swapchain formats, in order. Surfaces don't all offer BGRA (e.g. mobile GPUs
+    // are usually RGBA), see VulkanInstance::select_surface_format.
+    pub fn swapchain_formats(self) -> &'static [Format] {
+        match self {
+            ColorOutput::Srgb => &[
+                Format::B8G8R8A8_SRGB,
+                Format::R8G8B8A8_SRGB,
+                Format::A8B8G8R8_SRGB_PACK32,
+            ],
+            ColorOutput::Linear => &[
+                Format::B8G8R8A8_UNORM,
+                Format::R8G8B8A8_UNORM,
+                Format::A8B8G8R8_UNORM_PACK32,
+            ],
+            ColorOutput::ExtendedLinear => &[Format::R16G16B16A16_SFLOAT],
+        }
+    }
+
+    pub fn swapchain_format(self) -> Format {
+        self.swapchain_formats()[0]
+    }
+
+    pub fn color_space(self) -> ColorSpace {
+        match self {
+            ColorOutput::Srgb | ColorOutput::Linear => ColorSpace::SrgbNonLinear,
+            ColorOutput::ExtendedLinear => ColorSpace::ExtendedSrgbLinear,
+        }
+    }
+
+    // Whether the shaders have to encode to sRGB manually
+    pub fn encode_in_shader(self) -> bool {
+        self == ColorOutput::Linear
+    }
+}
+
+// Sampling quality of the material textures
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureQuality {
+    pub anisotropy: f32, // max anisotropic samples, 1.0 disables anisotropic filtering
+    pub lod_bias: f32,   // negative sharpens distant textures, positive blurs them
+    pub max_lod: f32,    // clamp the mip chain, LOD_CLAMP_NONE uses all levels
+}
+
+impl Default for TextureQuality {
+    fn default() -> Self {
+        Self {
+            anisotropy: 1.0,
+            lod_bias: 0.0,
+            max_lod: LOD_CLAMP_NONE,
+        }
+    }
+}
+
+impl TextureQuality {
+    // Clamp to what the device supports.
+    // `max_anisotropy` is None when the sampler_anisotropy feature is not available.
+    pub fn clamp_to(self, max_anisotropy: Option<f32>, max_lod_bias: f32) -> Self {
+        Self {
+            anisotropy: max_anisotropy.map_or(1.0, |max| self.anisotropy.clamp(1.0, max)),
+            lod_bias: self.lod_bias.clamp(-max_lod_bias, max_lod_bias),
+            max_lod: self.max_lod.max(0.0),
+        }
+    }
+
+    pub fn anisotropic(&self) -> bool {
+        self.anisotropy > 1.0
+    }
+}
+
+// Filtering and addressing of the material texture sampler, see textures::create_sampler
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SamplerConfig {
+    pub mag_filter: Filter,
+    pub min_filter: Filter,
+    pub mipmap_mode: SamplerMipmapMode, // Linear for trilinear filtering
+    pub address_mode: SamplerAddressMode, // U, V and W. ClampToEdge for textures that don't tile
+}
+
+impl Default for SamplerConfig {
+    fn default() -> Self {
+        Self {
+            mag_filter: Filter::Linear,
+            min_filter: Filter::Linear,
+            mipmap_mode: SamplerMipmapMode::Nearest,
+            address_mode: SamplerAddressMode::Repeat,
+        }
+    }
+}
+
+// Bloom post-processing knobs, see bloom.rs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BloomSettings {
+    pub enabled: bool,
+    pub threshold: f32, // brightness (max channel) where the glow starts, below 1.0
+    pub intensity: f32, // strength of the glow added to the scene
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold: 0.8,
+            intensity: 0.6,
+        }
+    }
+}
+
+// Keep the camera out of the model, see Camera::clamp_eye
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraClamp {
+    pub enabled: bool,
+    pub margin: f32, // closest distance to the bounding boxes, in world units
+}
+
+impl Default for CameraClamp {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            margin: 0.1,
+        }
+    }
+}
+
+// Model shown at startup when none is given
+pub const DEFAULT_MODEL: &str = "assets/BoxTextured.gltf";
+pub const DEFAULT_ICON: &str = "assets/icon.png";
+
+// Application settings, read once at startup
+#[derive(Debug, Clone)]
+pub struct AppConfig {
+    pub color_output: ColorOutput,
+    pub device_name: Option<String>, // preferred GPU, substring of its name
+    pub validation: bool,            // Khronos validation layer, when installed
+    pub max_fps: Option<u32>,        // frame limiter, independent of the present mode
+    pub present_mode: PresentMode,   // Fifo is vsync, falls back to Fifo when not supported
+    pub texture_quality: TextureQuality,
+    pub sampler: SamplerConfig,
+    pub bloom: BloomSettings,
+    pub camera_clamp: CameraClamp,
+    pub point_size: f32,              // in pixels, for the point list meshes
+    pub samples: SampleCount,         // requested MSAA, clamped to the device by VulkanDevice::new
+    pub model_path: String,           // glTF, GLB or PLY file loaded at startup
+    pub texture_path: Option<String>, // base color texture of the meshes without one
+    pub icon_path: String,            // window and taskbar icon, none when it can't be loaded
+    pub window_count: usize,          // the primary window and the secondary ones, at least 1
+    pub bench_frames: Option<u32>,    // --bench N: render N frames, print the stats and exit
+    pub headless: Option<String>,     // --headless OUT.png: render one frame without a window
+    pub reference: Option<String>,    // --reference REF.png: compare the headless frame to it
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            color_output: ColorOutput::default(),
+            device_name: None,
+            validation: false,
+            max_fps: None,
+            present_mode: PresentMode::Fifo,
+            texture_quality: TextureQuality::default(),
+            sampler: SamplerConfig::default(),
+            bloom: BloomSettings::default(),
+            camera_clamp: CameraClamp::default(),
+            point_size: 4.0,
+            samples: SampleCount::Sample4,
+            model_path: DEFAULT_MODEL.to_string(),
+            texture_path: None,
+            icon_path: DEFAULT_ICON.to_string(),
+            window_count: 2,
+            bench_frames: None,
+            headless: None,
+            reference: None,
+        }
+    }
+}
+
+impl AppConfig {
+    // Default config overridden by VULKANOX_* environment variables
+    //  VULKANOX_COLOR_OUTPUT=linear|srgb|hdr
+    //  VULKANOX_DEVICE=nvidia (substring of the GPU name)
+    //  VULKANOX_VALIDATION=1
+    //  VULKANOX_MAX_FPS=60
+    //  VULKANOX_PRESENT_MODE=fifo|relaxed|mailbox|immediate (immediate is uncapped)
+    //  VULKANOX_ANISOTROPY=16 VULKANOX_LOD_BIAS=-0.5 VULKANOX_MAX_LOD=4
+    //  VULKANOX_FILTER=linear|nearest VULKANOX_MIPMAP=nearest|linear
+    //  VULKANOX_ADDRESS_MODE=repeat|mirror|clamp|border
+    //  VULKANOX_BLOOM_THRESHOLD=0.8 VULKANOX_BLOOM_INTENSITY=0.6
+    //  VULKANOX_POINT_SIZE=4
+    //  VULKANOX_MSAA=1|2|4|8|16|32|64 (1 disables MSAA)
+    //  VULKANOX_MODEL=assets/Box.gltf
+    //  VULKANOX_TEXTURE=assets/texture.png
+    //  VULKANOX_ICON=assets/icon.png
+    //  VULKANOX_WINDOWS=2 (1 for the primary window only)
+    //  VULKANOX_CAMERA_MARGIN=0.1 (enables the camera clamp)
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+
+        if let Ok(color_output) = env::var("VULKANOX_COLOR_OUTPUT") {
+            config.color_output = match color_output.to_lowercase().as_str() {
+                "linear" | "unorm" => ColorOutput::Linear,
+                "hdr" | "scrgb" => ColorOutput::ExtendedLinear,
+                _ => ColorOutput::Srgb,
+            };
+        }
+
+        if let Ok(device_name) = env::var("VULKANOX_DEVICE") {
+            config.device_name = Some(device_name).filter(|name| !name.is_empty());
+        }
+
+        if let Ok(validation) = env::var("VULKANOX_VALIDATION") {
+            config.validation = matches!(validation.to_lowercase().as_str(), "1" | "true" | "on");
+        }
+
+        if let Ok(max_fps) = env::var("VULKANOX_MAX_FPS") {
+            config.max_fps = max_fps.parse().ok().filter(|max_fps| *max_fps > 0);
+        }
+
+        if let Ok(present_mode) = env::var("VULKANOX_PRESENT_MODE") {
+            match present_mode.to_lowercase().as_str() {
+                "fifo" | "vsync" => config.present_mode = PresentMode::Fifo,
+                "relaxed" | "fifo_relaxed" => config.present_mode = PresentMode::FifoRelaxed,
+                "mailbox" => config.present_mode = PresentMode::Mailbox,
+                "immediate" | "novsync" => config.present_mode = PresentMode::Immediate,
+                _ => warn!("VULKANOX_PRESENT_MODE: unknown present mode {present_mode}"),
+            }
+        }
+
+        if let Some(anisotropy) = parse_env("VULKANOX_ANISOTROPY") {
+            config.texture_quality.anisotropy = anisotropy;
+        }
+
+        if let Some(lod_bias) = parse_env("VULKANOX_LOD_BIAS") {
+            config.texture_quality.lod_bias = lod_bias;
+        }
+
+        if let Some(max_lod) = parse_env("VULKANOX_MAX_LOD") {
+            config.texture_quality.max_lod = max_lod;
+        }
+
+        if let Ok(filter) = env::var("VULKANOX_FILTER") {
+            match filter.to_lowercase().as_str() {
+                "linear" => {
+                    config.sampler.mag_filter = Filter::Linear;
+                    config.sampler.min_filter = Filter::Linear;
+                }
+                "nearest" => {
+                    config.sampler.mag_filter = Filter::Nearest;
+                    config.sampler.min_filter = Filter::Nearest;
+                }
+                _ => warn!("VULKANOX_FILTER: unknown filter {filter}"),
+            }
+        }
+
+        if let Ok(mipmap_mode) = env::var("VULKANOX_MIPMAP") {
+            match mipmap_mode.to_lowercase().as_str() {
+                "nearest" => config.sampler.mipmap_mode = SamplerMipmapMode::Nearest,
+                "linear" | "trilinear" => config.sampler.mipmap_mode = SamplerMipmapMode::Linear,
+                _ => warn!("VULKANOX_MIPMAP: unknown mipmap mode {mipmap_mode}"),
+            }
+        }
+
+        if let Ok(address_mode) = env::var("VULKANOX_ADDRESS_MODE") {
+            match address_mode.to_lowercase().as_str() {
+                "repeat" => config.sampler.address_mode = SamplerAddressMode::Repeat,
+                "mirror" | "mirrored_repeat" => {
+                    config.sampler.address_mode = SamplerAddressMode::MirroredRepeat
+                }
+                "clamp" | "clamp_to_edge" => {
+                    config.sampler.address_mode = SamplerAddressMode::ClampToEdge
+                }
+                "border" | "clamp_to_border" => {
+                    config.sampler.address_mode = SamplerAddressMode::ClampToBorder
+                }
+                _ => warn!("VULKANOX_ADDRESS_MODE: unknown address mode {address_mode}"),
+            }
+        }
+
+        if let Some(threshold) = parse_env("VULKANOX_BLOOM_THRESHOLD") {
+            config.bloom.threshold = threshold.max(0.0);
+        }
+
+        if let Some(intensity) = parse_env("VULKANOX_BLOOM_INTENSITY") {
+            config.bloom.intensity = intensity.max(0.0);
+        }
+
+        if let Some(point_size) = parse_env("VULKANOX_POINT_SIZE") {
+            config.point_size = point_size;
+        }
+
+        if let Ok(samples) = env::var("VULKANOX_MSAA") {
+            match samples
+                .parse()
+                .ok()
+                .and_then(|samples: u32| SampleCount::try_from(samples).ok())
+            {
+                Some(samples) => config.samples = samples,
+                None => warn!("VULKANOX_MSAA: {samples} is not a sample count"),
+            }
+        }
+
+        if let Ok(model_path) = env::var("VULKANOX_MODEL") {
+            config.model_path = model_path;
+        }
+
+        if let Ok(texture_path) = env::var("VULKANOX_TEXTURE") {
+            config.texture_path = Some(texture_path);
+        }
+
+        if let Ok(icon_path) = env::var("VULKANOX_ICON") {
+            config.icon_path = icon_path;
+        }
+
+        if let Ok(window_count) = env::var("VULKANOX_WINDOWS") {
+            config.window_count = window_count
+                .parse()
+                .map_or(config.window_count, |count| usize::max(count, 1));
+        }
+
+        if let Some(margin) = parse_env("VULKANOX_CAMERA_MARGIN") {
+            config.camera_clamp = CameraClamp {
+                enabled: true,
+                margin: margin.max(0.0),
+            };
+        }
+
+        config
+    }
+
+    // Command line flags, on top of the environment
+    //  --bench N
+    //  --headless OUT.png [--reference REF.png]
+    //  MODEL_PATH
+    pub fn with_args(mut self, args: impl IntoIterator<Item = String>) -> Self {
+        let mut args = args.into_iter().skip(1); // program name
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--bench" => {
+                    self.bench_frames = args
+                        .next()
+                        .and_then(|frames| frames.parse().ok())
+                        .filter(|frames| *frames > 0);
+
+                    if self.bench_frames.is_none() {
+                        warn!("--bench expects a number of frames");
+                    }
+                }
+                "--headless" => {
+                    self.headless = args.next();
+
+                    if self.headless.is_none() {
+                        warn!("--headless expects an output file");
+                    }
+                }
+                "--reference" => {
+                    self.reference = args.next();
+
+                    if self.reference.is_none() {
+                        warn!("--reference expects a PNG file");
+                    }
+                }
+                _ if !arg.starts_with('-') => self.model_path = arg,
+                _ => warn!("unknown argument {arg}"),
+            }
+        }
+
+        self
+    }
+}
+
+fn parse_env(name: &str) -> Option<f32> {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|value: &f32| value.is_finite())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_ANISOTROPY: Option<f32> = Some(16.0);
+    const MAX_LOD_BIAS: f32 = 15.0;
+
+    fn quality(anisotropy: f32, lod_bias: f32, max_lod: f32) -> TextureQuality {
+        TextureQuality {
+            anisotropy,
+            lod_bias,
+            max_lod,
+        }
+    }
+
+    #[test]
+    fn texture_quality_below_limits() {
+        let clamped = quality(0.25, -20.0, -1.0).clamp_to(MAX_ANISOTROPY, MAX_LOD_BIAS);
+
+        assert_eq!(clamped, quality(1.0, -MAX_LOD_BIAS, 0.0));
+        assert!(!clamped.anisotropic());
+    }
+
+    #[test]
+    fn texture_quality_within_limits() {
+        let requested = quality(8.0, -0.5, 4.0);
+        let clamped = requested.clamp_to(MAX_ANISOTROPY, MAX_LOD_BIAS);
+
+        assert_eq!(clamped, requested);
+        assert!(clamped.anisotropic());
+        assert_eq!(
+            TextureQuality::default().clamp_to(MAX_ANISOTROPY, MAX_LOD_BIAS),
+            TextureQuality::default()
+        );
+    }
+
+    #[test]
+    fn texture_quality_above_limits() {
+        let clamped = quality(64.0, 20.0, LOD_CLAMP_NONE).clamp_to(MAX_ANISOTROPY, MAX_LOD_BIAS);
+
+        assert_eq!(clamped, quality(16.0, MAX_LOD_BIAS, LOD_CLAMP_NONE));
+    }
+
+    #[test]
+    fn texture_quality_without_anisotropy_feature() {
+        let clamped = quality(8.0, 0.0, 4.0).clamp_to(None, MAX_LOD_BIAS);
+
+        assert_eq!(clamped.anisotropy, 1.0);
+        assert!(!clamped.anisotropic());
+    }
+}
@@ -1,15 +1,68 @@
-#[allow(unused)]
-use vulkano::instance::debug::DebugUtilsLabel;
+// Note: debug utils
+//
+// With VULKANOX_VALIDATION=1 the instance enables VK_EXT_debug_utils: the validation messages
+// go to tracing, and the command buffers get labels (shown by RenderDoc and in the validation
+// messages).
 
-#[allow(unused)]
+use std::sync::Arc;
+
+use tracing::{debug, error, info, warn};
+use vulkano::instance::{
+    debug::{
+        DebugUtilsMessageSeverity, DebugUtilsMessageType, DebugUtilsMessenger,
+        DebugUtilsMessengerCallback, DebugUtilsMessengerCreateInfo,
+    },
+    Instance,
+};
+
+use crate::error::Result;
+
+// Label of a command buffer region, see begin_debug_utils_label
 macro_rules! debug_label {
     ($label_name:expr) => {
-        DebugUtilsLabel {
+        vulkano::instance::debug::DebugUtilsLabel {
             label_name: $label_name.to_string(),
             color: [0., 1., 0., 1.], // Green
             ..Default::default()
         }
     };
-}   
-    #[allow(unused_imports)]
-    pub(crate) use debug_label ;
+}
+pub(crate) use debug_label;
+
+// Forward the messages of the layers and the driver to tracing, by severity.
+// Lives as long as the returned messenger.
+pub fn create_debug_messenger(instance: Arc<Instance>) -> Result<Arc<DebugUtilsMessenger>> {
+    // Safety: the callback doesn't call into Vulkan
+    let user_callback = unsafe {
+        DebugUtilsMessengerCallback::new(|severity, message_type, callback_data| {
+            let id = callback_data.message_id_name.unwrap_or("");
+            let message = callback_data.message;
+
+            if severity.intersects(DebugUtilsMessageSeverity::ERROR) {
+                error!(target: "vulkan", "[{message_type:?}] {id}: {message}");
+            } else if severity.intersects(DebugUtilsMessageSeverity::WARNING) {
+                warn!(target: "vulkan", "[{message_type:?}] {id}: {message}");
+            } else if severity.intersects(DebugUtilsMessageSeverity::INFO) {
+                info!(target: "vulkan", "[{message_type:?}] {id}: {message}");
+            } else {
+                debug!(target: "vulkan", "[{message_type:?}] {id}: {message}");
+            }
+        })
+    };
+
+    let messenger = DebugUtilsMessenger::new(
+        instance,
+        DebugUtilsMessengerCreateInfo {
+            message_severity: DebugUtilsMessageSeverity::ERROR
+                | DebugUtilsMessageSeverity::WARNING
+                | DebugUtilsMessageSeverity::INFO
+                | DebugUtilsMessageSeverity::VERBOSE,
+            message_type: DebugUtilsMessageType::GENERAL
+                | DebugUtilsMessageType::VALIDATION
+                | DebugUtilsMessageType::PERFORMANCE,
+            ..DebugUtilsMessengerCreateInfo::user_callback(user_callback)
+        },
+    )?;
+
+    Ok(Arc::new(messenger))
+}
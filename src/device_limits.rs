@@ -0,0 +1,150 @@
+// Note: Device limits
+//
+// Snapshot of the physical device limits the renderer depends on, taken once in VulkanDevice::new.
+// Only the limits a model file or the config can go over: the texture sizes are checked (a
+// DeviceLimitExceeded error naming the limit, instead of a cryptic driver failure), the samples,
+// the anisotropy and the point size are clamped. The push constants and the descriptor sets of the
+// pipelines fit the minimums of the Vulkan spec, asserted at compile time in vulkan_device.rs.
+
+use tracing::{info, warn};
+use vulkano::{
+    device::Device,
+    image::{SampleCount, SampleCounts},
+};
+
+use crate::error::{Result, VisualSystemError};
+
+#[derive(Debug, Clone, Copy)]
+pub struct DeviceLimits {
+    pub max_sampler_anisotropy: Option<f32>, // None when sampler_anisotropy is not enabled
+    pub max_sampler_lod_bias: f32,
+    pub max_image_dimension_2d: u32,
+    pub point_size_range: [f32; 2], // [1, 1] when large_points is not enabled
+    pub sample_counts: SampleCounts, // usable for both the color and the depth attachments
+}
+
+impl DeviceLimits {
+    pub fn new(device: &Device) -> Self {
+        let properties = device.physical_device().properties();
+
+        Self {
+            max_sampler_anisotropy: device
+                .enabled_features()
+                .sampler_anisotropy
+                .then_some(properties.max_sampler_anisotropy),
+            max_sampler_lod_bias: properties.max_sampler_lod_bias,
+            max_image_dimension_2d: properties.max_image_dimension2_d,
+            point_size_range: if device.enabled_features().large_points {
+                properties.point_size_range
+            } else {
+                [1.0, 1.0]
+            },
+            sample_counts: properties.framebuffer_color_sample_counts
+                & properties.framebuffer_depth_sample_counts,
+        }
+    }
+
+    pub fn log(&self) {
+        info!("device limits: {self:?}");
+    }
+
+    pub fn check_image_extent(&self, what: &str, extent: [u32; 2]) -> Result<()> {
+        check(
+            what,
+            "max_image_dimension_2d",
+            extent[0].max(extent[1]) as u64,
+            self.max_image_dimension_2d,
+        )
+    }
+
+    // Largest supported sample count not above `requested`, 1 when MSAA is not supported at all
+    pub fn clamp_samples(&self, requested: SampleCount) -> SampleCount {
+        let clamped = [
+            SampleCount::Sample64,
+            SampleCount::Sample32,
+            SampleCount::Sample16,
+            SampleCount::Sample8,
+            SampleCount::Sample4,
+            SampleCount::Sample2,
+        ]
+        .into_iter()
+        .filter(|samples| *samples as u32 <= requested as u32)
+        .find(|samples| self.sample_counts.contains_enum(*samples))
+        .unwrap_or(SampleCount::Sample1);
+
+        if clamped != requested {
+            warn!(
+                "MSAA: {} samples not supported, using {}",
+                requested as u32, clamped as u32
+            );
+        }
+
+        clamped
+    }
+
+    pub fn clamp_point_size(&self, requested: f32) -> f32 {
+        let [min, max] = self.point_size_range;
+        let clamped = requested.clamp(min, max);
+
+        if clamped != requested {
+            warn!("point size {requested} not supported, using {clamped}");
+        }
+
+        clamped
+    }
+}
+
+fn check(what: &str, limit: &'static str, requested: u64, max: u32) -> Result<()> {
+    if requested > max as u64 {
+        return Err(VisualSystemError::DeviceLimitExceeded {
+            what: what.to_string(),
+            limit,
+            requested,
+            max: max as u64,
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    fn limits() -> DeviceLimits {
+        DeviceLimits {
+            max_sampler_anisotropy: None,
+            max_sampler_lod_bias: 2.0,
+            max_image_dimension_2d: 4096,
+            point_size_range: [1.0, 64.0],
+            sample_counts: SampleCounts::SAMPLE_1 | SampleCounts::SAMPLE_4,
+        }
+    }
+
+    #[test]
+    fn texture_larger_than_the_device_is_rejected() {
+        assert!(limits().check_image_extent("texture", [4096, 16]).is_ok());
+        assert!(matches!(
+            limits().check_image_extent("texture", [16, 8192]),
+            Err(Error::VisualSystem(
+                VisualSystemError::DeviceLimitExceeded {
+                    requested: 8192,
+                    max: 4096,
+                    ..
+                }
+            ))
+        ));
+    }
+
+    #[test]
+    fn samples_and_point_size_are_clamped() {
+        assert_eq!(
+            limits().clamp_samples(SampleCount::Sample8),
+            SampleCount::Sample4
+        );
+        assert_eq!(limits().clamp_point_size(100.0), 64.0);
+        assert_eq!(limits().clamp_point_size(4.0), 4.0);
+    }
+}
@@ -1,31 +1,125 @@
 use thiserror::Error;
-use winit::error::OsError;
+use vulkano::{
+    buffer::AllocateBufferError, command_buffer::CommandBufferExecError, image::AllocateImageError,
+    memory::allocator::MemoryAllocatorError, pipeline::layout::IntoPipelineLayoutCreateInfoError,
+    sync::HostAccessError, LoadingError, Validated, ValidationError, VulkanError,
+};
+use winit::{
+    error::{EventLoopError, OsError},
+    window::BadIcon,
+};
 
 pub type Result<T> = core::result::Result<T, Error>;
-pub type Error = Box<dyn std::error::Error>; // for early dev.
 
+// Every failure of the app. The external errors keep their source.
 #[derive(Error, Debug)]
-pub enum VisualSystemError {
-    #[error("error creating new VisualSystem: {0}", self)]
-    ErrorCreatingVisualSystem,
-    #[error("error resizing VisualSystem: {0}", self)]
-    ErrorResizingVisualSystem,
-    #[error("error drawing VisualSystem: {0}", self)]
-    ErrorDrawingVisualSystem,
-    #[error("error resuming VisualSystem: {0}", self)]
-    ErrorResumingVisualSystem,
-    #[error("error request redraw VisualSystem: {0}", self)]
-    ErrorRequestReDrawVisualSystem,
-    #[error("error creating new Vulkan instance: {0}", self)]
-    ErrorCreatingVulkanInstance,
-    #[error("error creating new Vulkan device: {0}", self)]
-    ErrorCreatingVulkanDevice,
-    #[error("error creating new Vulkan renderer: {0}", self)]
-    ErrorCreatingVulkanRenderer,
-    #[error("error input visual system: {0}", self)]
-    ErrorInputVisualSystem,
+pub enum Error {
+    #[error(transparent)]
+    VisualSystem(#[from] VisualSystemError),
+
+    // -- Vulkan
+    #[error(transparent)]
+    Vulkan(#[from] Validated<VulkanError>),
+    #[error(transparent)]
+    VulkanRuntime(#[from] VulkanError),
+    #[error(transparent)]
+    VulkanLoading(#[from] LoadingError),
+    #[error(transparent)]
+    Validation(#[from] Box<ValidationError>),
+    #[error(transparent)]
+    AllocateBuffer(#[from] Validated<AllocateBufferError>),
+    #[error(transparent)]
+    AllocateImage(#[from] Validated<AllocateImageError>),
+    #[error(transparent)]
+    MemoryAllocator(#[from] MemoryAllocatorError),
+    #[error(transparent)]
+    HostAccess(#[from] HostAccessError),
+    #[error(transparent)]
+    CommandBufferExec(#[from] CommandBufferExecError),
+    #[error(transparent)]
+    PipelineLayout(#[from] IntoPipelineLayoutCreateInfoError),
 
-    // -- Externals
-    #[error("os error")]
+    // -- Assets
+    #[error(transparent)]
+    Gltf(#[from] gltf::Error),
+    #[error(transparent)]
+    Obj(#[from] tobj::LoadError),
+    #[error(transparent)]
+    Ktx2(#[from] ktx2::ParseError),
+    #[error(transparent)]
+    Image(#[from] image::ImageError), // PNG and the other image formats
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    // -- Window
+    #[error(transparent)]
     Os(#[from] OsError),
+    #[error(transparent)]
+    EventLoop(#[from] EventLoopError),
+    #[error(transparent)]
+    Icon(#[from] BadIcon),
+}
+
+#[derive(Error, Debug)]
+pub enum VisualSystemError {
+    #[error("error creating new VisualSystem: {0}")]
+    ErrorCreatingVisualSystem(#[source] Box<Error>),
+    #[error("error resizing VisualSystem: {0}")]
+    ErrorResizingVisualSystem(#[source] Box<Error>),
+    #[error("error drawing VisualSystem: {0}")]
+    ErrorDrawingVisualSystem(#[source] Box<Error>),
+    #[error("error resuming VisualSystem: {0}")]
+    ErrorResumingVisualSystem(#[source] Box<Error>),
+    #[error("error request redraw VisualSystem: {0}")]
+    ErrorRequestReDrawVisualSystem(#[source] Box<Error>),
+    #[error("error creating new Vulkan instance: {0}")]
+    ErrorCreatingVulkanInstance(#[source] Box<Error>),
+    #[error("error creating new Vulkan device: {0}")]
+    ErrorCreatingVulkanDevice(#[source] Box<Error>),
+    #[error("error creating new Vulkan renderer: {0}")]
+    ErrorCreatingVulkanRenderer(#[source] Box<Error>),
+    #[error("error input visual system: {0}")]
+    ErrorInputVisualSystem(#[source] Box<Error>),
+    #[error("no Vulkan 1.3 (or dynamic rendering) device with a graphics queue found")]
+    NoSuitableDevice,
+    #[error("model file not found: {0}")]
+    ModelNotFound(String),
+    #[error("unsupported model format: {0}")]
+    UnsupportedModelFormat(String),
+    #[error("image size mismatch: expected {expected:?}, got {actual:?}")]
+    ImageSizeMismatch {
+        expected: (u32, u32),
+        actual: (u32, u32),
+    },
+    #[error("{what} needs {requested}, above the device limit {limit} = {max}")]
+    DeviceLimitExceeded {
+        what: String,
+        limit: &'static str,
+        requested: u64,
+        max: u64,
+    },
+    #[error("unsupported texture: {0}")]
+    UnsupportedTexture(String),
+    #[error("frame capture not supported for the {0} format")]
+    UnsupportedCaptureFormat(String),
+    #[error("rendered image differs from the reference: {0:.2}% of the pixels")]
+    ImageRegression(f32),
+    #[error("render_offscreen needs a headless renderer")]
+    NotHeadless,
+    #[error("headless frame of {actual} bytes, {expected} expected")]
+    HeadlessFrameSize { expected: usize, actual: usize },
+    #[error("{actual} instances given, the instance buffer holds {expected}")]
+    InstanceCountMismatch { expected: u64, actual: u64 },
+    #[error("{attribute}: {actual} values for {expected} positions")]
+    MeshAttributeLengthMismatch {
+        attribute: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("index {index} outside of the {vertex_count} vertices")]
+    MeshIndexOutOfRange { index: u32, vertex_count: usize },
+    #[error("invalid shader {0}: {1}")]
+    InvalidShader(String, String),
 }
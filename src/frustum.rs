@@ -0,0 +1,45 @@
+// Note: frustum culling
+//
+// The six planes of the view volume, read from the rows of the view-projection matrix
+// (Gribb-Hartmann). The projection of Camera ends in Vulkan clip space: -w <= x, y <= w and
+// 0 <= z <= w. The planes point inward, a point is inside when it is in front of all six.
+// A bounding sphere is culled when it is entirely behind one of them: conservative, a sphere
+// crossing a corner outside the frustum is kept.
+
+use nalgebra::{Matrix4, Point3, Vector4};
+
+pub struct Frustum {
+    planes: [Vector4<f32>; 6], // (normal, distance), normalized
+}
+
+impl Frustum {
+    pub fn new(view_projection: &Matrix4<f32>) -> Self {
+        let row = |index: usize| view_projection.row(index).transpose();
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+
+        let planes = [
+            w + x, // left
+            w - x, // right
+            w + y, // bottom (top with the glTF Y flip, same pair)
+            w - y,
+            z,     // near
+            w - z, // far
+        ]
+        .map(|plane| {
+            let length = plane.xyz().magnitude();
+            if length > f32::EPSILON {
+                plane / length
+            } else {
+                plane
+            }
+        });
+
+        Self { planes }
+    }
+
+    pub fn intersects_sphere(&self, center: &Point3<f32>, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.xyz().dot(&center.coords) + plane.w >= -radius)
+    }
+}
@@ -0,0 +1,88 @@
+// Note: gamepad camera control (`gamepad` feature)
+//
+// `cargo run --features gamepad`: the left stick orbits the camera (looks around in fly mode),
+// the right stick (vertical) and the triggers zoom. Polled once per frame from App::process_event
+// (AboutToWait) and applied through the same VisualSystem path as the mouse. Any connected
+// gamepad drives the camera, the first one with a stick out of the dead zone wins.
+
+use gilrs::{Axis, Button, Gilrs};
+use tracing::{info, warn};
+
+// Stick travel ignored around the center, worn sticks don't rest at exactly 0
+const DEAD_ZONE: f32 = 0.15;
+
+// Full stick deflection: radians per frame, and wheel lines per frame
+const ORBIT_SPEED: f32 = 0.03;
+const ZOOM_SPEED: f32 = 0.2;
+
+// Per frame camera deltas, same units as the mouse ones of VisualSystem
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GamepadInput {
+    pub delta_yaw: f32,
+    pub delta_pitch: f32,
+    pub zoom_lines: f32, // positive zooms in
+}
+
+pub struct Gamepads {
+    gilrs: Gilrs,
+}
+
+impl Gamepads {
+    // None without gamepad support on the platform: the app goes on with mouse and keyboard
+    pub fn new() -> Option<Self> {
+        let gilrs = Gilrs::new()
+            .inspect_err(|e| warn!("no gamepad support: {e}"))
+            .ok()?;
+
+        for (_, gamepad) in gilrs.gamepads() {
+            info!("gamepad: {}", gamepad.name());
+        }
+
+        Some(Self { gilrs })
+    }
+
+    pub fn poll(&mut self) -> GamepadInput {
+        // The events update the cached state read below (and report the connections)
+        while let Some(event) = self.gilrs.next_event() {
+            match event.event {
+                gilrs::EventType::Connected => {
+                    info!("gamepad connected: {}", self.gilrs.gamepad(event.id).name())
+                }
+                gilrs::EventType::Disconnected => info!("gamepad disconnected"),
+                _ => {}
+            }
+        }
+
+        for (_, gamepad) in self.gilrs.gamepads() {
+            let axis = |axis: Axis| dead_zone(gamepad.value(axis));
+            let trigger = |button: Button| {
+                gamepad
+                    .button_data(button)
+                    .map_or(0.0, |data| dead_zone(data.value()))
+            };
+
+            let input = GamepadInput {
+                // Same directions as dragging with the mouse: right turns the model right,
+                // down tilts it toward the viewer (gilrs sticks are +Y up)
+                delta_yaw: -axis(Axis::LeftStickX) * ORBIT_SPEED,
+                delta_pitch: -axis(Axis::LeftStickY) * ORBIT_SPEED,
+                zoom_lines: (axis(Axis::RightStickY) + trigger(Button::RightTrigger2)
+                    - trigger(Button::LeftTrigger2))
+                    * ZOOM_SPEED,
+            };
+            if input != GamepadInput::default() {
+                return input;
+            }
+        }
+
+        GamepadInput::default()
+    }
+}
+
+fn dead_zone(value: f32) -> f32 {
+    if value.abs() < DEAD_ZONE {
+        0.0
+    } else {
+        value
+    }
+}
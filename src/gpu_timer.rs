@@ -0,0 +1,120 @@
+// Note: GPU timer
+//
+// GPU time of the frames with timestamp queries: one pair per swapchain image, written at the
+// start and at the end of the frame command buffer.
+// The results of an image are read (without waiting) just before the image is recorded again,
+// so they are a few frames old. None while the GPU is still busy with that frame.
+
+use std::sync::Arc;
+
+use vulkano::{
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder,
+        PrimaryAutoCommandBuffer,
+    },
+    device::Queue,
+    query::{QueryPool, QueryPoolCreateInfo, QueryResultFlags, QueryType},
+    sync::PipelineStage,
+};
+
+use crate::error::Result;
+
+pub struct GpuTimer {
+    query_pool: Arc<QueryPool>,
+    period: f32,        // ns per tick
+    written: Vec<bool>, // per image: the queries have been written at least once
+}
+
+impl GpuTimer {
+    // None when the queue doesn't support timestamps
+    pub fn new(queue: &Queue, image_count: usize) -> Result<Option<Self>> {
+        let device = queue.device();
+        let physical_device = device.physical_device();
+
+        if physical_device.queue_family_properties()[queue.queue_family_index() as usize]
+            .timestamp_valid_bits
+            .is_none()
+        {
+            return Ok(None);
+        }
+
+        let query_pool = QueryPool::new(
+            Arc::clone(device),
+            QueryPoolCreateInfo {
+                query_count: 2 * image_count as u32,
+                ..QueryPoolCreateInfo::query_type(QueryType::Timestamp)
+            },
+        )?;
+
+        Ok(Some(Self {
+            query_pool,
+            period: physical_device.properties().timestamp_period,
+            written: vec![false; image_count],
+        }))
+    }
+
+    // In ms, of the last frame recorded for `image_index`
+    pub fn read(&self, image_index: u32) -> Result<Option<f32>> {
+        if !self.written[image_index as usize] {
+            return Ok(None);
+        }
+
+        let first = 2 * image_index;
+        let mut timestamps = [0u64; 2];
+        let available = self.query_pool.get_results(
+            first..first + 2,
+            &mut timestamps,
+            QueryResultFlags::empty(),
+        )?;
+
+        Ok(available
+            .then(|| timestamps[1].wrapping_sub(timestamps[0]) as f32 * self.period / 1_000_000.0))
+    }
+
+    // First command of the frame
+    pub fn begin(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<
+            PrimaryAutoCommandBuffer<Arc<StandardCommandBufferAllocator>>,
+            Arc<StandardCommandBufferAllocator>,
+        >,
+        image_index: u32,
+    ) -> Result<()> {
+        let first = 2 * image_index;
+
+        // Safety: the previous results of these queries have been read (or dropped) by `read`
+        unsafe {
+            builder
+                .reset_query_pool(Arc::clone(&self.query_pool), first..first + 2)?
+                .write_timestamp(
+                    Arc::clone(&self.query_pool),
+                    first,
+                    PipelineStage::TopOfPipe,
+                )?;
+        }
+        self.written[image_index as usize] = true;
+
+        Ok(())
+    }
+
+    // Last command of the frame
+    pub fn end(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<
+            PrimaryAutoCommandBuffer<Arc<StandardCommandBufferAllocator>>,
+            Arc<StandardCommandBufferAllocator>,
+        >,
+        image_index: u32,
+    ) -> Result<()> {
+        // Safety: the query has been reset by `begin` in the same command buffer
+        unsafe {
+            builder.write_timestamp(
+                Arc::clone(&self.query_pool),
+                2 * image_index + 1,
+                PipelineStage::BottomOfPipe,
+            )?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+// Note: reference grid
+//
+// Ground grid on the XZ plane (toggled with `G`), drawn in the main pass after the meshes.
+// A fullscreen triangle: shader::grid_vs unprojects each pixel into a ray, shader::grid_fs
+// intersects it with y = 0 and writes the depth of the intersection. The grid is depth tested
+// against the meshes but doesn't write depth, and it is alpha blended: the lines fade out with
+// the distance instead of turning into moiré.
+
+use std::sync::Arc;
+
+use nalgebra::Matrix4;
+use vulkano::{
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder,
+        PrimaryAutoCommandBuffer,
+    },
+    device::Device,
+    pipeline::{
+        graphics::{
+            color_blend::{AttachmentBlend, ColorBlendAttachmentState, ColorBlendState},
+            depth_stencil::{DepthState, DepthStencilState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            subpass::PipelineRenderingCreateInfo,
+            vertex_input::VertexInputState,
+            viewport::ViewportState,
+            GraphicsPipelineCreateInfo,
+        },
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+        DynamicState, GraphicsPipeline, Pipeline, PipelineLayout, PipelineShaderStageCreateInfo,
+    },
+};
+
+use crate::{
+    error::Result,
+    shader::{grid_fs, grid_vs},
+    vulkan_context::VulkanContext,
+};
+
+pub struct Grid {
+    pipeline: Arc<GraphicsPipeline>,
+}
+
+impl Grid {
+    // Same attachments and sample count as the main pass: rebuilt with its pipelines
+    pub fn new(device: &Arc<Device>, vulkan_context: &VulkanContext) -> Result<Self> {
+        let vertex_shader = grid_vs::load(Arc::clone(device))?
+            .entry_point("main")
+            .unwrap();
+        // Specialization constant 0: ENCODE_SRGB
+        let fragment_shader = grid_fs::load(Arc::clone(device))?
+            .specialize(
+                [(0, vulkan_context.color_output.encode_in_shader().into())]
+                    .into_iter()
+                    .collect(),
+            )?
+            .entry_point("main")
+            .unwrap();
+
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vertex_shader),
+            PipelineShaderStageCreateInfo::new(fragment_shader),
+        ];
+
+        let layout = PipelineLayout::new(
+            Arc::clone(device),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                .into_pipeline_layout_create_info(Arc::clone(device))?,
+        )?;
+
+        let subpass = PipelineRenderingCreateInfo {
+            color_attachment_formats: vec![Some(vulkan_context.color_format())],
+            depth_attachment_format: Some(vulkan_context.depth_format()),
+            ..Default::default()
+        };
+
+        let pipeline = GraphicsPipeline::new(
+            Arc::clone(device),
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                // The fullscreen triangle comes from gl_VertexIndex
+                vertex_input_state: Some(VertexInputState::default()),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState::default()),
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState {
+                        write_enable: false,
+                        ..DepthState::simple()
+                    }),
+                    ..Default::default()
+                }),
+                multisample_state: Some(MultisampleState {
+                    rasterization_samples: vulkan_context.samples,
+                    ..Default::default()
+                }),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    subpass.color_attachment_formats.len() as u32,
+                    ColorBlendAttachmentState {
+                        blend: Some(AttachmentBlend::alpha()),
+                        ..Default::default()
+                    },
+                )),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )?;
+
+        Ok(Self { pipeline })
+    }
+
+    // Inside the main pass, with its viewport set. `fade_distance` is in world units.
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<
+            PrimaryAutoCommandBuffer<Arc<StandardCommandBufferAllocator>>,
+            Arc<StandardCommandBufferAllocator>,
+        >,
+        view_projection: &Matrix4<f32>,
+        fade_distance: f32,
+    ) -> Result<()> {
+        builder
+            .bind_pipeline_graphics(Arc::clone(&self.pipeline))?
+            .push_constants(
+                Arc::clone(self.pipeline.layout()),
+                0,
+                grid_vs::PushConstantData {
+                    view_projection: (*view_projection).into(),
+                    fade_distance,
+                },
+            )?
+            .draw(3, 1, 0, 0)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,589 @@
+// Note: Gui
+//
+// In-app control panel with egui, toggled with F10.
+// egui-winit turns the winit events into egui input, the vulkano backend is this file:
+// run builds the UI and tessellates it, record uploads the changed egui textures and draws the
+// meshes over the final image (after the text) in its own single sample pass, one scissor
+// rectangle per clip rect.
+//
+// GuiState is a copy of the settings the panel edits. VisualSystem fills it from the renderer
+// state each frame, and applies what changed through the usual setters. GuiInfo is shown read
+// only, at the top of the panel.
+
+use std::{collections::HashMap, sync::Arc};
+
+use egui::{
+    epaint::{ImageDelta, Primitive},
+    ClippedPrimitive, ImageData, TextureFilter, TextureId, TextureOptions, TextureWrapMode,
+    TexturesDelta, ViewportId,
+};
+use tracing::warn;
+use vulkano::{
+    buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage},
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, BufferImageCopy,
+        CopyBufferToImageInfo, PrimaryAutoCommandBuffer, RenderingAttachmentInfo, RenderingInfo,
+    },
+    descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet},
+    format::Format,
+    image::{
+        sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+        view::ImageView,
+        Image, ImageCreateInfo, ImageUsage, SampleCount,
+    },
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter},
+    pipeline::{
+        graphics::{
+            color_blend::{
+                AttachmentBlend, BlendFactor, BlendOp, ColorBlendAttachmentState, ColorBlendState,
+            },
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            subpass::PipelineRenderingCreateInfo,
+            vertex_input::{Vertex, VertexDefinition},
+            viewport::{Scissor, Viewport, ViewportState},
+            GraphicsPipelineCreateInfo,
+        },
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+        DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+    render_pass::{AttachmentLoadOp, AttachmentStoreOp},
+};
+use winit::{event::WindowEvent, window::Window};
+
+use crate::{
+    error::Result,
+    shader::{gui_fs, gui_vs},
+    staging::staging_buffer,
+    vulkan_device::VulkanDevice,
+    vulkan_renderer::FrameStats,
+};
+
+// Read only values of the window shown by the control panel
+#[derive(Debug, Clone, Copy)]
+pub struct GuiInfo {
+    pub frame_stats: FrameStats,
+    pub camera_position: [f32; 3],
+}
+
+// Settings edited by the control panel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GuiState {
+    pub light_color: [f32; 3],    // directional light, linear
+    pub light_position: [f32; 3], // same light, world space: the light comes from there
+    pub ambient_intensity: f32,
+    pub shininess: f32, // Blinn-Phong of every mesh, 0 for metallic-roughness
+    pub specular_strength: f32,
+    pub camera_speed: f32,
+    pub samples: SampleCount,
+    pub clear_color: [f32; 3], // sRGB
+}
+
+impl GuiState {
+    // The panel itself. `supported_samples` are the MSAA choices.
+    pub fn show(
+        &mut self,
+        context: &egui::Context,
+        supported_samples: &[SampleCount],
+        info: &GuiInfo,
+    ) {
+        egui::Window::new("Vulkanox")
+            .default_pos([8.0, 120.0])
+            .resizable(false)
+            .show(context, |ui| {
+                egui::Grid::new("info").num_columns(2).show(ui, |ui| {
+                    ui.label("FPS");
+                    ui.label(format!(
+                        "{:.0} ({:.2} ms)",
+                        info.frame_stats.fps, info.frame_stats.frame_time
+                    ));
+                    ui.end_row();
+
+                    let [x, y, z] = info.camera_position;
+                    ui.label("Camera");
+                    ui.label(format!("{x:.2}, {y:.2}, {z:.2}"));
+                    ui.end_row();
+                });
+                ui.separator();
+
+                egui::Grid::new("settings").num_columns(2).show(ui, |ui| {
+                    ui.label("Light color");
+                    ui.color_edit_button_rgb(&mut self.light_color);
+                    ui.end_row();
+
+                    ui.label("Light position");
+                    ui.horizontal(|ui| {
+                        for coordinate in &mut self.light_position {
+                            ui.add(egui::DragValue::new(coordinate).speed(0.05));
+                        }
+                    });
+                    ui.end_row();
+
+                    ui.label("Ambient");
+                    ui.add(egui::Slider::new(&mut self.ambient_intensity, 0.0..=2.0));
+                    ui.end_row();
+
+                    ui.label("Shininess");
+                    ui.add(egui::Slider::new(&mut self.shininess, 0.0..=256.0).logarithmic(true));
+                    ui.end_row();
+
+                    ui.label("Specular");
+                    ui.add(egui::Slider::new(&mut self.specular_strength, 0.0..=2.0));
+                    ui.end_row();
+
+                    ui.label("Camera speed");
+                    ui.add(egui::Slider::new(&mut self.camera_speed, 0.01..=1.0).logarithmic(true));
+                    ui.end_row();
+
+                    ui.label("MSAA");
+                    egui::ComboBox::from_id_source("msaa")
+                        .selected_text(format!("x{}", self.samples as u32))
+                        .show_ui(ui, |ui| {
+                            for samples in supported_samples {
+                                ui.selectable_value(
+                                    &mut self.samples,
+                                    *samples,
+                                    format!("x{}", *samples as u32),
+                                );
+                            }
+                        });
+                    ui.end_row();
+
+                    // The color picker works on bytes for sRGB
+                    ui.label("Clear color");
+                    let mut clear_color = self.clear_color.map(|c| (c * 255.0).round() as u8);
+                    if ui.color_edit_button_srgb(&mut clear_color).changed() {
+                        self.clear_color = clear_color.map(|c| c as f32 / 255.0);
+                    }
+                    ui.end_row();
+                });
+            });
+    }
+}
+
+// egui::epaint::Vertex, with the vulkano vertex input layout
+#[derive(Copy, Clone, BufferContents, Vertex)]
+#[repr(C)]
+pub struct GuiVertex {
+    #[format(R32G32_SFLOAT)]
+    pub position: [f32; 2], // in points
+    #[format(R32G32_SFLOAT)]
+    pub uv: [f32; 2],
+    #[format(R8G8B8A8_UNORM)]
+    pub color: [u8; 4], // sRGB, premultiplied alpha
+}
+
+pub struct Gui {
+    context: egui::Context,
+    winit_state: egui_winit::State,
+    pipeline: Arc<GraphicsPipeline>,
+    textures: HashMap<TextureId, (Arc<ImageView>, Arc<PersistentDescriptorSet>)>,
+    textures_delta: TexturesDelta, // applied by the next record
+    primitives: Vec<ClippedPrimitive>,
+    pixels_per_point: f32,
+}
+
+impl Gui {
+    pub fn new(vulkan_device: &VulkanDevice, window: &Window) -> Result<Self> {
+        let context = egui::Context::default();
+        let winit_state = egui_winit::State::new(
+            context.clone(),
+            ViewportId::ROOT,
+            window,
+            Some(window.scale_factor() as f32),
+            Some(vulkan_device.limits().max_image_dimension_2d as usize),
+        );
+
+        Ok(Self {
+            context,
+            winit_state,
+            pipeline: create_gui_pipeline(vulkan_device)?,
+            textures: HashMap::new(),
+            textures_delta: TexturesDelta::default(),
+            primitives: Vec::new(),
+            pixels_per_point: window.scale_factor() as f32,
+        })
+    }
+
+    // true when egui used the event (e.g. a click on the panel) and the camera should not see it
+    pub fn on_event(&mut self, window: &Window, event: &WindowEvent) -> bool {
+        self.winit_state.on_window_event(window, event).consumed
+    }
+
+    // Build the UI for the next frame
+    pub fn run(&mut self, window: &Window, ui: impl FnMut(&egui::Context)) {
+        let raw_input = self.winit_state.take_egui_input(window);
+        let output = self.context.run(raw_input, ui);
+
+        self.winit_state
+            .handle_platform_output(window, output.platform_output);
+
+        // Several runs between two records keep every texture change
+        self.textures_delta.append(output.textures_delta);
+        self.primitives = self
+            .context
+            .tessellate(output.shapes, output.pixels_per_point);
+        self.pixels_per_point = output.pixels_per_point;
+    }
+
+    // Upload the texture changes, then draw the UI over `target`
+    pub fn record(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<
+            PrimaryAutoCommandBuffer<Arc<StandardCommandBufferAllocator>>,
+            Arc<StandardCommandBufferAllocator>,
+        >,
+        vulkan_device: &VulkanDevice,
+        target: Arc<ImageView>,
+    ) -> Result<()> {
+        // Copies are not allowed inside the rendering pass
+        for (texture_id, delta) in std::mem::take(&mut self.textures_delta.set) {
+            self.update_texture(builder, vulkan_device, texture_id, &delta)?;
+        }
+
+        let meshes: Vec<_> = self
+            .primitives
+            .iter()
+            .filter_map(|primitive| match &primitive.primitive {
+                Primitive::Mesh(mesh) if !mesh.indices.is_empty() => {
+                    Some((primitive.clip_rect, mesh))
+                }
+                Primitive::Mesh(_) => None,
+                Primitive::Callback(_) => {
+                    warn!("gui: paint callbacks are not supported");
+                    None
+                }
+            })
+            .collect();
+
+        if !meshes.is_empty() {
+            // All the meshes in one vertex and one index buffer
+            let vertices: Vec<_> = meshes
+                .iter()
+                .flat_map(|(_, mesh)| {
+                    mesh.vertices.iter().map(|vertex| GuiVertex {
+                        position: [vertex.pos.x, vertex.pos.y],
+                        uv: [vertex.uv.x, vertex.uv.y],
+                        color: vertex.color.to_array(),
+                    })
+                })
+                .collect();
+            let indices: Vec<_> = meshes
+                .iter()
+                .flat_map(|(_, mesh)| mesh.indices.iter().copied())
+                .collect();
+
+            let vertex_buffer = Buffer::from_iter(
+                vulkan_device.memory_allocator.clone(),
+                BufferCreateInfo {
+                    usage: BufferUsage::VERTEX_BUFFER,
+                    ..Default::default()
+                },
+                AllocationCreateInfo {
+                    memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                        | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                    ..Default::default()
+                },
+                vertices,
+            )?;
+            let index_buffer = Buffer::from_iter(
+                vulkan_device.memory_allocator.clone(),
+                BufferCreateInfo {
+                    usage: BufferUsage::INDEX_BUFFER,
+                    ..Default::default()
+                },
+                AllocationCreateInfo {
+                    memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                        | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                    ..Default::default()
+                },
+                indices,
+            )?;
+
+            let extent = target.image().extent();
+            let [width, height] = [extent[0] as f32, extent[1] as f32];
+
+            builder
+                .begin_rendering(RenderingInfo {
+                    color_attachments: vec![Some(RenderingAttachmentInfo {
+                        load_op: AttachmentLoadOp::Load,
+                        store_op: AttachmentStoreOp::Store,
+                        ..RenderingAttachmentInfo::image_view(target)
+                    })],
+                    ..Default::default()
+                })?
+                .set_viewport(
+                    0,
+                    [Viewport {
+                        offset: [0.0, 0.0],
+                        extent: [width, height],
+                        depth_range: 0.0..=1.0,
+                    }]
+                    .into_iter()
+                    .collect(),
+                )?
+                .bind_pipeline_graphics(Arc::clone(&self.pipeline))?
+                .push_constants(
+                    Arc::clone(self.pipeline.layout()),
+                    0,
+                    gui_vs::PushConstantData {
+                        screen_size: [
+                            width / self.pixels_per_point,
+                            height / self.pixels_per_point,
+                        ],
+                    },
+                )?
+                .bind_vertex_buffers(0, vertex_buffer)?
+                .bind_index_buffer(index_buffer)?;
+
+            let mut first_index = 0;
+            let mut vertex_offset = 0;
+
+            for (clip_rect, mesh) in meshes {
+                let index_count = mesh.indices.len() as u32;
+                let vertex_count = mesh.vertices.len() as i32;
+
+                // Clip rect in points to pixels, inside the target
+                let min_x = (clip_rect.min.x * self.pixels_per_point)
+                    .round()
+                    .clamp(0.0, width);
+                let min_y = (clip_rect.min.y * self.pixels_per_point)
+                    .round()
+                    .clamp(0.0, height);
+                let max_x = (clip_rect.max.x * self.pixels_per_point)
+                    .round()
+                    .clamp(min_x, width);
+                let max_y = (clip_rect.max.y * self.pixels_per_point)
+                    .round()
+                    .clamp(min_y, height);
+
+                match self.textures.get(&mesh.texture_id) {
+                    Some((_, descriptor_set)) if max_x > min_x && max_y > min_y => {
+                        builder
+                            .set_scissor(
+                                0,
+                                [Scissor {
+                                    offset: [min_x as u32, min_y as u32],
+                                    extent: [(max_x - min_x) as u32, (max_y - min_y) as u32],
+                                }]
+                                .into_iter()
+                                .collect(),
+                            )?
+                            .bind_descriptor_sets(
+                                PipelineBindPoint::Graphics,
+                                Arc::clone(self.pipeline.layout()),
+                                0,
+                                Arc::clone(descriptor_set),
+                            )?
+                            .draw_indexed(index_count, 1, first_index, vertex_offset, 0)?;
+                    }
+                    Some(_) => {} // clipped out
+                    None => warn!("gui: unknown texture {:?}", mesh.texture_id),
+                }
+
+                first_index += index_count;
+                vertex_offset += vertex_count;
+            }
+
+            builder.end_rendering()?;
+        }
+
+        // The command buffer keeps the images alive until the frame is done
+        for texture_id in std::mem::take(&mut self.textures_delta.free) {
+            self.textures.remove(&texture_id);
+        }
+
+        Ok(())
+    }
+
+    // Whole new texture (delta.pos is None), or a patch of an existing one
+    fn update_texture(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<
+            PrimaryAutoCommandBuffer<Arc<StandardCommandBufferAllocator>>,
+            Arc<StandardCommandBufferAllocator>,
+        >,
+        vulkan_device: &VulkanDevice,
+        texture_id: TextureId,
+        delta: &ImageDelta,
+    ) -> Result<()> {
+        let [width, height] = delta.image.size().map(|size| size as u32);
+
+        // Premultiplied sRGBA bytes
+        let pixels: Vec<u8> = match &delta.image {
+            ImageData::Color(image) => image
+                .pixels
+                .iter()
+                .flat_map(|color| color.to_array())
+                .collect(),
+            ImageData::Font(image) => image
+                .srgba_pixels(None)
+                .flat_map(|color| color.to_array())
+                .collect(),
+        };
+
+        let upload_buffer = staging_buffer(&vulkan_device.memory_allocator, pixels)?;
+
+        let (image_view, offset) = match delta.pos {
+            None => {
+                vulkan_device
+                    .limits()
+                    .check_image_extent(&format!("gui texture {texture_id:?}"), [width, height])?;
+
+                let image = Image::new(
+                    vulkan_device.memory_allocator.clone(),
+                    ImageCreateInfo {
+                        format: Format::R8G8B8A8_SRGB,
+                        extent: [width, height, 1],
+                        usage: ImageUsage::TRANSFER_DST | ImageUsage::SAMPLED,
+                        ..Default::default()
+                    },
+                    AllocationCreateInfo::default(),
+                )?;
+                let image_view = ImageView::new_default(image)?;
+
+                let descriptor_set = PersistentDescriptorSet::new(
+                    vulkan_device.descriptor_set_allocator(),
+                    Arc::clone(
+                        self.pipeline
+                            .layout()
+                            .set_layouts()
+                            .first()
+                            .expect("error getting the gui layout"),
+                    ),
+                    [WriteDescriptorSet::image_view_sampler(
+                        0,
+                        Arc::clone(&image_view),
+                        create_gui_sampler(vulkan_device, delta.options)?,
+                    )],
+                    [],
+                )?;
+
+                self.textures
+                    .insert(texture_id, (Arc::clone(&image_view), descriptor_set));
+
+                (image_view, [0, 0])
+            }
+            Some([x, y]) => match self.textures.get(&texture_id) {
+                Some((image_view, _)) => (Arc::clone(image_view), [x as u32, y as u32]),
+                None => {
+                    warn!("gui: patch of unknown texture {texture_id:?}");
+                    return Ok(());
+                }
+            },
+        };
+
+        let image = Arc::clone(image_view.image());
+        builder.copy_buffer_to_image(CopyBufferToImageInfo {
+            regions: [BufferImageCopy {
+                image_subresource: image.subresource_layers(),
+                image_offset: [offset[0], offset[1], 0],
+                image_extent: [width, height, 1],
+                ..Default::default()
+            }]
+            .into(),
+            ..CopyBufferToImageInfo::buffer_image(upload_buffer, image)
+        })?;
+
+        Ok(())
+    }
+}
+
+fn create_gui_sampler(
+    vulkan_device: &VulkanDevice,
+    options: TextureOptions,
+) -> Result<Arc<Sampler>> {
+    let filter = |filter| match filter {
+        TextureFilter::Nearest => Filter::Nearest,
+        TextureFilter::Linear => Filter::Linear,
+    };
+    let address_mode = match options.wrap_mode {
+        TextureWrapMode::ClampToEdge => SamplerAddressMode::ClampToEdge,
+        TextureWrapMode::Repeat => SamplerAddressMode::Repeat,
+        TextureWrapMode::MirroredRepeat => SamplerAddressMode::MirroredRepeat,
+    };
+
+    Ok(Sampler::new(
+        Arc::clone(vulkan_device.queue().device()),
+        SamplerCreateInfo {
+            mag_filter: filter(options.magnification),
+            min_filter: filter(options.minification),
+            address_mode: [address_mode; 3],
+            ..Default::default()
+        },
+    )?)
+}
+
+// Premultiplied alpha blended triangles into the (single sample) swapchain image
+fn create_gui_pipeline(vulkan_device: &VulkanDevice) -> Result<Arc<GraphicsPipeline>> {
+    let device = vulkan_device.queue().device();
+    let vulkan_context = vulkan_device.vulkan_context.borrow();
+
+    let vertex_shader = gui_vs::load(Arc::clone(device))?
+        .entry_point("main")
+        .unwrap();
+    // Specialization constant 0: ENCODE_SRGB
+    let fragment_shader = gui_fs::load(Arc::clone(device))?
+        .specialize(
+            [(0, vulkan_context.color_output.encode_in_shader().into())]
+                .into_iter()
+                .collect(),
+        )?
+        .entry_point("main")
+        .unwrap();
+
+    let vertex_input_state =
+        GuiVertex::per_vertex().definition(&vertex_shader.info().input_interface)?;
+
+    let stages = [
+        PipelineShaderStageCreateInfo::new(vertex_shader),
+        PipelineShaderStageCreateInfo::new(fragment_shader),
+    ];
+
+    let layout = PipelineLayout::new(
+        Arc::clone(device),
+        PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+            .into_pipeline_layout_create_info(Arc::clone(device))?,
+    )?;
+
+    let subpass = PipelineRenderingCreateInfo {
+        color_attachment_formats: vec![Some(vulkan_context.color_format())],
+        ..Default::default()
+    };
+
+    let pipeline = GraphicsPipeline::new(
+        Arc::clone(device),
+        None,
+        GraphicsPipelineCreateInfo {
+            stages: stages.into_iter().collect(),
+            vertex_input_state: Some(vertex_input_state),
+            input_assembly_state: Some(InputAssemblyState::default()),
+            viewport_state: Some(ViewportState::default()),
+            rasterization_state: Some(RasterizationState::default()),
+            multisample_state: Some(MultisampleState::default()),
+            color_blend_state: Some(ColorBlendState::with_attachment_states(
+                subpass.color_attachment_formats.len() as u32,
+                ColorBlendAttachmentState {
+                    blend: Some(AttachmentBlend {
+                        src_color_blend_factor: BlendFactor::One,
+                        dst_color_blend_factor: BlendFactor::OneMinusSrcAlpha,
+                        color_blend_op: BlendOp::Add,
+                        src_alpha_blend_factor: BlendFactor::OneMinusDstAlpha,
+                        dst_alpha_blend_factor: BlendFactor::One,
+                        alpha_blend_op: BlendOp::Add,
+                    }),
+                    ..Default::default()
+                },
+            )),
+            dynamic_state: [DynamicState::Viewport, DynamicState::Scissor]
+                .into_iter()
+                .collect(),
+            subpass: Some(subpass.into()),
+            ..GraphicsPipelineCreateInfo::layout(layout)
+        },
+    )?;
+
+    Ok(pipeline)
+}
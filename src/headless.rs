@@ -0,0 +1,223 @@
+// Note: Headless rendering
+//
+// `--headless OUT.png` renders a single frame of the model without opening a window (no surface,
+// no swapchain) and saves it. For automated checks and thumbnails.
+// With `--reference REF.png` the frame is also compared to a reference image (see image_diff.rs):
+// the process fails on a regression.
+
+use std::{
+    cell::RefCell,
+    path::Path,
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
+
+use image::RgbaImage;
+use tracing::{info, warn};
+
+use crate::{
+    camera::{Camera, CameraController},
+    config::{AppConfig, ColorOutput},
+    error::{Result, VisualSystemError},
+    image_diff::{ImageDiff, DEFAULT_THRESHOLD, DEFAULT_TOLERANCE},
+    vulkan_context::VulkanContext,
+    vulkan_device::VulkanDevice,
+    vulkan_instance::VulkanInstance,
+    vulkan_renderer::VulkanRenderer,
+};
+
+// Size of the rendered frame, and of the --reference images
+pub const HEADLESS_EXTENT: [u32; 2] = [800, 600];
+
+pub fn render_headless(config: &AppConfig, output_path: &str) -> Result<()> {
+    let vulkan_instance = Arc::new(VulkanInstance::new_headless(
+        config.device_name.as_deref(),
+        config.validation,
+    )?);
+
+    let vulkan_device = create_device(vulkan_instance, config)?;
+    let image = render_frame(vulkan_device, HEADLESS_EXTENT, Camera::default())?;
+
+    image.save(output_path)?;
+    info!("headless frame saved to {output_path}");
+
+    if let Some(reference_path) = &config.reference {
+        let diff = ImageDiff::compare(&image, Path::new(reference_path), DEFAULT_TOLERANCE)?;
+
+        if diff.is_regression(DEFAULT_THRESHOLD) {
+            return Err(
+                VisualSystemError::ImageRegression(diff.differing_fraction() * 100.0).into(),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// Device with the model of `config` loaded, also used by the benchmark
+pub fn create_device(
+    vulkan_instance: Arc<VulkanInstance>,
+    config: &AppConfig,
+) -> Result<Rc<VulkanDevice>> {
+    // The read back is 8 bits per channel
+    let color_output = if config.color_output == ColorOutput::ExtendedLinear {
+        warn!("no HDR output when headless, using {:?}", ColorOutput::Srgb);
+        ColorOutput::Srgb
+    } else {
+        config.color_output
+    };
+
+    let vulkan_context = Rc::new(RefCell::new(VulkanContext::new(
+        Arc::new(Mutex::new(CameraController::new(0.2))),
+        config.samples,
+        color_output,
+        config.texture_quality,
+        config.bloom,
+        config.camera_clamp,
+        config.point_size,
+    )?));
+    vulkan_context
+        .borrow_mut()
+        .set_depth_format(vulkan_instance.select_depth_format()?);
+    vulkan_context.borrow_mut().default_texture = config.texture_path.clone();
+    vulkan_context.borrow_mut().sampler_config = config.sampler;
+
+    Ok(Rc::new(VulkanDevice::new(
+        vulkan_instance,
+        vulkan_context,
+        &config.model_path,
+    )?))
+}
+
+// One frame of the loaded model, seen by `camera`
+fn render_frame(
+    vulkan_device: Rc<VulkanDevice>,
+    extent: [u32; 2],
+    camera: Camera,
+) -> Result<RgbaImage> {
+    let mut vulkan_renderer = VulkanRenderer::new_headless(vulkan_device, extent, camera)?;
+
+    let [width, height] = extent;
+    let pixels = vulkan_renderer.render_offscreen()?;
+    let size = pixels.len();
+
+    RgbaImage::from_raw(width, height, pixels).ok_or_else(|| {
+        VisualSystemError::HeadlessFrameSize {
+            expected: width as usize * height as usize * 4,
+            actual: size,
+        }
+        .into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Point3;
+    use vulkano::image::SampleCount;
+
+    use super::*;
+    use crate::lighting::{AmbientLight, DirectionalLight, LightPreset};
+
+    // The Box scene of the regression test, and its reference frame
+    const BOX_MODEL: &str = "assets/Box.gltf";
+    const BOX_REFERENCE: &str = "assets/reference/box.png";
+    const BOX_EXTENT: [u32; 2] = [160, 120];
+
+    // The tests below need a Vulkan driver, e.g. lavapipe on CI: they are ignored by default and
+    // run with `cargo test -- --ignored`. No device then is a failure, not a skip.
+    fn vulkan_instance() -> Arc<VulkanInstance> {
+        Arc::new(VulkanInstance::new_headless(None, false).expect("no Vulkan device"))
+    }
+
+    #[test]
+    #[ignore = "needs a Vulkan device"]
+    fn headless_frame_is_not_empty() {
+        let config = AppConfig::default();
+        let vulkan_instance = vulkan_instance();
+
+        let vulkan_device = create_device(vulkan_instance, &config).unwrap();
+        let image = render_frame(vulkan_device, HEADLESS_EXTENT, Camera::default()).unwrap();
+
+        assert_eq!(image.dimensions(), (HEADLESS_EXTENT[0], HEADLESS_EXTENT[1]));
+        // The Box covers part of the frame: not everything is the clear color
+        let background = image.get_pixel(0, 0);
+        assert!(image.pixels().any(|pixel| pixel != background));
+    }
+
+    // The Box with a fixed camera and fixed lights, against the committed reference frame.
+    // VULKANOX_BLESS=1 writes the reference instead, after an intended change of the rendering.
+    #[test]
+    #[ignore = "needs a Vulkan device"]
+    fn box_frame_matches_reference() {
+        let config = AppConfig {
+            model_path: BOX_MODEL.to_string(),
+            samples: SampleCount::Sample1,
+            ..AppConfig::default()
+        };
+        let vulkan_instance = vulkan_instance();
+
+        let vulkan_device = create_device(vulkan_instance, &config).unwrap();
+        vulkan_device
+            .set_ambient_light(AmbientLight {
+                color: [1.0, 1.0, 1.0],
+                intensity: 0.3,
+            })
+            .unwrap();
+        vulkan_device
+            .set_directional_lights(&[DirectionalLight::new([1.5, 2.0, 3.0], [1.0, 1.0, 1.0])])
+            .unwrap();
+        let camera = Camera {
+            eye: Point3::new(1.5, 1.2, 2.5),
+            target: Point3::origin(),
+            ..Camera::default()
+        };
+
+        let image = render_frame(vulkan_device, BOX_EXTENT, camera).unwrap();
+
+        let reference_path = Path::new(env!("CARGO_MANIFEST_DIR")).join(BOX_REFERENCE);
+        if std::env::var_os("VULKANOX_BLESS").is_some() {
+            image.save(&reference_path).unwrap();
+            return;
+        }
+        assert!(
+            reference_path.exists(),
+            "no {BOX_REFERENCE}, write it with VULKANOX_BLESS=1 cargo test -- --ignored"
+        );
+        let diff = ImageDiff::compare(&image, &reference_path, DEFAULT_TOLERANCE).unwrap();
+
+        assert!(!diff.is_regression(DEFAULT_THRESHOLD));
+    }
+
+    // What each preset leaves in the light buffers read by the fragment shader. From one light
+    // to two and back: the directional buffer is reallocated, then written in place.
+    #[test]
+    #[ignore = "needs a Vulkan device"]
+    fn light_preset_fills_the_light_buffers() {
+        let config = AppConfig {
+            model_path: BOX_MODEL.to_string(),
+            ..AppConfig::default()
+        };
+        let vulkan_instance = vulkan_instance();
+        let vulkan_device = create_device(vulkan_instance, &config).unwrap();
+
+        for preset in [
+            LightPreset::Studio,
+            LightPreset::Outdoor,
+            LightPreset::Night,
+        ] {
+            vulkan_device.set_light_preset(preset).unwrap();
+            let (ambient_light, directional_lights) = preset.lights();
+
+            let ambient = vulkan_device.ambient_light().unwrap();
+            assert_eq!(ambient.color, ambient_light.color, "{preset:?}");
+            assert_eq!(ambient.intensity, ambient_light.intensity, "{preset:?}");
+
+            let directional = vulkan_device.directional_lights().unwrap();
+            assert_eq!(directional.len(), directional_lights.len(), "{preset:?}");
+            for (written, light) in directional.iter().zip(&directional_lights) {
+                assert_eq!(*written.position, *light.position, "{preset:?}");
+                assert_eq!(*written.color, *light.color, "{preset:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,146 @@
+// Note: image comparison for rendering regression checks
+//
+// Compares a rendered RGBA8 frame against a reference PNG. A pixel differs when one
+// of its channels is off by more than `tolerance`; the frame is a regression when
+// the fraction of differing pixels is above `threshold`.
+
+use std::path::Path;
+
+use image::RgbaImage;
+use tracing::{info, warn};
+
+use crate::error::{Result, VisualSystemError};
+
+// Defaults for the box scene: allow some driver/MSAA noise on the edges
+pub const DEFAULT_TOLERANCE: u8 = 8;
+pub const DEFAULT_THRESHOLD: f32 = 0.01;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ImageDiff {
+    pub differing_pixels: u32,
+    pub total_pixels: u32,
+    pub max_channel_difference: u8,
+}
+
+impl ImageDiff {
+    pub fn compare(actual: &RgbaImage, reference_path: &Path, tolerance: u8) -> Result<Self> {
+        let reference = image::open(reference_path)?.to_rgba8();
+
+        if reference.dimensions() != actual.dimensions() {
+            return Err(VisualSystemError::ImageSizeMismatch {
+                expected: reference.dimensions(),
+                actual: actual.dimensions(),
+            }
+            .into());
+        }
+
+        let mut differing_pixels = 0;
+        let mut max_channel_difference = 0;
+
+        for (actual, expected) in actual.pixels().zip(reference.pixels()) {
+            let difference = actual
+                .0
+                .iter()
+                .zip(expected.0.iter())
+                .map(|(a, e)| a.abs_diff(*e))
+                .max()
+                .unwrap_or(0);
+
+            max_channel_difference = max_channel_difference.max(difference);
+            if difference > tolerance {
+                differing_pixels += 1;
+            }
+        }
+
+        Ok(Self {
+            differing_pixels,
+            total_pixels: actual.width() * actual.height(),
+            max_channel_difference,
+        })
+    }
+
+    pub fn differing_fraction(&self) -> f32 {
+        if self.total_pixels == 0 {
+            return 0.0;
+        }
+        self.differing_pixels as f32 / self.total_pixels as f32
+    }
+
+    pub fn is_regression(&self, threshold: f32) -> bool {
+        let regression = self.differing_fraction() > threshold;
+
+        if regression {
+            warn!(
+                "image diff: {}/{} pixels differ ({:.2}%), max channel difference {}",
+                self.differing_pixels,
+                self.total_pixels,
+                self.differing_fraction() * 100.0,
+                self.max_channel_difference
+            );
+        } else {
+            info!(
+                "image diff: {:.2}% pixels differ, within threshold",
+                self.differing_fraction() * 100.0
+            );
+        }
+
+        regression
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    // 16x16 RGBA gradient
+    fn reference_path() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/reference/gradient.png")
+    }
+
+    fn reference() -> RgbaImage {
+        image::open(reference_path()).unwrap().to_rgba8()
+    }
+
+    #[test]
+    fn identical_image_is_not_a_regression() {
+        let diff = ImageDiff::compare(&reference(), &reference_path(), 0).unwrap();
+
+        assert_eq!(diff.differing_pixels, 0);
+        assert_eq!(diff.total_pixels, 16 * 16);
+        assert_eq!(diff.max_channel_difference, 0);
+        assert!(!diff.is_regression(0.0));
+    }
+
+    #[test]
+    fn perturbed_image_is_a_regression() {
+        let mut actual = reference();
+        // Noise within the tolerance on every pixel
+        for pixel in actual.pixels_mut() {
+            pixel.0[2] = pixel.0[2].saturating_add(DEFAULT_TOLERANCE);
+        }
+        let diff = ImageDiff::compare(&actual, &reference_path(), DEFAULT_TOLERANCE).unwrap();
+        assert_eq!(diff.differing_pixels, 0);
+        assert!(!diff.is_regression(DEFAULT_THRESHOLD));
+
+        // A 4x4 block well off: 16 of 256 pixels
+        for y in 0..4 {
+            for x in 0..4 {
+                actual.get_pixel_mut(x, y).0[0] ^= 0xFF;
+            }
+        }
+        let diff = ImageDiff::compare(&actual, &reference_path(), DEFAULT_TOLERANCE).unwrap();
+        assert_eq!(diff.differing_pixels, 16);
+        assert!(diff.max_channel_difference > DEFAULT_TOLERANCE);
+        assert!(diff.is_regression(DEFAULT_THRESHOLD));
+        assert!(!diff.is_regression(0.1));
+    }
+
+    #[test]
+    fn size_mismatch_is_an_error() {
+        let actual = RgbaImage::new(8, 8);
+
+        assert!(ImageDiff::compare(&actual, &reference_path(), DEFAULT_TOLERANCE).is_err());
+    }
+}
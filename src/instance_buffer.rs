@@ -1,30 +1,44 @@
 use std::f32::consts::FRAC_PI_2;
 
-use nalgebra::{Matrix4, Unit, UnitQuaternion, Vector3};
+use nalgebra::{Matrix4, Point3, Unit, UnitQuaternion, Vector3};
+use palette::{FromColor, Hsv, Srgb};
 use vulkano::{buffer::BufferContents, pipeline::graphics::vertex_input::Vertex};
 
-const NUM_INSTANCES_PER_ROW: u32 = 4;
-const INSTANCE_DISPLACEMENT: Vector3<f32> = Vector3::new(
-    NUM_INSTANCES_PER_ROW as f32 * 0.5,
-    NUM_INSTANCES_PER_ROW as f32 * 0.5,
-    0.0,
-);
-const SPACE_BETWEEN: f32 = 2.0;
+use crate::{camera::normal_matrix, frustum::Frustum};
+
+// Default grid
+pub const NUM_INSTANCES_PER_ROW: u32 = 4;
+pub const SPACE_BETWEEN: f32 = 2.0;
+
+// Saturation of the instance tints: light enough to keep the textures readable
+const TINT_SATURATION: f32 = 0.35;
+
 pub struct Instance {
     position: Vector3<f32>,
     rotation: UnitQuaternion<f32>,
+    color: [f32; 4], // linear tint, multiplied into the vertex color
 }
 
 impl Instance {
-    pub fn new() -> Vec<Instance> {
-        (0..(NUM_INSTANCES_PER_ROW.max(1)))
+    // Grid of rows x rows instances, `spacing` units apart.
+    // Each instance gets its own hue, going around the color wheel along the grid.
+    pub fn new(rows: u32, spacing: f32) -> Vec<Instance> {
+        let rows = rows.max(1);
+        let instance_displacement: Vector3<f32> =
+            Vector3::new(rows as f32 * 0.5, rows as f32 * 0.5, 0.0);
+
+        (0..rows)
             .flat_map(|y| {
-                (0..NUM_INSTANCES_PER_ROW).map(move |x| {
-                    let x = SPACE_BETWEEN * (x as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
-                    let y = SPACE_BETWEEN * (y as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
+                (0..rows).map(move |x| {
+                    let hue = 360.0 * (y * rows + x) as f32 / (rows * rows) as f32;
+                    let tint = Srgb::from_color(Hsv::new(hue, TINT_SATURATION, 1.0)).into_linear();
+                    let color = [tint.red, tint.green, tint.blue, 1.0];
+
+                    let x = spacing * (x as f32 - rows as f32 / 2.0);
+                    let y = spacing * (y as f32 - rows as f32 / 2.0);
 
                     let position: Vector3<f32> =
-                        Vector3::new(x , y , 0.0) - INSTANCE_DISPLACEMENT;
+                        Vector3::new(x , y , 0.0) - instance_displacement;
 
                     let rotation = if position == Vector3::zeros() {
                         // this is needed so an object at (0, 0, 0) won't get scaled to zero
@@ -34,7 +48,11 @@ impl Instance {
                         UnitQuaternion::from_axis_angle(&Unit::new_normalize(position), FRAC_PI_2)
                     };
 
-                    Instance { position, rotation }
+                    Instance {
+                        position,
+                        rotation,
+                        color,
+                    }
                 })
             })
             .collect::<Vec<_>>()
@@ -44,14 +62,23 @@ impl Instance {
 }
 
 impl Instance {
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::new_translation(&self.position) * self.rotation.to_homogeneous()
+    }
+
     pub fn to_raw(&self) -> InstanceRaw {
-        let full_matrix: [[f32; 4]; 4] =
-            (Matrix4::new_translation(&self.position) * self.rotation.to_homogeneous()).into();
+        let matrix = self.matrix();
+        let full_matrix: [[f32; 4]; 4] = matrix.into();
+        let normal_matrix = normal_matrix(&matrix);
         InstanceRaw {
             matrix1: full_matrix[0],
             matrix2: full_matrix[1],
             matrix3: full_matrix[2],
             matrix4: full_matrix[3],
+            instance_color: self.color,
+            normal1: normal_matrix[0],
+            normal2: normal_matrix[1],
+            normal3: normal_matrix[2],
         }
     }
 }
@@ -68,4 +95,41 @@ pub struct InstanceRaw {
     pub matrix3: [f32; 4],
     #[format(R32G32B32A32_SFLOAT)]
     pub matrix4: [f32; 4],
+    #[format(R32G32B32A32_SFLOAT)]
+    pub instance_color: [f32; 4], // `color` is the vertex color
+    // Inverse transpose of the matrix, 3 columns (w unused). Computed on the host, see to_raw
+    #[format(R32G32B32A32_SFLOAT)]
+    pub normal1: [f32; 4],
+    #[format(R32G32B32A32_SFLOAT)]
+    pub normal2: [f32; 4],
+    #[format(R32G32B32A32_SFLOAT)]
+    pub normal3: [f32; 4],
+}
+
+impl InstanceRaw {
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from([self.matrix1, self.matrix2, self.matrix3, self.matrix4])
+    }
+}
+
+// The instances of a mesh in the view of `frustum`, tested with the model space
+// `bounding_sphere` (center, radius) of the mesh. As in shader::vs, the instance transform applies
+// after the `model` matrix of the MVP.
+pub fn visible_instances<'a>(
+    instances: &'a [InstanceRaw],
+    bounding_sphere: ([f32; 3], f32),
+    model: &'a Matrix4<f32>,
+    frustum: &'a Frustum,
+) -> impl Iterator<Item = &'a InstanceRaw> + 'a {
+    let (center, radius) = (Point3::from(bounding_sphere.0), bounding_sphere.1);
+
+    instances.iter().filter(move |instance| {
+        let transform = instance.matrix() * model;
+        // Largest scale of the transform, the sphere stays around the mesh
+        let scale = (0..3)
+            .map(|axis| transform.fixed_view::<3, 1>(0, axis).magnitude())
+            .fold(0.0, f32::max);
+
+        frustum.intersects_sphere(&transform.transform_point(&center), radius * scale)
+    })
 }
@@ -45,22 +45,42 @@ pub const WHITE_AMBIENT_LIGHT: AmbientLight = AmbientLight {
     intensity: 1.0,
 };
 
+// Element of the DirectionalLights array: std430 rounds the struct to 32 bytes
 #[repr(C)]
 #[derive(Default, Debug, Copy, Clone,BufferContents)]
 pub struct DirectionalLight {
     pub position: Padded<[f32; 3], 4>,
-    pub color: [f32; 3],
+    pub color: Padded<[f32; 3], 4>,
 }
 
 impl DirectionalLight {
+    pub fn new(position: [f32; 3], color: [f32; 3]) -> Self {
+        Self {
+            position: position.into(),
+            color: color.into(),
+        }
+    }
+}
+
+// Storage buffer at binding 2 of the fragment shader (std430): the shader loops over `count`
+// lights. The buffer is sized for the lights: a different count needs a new buffer.
+#[repr(C)]
+#[derive(BufferContents)]
+pub struct DirectionalLights {
+    pub count: Padded<u32, 12>, // the array starts 16 bytes in
+    pub lights: [DirectionalLight],
+}
+
+impl DirectionalLights {
     pub fn setup_directional_light_buffers(
-        directional_light: DirectionalLight,
+        directional_lights: &[DirectionalLight],
         memory_allocator: Arc<GenericMemoryAllocator<FreeListAllocator>>,
-    ) -> Result<Subbuffer<DirectionalLight>> {
-        let directional_light_buffer = Buffer::from_data(
+    ) -> Result<Subbuffer<DirectionalLights>> {
+        // A zero sized array is not a valid buffer: keep one unused slot
+        let directional_light_buffer = Buffer::new_unsized::<DirectionalLights>(
             memory_allocator.clone(),
             BufferCreateInfo {
-                usage: BufferUsage::UNIFORM_BUFFER | BufferUsage::TRANSFER_DST,
+                usage: BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_DST,
                 ..Default::default()
             },
             AllocationCreateInfo {
@@ -68,9 +88,225 @@ impl DirectionalLight {
                     | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
                 ..Default::default()
             },
-            directional_light,
+            directional_lights.len().max(1) as u64,
         )?;
 
+        {
+            let mut contents = directional_light_buffer.write()?;
+            contents.count = (directional_lights.len() as u32).into();
+            contents.lights[..directional_lights.len()].copy_from_slice(directional_lights);
+        }
+
         Ok(directional_light_buffer)
     }
 }
+
+// Cone light. Element of the SpotLights array (std430, 48 bytes: each vec3 is followed by a float).
+// Full intensity inside the inner cone, smooth falloff to zero at the outer cone, and a
+// distance falloff reaching zero at `range`.
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, BufferContents)]
+pub struct SpotLight {
+    pub position: [f32; 3],
+    pub range: f32,
+    pub direction: [f32; 3], // normalized by the shader
+    pub inner_cos: f32,      // cosine of the inner half angle
+    pub color: [f32; 3],
+    pub outer_cos: f32, // cosine of the outer half angle, below inner_cos
+}
+
+impl SpotLight {
+    // Half angles in radians
+    pub fn new(
+        position: [f32; 3],
+        direction: [f32; 3],
+        color: [f32; 3],
+        inner_angle: f32,
+        outer_angle: f32,
+        range: f32,
+    ) -> Self {
+        Self {
+            position,
+            range,
+            direction,
+            inner_cos: inner_angle.cos(),
+            color,
+            // The shader smoothsteps between the two: keep them apart
+            outer_cos: outer_angle.max(inner_angle + 0.001).cos(),
+        }
+    }
+}
+
+// Storage buffer at binding 8 of the fragment shader (std430), same layout as DirectionalLights
+#[repr(C)]
+#[derive(BufferContents)]
+pub struct SpotLights {
+    pub count: Padded<u32, 12>, // the array starts 16 bytes in
+    pub lights: [SpotLight],
+}
+
+impl SpotLights {
+    pub fn setup_spot_light_buffers(
+        spot_lights: &[SpotLight],
+        memory_allocator: Arc<GenericMemoryAllocator<FreeListAllocator>>,
+    ) -> Result<Subbuffer<SpotLights>> {
+        // A zero sized array is not a valid buffer: keep one unused slot
+        let spot_light_buffer = Buffer::new_unsized::<SpotLights>(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            spot_lights.len().max(1) as u64,
+        )?;
+
+        {
+            let mut contents = spot_light_buffer.write()?;
+            contents.count = (spot_lights.len() as u32).into();
+            contents.lights[..spot_lights.len()].copy_from_slice(spot_lights);
+        }
+
+        Ok(spot_light_buffer)
+    }
+}
+
+// Built-in lighting rigs, see VulkanDevice::set_light_preset. Cycled with L.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LightPreset {
+    Studio,  // neutral, bright key light from the front
+    Outdoor, // warm sun high above, blue sky ambient
+    Night,   // dim moonlight, dark blue ambient
+}
+
+impl LightPreset {
+    pub fn next(self) -> Self {
+        match self {
+            LightPreset::Studio => LightPreset::Outdoor,
+            LightPreset::Outdoor => LightPreset::Night,
+            LightPreset::Night => LightPreset::Studio,
+        }
+    }
+
+    pub fn lights(self) -> (AmbientLight, Vec<DirectionalLight>) {
+        match self {
+            // Key light and a dimmer fill light from the other side
+            LightPreset::Studio => (
+                AmbientLight {
+                    color: [1.0, 1.0, 1.0],
+                    intensity: 0.3,
+                },
+                vec![
+                    DirectionalLight::new([1.5, 2.0, 3.0], [1.0, 1.0, 1.0]),
+                    DirectionalLight::new([-3.0, 1.0, 1.5], [0.3, 0.3, 0.35]),
+                ],
+            ),
+            LightPreset::Outdoor => (
+                AmbientLight {
+                    color: [0.6, 0.75, 1.0],
+                    intensity: 0.4,
+                },
+                vec![DirectionalLight::new([0.5, 10.0, 2.0], [1.0, 0.95, 0.8])],
+            ),
+            LightPreset::Night => (
+                AmbientLight {
+                    color: [0.2, 0.25, 0.5],
+                    intensity: 0.1,
+                },
+                vec![DirectionalLight::new([-2.0, 5.0, -1.0], [0.35, 0.4, 0.6])],
+            ),
+        }
+    }
+
+    // Set alongside the lights above
+    pub fn spot_lights(self) -> Vec<SpotLight> {
+        match self {
+            LightPreset::Studio | LightPreset::Outdoor => Vec::new(),
+            // Warm lamp above the model, pointing down
+            LightPreset::Night => vec![SpotLight::new(
+                [0.0, 4.0, 1.0],
+                [0.0, -1.0, -0.25],
+                [1.0, 0.85, 0.6],
+                0.3,
+                0.5,
+                10.0,
+            )],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRESETS: [LightPreset; 3] = [
+        LightPreset::Studio,
+        LightPreset::Outdoor,
+        LightPreset::Night,
+    ];
+
+    #[test]
+    fn directional_light_layout() {
+        // std430 array element of the DirectionalLights buffer: two vec3 padded to vec4
+        assert_eq!(std::mem::size_of::<DirectionalLight>(), 32);
+
+        let light = DirectionalLight::new([1.0, 2.0, 3.0], [0.5, 0.25, 0.125]);
+        assert_eq!(*light.position, [1.0, 2.0, 3.0]);
+        assert_eq!(*light.color, [0.5, 0.25, 0.125]);
+    }
+
+    #[test]
+    fn light_preset_counts() {
+        let counts = PRESETS.map(|preset| preset.lights().1.len());
+
+        assert_eq!(counts, [2, 1, 1]);
+
+        let spot_counts = PRESETS.map(|preset| preset.spot_lights().len());
+        assert_eq!(spot_counts, [0, 0, 1]);
+    }
+
+    #[test]
+    fn spot_light_cone() {
+        let light = SpotLight::new([0.0; 3], [0.0, -1.0, 0.0], [1.0; 3], 0.3, 0.5, 10.0);
+        assert!(light.inner_cos > light.outer_cos);
+
+        // An outer cone inside the inner one is widened
+        let light = SpotLight::new([0.0; 3], [0.0, -1.0, 0.0], [1.0; 3], 0.5, 0.3, 10.0);
+        assert!(light.inner_cos > light.outer_cos);
+    }
+
+    #[test]
+    fn light_preset_next_visits_every_preset() {
+        for (index, preset) in PRESETS.into_iter().enumerate() {
+            assert_eq!(preset.next(), PRESETS[(index + 1) % PRESETS.len()]);
+        }
+    }
+
+    // Every light above the ground, the night darker than the day
+    #[test]
+    fn light_preset_ranges() {
+        for preset in PRESETS {
+            let (ambient, directional) = preset.lights();
+            assert!((0.0..=1.0).contains(&ambient.intensity), "{preset:?}");
+            for light in &directional {
+                assert!(light.position[1] > 0.0, "{preset:?}");
+                assert!(light.color.iter().all(|c| (0.0..=1.0).contains(c)));
+            }
+        }
+
+        let brightness = |preset: LightPreset| {
+            let (ambient, directional) = preset.lights();
+            ambient.intensity
+                + directional
+                    .iter()
+                    .map(|l| l.color.iter().sum::<f32>())
+                    .sum::<f32>()
+        };
+        assert!(brightness(LightPreset::Night) < brightness(LightPreset::Outdoor));
+        assert!(brightness(LightPreset::Night) < brightness(LightPreset::Studio));
+    }
+}
@@ -1,30 +1,60 @@
 mod error;
 
 use app::App;
+use config::AppConfig;
 use error::Result;
 use winit::event_loop::EventLoopBuilder;
 
+mod animation;
 mod app;
+mod bench;
+mod bloom;
 mod camera;
-mod index_buffer;
+mod capture;
+mod compute;
+mod config;
+mod debug_utils;
+mod device_limits;
+mod frustum;
+#[cfg(feature = "gamepad")]
+mod gamepad;
+mod gpu_timer;
+mod grid;
+mod gui;
+mod headless;
+mod image_diff;
 mod instance_buffer;
 mod lighting;
+mod material;
+mod memory_report;
 mod mesh;
 mod shader;
+mod shader_reload;
+mod staging;
+mod text;
 mod textures;
 mod utils;
 mod vulkan_context;
 mod vulkan_device;
 mod vulkan_instance;
 mod vulkan_renderer;
-mod debug_utils;
 
 fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
 
+    let config = AppConfig::from_env().with_args(std::env::args());
+
+    // No window, no event loop
+    if let Some(output_path) = &config.headless {
+        return headless::render_headless(&config, output_path);
+    }
+    if let Some(frames) = config.bench_frames {
+        return bench::run_bench(&config, frames);
+    }
+
     let event_loop = EventLoopBuilder::new().build()?;
 
-    let mut app = App::new(&event_loop)?;
+    let mut app = App::new(&event_loop, config)?;
 
     event_loop
         .run(move |event, window_target| app.process_event(event, window_target).unwrap())?;
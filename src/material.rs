@@ -0,0 +1,438 @@
+use std::{path::Path, sync::Arc};
+
+use bytemuck::{Pod, Zeroable};
+use gltf::image::Format as GltfFormat;
+use image::{DynamicImage, ImageBuffer, RgbaImage};
+use serde::Deserialize;
+use tracing::warn;
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder,
+        PrimaryAutoCommandBuffer,
+    },
+    format::Format,
+    image::view::ImageView,
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+};
+
+use crate::{
+    device_limits::DeviceLimits,
+    error::Result,
+    textures::{
+        create_default_texture, create_texture, create_texture_from_bytes,
+        create_texture_from_image, create_texture_from_ktx2, KTX2_MAGIC,
+    },
+};
+
+// glTF metallic-roughness material.
+// A missing texture is replaced by a 1x1 white texture, so the factors alone drive the result.
+#[derive(Debug, Clone, Default)]
+pub struct Material {
+    pub base_color_texture: Option<TextureSource>,
+    pub metallic_roughness_texture: Option<TextureSource>,
+    pub occlusion_texture: Option<TextureSource>,
+    pub emissive_texture: Option<TextureSource>,
+    pub factors: MaterialFactors,
+    pub blend_mode: BlendMode,
+    pub emissive_only: bool, // default value of Mesh::emissive
+}
+
+// Where the image of a texture lives
+#[derive(Clone)]
+pub enum TextureSource {
+    // External file, resolved against the glTF file directory
+    Path(String),
+    // Encoded image (PNG, JPEG...) in a buffer view of the glTF (glb)
+    Bytes(Arc<[u8]>),
+    // Image of a glTF data URI, decoded by gltf::import
+    Pixels(Arc<RgbaImage>),
+}
+
+impl std::fmt::Debug for TextureSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TextureSource::Path(path) => f.debug_tuple("Path").field(path).finish(),
+            TextureSource::Bytes(bytes) => write!(f, "Bytes({} bytes)", bytes.len()),
+            TextureSource::Pixels(image) => {
+                write!(f, "Pixels({}x{})", image.width(), image.height())
+            }
+        }
+    }
+}
+
+// How the fragment color is combined with the framebuffer.
+// Premultiplied: the base color texture is multiplied by alpha at decode, so the filtering
+// between opaque and transparent texels doesn't darken the edges.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    #[default]
+    Opaque,
+    StraightAlpha,
+    PremultipliedAlpha,
+    // One/one blending, unlit. Used by the emissive only (glow) meshes
+    Additive,
+}
+
+// glTF has no premultiplied alpha or emissive only flag: read them from the material extras,
+// e.g. "extras": { "premultipliedAlpha": true, "emissiveOnly": true }
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MaterialExtras {
+    #[serde(default)]
+    premultiplied_alpha: bool,
+    #[serde(default)]
+    emissive_only: bool,
+}
+
+// Uniform block at binding 7 of the fragment shader (std140)
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct MaterialFactors {
+    pub base_color: [f32; 4],
+    pub emissive: [f32; 3],
+    pub metallic: f32,
+    pub roughness: f32,
+    pub occlusion_strength: f32,
+    // Blinn-Phong highlight instead of the metallic-roughness one when > 0, see with_specular
+    pub shininess: f32,
+    pub specular_strength: f32,
+}
+
+// glTF spec defaults
+impl Default for MaterialFactors {
+    fn default() -> Self {
+        Self::new([1.0; 4], [0.0; 3], 1.0, 1.0, 1.0)
+    }
+}
+
+impl MaterialFactors {
+    pub fn new(
+        base_color: [f32; 4],
+        emissive: [f32; 3],
+        metallic: f32,
+        roughness: f32,
+        occlusion_strength: f32,
+    ) -> Self {
+        Self {
+            base_color,
+            emissive,
+            metallic,
+            roughness,
+            occlusion_strength,
+            shininess: 0.0,
+            specular_strength: 0.0,
+        }
+    }
+
+    // Blinn-Phong shading: Lambert diffuse plus a `pow(n.h, shininess)` highlight scaled by
+    // `specular_strength`. The metallic and roughness factors are then unused.
+    // A shininess of 0 goes back to the glTF metallic-roughness shading.
+    pub fn with_specular(self, shininess: f32, specular_strength: f32) -> Self {
+        Self {
+            shininess: shininess.max(0.0),
+            specular_strength: specular_strength.max(0.0),
+            ..self
+        }
+    }
+
+    pub fn setup_material_buffer(
+        self,
+        memory_allocator: Arc<StandardMemoryAllocator>,
+    ) -> Result<Subbuffer<MaterialFactors>> {
+        let material_buffer = Buffer::from_data(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::UNIFORM_BUFFER | BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            self,
+        )?;
+
+        Ok(material_buffer)
+    }
+}
+
+impl Material {
+    // `buffers` are the glTF buffers, for the images stored in buffer views, `images` the ones
+    // gltf::import decoded, for the data URIs
+    pub fn from_gltf(
+        material: &gltf::Material,
+        gltf_path: &str,
+        buffers: &[gltf::buffer::Data],
+        images: &[gltf::image::Data],
+    ) -> Self {
+        let directory = Path::new(gltf_path).parent().unwrap_or(Path::new(""));
+
+        let texture_path = |texture: gltf::Texture| match texture.source().source() {
+            gltf::image::Source::Uri { uri, .. } if uri.starts_with("data:") => {
+                let image = images.get(texture.source().index())?;
+                match rgba_image(image) {
+                    Some(image) => Some(TextureSource::Pixels(Arc::new(image))),
+                    None => {
+                        warn!("embedded image of an unexpected size");
+                        None
+                    }
+                }
+            }
+            gltf::image::Source::Uri { uri, .. } => Some(TextureSource::Path(
+                directory.join(uri).to_string_lossy().into_owned(),
+            )),
+            gltf::image::Source::View { view, .. } => {
+                let buffer = buffers.get(view.buffer().index())?;
+                let bytes = buffer.get(view.offset()..view.offset() + view.length())?;
+                Some(TextureSource::Bytes(bytes.into()))
+            }
+        };
+
+        let pbr = material.pbr_metallic_roughness();
+        let occlusion = material.occlusion_texture();
+
+        let extras: MaterialExtras = material
+            .extras()
+            .as_ref()
+            .and_then(|extras| serde_json::from_str(extras.get()).ok())
+            .unwrap_or_default();
+
+        let blend_mode = match material.alpha_mode() {
+            gltf::material::AlphaMode::Blend if extras.premultiplied_alpha => {
+                BlendMode::PremultipliedAlpha
+            }
+            gltf::material::AlphaMode::Blend => BlendMode::StraightAlpha,
+            _ => BlendMode::Opaque,
+        };
+
+        Material {
+            base_color_texture: pbr
+                .base_color_texture()
+                .and_then(|info| texture_path(info.texture())),
+            metallic_roughness_texture: pbr
+                .metallic_roughness_texture()
+                .and_then(|info| texture_path(info.texture())),
+            occlusion_texture: occlusion
+                .as_ref()
+                .and_then(|occlusion| texture_path(occlusion.texture())),
+            emissive_texture: material
+                .emissive_texture()
+                .and_then(|info| texture_path(info.texture())),
+            factors: MaterialFactors::new(
+                pbr.base_color_factor(),
+                material.emissive_factor(),
+                pbr.metallic_factor(),
+                pbr.roughness_factor(),
+                occlusion.map_or(1.0, |occlusion| occlusion.strength()),
+            ),
+            blend_mode,
+            emissive_only: extras.emissive_only,
+        }
+    }
+
+    // Wavefront MTL material. The diffuse color and map become the base color, `d` the alpha.
+    // Not metallic, the roughness is derived from the Phong exponent `Ns`. With a specular
+    // color `Ks` too, the material is Blinn-Phong shaded as MTL intends.
+    pub fn from_obj(material: Option<&tobj::Material>, obj_path: &str) -> Self {
+        let Some(material) = material else {
+            return Material::default();
+        };
+        let directory = Path::new(obj_path).parent().unwrap_or(Path::new(""));
+
+        let [red, green, blue] = material.diffuse.unwrap_or([1.0; 3]);
+        let alpha = material.dissolve.unwrap_or(1.0);
+        let roughness = material
+            .shininess
+            .map_or(1.0, |shininess| (2.0 / (shininess.max(0.0) + 2.0)).sqrt());
+
+        let mut factors = MaterialFactors::new(
+            [red, green, blue, alpha],
+            material.emissive.unwrap_or([0.0; 3]),
+            0.0,
+            roughness,
+            1.0,
+        );
+        if let (Some(shininess), Some(specular)) = (material.shininess, material.specular) {
+            // Gray strength: the highlight takes the light color
+            factors = factors.with_specular(shininess, specular.into_iter().fold(0.0, f32::max));
+        }
+
+        Material {
+            base_color_texture: material.diffuse_texture.as_ref().map(|texture| {
+                TextureSource::Path(directory.join(texture).to_string_lossy().into_owned())
+            }),
+            emissive_texture: None,
+            metallic_roughness_texture: None,
+            occlusion_texture: None,
+            factors,
+            blend_mode: if alpha < 1.0 {
+                BlendMode::StraightAlpha
+            } else {
+                BlendMode::Opaque
+            },
+            emissive_only: false,
+        }
+    }
+}
+
+// Number of textures in MaterialTextures, counted against the per stage sampler limit
+pub const MATERIAL_TEXTURE_COUNT: u32 = 4;
+
+// GPU side of a Material. Bound at bindings 3 to 6.
+pub struct MaterialTextures {
+    pub base_color: Arc<ImageView>,
+    pub metallic_roughness: Arc<ImageView>,
+    pub occlusion: Arc<ImageView>,
+    pub emissive: Arc<ImageView>,
+}
+
+impl MaterialTextures {
+    pub fn new(
+        material: &Material,
+        command_builder: &mut AutoCommandBufferBuilder<
+            PrimaryAutoCommandBuffer<Arc<StandardCommandBufferAllocator>>,
+            Arc<StandardCommandBufferAllocator>,
+        >,
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        limits: &DeviceLimits,
+    ) -> Result<Self> {
+        // Color textures are sRGB encoded, metallic-roughness and occlusion hold linear data.
+        // KTX2 textures are compressed with their own format, by file extension or content, and
+        // can't be premultiplied at decode.
+        let premultiply = material.blend_mode == BlendMode::PremultipliedAlpha;
+        let mut load = |source: &Option<TextureSource>, format: Format, premultiply| match source {
+            Some(TextureSource::Path(path))
+                if Path::new(path)
+                    .extension()
+                    .is_some_and(|extension| extension.eq_ignore_ascii_case("ktx2")) =>
+            {
+                if premultiply {
+                    warn!("KTX2 texture {path} used as is by a premultiplied alpha material");
+                }
+                create_texture_from_ktx2(
+                    &std::fs::read(path)?,
+                    format,
+                    command_builder,
+                    memory_allocator.clone(),
+                    limits,
+                )
+            }
+            Some(TextureSource::Bytes(bytes)) if bytes.starts_with(&KTX2_MAGIC) => {
+                if premultiply {
+                    warn!("embedded KTX2 texture used as is by a premultiplied alpha material");
+                }
+                create_texture_from_ktx2(
+                    bytes,
+                    format,
+                    command_builder,
+                    memory_allocator.clone(),
+                    limits,
+                )
+            }
+            Some(TextureSource::Path(path)) => create_texture(
+                path,
+                format,
+                premultiply,
+                command_builder,
+                memory_allocator.clone(),
+                limits,
+            ),
+            Some(TextureSource::Bytes(bytes)) => create_texture_from_bytes(
+                bytes,
+                format,
+                premultiply,
+                command_builder,
+                memory_allocator.clone(),
+                limits,
+            ),
+            Some(TextureSource::Pixels(image)) => create_texture_from_image(
+                RgbaImage::clone(image),
+                format,
+                premultiply,
+                command_builder,
+                memory_allocator.clone(),
+                limits,
+            ),
+            None => {
+                create_default_texture([255; 4], format, command_builder, memory_allocator.clone())
+            }
+        };
+
+        Ok(MaterialTextures {
+            base_color: load(
+                &material.base_color_texture,
+                Format::R8G8B8A8_SRGB,
+                premultiply,
+            )?,
+            metallic_roughness: load(
+                &material.metallic_roughness_texture,
+                Format::R8G8B8A8_UNORM,
+                false,
+            )?,
+            occlusion: load(&material.occlusion_texture, Format::R8G8B8A8_UNORM, false)?,
+            emissive: load(&material.emissive_texture, Format::R8G8B8A8_SRGB, false)?,
+        })
+    }
+}
+
+// A glTF image as RGBA8, whatever its channels and depth. None when the pixels don't fill it.
+fn rgba_image(data: &gltf::image::Data) -> Option<RgbaImage> {
+    let (width, height) = (data.width, data.height);
+    // Native endian, see gltf::image::Data
+    let wide = || bytemuck::pod_collect_to_vec::<u8, u16>(&data.pixels);
+    let float = || bytemuck::pod_collect_to_vec::<u8, f32>(&data.pixels);
+    let bytes = || data.pixels.clone();
+
+    let image = match data.format {
+        GltfFormat::R8 => DynamicImage::ImageLuma8(ImageBuffer::from_raw(width, height, bytes())?),
+        GltfFormat::R8G8 => {
+            DynamicImage::ImageLumaA8(ImageBuffer::from_raw(width, height, bytes())?)
+        }
+        GltfFormat::R8G8B8 => {
+            DynamicImage::ImageRgb8(ImageBuffer::from_raw(width, height, bytes())?)
+        }
+        GltfFormat::R8G8B8A8 => return ImageBuffer::from_raw(width, height, bytes()),
+        GltfFormat::R16 => DynamicImage::ImageLuma16(ImageBuffer::from_raw(width, height, wide())?),
+        GltfFormat::R16G16 => {
+            DynamicImage::ImageLumaA16(ImageBuffer::from_raw(width, height, wide())?)
+        }
+        GltfFormat::R16G16B16 => {
+            DynamicImage::ImageRgb16(ImageBuffer::from_raw(width, height, wide())?)
+        }
+        GltfFormat::R16G16B16A16 => {
+            DynamicImage::ImageRgba16(ImageBuffer::from_raw(width, height, wide())?)
+        }
+        GltfFormat::R32G32B32FLOAT => {
+            DynamicImage::ImageRgb32F(ImageBuffer::from_raw(width, height, float())?)
+        }
+        GltfFormat::R32G32B32A32FLOAT => {
+            DynamicImage::ImageRgba32F(ImageBuffer::from_raw(width, height, float())?)
+        }
+    };
+
+    Some(image.to_rgba8())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gltf_rgb_image_gets_an_opaque_alpha() {
+        let data = gltf::image::Data {
+            pixels: vec![255, 0, 0, 0, 128, 255],
+            format: GltfFormat::R8G8B8,
+            width: 2,
+            height: 1,
+        };
+
+        let image = rgba_image(&data).unwrap();
+        assert_eq!(image.as_raw(), &[255, 0, 0, 255, 0, 128, 255, 255]);
+
+        // Not enough pixels for the size
+        let short = gltf::image::Data { width: 3, ..data };
+        assert!(rgba_image(&short).is_none());
+    }
+}
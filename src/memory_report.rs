@@ -0,0 +1,106 @@
+use tracing::info;
+use vulkano::{
+    device::physical::PhysicalDevice, memory::MemoryHeapFlags, DeviceSize, Version, VulkanObject,
+};
+
+// Memory usage of one heap of the physical device
+#[derive(Debug, Clone, Copy)]
+pub struct HeapUsage {
+    pub heap_index: usize,
+    pub device_local: bool,
+    pub size: DeviceSize,
+    pub used: DeviceSize,      // by this process. 0 without ext_memory_budget
+    pub available: DeviceSize, // budget left. Heap size without ext_memory_budget
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryReport {
+    pub heaps: Vec<HeapUsage>,
+    pub from_budget: bool, // false when the static heap sizes are reported
+}
+
+impl MemoryReport {
+    pub fn new(physical_device: &PhysicalDevice) -> Self {
+        let memory_heaps = &physical_device.memory_properties().memory_heaps;
+        let budgets = if physical_device.supported_extensions().ext_memory_budget {
+            query_heap_budgets(physical_device)
+        } else {
+            None
+        };
+
+        let heaps = memory_heaps
+            .iter()
+            .enumerate()
+            .map(|(heap_index, heap)| {
+                let (used, available) = match &budgets {
+                    Some((usages, budgets)) => (
+                        usages[heap_index],
+                        budgets[heap_index].saturating_sub(usages[heap_index]),
+                    ),
+                    None => (0, heap.size),
+                };
+
+                HeapUsage {
+                    heap_index,
+                    device_local: heap.flags.intersects(MemoryHeapFlags::DEVICE_LOCAL),
+                    size: heap.size,
+                    used,
+                    available,
+                }
+            })
+            .collect();
+
+        Self {
+            heaps,
+            from_budget: budgets.is_some(),
+        }
+    }
+
+    pub fn log(&self) {
+        if !self.from_budget {
+            info!("GPU memory: ext_memory_budget not available, reporting heap sizes");
+        }
+
+        for heap in &self.heaps {
+            info!(
+                "GPU heap #{} ({}): used {} MiB, available {} MiB, size {} MiB",
+                heap.heap_index,
+                if heap.device_local { "device" } else { "host" },
+                heap.used / (1024 * 1024),
+                heap.available / (1024 * 1024),
+                heap.size / (1024 * 1024),
+            );
+        }
+    }
+}
+
+// VK_EXT_memory_budget isn't wrapped by vulkano: chain the budget struct to
+// vkGetPhysicalDeviceMemoryProperties2 ourselves. Returns per heap (usage, budget).
+fn query_heap_budgets(
+    physical_device: &PhysicalDevice,
+) -> Option<(Vec<DeviceSize>, Vec<DeviceSize>)> {
+    let instance = physical_device.instance();
+    if instance.api_version() < Version::V1_1 {
+        return None;
+    }
+
+    let mut budget = ash::vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+    let mut properties = ash::vk::PhysicalDeviceMemoryProperties2::builder()
+        .push_next(&mut budget)
+        .build();
+
+    // Safety: valid physical device handle, `budget` outlives the call
+    unsafe {
+        (instance.fns().v1_1.get_physical_device_memory_properties2)(
+            physical_device.handle(),
+            &mut properties,
+        );
+    }
+
+    let heap_count = properties.memory_properties.memory_heap_count as usize;
+
+    Some((
+        budget.heap_usage[..heap_count].to_vec(),
+        budget.heap_budget[..heap_count].to_vec(),
+    ))
+}
@@ -1,105 +1,367 @@
 
-use gltf::Gltf;
-use tracing::{info, warn};
+use std::path::Path;
+use std::sync::Arc;
 
-use crate::error::Result;
+use gltf::mesh::Mode;
+use nalgebra::{Matrix3, Matrix4, Point3, Vector3};
+use ply_rs::{
+    parser::Parser,
+    ply::{DefaultElement, Property},
+};
+use tracing::{debug, info, warn};
+use vulkano::{
+    buffer::Subbuffer, descriptor_set::PersistentDescriptorSet,
+    pipeline::graphics::input_assembly::PrimitiveTopology,
+};
+
+use crate::animation::Skeleton;
+use crate::error::{Result, VisualSystemError};
+use crate::instance_buffer::InstanceRaw;
+use crate::material::{BlendMode, Material, MaterialFactors, MaterialTextures, TextureSource};
 use crate::shader::Vertex;
+
+// A mesh uploaded to the GPU. Drawn with one draw call.
+pub struct Mesh {
+    pub vertex_buffer: Subbuffer<[Vertex]>,
+    pub index_buffer: Option<Subbuffer<[u32]>>,
+    pub instance_buffer: Subbuffer<[InstanceRaw]>, // shared by all the meshes
+    pub first_instance: u32,                       // subrange of the instance buffer drawn
+    pub instance_count: u32,
+    pub material: Material,
+    pub material_buffer: Subbuffer<MaterialFactors>, // kept for rebuild_descriptor_set
+    pub material_textures: MaterialTextures,
+    // Glow: drawn unlit with additive blending, after the other meshes
+    pub emissive: bool,
+    pub topology: Topology,
+    pub bounds: Option<([f32; 3], [f32; 3])>, // model space, None for an empty mesh
+    pub bounding_sphere: Option<([f32; 3], f32)>, // around `bounds`, None: never culled
+    pub descriptor_set: Arc<PersistentDescriptorSet>, // set 1: lights and material textures
+}
+
+impl Mesh {
+    // Pipeline of the mesh: the emissive only meshes are drawn additive whatever their material
+    pub fn blend_mode(&self) -> BlendMode {
+        if self.emissive {
+            BlendMode::Additive
+        } else {
+            self.material.blend_mode
+        }
+    }
+}
+
+// How the vertices are assembled. Each topology has its own pipelines.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Topology {
+    #[default]
+    Triangles,
+    Lines,
+    Points, // point cloud, sized by VULKANOX_POINT_SIZE
+}
+
+impl Topology {
+    pub const ALL: [Topology; 3] = [Topology::Triangles, Topology::Lines, Topology::Points];
+
+    pub fn primitive_topology(self) -> PrimitiveTopology {
+        match self {
+            Topology::Triangles => PrimitiveTopology::TriangleList,
+            Topology::Lines => PrimitiveTopology::LineList,
+            Topology::Points => PrimitiveTopology::PointList,
+        }
+    }
+
+    // Strips, loops and fans are unrolled into lists at load, see unroll_indices
+    fn from_gltf(mode: Mode) -> Self {
+        match mode {
+            Mode::Points => Topology::Points,
+            Mode::Lines | Mode::LineLoop | Mode::LineStrip => Topology::Lines,
+            Mode::Triangles | Mode::TriangleStrip | Mode::TriangleFan => Topology::Triangles,
+        }
+    }
+}
+
+// The parts of a model and its skeleton, None without skin (or for a format without skins)
+pub type Model = (Vec<MeshBuilder>, Option<Skeleton>);
+
 // Struct to read GLTF and store Mesh data
 pub struct MeshBuilder {
     positions: Vec<[f32; 3]>,
-    indices: Vec<u16>,
+    indices: Vec<u32>,
     normals: Option<Vec<[f32; 3]>>,
+    tangents: Option<Vec<[f32; 4]>>, // w: handedness of the bitangent
     uvs: Option<Vec<[f32; 2]>>,
+    colors: Option<Vec<[f32; 4]>>,
+    // Skinning: joints of the skeleton palette and their weights, see animation.rs
+    joints: Option<Vec<[u16; 4]>>,
+    weights: Option<Vec<[f32; 4]>>,
+    material: Material,
+    topology: Topology,
 }
 
+// Buffers and decoded images of a glTF file, from gltf::import
+type GltfData<'a> = (&'a [gltf::buffer::Data], &'a [gltf::image::Data]);
+
+// Reads one model file format, see MeshBuilder::read
+type ModelLoader = fn(&str) -> Result<Model>;
+
 impl MeshBuilder {
-    pub fn read_gltf(path: &str) -> Result<MeshBuilder> {
-        //"assets/Box.gltf"
-        let gltf = Gltf::open(path)?;
-        for scene in gltf.scenes() {
+    // Loader of each supported file extension (lowercase)
+    const LOADERS: [(&'static str, ModelLoader); 4] = [
+        ("gltf", Self::read_gltf),
+        ("glb", Self::read_gltf),
+        ("obj", |path| Ok((Self::read_obj(path)?, None))),
+        ("ply", |path| Ok((vec![Self::read_ply(path)?], None))),
+    ];
+
+    // Pick the loader from the file extension. One builder per part of the model (glTF
+    // primitive), drawn each with its own call.
+    pub fn read(path: &str) -> Result<Model> {
+        if !Path::new(path).is_file() {
+            return Err(VisualSystemError::ModelNotFound(path.to_string()).into());
+        }
+
+        let loader = Self::loader(Path::new(path))
+            .ok_or_else(|| VisualSystemError::UnsupportedModelFormat(path.to_string()))?;
+
+        loader(path)
+    }
+
+    // Whether `read` knows the extension of `path`, e.g. for a dropped file
+    pub fn is_supported(path: &Path) -> bool {
+        Self::loader(path).is_some()
+    }
+
+    fn loader(path: &Path) -> Option<ModelLoader> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+
+        Self::LOADERS
+            .iter()
+            .find(|(supported, _)| *supported == extension)
+            .map(|(_, loader)| *loader)
+    }
+
+    // Mesh from in-memory data, e.g. procedural geometry. No file is read.
+    // Empty `indices` draws the vertices as a plain triangle list.
+    // `normals` and `uvs` have one value per position, `indices` point into the positions.
+    pub fn from_raw(
+        positions: Vec<[f32; 3]>,
+        normals: Option<Vec<[f32; 3]>>,
+        uvs: Option<Vec<[f32; 2]>>,
+        indices: Vec<u32>,
+    ) -> Result<MeshBuilder> {
+        let check_length = |attribute, actual| {
+            if actual == positions.len() {
+                Ok(())
+            } else {
+                Err(VisualSystemError::MeshAttributeLengthMismatch {
+                    attribute,
+                    expected: positions.len(),
+                    actual,
+                })
+            }
+        };
+        if let Some(normals) = &normals {
+            check_length("normals", normals.len())?;
+        }
+        if let Some(uvs) = &uvs {
+            check_length("uvs", uvs.len())?;
+        }
+        check_indices(&indices, positions.len())?;
+
+        Ok(MeshBuilder {
+            positions,
+            indices,
+            normals,
+            tangents: None,
+            uvs,
+            colors: None,
+            joints: None,
+            weights: None,
+            material: Material::default(),
+            topology: Topology::default(),
+        })
+    }
+
+    // e.g. Topology::Points to draw `positions` as a point cloud
+    pub fn with_topology(mut self, topology: Topology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    // One MeshBuilder per primitive of every mesh node of the scene. Each primitive keeps its
+    // own vertices, indices (relative to its own vertices), material and topology: one draw call
+    // each. The world transform of the node is baked into the vertices, except for the skinned
+    // meshes: their joints place them (animation::Skeleton).
+    pub fn read_gltf(path: &str) -> Result<Model> {
+        // One parse: the document, the buffers and the images
+        let (gltf, buffers, images) = gltf::import(path)?;
+
+        // The skins share one joint palette: first joint of each skin in it
+        let skin_offsets: Vec<u16> = gltf
+            .skins()
+            .scan(0u16, |offset, skin| {
+                let first = *offset;
+                *offset = offset.saturating_add(skin.joints().len() as u16);
+                Some(first)
+            })
+            .collect();
+
+        let mut primitives = Vec::new();
+        let scene = gltf.default_scene().or_else(|| gltf.scenes().next());
+        if let Some(scene) = scene {
             for node in scene.nodes() {
-                println!(
-                    "Node #{} has {} children",
-                    node.index(),
-                    node.children().count(),
+                Self::read_gltf_node(
+                    &node,
+                    &Matrix4::identity(),
+                    path,
+                    (&buffers, &images),
+                    &skin_offsets,
+                    &mut primitives,
                 );
             }
+        } else {
+            // No scene graph (a glTF library of meshes): the meshes as they are, without skin
+            for mesh in gltf.meshes() {
+                debug!("Mesh #{}", mesh.index());
+                for primitive in mesh.primitives() {
+                    debug!("- Primitive #{}", primitive.index());
+                    let mut builder =
+                        Self::from_gltf_primitive(&primitive, path, (&buffers, &images));
+                    builder.remove_skin();
+                    primitives.push(builder);
+                }
+            }
         }
 
+        info!("glTF {path}: {} primitives", primitives.len());
+
+        let skeleton = Skeleton::from_gltf(&gltf, &buffers, path);
+
+        Ok((primitives, skeleton))
+    }
+
+    // Depth first: the world transform of a node is the one of its parent times its local one.
+    // A mesh referenced by several nodes gets one set of builders per node.
+    fn read_gltf_node(
+        node: &gltf::Node,
+        parent_transform: &Matrix4<f32>,
+        path: &str,
+        data: GltfData,
+        skin_offsets: &[u16],
+        primitives: &mut Vec<MeshBuilder>,
+    ) {
+        let transform = parent_transform * Matrix4::from(node.transform().matrix());
+        debug!(
+            "Node #{} has {} children",
+            node.index(),
+            node.children().count(),
+        );
+
+        if let Some(mesh) = node.mesh() {
+            debug!("Mesh #{}", mesh.index());
+            for primitive in mesh.primitives() {
+                debug!("- Primitive #{}", primitive.index());
+                let mut builder = Self::from_gltf_primitive(&primitive, path, data);
+                match node.skin() {
+                    // glTF: the transform of a skinned mesh node is ignored
+                    Some(skin) => builder.offset_joints(skin_offsets[skin.index()]),
+                    None => {
+                        builder.remove_skin();
+                        builder.transform(&transform);
+                    }
+                }
+                primitives.push(builder);
+            }
+        }
+
+        for child in node.children() {
+            Self::read_gltf_node(&child, &transform, path, data, skin_offsets, primitives);
+        }
+    }
+
+    fn from_gltf_primitive(
+        primitive: &gltf::Primitive,
+        path: &str,
+        (buffers, images): GltfData,
+    ) -> MeshBuilder {
         let mut positions: Vec<[f32; 3]> = Vec::new();
         let mut indices = Vec::new();
         let mut uvs = Vec::new();
         let mut normals = Vec::new();
+        let mut tangents = Vec::new();
+        let mut colors = Vec::new();
         let mut joint_indices = Vec::new();
         let mut joint_weigths = Vec::new();
 
-        let (gltf, buffers, _) = gltf::import("assets/Box.gltf")?;
-        for mesh in gltf.meshes() {
-            println!("Mesh #{}", mesh.index());
-            for primitive in mesh.primitives() {
-                println!("- Primitive #{}", primitive.index());
-                let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
 
-                // Positions
-                if let Some(iter) = reader.read_positions() {
-                    println!("VERTICES NUMBER: {:?}", iter.len());
+        // The reader iterators substitute the sparse accessor values (morph targets,
+        // compressed data) and convert the component types: take them as a whole.
 
-                    for vertex_position in iter {
-                        positions.push(vertex_position);
-                    }
-                }
-                // Indices
+        // Positions
+        if let Some(iter) = reader.read_positions() {
+            debug!("{} vertices", iter.len());
 
-                if let Some(gltf::mesh::util::ReadIndices::U16(gltf::accessor::Iter::Standard(
-                    iter,
-                ))) = reader.read_indices()
-                {
-                    println!("INDICES NUMBER: {:?}", iter.len());
+            for vertex_position in iter {
+                positions.push(vertex_position);
+            }
+        }
+        // Indices: U8, U16 or U32
+        if let Some(iter) = reader.read_indices().map(|indices| indices.into_u32()) {
+            debug!("{} indices", iter.len());
 
-                    for indice in iter {
-                        indices.push(indice);
-                    }
-                }
+            for indice in iter {
+                indices.push(indice);
+            }
+        }
 
-                if let Some(gltf::mesh::util::ReadTexCoords::F32(gltf::accessor::Iter::Standard(
-                    iter,
-                ))) = reader.read_tex_coords(0)
-                {
-                    println!("UVS NUMBER: {:?}", iter.len());
-                    for texture_coord in iter {
-                        uvs.push(texture_coord);
-                    }
-                }
-                if let Some(iter) = reader.read_normals() {
-                    for normal in iter {
-                        normals.push(normal);
-                    }
-                }
-                if let Some(gltf::mesh::util::ReadJoints::U8(gltf::accessor::Iter::Standard(
-                    iter,
-                ))) = reader.read_joints(0)
-                {
-                    for joint_indice in iter {
-                        joint_indices.push(joint_indice);
-                    }
-                }
-                if let Some(gltf::mesh::util::ReadWeights::F32(gltf::accessor::Iter::Standard(
-                    iter,
-                ))) = reader.read_weights(0)
-                {
-                    for joint_weigth in iter {
-                        joint_weigths.push(joint_weigth);
-                    }
-                }
+        // UVs: F32, or U8/U16 normalized
+        if let Some(iter) = reader.read_tex_coords(0).map(|uvs| uvs.into_f32()) {
+            debug!("{} UVs", iter.len());
+            for texture_coord in iter {
+                uvs.push(texture_coord);
+            }
+        }
+        if let Some(iter) = reader.read_normals() {
+            for normal in iter {
+                normals.push(normal);
+            }
+        }
+        // Tangents: computed from the UVs when missing, see vertices
+        if let Some(iter) = reader.read_tangents() {
+            for tangent in iter {
+                tangents.push(tangent);
+            }
+        }
+        // Vertex colors: RGB or RGBA, U8/U16 normalized or F32
+        if let Some(iter) = reader.read_colors(0) {
+            for color in iter.into_rgba_f32() {
+                colors.push(color);
+            }
+        }
+        if let Some(iter) = reader.read_joints(0).map(|joints| joints.into_u16()) {
+            for joint_indice in iter {
+                joint_indices.push(joint_indice);
+            }
+        }
+        if let Some(iter) = reader.read_weights(0).map(|weights| weights.into_f32()) {
+            for joint_weigth in iter {
+                joint_weigths.push(joint_weigth);
             }
         }
 
+        let indices = unroll_indices(primitive.mode(), indices, positions.len());
+
         //let indices = if indices.len() == 0 { None } else {Some(indices)};
         let normals = if normals.is_empty() {
             None
         } else {
             Some(normals)
         };
+        let tangents = if tangents.is_empty() {
+            None
+        } else {
+            Some(tangents)
+        };
         let uvs = if uvs.is_empty() {
             warn!("no UV found");
             None
@@ -108,37 +370,328 @@ impl MeshBuilder {
             Some(uvs)
         };
 
-        Ok(MeshBuilder {
+        let colors = if colors.is_empty() {
+            None
+        } else {
+            Some(colors)
+        };
+        // Both or none
+        let (joints, weights) = if joint_indices.is_empty() || joint_weigths.is_empty() {
+            (None, None)
+        } else {
+            (Some(joint_indices), Some(joint_weigths))
+        };
+
+        MeshBuilder {
             positions,
             normals,
+            tangents,
             indices,
             uvs,
-        })
+            colors,
+            joints,
+            weights,
+            material: Material::from_gltf(&primitive.material(), path, buffers, images),
+            topology: Topology::from_gltf(primitive.mode()),
+        }
+    }
+
+    // Joint indices of the skin into the palette shared by the skins of the file
+    fn offset_joints(&mut self, offset: u16) {
+        if let Some(joints) = &mut self.joints {
+            for joint in joints.iter_mut().flatten() {
+                *joint = joint.saturating_add(offset);
+            }
+        }
+    }
+
+    // Joints and weights without a skin to animate them
+    fn remove_skin(&mut self) {
+        self.joints = None;
+        self.weights = None;
+    }
+
+    // Moved by the joint palette in the vertex shader: its bounds are the ones of the bind pose
+    pub fn is_skinned(&self) -> bool {
+        self.weights.is_some()
+    }
+
+    // Bake `matrix` into the positions, the normals follow its inverse transpose.
+    // A mirroring transform (negative determinant) would turn the triangles inside out: their
+    // winding is reversed to keep them front facing.
+    fn transform(&mut self, matrix: &Matrix4<f32>) {
+        if *matrix == Matrix4::identity() {
+            return;
+        }
+
+        for position in &mut self.positions {
+            *position = matrix
+                .transform_point(&Point3::from(*position))
+                .coords
+                .into();
+        }
+
+        let linear = matrix.fixed_view::<3, 3>(0, 0).into_owned();
+        if let Some(normals) = &mut self.normals {
+            let normal_matrix = linear
+                .try_inverse()
+                .unwrap_or_else(Matrix3::identity)
+                .transpose();
+            for normal in normals {
+                *normal = (normal_matrix * Vector3::from(*normal))
+                    .try_normalize(f32::EPSILON)
+                    .unwrap_or_else(Vector3::z)
+                    .into();
+            }
+        }
+
+        // The bitangent is mirrored with the geometry
+        let handedness = linear.determinant().signum();
+        if let Some(tangents) = &mut self.tangents {
+            for tangent in tangents {
+                let direction = (linear * Vector3::new(tangent[0], tangent[1], tangent[2]))
+                    .try_normalize(f32::EPSILON)
+                    .unwrap_or_else(Vector3::x);
+                *tangent = [
+                    direction.x,
+                    direction.y,
+                    direction.z,
+                    tangent[3] * handedness,
+                ];
+            }
+        }
+
+        if handedness < 0.0 && self.topology == Topology::Triangles {
+            if self.indices.is_empty() {
+                self.indices = (0..self.positions.len() as u32).collect();
+            }
+            for triangle in self.indices.chunks_exact_mut(3) {
+                triangle.swap(1, 2);
+            }
+        }
+    }
+
+    // Wavefront OBJ, with its MTL materials when found. One builder per object (or group).
+    // Polygons are triangulated. Without normals in the file, the normals are computed later
+    // (see vertices).
+    pub fn read_obj(path: &str) -> Result<Vec<MeshBuilder>> {
+        let (models, materials) = tobj::load_obj(
+            path,
+            &tobj::LoadOptions {
+                single_index: true, // one index for the positions, normals and uvs
+                triangulate: true,
+                ..Default::default()
+            },
+        )?;
+
+        let materials = materials.unwrap_or_else(|e| {
+            warn!("OBJ {path}: no materials: {e}");
+            Vec::new()
+        });
+
+        let meshes: Vec<_> = models
+            .into_iter()
+            .map(|model| {
+                let mesh = model.mesh;
+
+                let normals = (!mesh.normals.is_empty()).then(|| {
+                    mesh.normals
+                        .chunks_exact(3)
+                        .map(|normal| [normal[0], normal[1], normal[2]])
+                        .collect()
+                });
+                if normals.is_none() {
+                    warn!("OBJ {path}: {} has no normals", model.name);
+                }
+
+                // OBJ puts v = 0 at the bottom of the image, Vulkan at the top
+                let uvs = (!mesh.texcoords.is_empty()).then(|| {
+                    mesh.texcoords
+                        .chunks_exact(2)
+                        .map(|uv| [uv[0], 1.0 - uv[1]])
+                        .collect()
+                });
+
+                let colors = (!mesh.vertex_color.is_empty()).then(|| {
+                    mesh.vertex_color
+                        .chunks_exact(3)
+                        .map(|color| [color[0], color[1], color[2], 1.0])
+                        .collect()
+                });
+
+                MeshBuilder {
+                    positions: mesh
+                        .positions
+                        .chunks_exact(3)
+                        .map(|position| [position[0], position[1], position[2]])
+                        .collect(),
+                    indices: mesh.indices,
+                    normals,
+                    tangents: None,
+                    uvs,
+                    colors,
+                    joints: None,
+                    weights: None,
+                    material: Material::from_obj(
+                        mesh.material_id.and_then(|id| materials.get(id)),
+                        path,
+                    ),
+                    topology: Topology::Triangles,
+                }
+            })
+            .collect();
+
+        info!("OBJ {path}: {} objects", meshes.len());
+
+        Ok(meshes)
+    }
+
+    // PLY, ascii or binary: positions, optional normals and vertex colors.
+    // The faces are triangulated as fans. Without faces the vertices are drawn as a point cloud.
+    pub fn read_ply(path: &str) -> Result<MeshBuilder> {
+        let mut file = std::fs::File::open(path)?;
+        let ply = Parser::<DefaultElement>::new().read_ply(&mut file)?;
+
+        let vertices = ply.payload.get("vertex").map_or(&[][..], Vec::as_slice);
+        let faces = ply.payload.get("face").map_or(&[][..], Vec::as_slice);
+
+        let positions = vertices
+            .iter()
+            .map(|vertex| {
+                [
+                    ply_float(vertex, "x").unwrap_or(0.0),
+                    ply_float(vertex, "y").unwrap_or(0.0),
+                    ply_float(vertex, "z").unwrap_or(0.0),
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        let normals = vertices
+            .iter()
+            .map(|vertex| {
+                Some([
+                    ply_float(vertex, "nx")?,
+                    ply_float(vertex, "ny")?,
+                    ply_float(vertex, "nz")?,
+                ])
+            })
+            .collect::<Option<Vec<_>>>()
+            .filter(|normals| !normals.is_empty());
+
+        // Alpha is optional
+        let colors = vertices
+            .iter()
+            .map(|vertex| {
+                Some([
+                    ply_color(vertex, "red")?,
+                    ply_color(vertex, "green")?,
+                    ply_color(vertex, "blue")?,
+                    ply_color(vertex, "alpha").unwrap_or(1.0),
+                ])
+            })
+            .collect::<Option<Vec<_>>>()
+            .filter(|colors| !colors.is_empty());
+
+        let mut indices = Vec::new();
+        for face in faces {
+            let polygon = face
+                .get("vertex_indices")
+                .or_else(|| face.get("vertex_index"))
+                .and_then(ply_indices)
+                .unwrap_or_default();
+
+            for idx in 1..polygon.len().saturating_sub(1) {
+                indices.extend([polygon[0], polygon[idx], polygon[idx + 1]]);
+            }
+        }
+
+        let topology = if indices.is_empty() {
+            Topology::Points
+        } else {
+            Topology::Triangles
+        };
+
+        // Checks the indices
+        let mut mesh =
+            MeshBuilder::from_raw(positions, normals, None, indices)?.with_topology(topology);
+        mesh.colors = colors;
+
+        info!(
+            "PLY {path}: {} vertices, {topology:?}, bounds {:?}",
+            mesh.positions.len(),
+            mesh.bounding_box()
+        );
+
+        Ok(mesh)
+    }
+
+    // Axis aligned (min, max) corners of the positions, to frame the model.
+    // None for an empty mesh.
+    pub fn bounding_box(&self) -> Option<([f32; 3], [f32; 3])> {
+        let first = *self.positions.first()?;
+
+        Some(
+            self.positions
+                .iter()
+                .fold((first, first), |(mut min, mut max), position| {
+                    for axis in 0..3 {
+                        min[axis] = min[axis].min(position[axis]);
+                        max[axis] = max[axis].max(position[axis]);
+                    }
+                    (min, max)
+                }),
+        )
+    }
+
+    // Same as bounding_box, a zero size box at the origin for an empty mesh
+    pub fn aabb(&self) -> ([f32; 3], [f32; 3]) {
+        self.bounding_box().unwrap_or_default()
+    }
+
+    // (center, radius) of the sphere around the bounding box. Looser than the smallest sphere,
+    // but cheap and enough for culling and framing.
+    pub fn bounding_sphere(&self) -> ([f32; 3], f32) {
+        let (min, max) = self.aabb();
+        let (min, max) = (Point3::from(min), Point3::from(max));
+
+        (
+            nalgebra::center(&min, &max).into(),
+            (max - min).magnitude() / 2.0,
+        )
     }
 
     pub fn vertices(&self) -> Result<Vec<Vertex>> {
         let mut vertices = Vec::<Vertex>::new();
 
-        match &self.normals {
-            Some(normals) => {
-                for (position, normal) in self.positions.iter().zip(normals.iter()) {
-                    vertices.push(Vertex {
-                        position: *position,
-                        normal: *normal,
-                        uvs: [0., 0.],
-                    });
-                }
+        let computed_normals;
+        let normals = match &self.normals {
+            Some(normals) => normals,
+            None => {
+                info!("no normal found, computed from the faces");
+                computed_normals = self.compute_normals();
+                &computed_normals
             }
+        };
+
+        let computed_tangents;
+        let tangents = match &self.tangents {
+            Some(tangents) => tangents,
             None => {
-                for position in &self.positions {
-                    warn!("no normal found. compute default");
-                    vertices.push(Vertex {
-                        position: *position,
-                        normal: [0., 0., 1.],
-                        uvs: [0., 0.],
-                    });
-                }
+                computed_tangents = self.compute_tangents(normals);
+                &computed_tangents
             }
+        };
+
+        for ((position, normal), tangent) in self.positions.iter().zip(normals).zip(tangents) {
+            vertices.push(Vertex {
+                position: *position,
+                normal: *normal,
+                tangent: *tangent,
+                uvs: [0., 0.],
+                color: [1., 1., 1., 1.],
+                joints: [0; 4],
+                weights: [0.0; 4],
+            });
         }
 
         // The UVS part is a bit hacky. Note: the Vertex struct has a default and the UVs by default will be [0.0, 0.0]
@@ -188,10 +741,415 @@ impl MeshBuilder {
             }
         }
 
+        // White when the mesh has no vertex colors
+        if let Some(colors) = &self.colors {
+            for (vertex, color) in vertices.iter_mut().zip(colors) {
+                vertex.color = *color;
+            }
+        }
+
+        // Zero weights when not skinned: the vertex shader skips the joint palette
+        if let (Some(joints), Some(weights)) = (&self.joints, &self.weights) {
+            for ((vertex, joints), weights) in vertices.iter_mut().zip(joints).zip(weights) {
+                vertex.joints = *joints;
+                vertex.weights = *weights;
+            }
+        }
+
         Ok(vertices)
     }
 
-    pub fn indices(&self) -> Vec<u16> {
+    // Normals of a mesh without them. Indexed: smooth, the sum of the normals of the faces
+    // sharing the vertex, weighted by their area (the length of the cross product). Not
+    // indexed: each vertex belongs to a single triangle, so the same sum gives flat normals.
+    // Points and lines have no faces: they face the default +Z.
+    fn compute_normals(&self) -> Vec<[f32; 3]> {
+        const DEFAULT_NORMAL: [f32; 3] = [0., 0., 1.];
+
+        if self.topology != Topology::Triangles {
+            return vec![DEFAULT_NORMAL; self.positions.len()];
+        }
+
+        let indices = if self.indices.is_empty() {
+            (0..self.positions.len() as u32).collect()
+        } else {
+            self.indices.clone()
+        };
+
+        let mut sums = vec![Vector3::<f32>::zeros(); self.positions.len()];
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|index| index as usize);
+            // Out of range indices are left to the validation of the index buffer
+            let (Some(pa), Some(pb), Some(pc)) = (
+                self.positions.get(a),
+                self.positions.get(b),
+                self.positions.get(c),
+            ) else {
+                continue;
+            };
+
+            let [pa, pb, pc] = [pa, pb, pc].map(|position| Vector3::from(*position));
+            let face_normal = (pb - pa).cross(&(pc - pa));
+            for index in [a, b, c] {
+                sums[index] += face_normal;
+            }
+        }
+
+        sums.iter()
+            .map(|sum| {
+                sum.try_normalize(f32::EPSILON)
+                    .map_or(DEFAULT_NORMAL, Into::into)
+            })
+            .collect()
+    }
+
+    // Tangents of a mesh without them, for the normal maps: per triangle, the direction of
+    // increasing U on the surface (and of V for the handedness), summed per vertex like the
+    // normals, then made perpendicular to the normal (Gram-Schmidt). w is -1 when the UVs are
+    // mirrored. Without UVs (or faces) any direction perpendicular to the normal.
+    fn compute_tangents(&self, normals: &[[f32; 3]]) -> Vec<[f32; 4]> {
+        let mut u_sums = vec![Vector3::<f32>::zeros(); self.positions.len()];
+        let mut v_sums = vec![Vector3::<f32>::zeros(); self.positions.len()];
+
+        if let (Some(uvs), Topology::Triangles) = (&self.uvs, self.topology) {
+            let indices = if self.indices.is_empty() {
+                (0..self.positions.len() as u32).collect()
+            } else {
+                self.indices.clone()
+            };
+
+            for triangle in indices.chunks_exact(3) {
+                let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|index| index as usize);
+                let (Some(pa), Some(pb), Some(pc), Some(ta), Some(tb), Some(tc)) = (
+                    self.positions.get(a),
+                    self.positions.get(b),
+                    self.positions.get(c),
+                    uvs.get(a),
+                    uvs.get(b),
+                    uvs.get(c),
+                ) else {
+                    continue;
+                };
+
+                let [pa, pb, pc] = [pa, pb, pc].map(|position| Vector3::from(*position));
+                let (edge1, edge2) = (pb - pa, pc - pa);
+                let (du1, dv1) = (tb[0] - ta[0], tb[1] - ta[1]);
+                let (du2, dv2) = (tc[0] - ta[0], tc[1] - ta[1]);
+
+                // Degenerate UVs: no direction
+                let determinant = du1 * dv2 - du2 * dv1;
+                if determinant.abs() <= f32::EPSILON {
+                    continue;
+                }
+                let u_direction = (edge1 * dv2 - edge2 * dv1) / determinant;
+                let v_direction = (edge2 * du1 - edge1 * du2) / determinant;
+                for index in [a, b, c] {
+                    u_sums[index] += u_direction;
+                    v_sums[index] += v_direction;
+                }
+            }
+        }
+
+        normals
+            .iter()
+            .zip(u_sums.iter().zip(&v_sums))
+            .map(|(normal, (u_sum, v_sum))| {
+                let normal = Vector3::from(*normal);
+                let tangent = (u_sum - normal * normal.dot(u_sum))
+                    .try_normalize(f32::EPSILON)
+                    .unwrap_or_else(|| {
+                        let side = if normal.x.abs() < 0.9 {
+                            Vector3::x()
+                        } else {
+                            Vector3::y()
+                        };
+                        (side - normal * normal.dot(&side)).normalize()
+                    });
+                let handedness = if normal.cross(&tangent).dot(v_sum) < 0.0 {
+                    -1.0
+                } else {
+                    1.0
+                };
+
+                [tangent.x, tangent.y, tangent.z, handedness]
+            })
+            .collect()
+    }
+
+    pub fn indices(&self) -> Vec<u32> {
         self.indices.clone()
     }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+
+    // Base color texture for a model that has none, e.g. a PLY or an OBJ without MTL
+    pub fn with_default_texture(mut self, path: Option<&str>) -> Self {
+        if self.material.base_color_texture.is_none() {
+            self.material.base_color_texture =
+                path.map(|path| TextureSource::Path(path.to_string()));
+        }
+        self
+    }
+
+    pub fn topology(&self) -> Topology {
+        self.topology
+    }
+}
+
+// Every index points into the vertices: an out of range one would be fetched by the GPU
+fn check_indices(indices: &[u32], vertex_count: usize) -> Result<()> {
+    match indices
+        .iter()
+        .find(|index| **index as usize >= vertex_count)
+    {
+        Some(index) => Err(VisualSystemError::MeshIndexOutOfRange {
+            index: *index,
+            vertex_count,
+        }
+        .into()),
+        None => Ok(()),
+    }
+}
+
+// List indices drawing the primitives of `indices` in `mode`, the vertices in order when
+// `indices` is empty. Every other triangle of a strip is flipped to keep the winding.
+fn unroll_indices(mode: Mode, indices: Vec<u32>, vertex_count: usize) -> Vec<u32> {
+    let indices = match mode {
+        Mode::Points | Mode::Lines | Mode::Triangles => return indices,
+        _ if indices.is_empty() => (0..vertex_count as u32).collect(),
+        _ => indices,
+    };
+
+    match mode {
+        Mode::LineStrip => indices.windows(2).flatten().copied().collect(),
+        Mode::LineLoop if indices.len() >= 2 => indices
+            .iter()
+            .zip(indices.iter().cycle().skip(1))
+            .flat_map(|(&start, &end)| [start, end])
+            .collect(),
+        Mode::TriangleStrip => indices
+            .windows(3)
+            .enumerate()
+            .flat_map(|(i, triangle)| match i % 2 {
+                0 => [triangle[0], triangle[1], triangle[2]],
+                _ => [triangle[1], triangle[0], triangle[2]],
+            })
+            .collect(),
+        // glTF order: (i + 1, i + 2, 0)
+        Mode::TriangleFan => match indices.split_first() {
+            Some((&center, rest)) => rest
+                .windows(2)
+                .flat_map(|edge| [edge[0], edge[1], center])
+                .collect(),
+            None => Vec::new(),
+        },
+        _ => Vec::new(),
+    }
+}
+
+// Scalar PLY property as f32, whatever its type
+fn ply_float(element: &DefaultElement, name: &str) -> Option<f32> {
+    match element.get(name)? {
+        Property::Char(value) => Some(*value as f32),
+        Property::UChar(value) => Some(*value as f32),
+        Property::Short(value) => Some(*value as f32),
+        Property::UShort(value) => Some(*value as f32),
+        Property::Int(value) => Some(*value as f32),
+        Property::UInt(value) => Some(*value as f32),
+        Property::Float(value) => Some(*value),
+        Property::Double(value) => Some(*value as f32),
+        _ => None,
+    }
+}
+
+// Color channel in [0, 1]: integers are 0-255, floats are already normalized
+fn ply_color(element: &DefaultElement, name: &str) -> Option<f32> {
+    match element.get(name)? {
+        Property::Float(value) => Some(*value),
+        Property::Double(value) => Some(*value as f32),
+        _ => ply_float(element, name).map(|value| value / 255.0),
+    }
+}
+
+fn ply_indices(property: &Property) -> Option<Vec<u32>> {
+    match property {
+        Property::ListChar(list) => Some(list.iter().map(|idx| *idx as u32).collect()),
+        Property::ListUChar(list) => Some(list.iter().map(|idx| *idx as u32).collect()),
+        Property::ListShort(list) => Some(list.iter().map(|idx| *idx as u32).collect()),
+        Property::ListUShort(list) => Some(list.iter().map(|idx| *idx as u32).collect()),
+        Property::ListInt(list) => Some(list.iter().map(|idx| *idx as u32).collect()),
+        Property::ListUInt(list) => Some(list.clone()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    fn triangle_positions() -> Vec<[f32; 3]> {
+        vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]
+    }
+
+    #[test]
+    fn raw_triangle() {
+        let mesh = MeshBuilder::from_raw(
+            triangle_positions(),
+            Some(vec![[0.0, 0.0, 1.0]; 3]),
+            Some(vec![[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]]),
+            vec![0, 1, 2],
+        )
+        .unwrap();
+
+        assert_eq!(mesh.vertices().unwrap().len(), 3);
+        assert_eq!(mesh.indices(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn raw_attribute_length_mismatch() {
+        let normals = MeshBuilder::from_raw(
+            triangle_positions(),
+            Some(vec![[0.0, 0.0, 1.0]; 2]),
+            None,
+            vec![0, 1, 2],
+        );
+        assert!(matches!(
+            normals,
+            Err(Error::VisualSystem(
+                VisualSystemError::MeshAttributeLengthMismatch {
+                    attribute: "normals",
+                    expected: 3,
+                    actual: 2
+                }
+            ))
+        ));
+
+        let uvs = MeshBuilder::from_raw(triangle_positions(), None, Some(vec![]), vec![]);
+        assert!(uvs.is_err());
+    }
+
+    #[test]
+    fn raw_index_out_of_range() {
+        let mesh = MeshBuilder::from_raw(triangle_positions(), None, None, vec![0, 1, 3]);
+
+        assert!(matches!(
+            mesh,
+            Err(Error::VisualSystem(
+                VisualSystemError::MeshIndexOutOfRange {
+                    index: 3,
+                    vertex_count: 3
+                }
+            ))
+        ));
+    }
+
+    // Quad whose vertices 1 and 3 are replaced by a sparse accessor
+    #[test]
+    fn gltf_sparse_positions() {
+        let (meshes, skeleton) = MeshBuilder::read_gltf(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/assets/SparseQuad.gltf"
+        ))
+        .unwrap();
+
+        assert!(skeleton.is_none());
+
+        assert_eq!(meshes.len(), 1);
+        assert_eq!(
+            meshes[0].positions,
+            vec![
+                [0.0, 0.0, 0.0],
+                [5.0, 0.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [0.0, 7.0, 0.0]
+            ]
+        );
+        assert_eq!(meshes[0].indices(), vec![0, 1, 2, 0, 2, 3]);
+    }
+
+    #[test]
+    fn ply_quad() {
+        let mesh =
+            MeshBuilder::read_ply(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/Quad.ply")).unwrap();
+
+        assert_eq!(mesh.vertices().unwrap().len(), 4);
+        // The quad is fanned into 2 triangles
+        assert_eq!(mesh.indices(), vec![0, 1, 2, 0, 2, 3]);
+        assert_eq!(mesh.topology, Topology::Triangles);
+        assert_eq!(mesh.colors.as_ref().map(Vec::len), Some(4));
+    }
+
+    #[test]
+    fn ply_face_index_out_of_range() {
+        let mesh = MeshBuilder::read_ply(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/assets/QuadBadIndex.ply"
+        ));
+
+        assert!(matches!(
+            mesh,
+            Err(Error::VisualSystem(
+                VisualSystemError::MeshIndexOutOfRange {
+                    index: 7,
+                    vertex_count: 4
+                }
+            ))
+        ));
+    }
+
+    #[test]
+    fn ply_without_faces_is_a_point_cloud() {
+        let mesh = MeshBuilder::read_ply(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/Points.ply"))
+            .unwrap();
+
+        assert_eq!(mesh.positions.len(), 3);
+        assert!(mesh.indices().is_empty());
+        assert_eq!(mesh.topology, Topology::Points);
+    }
+
+    #[test]
+    fn supported_model_extensions() {
+        assert!(MeshBuilder::is_supported(Path::new("assets/Box.gltf")));
+        assert!(MeshBuilder::is_supported(Path::new("scan.PLY")));
+        assert!(!MeshBuilder::is_supported(Path::new("assets/font.png")));
+        assert!(!MeshBuilder::is_supported(Path::new("gltf")));
+    }
+
+    #[test]
+    fn strips_and_fans_are_unrolled() {
+        // A quad as a strip and as a fan, same counter-clockwise winding as the list
+        assert_eq!(
+            unroll_indices(Mode::TriangleStrip, vec![0, 1, 2, 3], 4),
+            vec![0, 1, 2, 2, 1, 3]
+        );
+        assert_eq!(
+            unroll_indices(Mode::TriangleFan, vec![0, 1, 2, 3], 4),
+            vec![1, 2, 0, 2, 3, 0]
+        );
+        // Not indexed: the vertices in order
+        assert_eq!(
+            unroll_indices(Mode::TriangleStrip, vec![], 3),
+            vec![0, 1, 2]
+        );
+        assert_eq!(
+            unroll_indices(Mode::Triangles, vec![2, 1, 0], 3),
+            vec![2, 1, 0]
+        );
+    }
+
+    #[test]
+    fn line_strips_and_loops_are_unrolled() {
+        assert_eq!(
+            unroll_indices(Mode::LineStrip, vec![4, 5, 6], 7),
+            vec![4, 5, 5, 6]
+        );
+        assert_eq!(
+            unroll_indices(Mode::LineLoop, vec![], 3),
+            vec![0, 1, 1, 2, 2, 0]
+        );
+        assert!(unroll_indices(Mode::LineLoop, vec![0], 1).is_empty());
+    }
 }
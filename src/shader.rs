@@ -29,22 +29,49 @@ pub mod vs {
                  layout(location = 5) in vec4 matrix3;
                  layout(location = 6) in vec4 matrix4;
 
+                layout(location = 7) in vec4 color; // glTF COLOR_0, white by default
+
+                // Per instance tint, linear
+                layout(location = 8) in vec4 instance_color;
+
+                // xyz along increasing U, w: handedness, bitangent = cross(normal, tangent) * w
+                layout(location = 9) in vec4 tangent;
+
+                // Skinning, see animation.rs. All weights 0 when the mesh has no skin.
+                layout(location = 10) in uvec4 joints;
+                layout(location = 11) in vec4 weights;
+
+                // Inverse transpose of the instance matrix, see InstanceRaw::normal1
+                layout(location = 12) in vec4 normal1;
+                layout(location = 13) in vec4 normal2;
+                layout(location = 14) in vec4 normal3;
 
                 layout(location = 0) out vec3 fragColor;
                 layout(location = 1) out vec3 out_normal;
                 layout(location = 2) out vec3 frag_pos;
                 layout(location = 3) out vec2 tex_coords;
+                layout(location = 4) out vec3 camera_pos;
+                layout(location = 5) out vec4 vertex_color;
+                layout(location = 6) out vec4 out_tangent; // world space, for a TBN matrix
 
-               // MVP 
+               // MVP. Set 0: one per window, each window has its own camera
                layout(set = 0, binding = 0) uniform MVP {
                     mat4 model;
                     mat4 view;
                     mat4 projection;
+                    mat3 normal_matrix; // inverse transpose of model, see Mvp
                } uniforms;
 
+                // Joint matrices of the frame, animation::MAX_JOINTS of them
+                layout(set = 0, binding = 1) readonly buffer JointMatrices {
+                    mat4 joint_matrices[];
+                };
+
                 // Use push constant for time. Time is available but no used.
                 layout(push_constant) uniform PushConstantData {
                     float time;
+                    float point_size; // point list meshes
+                    float spin;       // radians around the model Y axis, 0 when not spinning (R)
                 } pc;
 
                 // Matrix for the instances
@@ -55,24 +82,64 @@ pub mod vs {
                         matrix4
                 );
 
+                // Spin of the model itself, applied first: each instance turns in place
+                float c = cos(pc.spin);
+                float s = sin(pc.spin);
+                mat4 spin_matrix = mat4(
+                    c,   0.0, -s,  0.0,
+                    0.0, 1.0, 0.0, 0.0,
+                    s,   0.0, c,   0.0,
+                    0.0, 0.0, 0.0, 1.0
+                );
+
+                // The joints out of the palette keep their bind pose
+                mat4 joint_matrix(uint joint) {
+                    return joint < joint_matrices.length() ? joint_matrices[joint] : mat4(1.0);
+                }
+
                 void main() {
                    // Original gl_Position = vec4(position*sin(pc.time), 1.0);
 
+                   // Linear blend skinning, in model space before the other transforms
+                   mat4 skin_matrix = mat4(1.0);
+                   if (weights != vec4(0.0)) {
+                       skin_matrix = weights.x * joint_matrix(joints.x)
+                           + weights.y * joint_matrix(joints.y)
+                           + weights.z * joint_matrix(joints.z)
+                           + weights.w * joint_matrix(joints.w);
+                   }
+                   vec4 skinned_position = skin_matrix * vec4(position, 1.0);
+
                    // world view . Note: model aka local view
-                   mat4 worldview = uniforms.view * model_matrix * uniforms.model;
+                   mat4 worldview = uniforms.view * model_matrix * uniforms.model * spin_matrix;
                    
                    // Final coord with projection
-                   gl_Position = uniforms.projection * worldview  * vec4(position, 1.0);
+                   gl_Position = uniforms.projection * worldview  * skinned_position;
+                   gl_PointSize = pc.point_size;
                     //gl_Position =  vec4(position, 1.0);
 
                     // Rainbow effect
                     fragColor = position ;
 
-                    // Normal for the model
-                    out_normal = mat3(uniforms.model) * normal;
-                    frag_pos = vec3(uniforms.model * vec4(position, 1.0)); 
+                    // Normal and position in world space, instance transform included.
+                    // The normals go through the inverse transposes, correct under non-uniform
+                    // scale. Both are computed on the host: the instance one per instance, the
+                    // model one in the MVP. The spin is a rotation, its own inverse transpose.
+                    mat4 world = model_matrix * uniforms.model * spin_matrix;
+                    mat3 instance_normal_matrix = mat3(normal1.xyz, normal2.xyz, normal3.xyz);
+                    // The joints are taken as rigid: their upper 3x3 turns the normal as well.
+                    vec3 skinned_normal = mat3(skin_matrix) * normal;
+                    out_normal = instance_normal_matrix * uniforms.normal_matrix * mat3(spin_matrix) * skinned_normal;
+                    frag_pos = vec3(world * skinned_position);
+
+                    // The tangent lies on the surface: it follows the model matrices themselves
+                    out_tangent = vec4(mat3(world) * mat3(skin_matrix) * tangent.xyz, tangent.w);
+
+                    // Camera position from the (rigid) view matrix: -R^T * t
+                    camera_pos = -transpose(mat3(uniforms.view)) * uniforms.view[3].xyz;
 
                     tex_coords = uvs;           
+                    vertex_color = color * instance_color;
                 }
             ",
     }
@@ -88,44 +155,745 @@ pub mod fs {
                 layout(location = 1) in vec3 in_normal;
                 layout(location = 2) in vec3 frag_pos;
                 layout(location = 3) in vec2 tex_coords;
+                layout(location = 4) in vec3 camera_pos;
+                layout(location = 5) in vec4 vertex_color;
 
                 layout(location = 0) out vec4 outColor;
 
-                layout(set = 0, binding = 1) uniform AmbientLight {
+                // true when rendering to a UNORM swapchain: encode to sRGB here
+                layout(constant_id = 0) const bool ENCODE_SRGB = false;
+
+                // Set 1: one per mesh, lights and material
+                layout(set = 1, binding = 1) uniform AmbientLight {
                     vec3 color;
                     float intensity;
                 } ambient;
 
-                layout(set = 0, binding = 2) uniform DirectionalLight {
+                struct DirectionalLight {
+                    vec3 position;
+                    vec3 color;
+                };
+
+                // `directional_count` lights are used, see lighting::DirectionalLights
+                layout(set = 1, binding = 2) readonly buffer DirectionalLights {
+                    uint directional_count;
+                    DirectionalLight directional_lights[];
+                };
+
+                struct SpotLight {
                     vec3 position;
+                    float range;
+                    vec3 direction;
+                    float inner_cos;
                     vec3 color;
-                } directional;
+                    float outer_cos;
+                };
+
+                // `spot_count` lights are used, see lighting::SpotLights
+                layout(set = 1, binding = 8) readonly buffer SpotLights {
+                    uint spot_count;
+                    SpotLight spot_lights[];
+                };
 
-                layout(set = 0, binding = 3) uniform sampler2D tex;
-//                layout(set = 0, binding = 3) uniform sampler s;
+                // Material: glTF metallic-roughness
+                layout(set = 1, binding = 3) uniform sampler2D tex; // base color
+                layout(set = 1, binding = 4) uniform sampler2D metallic_roughness_tex;
+                layout(set = 1, binding = 5) uniform sampler2D occlusion_tex;
+                layout(set = 1, binding = 6) uniform sampler2D emissive_tex;
 
-//                layout(set = 0, binding = 4) uniform texture2D tex;
+                layout(set = 1, binding = 7) uniform Material {
+                    vec4 base_color;
+                    vec3 emissive;
+                    float metallic;
+                    float roughness;
+                    float occlusion_strength;
+                    float shininess; // > 0: Blinn-Phong, see material::MaterialFactors
+                    float specular_strength;
+                } material;
 
+                const float PI = 3.14159265359;
+
+                // Same curve as utils::linear_to_srgb
+                vec3 linear_to_srgb(vec3 linear) {
+                    vec3 higher = 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055;
+                    vec3 lower = linear * 12.92;
+                    return mix(higher, lower, lessThanEqual(linear, vec3(0.0031308)));
+                }
+
+                // Cook-Torrance BRDF terms
+                float distribution_ggx(float n_dot_h, float roughness) {
+                    float a = roughness * roughness;
+                    float a2 = a * a;
+                    float d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+                    return a2 / (PI * d * d);
+                }
+
+                float geometry_smith(float n_dot_v, float n_dot_l, float roughness) {
+                    float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+                    float g_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+                    float g_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+                    return g_v * g_l;
+                }
+
+                vec3 fresnel_schlick(float cos_theta, vec3 f0) {
+                    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+                }
+
+                // Blinn-Phong, normalized so the highlight keeps its energy as it narrows.
+                // Same scale as the metallic-roughness diffuse, the lights need no retuning.
+                vec3 shade_blinn_phong(vec3 n, vec3 h, float n_dot_l, vec3 base_color) {
+                    float n_dot_h = max(dot(n, h), 0.0);
+                    float normalization = (material.shininess + 8.0) / (8.0 * PI);
+                    float specular = material.specular_strength * normalization
+                        * pow(n_dot_h, material.shininess);
+                    return (base_color / PI + vec3(specular)) * n_dot_l;
+                }
+
+                // Reflected light for the light direction `l`, before the light color
+                vec3 shade(vec3 n, vec3 v, vec3 l, vec3 base_color, float metallic, float roughness) {
+                    vec3 h = normalize(v + l);
+
+                    if (material.shininess > 0.0) {
+                        return shade_blinn_phong(n, h, max(dot(n, l), 0.0), base_color);
+                    }
+
+                    float n_dot_v = max(dot(n, v), 0.0001);
+                    float n_dot_l = max(dot(n, l), 0.0);
+                    float n_dot_h = max(dot(n, h), 0.0);
+
+                    vec3 f0 = mix(vec3(0.04), base_color, metallic);
+                    vec3 fresnel = fresnel_schlick(max(dot(h, v), 0.0), f0);
+                    vec3 specular = distribution_ggx(n_dot_h, roughness)
+                        * geometry_smith(n_dot_v, n_dot_l, roughness) * fresnel
+                        / (4.0 * n_dot_v * max(n_dot_l, 0.0001));
+                    vec3 diffuse = (1.0 - fresnel) * (1.0 - metallic) * base_color / PI;
+                    return (diffuse + specular) * n_dot_l;
+                }
 
                 void main(){
+                    vec4 base_color = texture(tex, tex_coords) * material.base_color * vertex_color;
+                    // glTF: roughness in G, metallic in B
+                    vec4 metallic_roughness = texture(metallic_roughness_tex, tex_coords);
+                    float roughness = clamp(metallic_roughness.g * material.roughness, 0.04, 1.0);
+                    float metallic = clamp(metallic_roughness.b * material.metallic, 0.0, 1.0);
+                    float occlusion = mix(1.0, texture(occlusion_tex, tex_coords).r, material.occlusion_strength);
+                    vec3 emissive = texture(emissive_tex, tex_coords).rgb * material.emissive;
+
+                    vec3 n = normalize(in_normal);
+                    vec3 v = normalize(camera_pos - frag_pos);
+
+                    //  Directional Lights
+                    vec3 directional_color = vec3(0.0);
+                    for (uint i = 0; i < directional_count; i++) {
+                        vec3 l = normalize(directional_lights[i].position - frag_pos);
+                        directional_color += shade(n, v, l, base_color.rgb, metallic, roughness)
+                            * directional_lights[i].color;
+                    }
+
+                    // Spot Lights: smooth cone edge between the inner and the outer cosines,
+                    // windowed inverse square falloff reaching 0 at the range
+                    vec3 spot_color = vec3(0.0);
+                    for (uint i = 0; i < spot_count; i++) {
+                        vec3 to_light = spot_lights[i].position - frag_pos;
+                        float distance = length(to_light);
+                        vec3 l = to_light / max(distance, 0.0001);
+
+                        float cos_angle = dot(-l, normalize(spot_lights[i].direction));
+                        float cone = smoothstep(spot_lights[i].outer_cos, spot_lights[i].inner_cos, cos_angle);
+
+                        float ratio = distance / max(spot_lights[i].range, 0.0001);
+                        float window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+                        float falloff = window * window / max(distance * distance, 0.01);
+
+                        spot_color += shade(n, v, l, base_color.rgb, metallic, roughness)
+                            * spot_lights[i].color * cone * falloff;
+                    }
+
                     // Ambient Light
-                    vec3 ambient_color = ambient.intensity * ambient.color;
+                    vec3 ambient_color = ambient.intensity * ambient.color * base_color.rgb * occlusion;
+
+                    outColor = vec4(ambient_color + directional_color + spot_color + emissive, base_color.a);
 
-                    //  Directional Light
-                    vec3 light_direction = normalize(directional.position - frag_pos);
-                    float directional_intensity = max(dot(in_normal, light_direction), 0.0);
-                    vec3 directional_color = directional_intensity * directional.color;
+                    if (ENCODE_SRGB) {
+                        outColor.rgb = linear_to_srgb(outColor.rgb);
+                    }
+                }
+            ",
+    }
+}
+
+// Emissive only (glow) fragment shader: unlit base color plus emissive, no lighting.
+// Drawn with additive blending. Same bindings as `fs`, so it shares its pipeline layout.
+pub mod fs_emissive {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 3) in vec2 tex_coords;
+                layout(location = 5) in vec4 vertex_color;
+
+                layout(location = 0) out vec4 outColor;
+
+                layout(constant_id = 0) const bool ENCODE_SRGB = false;
+
+                layout(set = 1, binding = 3) uniform sampler2D tex; // base color
+                layout(set = 1, binding = 6) uniform sampler2D emissive_tex;
+
+                layout(set = 1, binding = 7) uniform Material {
+                    vec4 base_color;
+                    vec3 emissive;
+                    float metallic;
+                    float roughness;
+                    float occlusion_strength;
+                    float shininess; // > 0: Blinn-Phong, see material::MaterialFactors
+                    float specular_strength;
+                } material;
+
+                vec3 linear_to_srgb(vec3 linear) {
+                    vec3 higher = 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055;
+                    vec3 lower = linear * 12.92;
+                    return mix(higher, lower, lessThanEqual(linear, vec3(0.0031308)));
+                }
+
+                void main(){
+                    vec4 base_color = texture(tex, tex_coords) * material.base_color * vertex_color;
+                    vec3 emissive = texture(emissive_tex, tex_coords).rgb * material.emissive;
+
+                    // Alpha only scales the glow: the blending is one/one
+                    outColor = vec4(base_color.rgb * base_color.a + emissive, 1.0);
+
+                    if (ENCODE_SRGB) {
+                        outColor.rgb = linear_to_srgb(outColor.rgb);
+                    }
+                }
+            ",
+    }
+}
+
+// Wireframe overlay: flat color lines drawn over the shaded mesh
+pub mod fs_wireframe {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) out vec4 outColor;
+
+                layout(constant_id = 0) const bool ENCODE_SRGB = false;
+
+                void main(){
+                    // Dark gray, the same after sRGB encoding on a UNORM swapchain
+                    outColor = vec4(vec3(ENCODE_SRGB ? 0.1 : 0.01), 1.0);
+                }
+            ",
+    }
+}
 
-                    // Combined Ambient Light and directional Light
-                    vec3 combined_color = (ambient_color + directional_color)  * fragColor;
+// Compute shader updating the instance transforms on the GPU.
+// Reads the initial grid from `base` and writes the spinning result into `instances`,
+// which is the same buffer bound as per-instance vertex input in the graphics pipeline.
+pub mod cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src: r"
+                #version 460
+
+                layout(local_size_x = 64, local_size_y = 1, local_size_z = 1) in;
+
+                // Same layout as InstanceRaw (8 x vec4)
+                struct InstanceData {
+                    vec4 matrix1;
+                    vec4 matrix2;
+                    vec4 matrix3;
+                    vec4 matrix4;
+                    vec4 instance_color;
+                    vec4 normal1;
+                    vec4 normal2;
+                    vec4 normal3;
+                };
+
+                layout(set = 0, binding = 0) readonly buffer BaseInstances {
+                    InstanceData data[];
+                } base;
+
+                layout(set = 0, binding = 1) buffer Instances {
+                    InstanceData data[];
+                } instances;
+
+                layout(push_constant) uniform PushConstantData {
+                    float time;
+                    uint count;
+                } pc;
+
+                void main() {
+                    uint idx = gl_GlobalInvocationID.x;
+                    if (idx >= pc.count) {
+                        return;
+                    }
+
+                    InstanceData src = base.data[idx];
+                    mat4 model_matrix = mat4(src.matrix1, src.matrix2, src.matrix3, src.matrix4);
+
+                    // Spin each instance around its local Y axis
+                    float c = cos(pc.time);
+                    float s = sin(pc.time);
+                    mat4 spin = mat4(
+                        c,   0.0, -s,  0.0,
+                        0.0, 1.0, 0.0, 0.0,
+                        s,   0.0, c,   0.0,
+                        0.0, 0.0, 0.0, 1.0
+                    );
+
+                    mat4 result = model_matrix * spin;
+
+                    instances.data[idx].matrix1 = result[0];
+                    instances.data[idx].matrix2 = result[1];
+                    instances.data[idx].matrix3 = result[2];
+                    instances.data[idx].matrix4 = result[3];
+                    instances.data[idx].instance_color = src.instance_color;
+
+                    // The spin is a rotation: the host computed normal matrix only turns with it
+                    mat3 normal_matrix = mat3(src.normal1.xyz, src.normal2.xyz, src.normal3.xyz)
+                        * mat3(spin);
+                    instances.data[idx].normal1 = vec4(normal_matrix[0], 0.0);
+                    instances.data[idx].normal2 = vec4(normal_matrix[1], 0.0);
+                    instances.data[idx].normal3 = vec4(normal_matrix[2], 0.0);
+                }
+            ",
+    }
+}
+
+// Fullscreen triangle for the post-processing passes (depth view, bloom).
+// No vertex buffer, the triangle is generated from gl_VertexIndex.
+pub mod fullscreen_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: r"
+                #version 460
+
+                layout(location = 0) out vec2 uv;
+
+                void main() {
+                    uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+                    gl_Position = vec4(uv * 2.0 - 1.0, 0.0, 1.0);
+                }
+            ",
+    }
+}
+
+// Depth buffer visualization: the linearized depth as grayscale
+
+pub mod depth_view_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) out vec4 outColor;
+
+                // Multisampled depth attachment of the main pass
+                layout(set = 0, binding = 0) uniform texture2DMS depth_tex;
+
+                // Camera clip planes and projection
+                layout(push_constant) uniform PushConstantData {
+                    float near;
+                    float far;
+                    uint orthographic; // 1: the depth is already linear
+                } pc;
+
+                void main() {
+                    float depth = texelFetch(depth_tex, ivec2(gl_FragCoord.xy), 0).r;
+
+                    // Orthographic: [0, 1] from near to far already
+                    if (pc.orthographic != 0) {
+                        outColor = vec4(vec3(depth), 1.0);
+                        return;
+                    }
+
+                    // Back to NDC [-1, 1], then undo the perspective divide
+                    float z = depth * 2.0 - 1.0;
+                    float linear_depth = 2.0 * pc.near * pc.far / (pc.far + pc.near - z * (pc.far - pc.near));
+
+                    float gray = clamp((linear_depth - pc.near) / (pc.far - pc.near), 0.0, 1.0);
+                    outColor = vec4(vec3(gray), 1.0);
+                }
+            ",
+    }
+}
+
+// Same for a single sample depth attachment (MSAA off)
+pub mod depth_view_single_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) out vec4 outColor;
+
+                // Single sample depth attachment of the main pass
+                layout(set = 0, binding = 0) uniform texture2D depth_tex;
+
+                // Camera clip planes and projection
+                layout(push_constant) uniform PushConstantData {
+                    float near;
+                    float far;
+                    uint orthographic; // 1: the depth is already linear
+                } pc;
+
+                void main() {
+                    float depth = texelFetch(depth_tex, ivec2(gl_FragCoord.xy), 0).r;
+
+                    // Orthographic: [0, 1] from near to far already
+                    if (pc.orthographic != 0) {
+                        outColor = vec4(vec3(depth), 1.0);
+                        return;
+                    }
+
+                    // Back to NDC [-1, 1], then undo the perspective divide
+                    float z = depth * 2.0 - 1.0;
+                    float linear_depth = 2.0 * pc.near * pc.far / (pc.far + pc.near - z * (pc.far - pc.near));
+
+                    float gray = clamp((linear_depth - pc.near) / (pc.far - pc.near), 0.0, 1.0);
+                    outColor = vec4(vec3(gray), 1.0);
+                }
+            ",
+    }
+}
+
+// Bloom: bright pass. Keeps the part of the color above the threshold.
+pub mod bloom_bright_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
 
-                    // Final color output
-                   vec4   outColorL = vec4((ambient_color + directional_color), 1.0);
-                    //outColor = vec4(fragColor, 1.0);
+                layout(location = 0) in vec2 uv;
+                layout(location = 0) out vec4 outColor;
+
+                layout(set = 0, binding = 0) uniform sampler2D scene;
+
+                layout(push_constant) uniform PushConstantData {
+                    float threshold;
+                } pc;
+
+                void main() {
+                    vec3 color = texture(scene, uv).rgb;
+                    float brightness = max(color.r, max(color.g, color.b));
+                    float contribution = max(brightness - pc.threshold, 0.0) / max(brightness, 0.0001);
+                    outColor = vec4(color * contribution, 1.0);
+                }
+            ",
+    }
+}
+
+// Bloom: 3x3 gaussian blur, sampling the previous (twice as big) level
+pub mod bloom_blur_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec2 uv;
+                layout(location = 0) out vec4 outColor;
+
+                layout(set = 0, binding = 0) uniform sampler2D source;
+
+                void main() {
+                    vec2 texel = 1.0 / vec2(textureSize(source, 0));
+
+                    vec3 sum = texture(source, uv).rgb * 4.0;
+                    sum += texture(source, uv + vec2(texel.x, 0.0)).rgb * 2.0;
+                    sum += texture(source, uv - vec2(texel.x, 0.0)).rgb * 2.0;
+                    sum += texture(source, uv + vec2(0.0, texel.y)).rgb * 2.0;
+                    sum += texture(source, uv - vec2(0.0, texel.y)).rgb * 2.0;
+                    sum += texture(source, uv + texel).rgb;
+                    sum += texture(source, uv - texel).rgb;
+                    sum += texture(source, uv + vec2(texel.x, -texel.y)).rgb;
+                    sum += texture(source, uv + vec2(-texel.x, texel.y)).rgb;
+
+                    outColor = vec4(sum / 16.0, 1.0);
+                }
+            ",
+    }
+}
+
+// Bloom: scene plus the blurred levels, written to the swapchain image
+pub mod bloom_composite_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec2 uv;
+                layout(location = 0) out vec4 outColor;
+
+                layout(set = 0, binding = 0) uniform sampler2D scene;
+                layout(set = 0, binding = 1) uniform sampler2D bloom0;
+                layout(set = 0, binding = 2) uniform sampler2D bloom1;
+                layout(set = 0, binding = 3) uniform sampler2D bloom2;
+                layout(set = 0, binding = 4) uniform sampler2D bloom3;
+
+                layout(push_constant) uniform PushConstantData {
+                    float intensity;
+                } pc;
+
+                void main() {
+                    vec3 bloom = texture(bloom0, uv).rgb + texture(bloom1, uv).rgb
+                        + texture(bloom2, uv).rgb + texture(bloom3, uv).rgb;
+
+                    outColor = vec4(texture(scene, uv).rgb + pc.intensity * bloom / 4.0, 1.0);
+                }
+            ",
+    }
+}
+
+// Reference grid on the XZ plane, see grid.rs. Fullscreen triangle: each pixel casts a ray
+// between the near and the far plane, the grid is where the ray crosses y = 0.
+pub mod grid_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: r"
+                #version 460
+
+                layout(location = 0) out vec3 near_point; // world space, on the near plane
+                layout(location = 1) out vec3 far_point;
+
+                layout(push_constant) uniform PushConstantData {
+                    mat4 view_projection;
+                    float fade_distance; // the grid fades out up to this distance from the eye
+                } pc;
+
+                vec3 unproject(vec2 ndc, float depth) {
+                    vec4 point = inverse(pc.view_projection) * vec4(ndc, depth, 1.0);
+                    return point.xyz / point.w;
+                }
+
+                void main() {
+                    vec2 uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+                    vec2 ndc = uv * 2.0 - 1.0;
+
+                    near_point = unproject(ndc, 0.0);
+                    far_point = unproject(ndc, 1.0);
+                    gl_Position = vec4(ndc, 0.0, 1.0);
+                }
+            ",
+    }
+}
+
+// Grid lines every unit and every 10 units, antialiased with the screen space derivatives.
+// The X axis is red, the Z axis blue. The depth is the one of the plane: the meshes hide it.
+pub mod grid_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec3 near_point;
+                layout(location = 1) in vec3 far_point;
+
+                layout(location = 0) out vec4 outColor;
+
+                layout(constant_id = 0) const bool ENCODE_SRGB = false;
+
+                layout(push_constant) uniform PushConstantData {
+                    mat4 view_projection;
+                    float fade_distance;
+                } pc;
+
+                vec3 linear_to_srgb(vec3 linear) {
+                    vec3 higher = 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055;
+                    vec3 lower = linear * 12.92;
+                    return mix(higher, lower, lessThanEqual(linear, vec3(0.0031308)));
+                }
+
+                // 1 on a line of the `cell` sized grid, about one pixel wide
+                float grid_line(vec2 position, float cell) {
+                    vec2 coord = position / cell;
+                    vec2 distance = abs(fract(coord - 0.5) - 0.5) / fwidth(coord);
+                    return 1.0 - min(min(distance.x, distance.y), 1.0);
+                }
+
+                void main() {
+                    vec3 ray = far_point - near_point;
+                    // Parallel to the plane
+                    if (abs(ray.y) < 1e-6) {
+                        discard;
+                    }
+                    // The plane is behind the eye
+                    float t = -near_point.y / ray.y;
+                    if (t < 0.0) {
+                        discard;
+                    }
+                    vec3 position = near_point + t * ray;
+
+                    vec4 clip = pc.view_projection * vec4(position, 1.0);
+                    float depth = clip.z / clip.w;
+                    if (depth < 0.0 || depth > 1.0) {
+                        discard;
+                    }
+                    gl_FragDepth = depth;
+
+                    float minor = grid_line(position.xz, 1.0);
+                    float major = grid_line(position.xz, 10.0);
+                    vec4 color = vec4(vec3(0.3), 0.35 * max(minor, major * 1.5));
+
+                    // The axes, over the lines
+                    vec2 axis_width = fwidth(position.xz);
+                    if (abs(position.z) < axis_width.y) {
+                        color = vec4(0.8, 0.1, 0.1, 0.9); // X
+                    }
+                    if (abs(position.x) < axis_width.x) {
+                        color = vec4(0.1, 0.1, 0.8, 0.9); // Z
+                    }
+
+                    float fade = 1.0 - clamp(length(position - near_point) / pc.fade_distance, 0.0, 1.0);
+                    color.a *= fade * fade;
+                    if (color.a <= 0.0) {
+                        discard;
+                    }
+
+                    outColor = color;
+                    if (ENCODE_SRGB) {
+                        outColor.rgb = linear_to_srgb(outColor.rgb);
+                    }
+                }
+            ",
+    }
+}
+
+// Screen space text: one quad per glyph instance, see text.rs.
+// The quad corners are generated from gl_VertexIndex (triangle strip, 4 vertices).
+pub mod text_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec2 position; // top left, in pixels
+                layout(location = 1) in vec2 size;     // in pixels
+                layout(location = 2) in vec2 uv_offset;
+                layout(location = 3) in vec2 uv_size;
+                layout(location = 4) in vec4 color;
+
+                layout(location = 0) out vec2 uv;
+                layout(location = 1) out vec4 glyph_color;
+
+                // Orthographic projection: pixels to clip space
+                layout(push_constant) uniform PushConstantData {
+                    mat4 projection;
+                } pc;
+
+                void main() {
+                    vec2 corner = vec2(gl_VertexIndex & 1, gl_VertexIndex >> 1);
+
+                    gl_Position = pc.projection * vec4(position + corner * size, 0.0, 1.0);
+                    uv = uv_offset + corner * uv_size;
+                    glyph_color = color;
+                }
+            ",
+    }
+}
+
+// Glyph coverage from the font atlas alpha, times the text color
+pub mod text_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec2 uv;
+                layout(location = 1) in vec4 glyph_color;
+
+                layout(location = 0) out vec4 outColor;
+
+                layout(constant_id = 0) const bool ENCODE_SRGB = false;
+
+                layout(set = 0, binding = 0) uniform sampler2D atlas;
+
+                // Same curve as utils::linear_to_srgb
+                vec3 linear_to_srgb(vec3 linear) {
+                    vec3 higher = 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055;
+                    vec3 lower = linear * 12.92;
+                    return mix(higher, lower, lessThanEqual(linear, vec3(0.0031308)));
+                }
+
+                void main() {
+                    outColor = vec4(glyph_color.rgb, glyph_color.a * texture(atlas, uv).a);
+
+                    if (ENCODE_SRGB) {
+                        outColor.rgb = linear_to_srgb(outColor.rgb);
+                    }
+                }
+            ",
+    }
+}
+
+// egui meshes: positions in points, from the top left corner
+pub mod gui_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec2 position; // in points
+                layout(location = 1) in vec2 uv;
+                layout(location = 2) in vec4 color;    // sRGB, premultiplied alpha
+
+                layout(location = 0) out vec2 frag_uv;
+                layout(location = 1) out vec4 vertex_color;
+
+                layout(push_constant) uniform PushConstantData {
+                    vec2 screen_size; // in points
+                } pc;
+
+                void main() {
+                    gl_Position = vec4(2.0 * position / pc.screen_size - 1.0, 0.0, 1.0);
+                    frag_uv = uv;
+                    vertex_color = color;
+                }
+            ",
+    }
+}
+
+// egui colors are premultiplied sRGB. The textures are _SRGB, sampled as linear.
+pub mod gui_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+                #version 460
+
+                layout(location = 0) in vec2 frag_uv;
+                layout(location = 1) in vec4 vertex_color;
+
+                layout(location = 0) out vec4 outColor;
+
+                layout(constant_id = 0) const bool ENCODE_SRGB = false;
+
+                layout(set = 0, binding = 0) uniform sampler2D ui_texture;
+
+                // Same curve as utils::linear_to_srgb, and its inverse
+                vec3 linear_to_srgb(vec3 linear) {
+                    vec3 higher = 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055;
+                    vec3 lower = linear * 12.92;
+                    return mix(higher, lower, lessThanEqual(linear, vec3(0.0031308)));
+                }
+
+                vec3 srgb_to_linear(vec3 srgb) {
+                    vec3 higher = pow((srgb + 0.055) / 1.055, vec3(2.4));
+                    vec3 lower = srgb / 12.92;
+                    return mix(higher, lower, lessThanEqual(srgb, vec3(0.04045)));
+                }
+
+                void main() {
+                    vec4 texel = texture(ui_texture, frag_uv);
 
-                    vec4 outColorT = texture(tex,  tex_coords);
-//                    outColort = texture(sampler2D(tex, s), tex_coords);
-                    outColor = outColorT * outColorL;
+                    if (ENCODE_SRGB) {
+                        // UNORM target: blend in gamma space, as egui expects
+                        outColor = vertex_color * vec4(linear_to_srgb(texel.rgb), texel.a);
+                    } else {
+                        outColor = vec4(srgb_to_linear(vertex_color.rgb), vertex_color.a) * texel;
+                    }
                 }
             ",
     }
@@ -138,6 +906,14 @@ pub struct Vertex {
     pub position: [f32; 3],
     #[format(R32G32B32_SFLOAT)]
     pub normal: [f32; 3],
+    #[format(R32G32B32A32_SFLOAT)]
+    pub tangent: [f32; 4], // w: handedness of the bitangent, see MeshBuilder::compute_tangents
     #[format(R32G32_SFLOAT)]
     pub uvs: [f32; 2],
+    #[format(R32G32B32A32_SFLOAT)]
+    pub color: [f32; 4],
+    #[format(R16G16B16A16_UINT)]
+    pub joints: [u16; 4], // into the joint palette, see animation.rs
+    #[format(R32G32B32A32_SFLOAT)]
+    pub weights: [f32; 4], // all 0 for a mesh without skin
 }
@@ -0,0 +1,61 @@
+// Note: shader hot reload
+//
+// `F5` reads the SPIR-V of the main pass from `shaders/vs.spv` and `shaders/fs.spv` (e.g.
+// `glslc shader.frag -o shaders/fs.spv`) and rebuilds the graphics pipelines with them.
+// A missing file keeps the shader compiled in shader.rs. The shaders must match the interface
+// of the built-in ones (vertex inputs, descriptor sets, push constants): the pipelines keep
+// their layout.
+
+use std::{fs, path::Path, sync::Arc};
+
+use vulkano::{
+    device::Device,
+    shader::{spirv::bytes_to_words, ShaderModule, ShaderModuleCreateInfo},
+};
+
+use crate::error::{Result, VisualSystemError};
+
+pub const SHADER_DIR: &str = "shaders";
+
+// Replacements of shader::vs and shader::fs, None for the built-in ones
+#[derive(Clone, Default)]
+pub struct MainShaders {
+    pub vertex: Option<Arc<ShaderModule>>,
+    pub fragment: Option<Arc<ShaderModule>>,
+}
+
+impl MainShaders {
+    pub fn load(device: &Arc<Device>, dir: &Path) -> Result<Self> {
+        Ok(Self {
+            vertex: load_module(device, &dir.join("vs.spv"))?,
+            fragment: load_module(device, &dir.join("fs.spv"))?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertex.is_none() && self.fragment.is_none()
+    }
+}
+
+fn load_module(device: &Arc<Device>, path: &Path) -> Result<Option<Arc<ShaderModule>>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let invalid =
+        |reason: &str| VisualSystemError::InvalidShader(path.display().to_string(), reason.into());
+
+    let bytes = fs::read(path)?;
+    let words = bytes_to_words(&bytes).map_err(|_| invalid("size not a multiple of 4 bytes"))?;
+
+    // Safety: the SPIR-V comes from a compiler (glslc, glslangValidator), vulkano checks its
+    // header and parses it, the pipeline creation checks its interface
+    let module =
+        unsafe { ShaderModule::new(Arc::clone(device), ShaderModuleCreateInfo::new(&words)) }?;
+
+    if module.entry_point("main").is_none() {
+        return Err(invalid("no main entry point").into());
+    }
+
+    Ok(Some(module))
+}
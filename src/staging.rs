@@ -0,0 +1,112 @@
+// Note: staging uploads
+//
+// The data goes into a host visible staging buffer, and a copy command recorded in the caller's
+// command buffer moves it into device local memory. The caller submits the command buffer (on
+// the graphics or the transfer queue) and waits for it, or joins its future, before the
+// destination is used. The staging buffer is kept alive by the command buffer.
+
+use std::sync::Arc;
+
+use vulkano::{
+    buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, CopyBufferInfo,
+        PrimaryAutoCommandBuffer,
+    },
+    memory::{
+        allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+        MemoryPropertyFlags,
+    },
+    DeviceSize,
+};
+
+use crate::{error::Result, utils::shared_buffer_create_info};
+
+// Host visible source of a copy to a buffer or an image, written once
+pub fn staging_buffer<T, I>(
+    memory_allocator: &Arc<StandardMemoryAllocator>,
+    data: I,
+) -> Result<Subbuffer<[T]>>
+where
+    T: BufferContents,
+    I: IntoIterator<Item = T>,
+    I::IntoIter: ExactSizeIterator,
+{
+    let staging_buffer = Buffer::from_iter(
+        memory_allocator.clone(),
+        BufferCreateInfo {
+            usage: BufferUsage::TRANSFER_SRC,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        },
+        data,
+    )?;
+
+    Ok(staging_buffer)
+}
+
+// Copy `data` into the start of an existing buffer, created with TRANSFER_DST
+pub fn upload_to_buffer<T, I>(
+    data: I,
+    destination: Subbuffer<[T]>,
+    memory_allocator: &Arc<StandardMemoryAllocator>,
+    command_builder: &mut AutoCommandBufferBuilder<
+        PrimaryAutoCommandBuffer<Arc<StandardCommandBufferAllocator>>,
+        Arc<StandardCommandBufferAllocator>,
+    >,
+) -> Result<()>
+where
+    T: BufferContents,
+    I: IntoIterator<Item = T>,
+    I::IntoIter: ExactSizeIterator,
+{
+    let staging_buffer = staging_buffer(memory_allocator, data)?;
+    command_builder.copy_buffer(CopyBufferInfo::buffers(staging_buffer, destination))?;
+
+    Ok(())
+}
+
+// New device local buffer holding `data`, for `usage` (TRANSFER_DST is added).
+// `queue_family_indices`: the families using it, see shared_buffer_create_info. Empty `data` is
+// not allowed: a buffer can't be empty.
+pub fn upload_to_device_buffer<T, I>(
+    data: I,
+    usage: BufferUsage,
+    queue_family_indices: &[u32],
+    memory_allocator: &Arc<StandardMemoryAllocator>,
+    command_builder: &mut AutoCommandBufferBuilder<
+        PrimaryAutoCommandBuffer<Arc<StandardCommandBufferAllocator>>,
+        Arc<StandardCommandBufferAllocator>,
+    >,
+) -> Result<Subbuffer<[T]>>
+where
+    T: BufferContents,
+    I: IntoIterator<Item = T>,
+    I::IntoIter: ExactSizeIterator,
+{
+    let staging_buffer = staging_buffer(memory_allocator, data)?;
+
+    let device_buffer = Buffer::new_slice(
+        memory_allocator.clone(),
+        shared_buffer_create_info(usage | BufferUsage::TRANSFER_DST, queue_family_indices),
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter {
+                required_flags: MemoryPropertyFlags::DEVICE_LOCAL, // Make sure this buffer is on the Device=GPU
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        staging_buffer.len() as DeviceSize,
+    )?;
+
+    command_builder.copy_buffer(CopyBufferInfo::buffers(
+        staging_buffer,
+        device_buffer.clone(),
+    ))?;
+
+    Ok(device_buffer)
+}
@@ -0,0 +1,330 @@
+// Note: Text
+//
+// Minimal screen space text for FPS / debug info, without a UI crate.
+// The font is a bitmap atlas: a 16 x 6 grid of glyphs, ASCII 32 (space) to 127, white on
+// transparent. assets/font.png is DejaVu Sans Mono rasterized at 16 x 32 pixels per glyph.
+//
+// draw_text queues one quad instance per glyph, in pixels from the top left corner.
+// record draws the queued glyphs over the final image (after bloom and the depth view) with
+// alpha blending and an orthographic projection, in its own single sample pass without depth.
+
+use std::sync::Arc;
+
+use nalgebra::Orthographic3;
+use vulkano::{
+    buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage},
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, CommandBufferUsage,
+        PrimaryAutoCommandBuffer, RenderingAttachmentInfo, RenderingInfo,
+    },
+    descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet},
+    format::Format,
+    image::{
+        sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+        view::ImageView,
+    },
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter},
+    pipeline::{
+        graphics::{
+            color_blend::{AttachmentBlend, ColorBlendAttachmentState, ColorBlendState},
+            input_assembly::{InputAssemblyState, PrimitiveTopology},
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            subpass::PipelineRenderingCreateInfo,
+            vertex_input::{Vertex, VertexDefinition},
+            viewport::{Viewport, ViewportState},
+            GraphicsPipelineCreateInfo,
+        },
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+        DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+    render_pass::{AttachmentLoadOp, AttachmentStoreOp},
+    sync::{self, GpuFuture},
+};
+
+use crate::{
+    error::Result,
+    shader::{text_fs, text_vs},
+    textures::create_texture_from_bytes,
+    vulkan_device::VulkanDevice,
+};
+
+// Embedded: the binary can start from any directory
+pub const FONT_ATLAS: &[u8] = include_bytes!("../assets/font.png");
+
+// Layout of the atlas
+const ATLAS_COLUMNS: u32 = 16;
+const ATLAS_ROWS: u32 = 6;
+const FIRST_CHAR: u8 = b' ';
+const LAST_CHAR: u8 = 127;
+
+// One glyph quad. Per instance vertex input of text_vs.
+#[derive(Copy, Clone, BufferContents, Vertex)]
+#[repr(C)]
+pub struct GlyphInstance {
+    #[format(R32G32_SFLOAT)]
+    pub position: [f32; 2], // top left, in pixels
+    #[format(R32G32_SFLOAT)]
+    pub size: [f32; 2], // in pixels
+    #[format(R32G32_SFLOAT)]
+    pub uv_offset: [f32; 2],
+    #[format(R32G32_SFLOAT)]
+    pub uv_size: [f32; 2],
+    #[format(R32G32B32A32_SFLOAT)]
+    pub color: [f32; 4], // linear
+}
+
+pub struct TextRenderer {
+    pipeline: Arc<GraphicsPipeline>,
+    descriptor_set: Arc<PersistentDescriptorSet>,
+    glyph_size: [f32; 2],       // in pixels, at scale 1
+    glyphs: Vec<GlyphInstance>, // queued until the next record
+    pub color: [f32; 4],
+}
+
+impl TextRenderer {
+    // `font_atlas`: PNG bytes, see FONT_ATLAS
+    pub fn new(vulkan_device: &VulkanDevice, font_atlas: &[u8]) -> Result<Self> {
+        let device = vulkan_device.queue().device();
+
+        // Upload the atlas
+        let mut command_builder = AutoCommandBufferBuilder::primary(
+            vulkan_device.command_allocator(),
+            vulkan_device.queue().queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )?;
+
+        // UNORM: only the alpha (coverage) is used
+        let atlas = create_texture_from_bytes(
+            font_atlas,
+            Format::R8G8B8A8_UNORM,
+            false,
+            &mut command_builder,
+            vulkan_device.memory_allocator.clone(),
+            vulkan_device.limits(),
+        )?;
+
+        sync::now(Arc::clone(device))
+            .then_execute(Arc::clone(vulkan_device.queue()), command_builder.build()?)?
+            .then_signal_fence_and_flush()?
+            .wait(None)?;
+
+        let extent = atlas.image().extent();
+        let glyph_size = [
+            (extent[0] / ATLAS_COLUMNS) as f32,
+            (extent[1] / ATLAS_ROWS) as f32,
+        ];
+
+        let pipeline = create_text_pipeline(vulkan_device)?;
+
+        let sampler = Sampler::new(
+            Arc::clone(device),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )?;
+
+        let descriptor_set = PersistentDescriptorSet::new(
+            vulkan_device.descriptor_set_allocator(),
+            Arc::clone(
+                pipeline
+                    .layout()
+                    .set_layouts()
+                    .first()
+                    .expect("error getting the text layout"),
+            ),
+            [WriteDescriptorSet::image_view_sampler(0, atlas, sampler)],
+            [],
+        )?;
+
+        Ok(Self {
+            pipeline,
+            descriptor_set,
+            glyph_size,
+            glyphs: Vec::new(),
+            color: [1.0; 4],
+        })
+    }
+
+    // Queue `text` for the next frame. (x, y) is the top left corner in pixels,
+    // `scale` 1.0 draws the glyphs at the atlas size. '\n' starts a new line.
+    pub fn draw_text(&mut self, text: &str, x: f32, y: f32, scale: f32) {
+        let size = [self.glyph_size[0] * scale, self.glyph_size[1] * scale];
+        let uv_size = [1.0 / ATLAS_COLUMNS as f32, 1.0 / ATLAS_ROWS as f32];
+
+        let mut position = [x, y];
+        for char in text.chars() {
+            if char == '\n' {
+                position = [x, position[1] + size[1]];
+                continue;
+            }
+
+            // Outside of the atlas
+            let code = u8::try_from(char)
+                .ok()
+                .filter(|code| (FIRST_CHAR..=LAST_CHAR).contains(code))
+                .unwrap_or(b'?');
+
+            if code != b' ' {
+                let idx = (code - FIRST_CHAR) as u32;
+                self.glyphs.push(GlyphInstance {
+                    position,
+                    size,
+                    uv_offset: [
+                        (idx % ATLAS_COLUMNS) as f32 * uv_size[0],
+                        (idx / ATLAS_COLUMNS) as f32 * uv_size[1],
+                    ],
+                    uv_size,
+                    color: self.color,
+                });
+            }
+
+            position[0] += size[0];
+        }
+    }
+
+    // Draw the queued glyphs over `target`, then clear the queue
+    pub fn record(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<
+            PrimaryAutoCommandBuffer<Arc<StandardCommandBufferAllocator>>,
+            Arc<StandardCommandBufferAllocator>,
+        >,
+        vulkan_device: &VulkanDevice,
+        target: Arc<ImageView>,
+    ) -> Result<()> {
+        if self.glyphs.is_empty() {
+            return Ok(());
+        }
+
+        let glyph_count = self.glyphs.len() as u32;
+        let instance_buffer = Buffer::from_iter(
+            vulkan_device.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::VERTEX_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            self.glyphs.drain(..),
+        )?;
+
+        let extent = target.image().extent();
+        let [width, height] = [extent[0] as f32, extent[1] as f32];
+
+        // Pixels, y down, to clip space
+        let projection = Orthographic3::new(0.0, width, 0.0, height, -1.0, 1.0);
+
+        builder
+            .begin_rendering(RenderingInfo {
+                color_attachments: vec![Some(RenderingAttachmentInfo {
+                    load_op: AttachmentLoadOp::Load,
+                    store_op: AttachmentStoreOp::Store,
+                    ..RenderingAttachmentInfo::image_view(target)
+                })],
+                ..Default::default()
+            })?
+            .set_viewport(
+                0,
+                [Viewport {
+                    offset: [0.0, 0.0],
+                    extent: [width, height],
+                    depth_range: 0.0..=1.0,
+                }]
+                .into_iter()
+                .collect(),
+            )?
+            .bind_pipeline_graphics(Arc::clone(&self.pipeline))?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                Arc::clone(self.pipeline.layout()),
+                0,
+                Arc::clone(&self.descriptor_set),
+            )?
+            .push_constants(
+                Arc::clone(self.pipeline.layout()),
+                0,
+                text_vs::PushConstantData {
+                    projection: projection.to_homogeneous().into(),
+                },
+            )?
+            .bind_vertex_buffers(0, instance_buffer)?
+            .draw(4, glyph_count, 0, 0)?
+            .end_rendering()?;
+
+        Ok(())
+    }
+}
+
+// Alpha blended quads into the (single sample) swapchain image
+fn create_text_pipeline(vulkan_device: &VulkanDevice) -> Result<Arc<GraphicsPipeline>> {
+    let device = vulkan_device.queue().device();
+    let vulkan_context = vulkan_device.vulkan_context.borrow();
+
+    let vertex_shader = text_vs::load(Arc::clone(device))?
+        .entry_point("main")
+        .unwrap();
+    // Specialization constant 0: ENCODE_SRGB
+    let fragment_shader = text_fs::load(Arc::clone(device))?
+        .specialize(
+            [(0, vulkan_context.color_output.encode_in_shader().into())]
+                .into_iter()
+                .collect(),
+        )?
+        .entry_point("main")
+        .unwrap();
+
+    let vertex_input_state =
+        GlyphInstance::per_instance().definition(&vertex_shader.info().input_interface)?;
+
+    let stages = [
+        PipelineShaderStageCreateInfo::new(vertex_shader),
+        PipelineShaderStageCreateInfo::new(fragment_shader),
+    ];
+
+    let layout = PipelineLayout::new(
+        Arc::clone(device),
+        PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+            .into_pipeline_layout_create_info(Arc::clone(device))?,
+    )?;
+
+    let subpass = PipelineRenderingCreateInfo {
+        color_attachment_formats: vec![Some(vulkan_context.color_format())],
+        ..Default::default()
+    };
+
+    let pipeline = GraphicsPipeline::new(
+        Arc::clone(device),
+        None,
+        GraphicsPipelineCreateInfo {
+            stages: stages.into_iter().collect(),
+            vertex_input_state: Some(vertex_input_state),
+            input_assembly_state: Some(InputAssemblyState {
+                topology: PrimitiveTopology::TriangleStrip,
+                ..Default::default()
+            }),
+            viewport_state: Some(ViewportState::default()),
+            rasterization_state: Some(RasterizationState::default()),
+            multisample_state: Some(MultisampleState::default()),
+            color_blend_state: Some(ColorBlendState::with_attachment_states(
+                subpass.color_attachment_formats.len() as u32,
+                ColorBlendAttachmentState {
+                    blend: Some(AttachmentBlend::alpha()),
+                    ..Default::default()
+                },
+            )),
+            dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+            subpass: Some(subpass.into()),
+            ..GraphicsPipelineCreateInfo::layout(layout)
+        },
+    )?;
+
+    Ok(pipeline)
+}
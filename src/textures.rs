@@ -1,61 +1,69 @@
-use std::io::Cursor;
 use std::sync::Arc;
 
-use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage};
 use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
 use vulkano::command_buffer::{
     AutoCommandBufferBuilder, BlitImageInfo, BufferImageCopy, ClearColorImageInfo,
      CopyBufferToImageInfo, CopyImageInfo, ImageBlit, ImageCopy,
     PrimaryAutoCommandBuffer,
 };
-use vulkano::device::Device;
-use vulkano::format::Format;
-use vulkano::image::sampler::{
-    Filter, Sampler, SamplerAddressMode, SamplerCreateInfo, SamplerMipmapMode,
-};
+use vulkano::device::{Device, DeviceOwned};
+use vulkano::format::{CompressionType, Format, FormatFeatures, NumericFormat};
+use vulkano::image::sampler::{Filter, Sampler, SamplerCreateInfo};
 use vulkano::image::view::ImageView;
 use vulkano::image::{
     Image, ImageAspects, ImageCreateInfo, ImageLayout, ImageSubresourceLayers,
     ImageUsage,
 };
-use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
+use vulkano::memory::allocator::{AllocationCreateInfo, StandardMemoryAllocator};
 use vulkano::DeviceSize;
 
-use crate::{error::Result, utils::read_file_to_bytes};
+use image::RgbaImage;
+use tracing::warn;
+
+use crate::{
+    config::{SamplerConfig, TextureQuality},
+    device_limits::DeviceLimits,
+    error::{Result, VisualSystemError},
+    staging::staging_buffer,
+    utils::{linear_to_srgb, srgb_to_linear},
+};
+
+// File identifier at the start of every KTX2 file
+pub const KTX2_MAGIC: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
 
 // Function
-// 1. takes a path to a png image and returns a ImageView (texture).
+// 1. takes a path to an image (PNG, JPEG...) and returns a ImageView (texture).
 // 2. takes in an existing command buffer builder and add the blit image commands
+// `format` is R8G8B8A8_SRGB for color textures, R8G8B8A8_UNORM for data (metallic-roughness, occlusion)
+// `premultiply`: for the PremultipliedAlpha materials, see premultiply_alpha
 pub fn create_texture(
     path: &str,
+    format: Format,
+    premultiply: bool,
     command_builder: &mut AutoCommandBufferBuilder<
         PrimaryAutoCommandBuffer<Arc<StandardCommandBufferAllocator>>,
         Arc<StandardCommandBufferAllocator>,
     >,
     memory_allocator: Arc<StandardMemoryAllocator>,
+    limits: &DeviceLimits,
 ) -> Result<Arc<ImageView>> {
     // load the image data and dimensions before event loop
     let texture = {
         
-        //  to read in the texture file as bytes
-        let png_bytes = read_file_to_bytes(path);
-        // Cursor wraps a byte array to provide an implementation of the Read trait.
-        // This lets us pass it into the Decoder provided by the png crate.
-        let cursor = Cursor::new(png_bytes);
-        let decoder = png::Decoder::new(cursor);
-
-        // This gives us some information about the png image.
-        // as well as a new Reader to actually load the source bytes into a vector.
-        let mut reader = decoder.read_info().expect("error png reader");
-
-        let info = reader.info();
+        //  to read in the texture file as bytes.
+        // Decoded to RGBA8 by the image crate: PNG (any bit depth), JPEG, BMP, TGA...
+        let mut decoded = image::load_from_memory(&std::fs::read(path)?)?.to_rgba8();
+        if premultiply {
+            premultiply_alpha(&mut decoded, format);
+        }
+        let (width, height) = decoded.dimensions();
 
-        let img_size = [info.width, info.height];
+        let img_size = [width, height];
         // These are the image dimensions we’ll pass along to Vulkan when we create the texture.
-        let extent = [info.width * 2, info.height * 2, 1]; // make the image twice as big in order to blit full image into it. Basically you can put the same image 4 time 2x2
-
-        let mut mip_width = info.width;
-        let mut mip_height = info.height;
+        let extent = [width * 2, height * 2, 1]; // make the image twice as big in order to blit full image into it. Basically you can put the same image 4 time 2x2
+        limits.check_image_extent(&format!("texture {path}"), [extent[0], extent[1]])?;
 
         // Mip level for mipmap
         // This calculates the number of levels in the mip chain.
@@ -63,39 +71,17 @@ pub fn create_texture(
         // The log2 method calculates how many times that dimension can be divided by 2.
         //The floor method handles cases where the largest dimension is not a power of 2.
         // 1 is added so that the original image has a mip level.
-        let mip_levels = (info.width.max(info.height) as f32).log2().floor() as u32 + 1;
+        // Of the whole image: the mips downsample all of it, not only the uploaded corner.
+        let mip_levels = (extent[0].max(extent[1]) as f32).log2().floor() as u32 + 1;
         println!("Mip levels: {mip_levels:}");
-        // This is how we actually load the image into a Rust vector.
-        // The specific call to reader.next_frame is because a png file can have multiple “frames”.
-
-        let depth: u32 = match info.bit_depth {
-            png::BitDepth::One => 1,
-            png::BitDepth::Two => 2,
-            png::BitDepth::Four => 4,
-            png::BitDepth::Eight => 8,
-            png::BitDepth::Sixteen => 16,
-        };
-        // Buffer for the picture
-        let upload_buffer = Buffer::new_slice(
-            memory_allocator.clone(),
-            BufferCreateInfo {
-                usage: BufferUsage::TRANSFER_SRC,
-                ..Default::default()
-            },
-            AllocationCreateInfo {
-                memory_type_filter: MemoryTypeFilter::PREFER_HOST
-                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
-                ..Default::default()
-            },
-            (info.width * info.height * depth) as DeviceSize,
-        )?;
 
-        reader.next_frame(&mut upload_buffer.write()?)?;
+        // Buffer for the picture
+        let upload_buffer = staging_buffer(&memory_allocator, decoded.into_raw())?;
 
         let image = Image::new(
             memory_allocator.clone(),
             ImageCreateInfo {
-                format: Format::R8G8B8A8_SRGB,
+                format,
                 extent,
                 usage: ImageUsage::TRANSFER_SRC | ImageUsage::TRANSFER_DST | ImageUsage::SAMPLED,
                 mip_levels,
@@ -143,74 +129,387 @@ pub fn create_texture(
             // .end_debug_utils_label() }?; // This needs unsafe block.
             //  
         // MIPMAP
-        for level in 1..mip_levels {
-            let src_subresource = ImageSubresourceLayers {
-                mip_level: level - 1,
-                array_layers: 0..1,
-                aspects: ImageAspects::COLOR,
-            };
+        // Each level from the whole previous one. Downsampling only the top left corner left the
+        // rest of the levels cleared to black, and the texture changed with the distance.
+        blit_mip_chain(&image, [extent[0], extent[1]], mip_levels, command_builder)?;
+        ImageView::new_default(image)?
+    };
 
-            let dst_subresource = ImageSubresourceLayers {
-                mip_level: level,
-                array_layers: 0..1,
-                aspects: ImageAspects::COLOR,
-            };
+    Ok(texture)
+}
 
-            let src_offsets = [[0, 0, 0], [mip_width, mip_height, 1]];
-            let dst_offsets = [
-                [0, 0, 0],
-                [
-                    (if mip_width > 1 { mip_width / 2 } else { 1 }),
-                    (if mip_height > 1 { mip_height / 2 } else { 1 }),
-                    1,
-                ],
-            ];
-
-            // println!("Debug --> src offset: {:?} ; dst offset: {:?}",src_offsets , dst_offsets);
-
-            let blit = ImageBlit {
-                src_subresource,
-                src_offsets,
-                dst_subresource,
-                dst_offsets,
-                ..Default::default()
+// Same as create_texture for an encoded image already in memory (PNG, JPEG...),
+// e.g. an image embedded in a glTF buffer. Decoded with the image crate.
+pub fn create_texture_from_bytes(
+    bytes: &[u8],
+    format: Format,
+    premultiply: bool,
+    command_builder: &mut AutoCommandBufferBuilder<
+        PrimaryAutoCommandBuffer<Arc<StandardCommandBufferAllocator>>,
+        Arc<StandardCommandBufferAllocator>,
+    >,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    limits: &DeviceLimits,
+) -> Result<Arc<ImageView>> {
+    let decoded = image::load_from_memory(bytes)?.to_rgba8();
+
+    create_texture_from_image(
+        decoded,
+        format,
+        premultiply,
+        command_builder,
+        memory_allocator,
+        limits,
+    )
+}
+
+// Same for an image already decoded, e.g. by gltf::import
+pub fn create_texture_from_image(
+    mut decoded: RgbaImage,
+    format: Format,
+    premultiply: bool,
+    command_builder: &mut AutoCommandBufferBuilder<
+        PrimaryAutoCommandBuffer<Arc<StandardCommandBufferAllocator>>,
+        Arc<StandardCommandBufferAllocator>,
+    >,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    limits: &DeviceLimits,
+) -> Result<Arc<ImageView>> {
+    if premultiply {
+        premultiply_alpha(&mut decoded, format);
+    }
+    let (width, height) = decoded.dimensions();
+    limits.check_image_extent("embedded texture", [width, height])?;
+
+    let mip_levels = (width.max(height) as f32).log2().floor() as u32 + 1;
+
+    let upload_buffer = staging_buffer(&memory_allocator, decoded.into_raw())?;
+
+    let image = Image::new(
+        memory_allocator,
+        ImageCreateInfo {
+            format,
+            extent: [width, height, 1],
+            usage: ImageUsage::TRANSFER_SRC | ImageUsage::TRANSFER_DST | ImageUsage::SAMPLED,
+            mip_levels,
+            ..Default::default()
+        },
+        AllocationCreateInfo::default(),
+    )?;
+
+    command_builder.copy_buffer_to_image(CopyBufferToImageInfo::buffer_image(
+        upload_buffer,
+        image.clone(),
+    ))?;
+
+    blit_mip_chain(&image, [width, height], mip_levels, command_builder)?;
+
+    Ok(ImageView::new_default(image)?)
+}
+
+// Color times alpha, as the premultiplied blending expects (PNG and JPEG store straight alpha).
+// For an sRGB `format` in linear space: the sampler decodes the color before the blend.
+fn premultiply_alpha(image: &mut RgbaImage, format: Format) {
+    let srgb = format.numeric_format_color() == Some(NumericFormat::SRGB);
+
+    for pixel in image.pixels_mut() {
+        let alpha = pixel[3] as f64 / 255.0;
+        for channel in &mut pixel.0[..3] {
+            let value = *channel as f64 / 255.0;
+            let value = if srgb {
+                linear_to_srgb(srgb_to_linear(value) * alpha)
+            } else {
+                value * alpha
             };
+            *channel = (value * 255.0).round() as u8;
+        }
+    }
+}
+
+// KTX2 texture with BCn (BC1 to BC7) compressed blocks, e.g. from `toktx` or `compressonator`.
+// The blocks and the mip levels of the file are uploaded as is: no decompression on the CPU and
+// no blit_mip_chain (compressed formats can't be blitted into). The file keeps its own format, but
+// it must be sRGB when `requested` is, UNORM when it isn't (see create_texture).
+// Supercompressed (Basis Universal, zstd) files are not supported.
+pub fn create_texture_from_ktx2(
+    bytes: &[u8],
+    requested: Format,
+    command_builder: &mut AutoCommandBufferBuilder<
+        PrimaryAutoCommandBuffer<Arc<StandardCommandBufferAllocator>>,
+        Arc<StandardCommandBufferAllocator>,
+    >,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    limits: &DeviceLimits,
+) -> Result<Arc<ImageView>> {
+    let reader = ktx2::Reader::new(bytes)?;
+    let header = reader.header();
+
+    if header.supercompression_scheme.is_some() {
+        return Err(VisualSystemError::UnsupportedTexture(format!(
+            "KTX2 supercompression {:?}",
+            header.supercompression_scheme
+        ))
+        .into());
+    }
+    if header.pixel_depth > 1 || header.layer_count > 1 || header.face_count > 1 {
+        return Err(VisualSystemError::UnsupportedTexture(
+            "KTX2 3D, array or cube map texture".to_string(),
+        )
+        .into());
+    }
+
+    let format = ktx2_format(header.format, requested)?;
 
-            // Here, we perform image copying and blitting on the same image.
-            command_builder.blit_image(BlitImageInfo {
-                src_image_layout: ImageLayout::TransferSrcOptimal,
-                dst_image_layout: ImageLayout::TransferDstOptimal,
-                regions: [blit].into(),
-                filter: Filter::Linear,
-                ..BlitImageInfo::images(image.clone(), image.clone())
-            })?;
-
-            if mip_width > 1 {
-                mip_width /= 2;
-            }
-
-            if mip_height > 1 {
-                mip_height /= 2;
-            }
+    let device = memory_allocator.device();
+    let sampled = device
+        .physical_device()
+        .format_properties(format)?
+        .optimal_tiling_features
+        .contains(FormatFeatures::SAMPLED_IMAGE);
+    if !device.enabled_features().texture_compression_bc || !sampled {
+        return Err(VisualSystemError::UnsupportedTexture(format!(
+            "{format:?} not supported by the device"
+        ))
+        .into());
+    }
+
+    let extent = [header.pixel_width, header.pixel_height.max(1)];
+    limits.check_image_extent("KTX2 texture", extent)?;
+
+    // level_count 0 asks the loader to generate the mips: not possible for compressed blocks
+    let mip_levels = header.level_count.max(1);
+
+    // All the levels in one upload buffer, one copy region each
+    let mut data = Vec::new();
+    let mut regions = Vec::new();
+    for (level, level_data) in reader.levels().enumerate().take(mip_levels as usize) {
+        regions.push(BufferImageCopy {
+            buffer_offset: data.len() as DeviceSize,
+            image_subresource: ImageSubresourceLayers {
+                mip_level: level as u32,
+                ..ImageSubresourceLayers::from_parameters(format, 1)
+            },
+            image_extent: [(extent[0] >> level).max(1), (extent[1] >> level).max(1), 1],
+            ..Default::default()
+        });
+        data.extend_from_slice(level_data);
+    }
+
+    let upload_buffer = staging_buffer(&memory_allocator, data)?;
+
+    let image = Image::new(
+        memory_allocator,
+        ImageCreateInfo {
+            format,
+            extent: [extent[0], extent[1], 1],
+            usage: ImageUsage::TRANSFER_DST | ImageUsage::SAMPLED,
+            mip_levels,
+            ..Default::default()
+        },
+        AllocationCreateInfo::default(),
+    )?;
+
+    command_builder.copy_buffer_to_image(CopyBufferToImageInfo {
+        regions: regions.into(),
+        ..CopyBufferToImageInfo::buffer_image(upload_buffer, image.clone())
+    })?;
+
+    Ok(ImageView::new_default(image)?)
+}
+
+// The BCn format of a KTX2 file, with the color encoding of `requested`
+fn ktx2_format(ktx2_format: Option<ktx2::Format>, requested: Format) -> Result<Format> {
+    // KTX2 stores the VkFormat value
+    let format = ktx2_format
+        .and_then(|format| Format::try_from(ash::vk::Format::from_raw(format.0.get() as i32)).ok())
+        .filter(|format| format.compression() == Some(CompressionType::BC))
+        .ok_or_else(|| {
+            VisualSystemError::UnsupportedTexture(format!(
+                "KTX2 format {ktx2_format:?}, expected BC1 to BC7"
+            ))
+        })?;
+
+    let is_srgb = |format: Format| format.numeric_format_color() == Some(NumericFormat::SRGB);
+    if is_srgb(format) != is_srgb(requested) {
+        return Err(VisualSystemError::UnsupportedTexture(format!(
+            "KTX2 format {format:?} used as {requested:?}"
+        ))
+        .into());
+    }
+
+    Ok(format)
+}
+
+// Fills mip levels 1.. by blitting each level from the previous one.
+// `extent` is the size of the region of level 0 to downsample.
+// The layout transitions are per level, as in the usual Vulkan mipmap generation: the auto
+// command buffer tracks the layout of each mip level separately. Before the blit of `level`, it
+// moves `level - 1` from TransferDstOptimal to TransferSrcOptimal behind a barrier on the write
+// of the previous blit, and `level` to TransferDstOptimal. Each blit reads and writes a single
+// level, so the source and the destination never share a subresource.
+fn blit_mip_chain(
+    image: &Arc<Image>,
+    extent: [u32; 2],
+    mip_levels: u32,
+    command_builder: &mut AutoCommandBufferBuilder<
+        PrimaryAutoCommandBuffer<Arc<StandardCommandBufferAllocator>>,
+        Arc<StandardCommandBufferAllocator>,
+    >,
+) -> Result<()> {
+    let [mut mip_width, mut mip_height] = extent;
+
+    for level in 1..mip_levels {
+        let src_subresource = ImageSubresourceLayers {
+            mip_level: level - 1,
+            array_layers: 0..1,
+            aspects: ImageAspects::COLOR,
+        };
+
+        let dst_subresource = ImageSubresourceLayers {
+            mip_level: level,
+            array_layers: 0..1,
+            aspects: ImageAspects::COLOR,
+        };
+
+        let src_offsets = [[0, 0, 0], [mip_width, mip_height, 1]];
+        let dst_offsets = [
+            [0, 0, 0],
+            [
+                (if mip_width > 1 { mip_width / 2 } else { 1 }),
+                (if mip_height > 1 { mip_height / 2 } else { 1 }),
+                1,
+            ],
+        ];
+
+        let blit = ImageBlit {
+            src_subresource,
+            src_offsets,
+            dst_subresource,
+            dst_offsets,
+            ..Default::default()
+        };
+
+        // Here, we perform image copying and blitting on the same image.
+        command_builder.blit_image(BlitImageInfo {
+            src_image_layout: ImageLayout::TransferSrcOptimal,
+            dst_image_layout: ImageLayout::TransferDstOptimal,
+            regions: [blit].into(),
+            filter: Filter::Linear,
+            ..BlitImageInfo::images(image.clone(), image.clone())
+        })?;
+
+        if mip_width > 1 {
+            mip_width /= 2;
         }
-        ImageView::new_default(image)?
-    };
 
-    Ok(texture)
+        if mip_height > 1 {
+            mip_height /= 2;
+        }
+    }
+
+    Ok(())
+}
+
+// 1x1 texture of a single color. Stands in for a texture missing from a material.
+pub fn create_default_texture(
+    color: [u8; 4],
+    format: Format,
+    command_builder: &mut AutoCommandBufferBuilder<
+        PrimaryAutoCommandBuffer<Arc<StandardCommandBufferAllocator>>,
+        Arc<StandardCommandBufferAllocator>,
+    >,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+) -> Result<Arc<ImageView>> {
+    let upload_buffer = staging_buffer(&memory_allocator, color)?;
+
+    let image = Image::new(
+        memory_allocator,
+        ImageCreateInfo {
+            format,
+            extent: [1, 1, 1],
+            usage: ImageUsage::TRANSFER_DST | ImageUsage::SAMPLED,
+            ..Default::default()
+        },
+        AllocationCreateInfo::default(),
+    )?;
+
+    command_builder.copy_buffer_to_image(CopyBufferToImageInfo::buffer_image(
+        upload_buffer,
+        image.clone(),
+    ))?;
+
+    Ok(ImageView::new_default(image)?)
 }
 
-pub fn create_sampler(device: Arc<Device>) -> Result<Arc<Sampler>> {
+// Sampler for the material textures. `quality` is clamped to the device limits first.
+pub fn create_sampler(
+    device: Arc<Device>,
+    limits: &DeviceLimits,
+    quality: TextureQuality,
+    config: SamplerConfig,
+) -> Result<Arc<Sampler>> {
+    let clamped = quality.clamp_to(limits.max_sampler_anisotropy, limits.max_sampler_lod_bias);
+
+    if clamped != quality {
+        warn!("texture quality {quality:?} not supported, using {clamped:?}");
+    }
+
     let sampler = Sampler::new(
         device.clone(),
         SamplerCreateInfo {
-            mag_filter: Filter::Linear,
-            min_filter: Filter::Linear,
-            mipmap_mode: SamplerMipmapMode::Nearest,
-            address_mode: [SamplerAddressMode::Repeat; 3],
-            mip_lod_bias: 0.0,
+            mag_filter: config.mag_filter,
+            min_filter: config.min_filter,
+            mipmap_mode: config.mipmap_mode,
+            address_mode: [config.address_mode; 3],
+            mip_lod_bias: clamped.lod_bias,
+            anisotropy: clamped.anisotropic().then_some(clamped.anisotropy),
+            lod: 0.0..=clamped.max_lod,
             ..Default::default()
         },
     )?;
 
     Ok(sampler)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ktx2_format_keeps_the_requested_encoding() {
+        let srgb = ktx2_format(Some(ktx2::Format::BC7_SRGB_BLOCK), Format::R8G8B8A8_SRGB);
+        assert_eq!(srgb.unwrap(), Format::BC7_SRGB_BLOCK);
+        let unorm = ktx2_format(
+            Some(ktx2::Format::BC1_RGBA_UNORM_BLOCK),
+            Format::R8G8B8A8_UNORM,
+        );
+        assert_eq!(unorm.unwrap(), Format::BC1_RGBA_UNORM_BLOCK);
+
+        // A color texture stored linear, and a data texture stored sRGB
+        assert!(ktx2_format(Some(ktx2::Format::BC7_UNORM_BLOCK), Format::R8G8B8A8_SRGB).is_err());
+        assert!(ktx2_format(Some(ktx2::Format::BC3_SRGB_BLOCK), Format::R8G8B8A8_UNORM).is_err());
+    }
+
+    #[test]
+    fn ktx2_format_needs_bc_blocks() {
+        assert!(ktx2_format(Some(ktx2::Format::R8G8B8A8_SRGB), Format::R8G8B8A8_SRGB).is_err());
+        assert!(ktx2_format(None, Format::R8G8B8A8_SRGB).is_err());
+    }
+
+    #[test]
+    fn premultiply_alpha_in_the_texture_encoding() {
+        let pixels = vec![200, 100, 50, 128, 255, 255, 255, 128, 90, 90, 90, 255, 90, 90, 90, 0];
+
+        let mut unorm = RgbaImage::from_raw(4, 1, pixels.clone()).unwrap();
+        premultiply_alpha(&mut unorm, Format::R8G8B8A8_UNORM);
+        assert_eq!(&unorm.as_raw()[..4], &[100, 50, 25, 128]);
+
+        // Half of linear white, not half of the encoded value
+        let mut srgb = RgbaImage::from_raw(4, 1, pixels).unwrap();
+        premultiply_alpha(&mut srgb, Format::R8G8B8A8_SRGB);
+        assert_eq!(
+            &srgb.as_raw()[4..],
+            &[188, 188, 188, 128, 90, 90, 90, 255, 0, 0, 0, 0]
+        );
+    }
+}
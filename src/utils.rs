@@ -1,16 +1,19 @@
-use std::{fs::File, io::Read};
-
+use vulkano::{
+    buffer::{BufferCreateInfo, BufferUsage},
+    sync::Sharing,
+};
 use winit::window::Icon;
 
-pub fn load_icon(path: &str) -> Icon {
-    let img = image::open(path).expect("error opening image").to_rgba8();
+use crate::error::Result;
+
+pub fn load_icon(path: &str) -> Result<Icon> {
+    let img = image::open(path)?.to_rgba8();
     let (width, height) = img.dimensions();
     let rgba = img.into_raw();
-    Icon::from_rgba(rgba, width, height).expect("error convert image to rgba")
+    Ok(Icon::from_rgba(rgba, width, height)?)
 }
 
-// Color correction. needed for web browser
-#[allow(unused)]
+// Color correction. needed for web browser and UNORM swapchains
 pub fn linear_to_srgb(linear: f64) -> f64 {
     if linear <= 0.0031308 {
         linear * 12.92
@@ -19,12 +22,34 @@ pub fn linear_to_srgb(linear: f64) -> f64 {
     }
 }
 
-pub fn read_file_to_bytes(path: &str) -> Vec<u8> {
-    //let img = image::open(path).expect("error opening image").to_rgba8();
-    //img.into_raw()
+// Inverse of linear_to_srgb
+pub fn srgb_to_linear(srgb: f64) -> f64 {
+    if srgb <= 0.04045 {
+        srgb / 12.92
+    } else {
+        ((srgb + 0.055) / 1.055).powf(2.4)
+    }
+}
 
-    let mut file = File::open(path).expect("Failed to open file");
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer).expect("Failed to read file");
-    buffer
+// Create info for a buffer written on one queue family and read on another.
+//
+// With Sharing::Exclusive, a buffer written by the transfer queue must be released by the
+// transfer family and acquired by the graphics family (a pair of ownership transfer barriers)
+// before the graphics queue can read it. vulkano's AutoCommandBufferBuilder does not record
+// these barriers, so the buffers shared by several families are created with
+// Sharing::Concurrent instead: no barriers, at the cost of possibly slower access on some GPUs.
+// With a single family the buffer stays Exclusive.
+pub fn shared_buffer_create_info(
+    usage: BufferUsage,
+    queue_family_indices: &[u32],
+) -> BufferCreateInfo {
+    BufferCreateInfo {
+        usage,
+        sharing: if queue_family_indices.len() > 1 {
+            Sharing::Concurrent(queue_family_indices.iter().copied().collect())
+        } else {
+            Sharing::Exclusive
+        },
+        ..Default::default()
+    }
 }
@@ -1,45 +1,202 @@
 use std::sync::{Arc, Mutex};
 
-use vulkano::image::SampleCount;
+use palette::Srgb;
+use vulkano::{
+    format::Format,
+    image::SampleCount,
+    pipeline::graphics::rasterization::{CullMode, FrontFace},
+    swapchain::PresentMode,
+};
 use winit::event::WindowEvent;
 
 use crate::{
-    camera::{Camera, CameraController, Mvp},
+    camera::{CameraController, ControllerMode, FlyController, SphericalController},
+    config::{BloomSettings, CameraClamp, ColorOutput, SamplerConfig, TextureQuality},
     error::Result,
+    instance_buffer::{NUM_INSTANCES_PER_ROW, SPACE_BETWEEN},
+    shader_reload::MainShaders,
 };
 
+// Until set_depth_format: the only depth format every device supports
+const DEPTH_FORMAT: Format = Format::D16_UNORM;
+
+// Radians per frame of the spherical controller keys
+const SPHERICAL_SPEED: f32 = 0.02;
+
+// Backgrounds cycled by cycle_clear_color (sRGB), the first one is the default
+pub const CLEAR_COLOR_PRESETS: [[f32; 3]; 4] = [
+    [0.2, 0.2, 0.3], // slate
+    [0.0, 0.0, 0.0], // black
+    [1.0, 1.0, 1.0], // white
+    [0.5, 0.7, 0.9], // sky
+];
+
+// Settings shared by all the windows. The cameras are per window, see VulkanRenderer.
 pub struct VulkanContext {
-    pub camera: Arc<Mutex<Camera>>,
-    pub mvp_uniform: Arc<Mutex<Mvp>>,
     pub camera_controller: Arc<Mutex<CameraController>>,
+    pub fly_controller: Arc<Mutex<FlyController>>, // same speed as camera_controller
+    pub spherical_controller: Arc<Mutex<SphericalController>>,
+    pub controller_mode: ControllerMode,
     pub samples: SampleCount,
+    pub color_output: ColorOutput,
+    pub texture_quality: TextureQuality,
+    pub sampler_config: SamplerConfig, // read by VulkanDevice::new
+    color_format: Format,              // swapchain and color attachments
+    depth_format: Format,
+    pub animate_instances: bool, // spin the instances with the compute shader
+    pub spin_model: bool,        // turn the model around its Y axis in the vertex shader
+    pub frustum_culling: bool,   // draw only the instances in view, not while animated
+    pub show_depth: bool,        // draw the depth buffer instead of the scene
+    pub bloom: BloomSettings,
+    pub camera_clamp: CameraClamp,
+    pub show_wireframe: bool,            // wireframe over the shaded meshes
+    pub show_grid: bool,                 // reference grid on the XZ plane, see grid.rs
+    pub point_size: f32,                 // clamped to the device range by VulkanDevice
+    pub show_debug_text: bool,           // FPS and settings in the top left corner
+    pub clear_color: [f32; 3],           // sRGB
+    pub present_mode: PresentMode,       // preferred, the renderers fall back to Fifo
+    pub default_texture: Option<String>, // base color of the meshes without one
+    pub instances_per_row: u32,
+    pub instance_spacing: f32,
+    pub cull_mode: CullMode, // not a dynamic state: set_cull_mode rebuilds the pipelines
+    pub front_face: FrontFace, // same, VulkanDevice::set_front_face
+    pub reloaded_shaders: MainShaders, // F5, see VulkanDevice::reload_shaders
 }
 
 impl VulkanContext {
     pub fn new(
-        camera: Arc<Mutex<Camera>>,
-        mvp_uniform: Arc<Mutex<Mvp>>,
         camera_controller: Arc<Mutex<CameraController>>,
         samples: SampleCount,
+        color_output: ColorOutput,
+        texture_quality: TextureQuality,
+        bloom: BloomSettings,
+        camera_clamp: CameraClamp,
+        point_size: f32,
     ) -> Result<Self> {
+        let fly_controller = Arc::new(Mutex::new(FlyController::new(
+            camera_controller.lock().unwrap().speed(),
+        )));
+
         Ok(Self {
-            camera,
-            mvp_uniform,
             camera_controller,
+            fly_controller,
+            spherical_controller: Arc::new(Mutex::new(SphericalController::new(SPHERICAL_SPEED))),
+            controller_mode: ControllerMode::default(),
             samples,
+            color_output,
+            texture_quality,
+            sampler_config: SamplerConfig::default(),
+            color_format: color_output.swapchain_format(),
+            depth_format: DEPTH_FORMAT,
+            animate_instances: false,
+            spin_model: false,
+            frustum_culling: true,
+            show_depth: false,
+            bloom,
+            camera_clamp,
+            show_wireframe: false,
+            show_grid: false,
+            point_size,
+            show_debug_text: false,
+            clear_color: CLEAR_COLOR_PRESETS[0],
+            present_mode: PresentMode::Fifo,
+            default_texture: None,
+            instances_per_row: NUM_INSTANCES_PER_ROW,
+            instance_spacing: SPACE_BETWEEN,
+            cull_mode: CullMode::Back,
+            front_face: FrontFace::CounterClockwise,
+            reloaded_shaders: MainShaders::default(),
         })
     }
 
-    #[allow(unused)]
-    pub fn camera(&self) -> &Arc<Mutex<Camera>> {
-        &self.camera
+    // Formats shared by the swapchain, the render targets and the pipelines
+    pub fn color_format(&self) -> Format {
+        self.color_format
+    }
+
+    // The swapchain format the surface offers, see VulkanInstance::select_surface_format.
+    // Before creating the pipelines.
+    pub fn set_color_format(&mut self, color_format: Format) {
+        self.color_format = color_format;
+    }
+
+    pub fn depth_format(&self) -> Format {
+        self.depth_format
+    }
+
+    // See VulkanInstance::select_depth_format. Before creating the pipelines.
+    pub fn set_depth_format(&mut self, depth_format: Format) {
+        self.depth_format = depth_format;
+    }
+
+    pub fn toggle_animate_instances(&mut self) {
+        self.animate_instances = !self.animate_instances;
+    }
+
+    pub fn toggle_spin_model(&mut self) {
+        self.spin_model = !self.spin_model;
+    }
+
+    pub fn toggle_frustum_culling(&mut self) {
+        self.frustum_culling = !self.frustum_culling;
     }
 
-    pub fn mvp_uniform(&self) -> &Arc<Mutex<Mvp>> {
-        &self.mvp_uniform
+    pub fn toggle_show_depth(&mut self) {
+        self.show_depth = !self.show_depth;
+    }
+
+    pub fn toggle_show_debug_text(&mut self) {
+        self.show_debug_text = !self.show_debug_text;
+    }
+
+    pub fn toggle_show_wireframe(&mut self) {
+        self.show_wireframe = !self.show_wireframe;
+    }
+
+    pub fn toggle_show_grid(&mut self) {
+        self.show_grid = !self.show_grid;
+    }
+
+    pub fn toggle_bloom(&mut self) {
+        self.bloom.enabled = !self.bloom.enabled;
+    }
+
+    pub fn toggle_camera_clamp(&mut self) {
+        self.camera_clamp.enabled = !self.camera_clamp.enabled;
+    }
+
+    // Background of every window, converted to linear by the renderers
+    pub fn set_clear_color(&mut self, clear_color: Srgb) {
+        self.clear_color = clear_color.into();
+    }
+
+    // Next preset after the current color (the first one after a custom color)
+    pub fn cycle_clear_color(&mut self) {
+        let next = CLEAR_COLOR_PRESETS
+            .iter()
+            .position(|preset| *preset == self.clear_color)
+            .map_or(0, |index| (index + 1) % CLEAR_COLOR_PRESETS.len());
+
+        self.set_clear_color(CLEAR_COLOR_PRESETS[next].into());
     }
 
     pub fn input(&mut self, event: &WindowEvent) -> bool {
-        self.camera_controller.lock().unwrap().process_events(event)
+        match self.controller_mode {
+            ControllerMode::Orbit => self.camera_controller.lock().unwrap().process_events(event),
+            ControllerMode::Fly => self.fly_controller.lock().unwrap().process_events(event),
+            ControllerMode::Spherical => self
+                .spherical_controller
+                .lock()
+                .unwrap()
+                .process_events(event),
+        }
+    }
+
+    // The held keys of the previous controller would keep it moving
+    pub fn cycle_controller_mode(&mut self) {
+        self.camera_controller.lock().unwrap().release_keys();
+        self.fly_controller.lock().unwrap().release_keys();
+        self.spherical_controller.lock().unwrap().release_keys();
+        self.controller_mode = self.controller_mode.next();
     }
 }
@@ -1,15 +1,19 @@
 // Note: Logical Device
 
-use std::{cell::RefCell, rc::Rc, sync::Arc};
+use std::{
+    cell::{Cell, Ref, RefCell},
+    collections::HashMap,
+    path::Path,
+    rc::Rc,
+    sync::Arc,
+};
 
+use nalgebra::{Matrix4, Point3};
+use tracing::{error, info};
 use vulkano::{
-    buffer::{
-        allocator::{SubbufferAllocator, SubbufferAllocatorCreateInfo},
-        Buffer, BufferCreateInfo, BufferUsage, Subbuffer,
-    },
+    buffer::{BufferUsage, Subbuffer},
     command_buffer::{
         allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, CommandBufferUsage,
-        CopyBufferInfo,
     },
     descriptor_set::{
         allocator::{StandardDescriptorSetAllocator, StandardDescriptorSetAllocatorCreateInfo},
@@ -17,20 +21,19 @@ use vulkano::{
         PersistentDescriptorSet, WriteDescriptorSet,
     },
     device::{Device, DeviceCreateInfo, Features, Queue, QueueCreateInfo},
-    format::Format,
-    memory::{
-        allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
-        MemoryPropertyFlags,
-    },
+    image::{sampler::Sampler, SampleCount},
+    memory::allocator::StandardMemoryAllocator,
     pipeline::{
         graphics::{
-            color_blend::{ColorBlendAttachmentState, ColorBlendState},
+            color_blend::{
+                AttachmentBlend, BlendFactor, BlendOp, ColorBlendAttachmentState, ColorBlendState,
+            },
             depth_stencil::{DepthState, DepthStencilState},
             input_assembly::InputAssemblyState,
             multisample::MultisampleState,
-            rasterization::{CullMode, RasterizationState},
+            rasterization::{CullMode, DepthBiasState, FrontFace, PolygonMode, RasterizationState},
             subpass::PipelineRenderingCreateInfo,
-            vertex_input::{Vertex as VertexInput, VertexDefinition},
+            vertex_input::{Vertex as VertexInput, VertexDefinition, VertexInputState},
             viewport::ViewportState,
             GraphicsPipelineCreateInfo,
         },
@@ -38,45 +41,82 @@ use vulkano::{
         DynamicState, GraphicsPipeline, Pipeline, PipelineLayout, PipelineShaderStageCreateInfo,
     },
     shader::ShaderStages,
-    sync::{self, GpuFuture},
+    sync::{self, future::FenceSignalFuture, GpuFuture},
     DeviceSize,
 };
 
 use crate::{
-    camera::Mvp,
-    error::Result,
-    index_buffer::setup_index_buffers,
+    animation::Skeleton,
+    bloom::{BloomPipelines, BLOOM_LEVELS},
+    compute::InstanceAnimation,
+    device_limits::DeviceLimits,
+    error::{Result, VisualSystemError},
+    grid::Grid,
     instance_buffer::{self, Instance, InstanceRaw},
-    lighting::{AmbientLight, DirectionalLight, WHITE_AMBIENT_LIGHT},
-    mesh::MeshBuilder,
-    shader::{self, fs, vs, Vertex},
-    textures::{create_sampler, create_texture},
+    lighting::{
+        AmbientLight, DirectionalLight, DirectionalLights, LightPreset, SpotLight, SpotLights,
+        WHITE_AMBIENT_LIGHT,
+    },
+    material::{BlendMode, MaterialFactors, MaterialTextures, MATERIAL_TEXTURE_COUNT},
+    memory_report::MemoryReport,
+    mesh::{Mesh, MeshBuilder, Topology},
+    shader::{
+        self, bloom_bright_fs, bloom_composite_fs, cs, depth_view_fs, depth_view_single_fs, fs,
+        fs_emissive, fs_wireframe, fullscreen_vs, grid_vs, gui_vs, text_vs, vs,
+    },
+    shader_reload::{MainShaders, SHADER_DIR},
+    staging::upload_to_device_buffer,
+    textures::create_sampler,
     vulkan_context::VulkanContext,
     vulkan_instance::VulkanInstance,
 };
+
+// Copies submitted by VulkanDevice::new, see upload_future
+pub type UploadFuture = FenceSignalFuture<Box<dyn GpuFuture + Send + Sync>>;
+
 pub struct VulkanDevice {
     pub device: Arc<Device>,
     pub queue: Arc<Queue>,
+    transfer_queue: Arc<Queue>, // dedicated transfer queue, or the graphics queue
+    limits: DeviceLimits,
     pub memory_allocator: Arc<StandardMemoryAllocator>,
     command_allocator: Arc<StandardCommandBufferAllocator>,
-    graphics_pipeline: Arc<GraphicsPipeline>,
-    pub vertex_buffer: Subbuffer<[shader::Vertex]>,
-    pub instance_buffer: Subbuffer<[InstanceRaw]>,
-    pub index_buffer: Option<Subbuffer<[u32]>>,
-    pub descriptor_set: Arc<PersistentDescriptorSet>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    graphics_pipelines: RefCell<HashMap<(BlendMode, Topology), Arc<GraphicsPipeline>>>, // RefCell: rebuilt when the MSAA sample count, the culling or the shaders change
+    wireframe_pipeline: RefCell<Option<Arc<GraphicsPipeline>>>, // None without fill_mode_non_solid
+    instance_animation: InstanceAnimation,
+    depth_view_pipeline: RefCell<Arc<GraphicsPipeline>>, // RefCell: multisampled or not, see set_samples
+    grid: RefCell<Grid>,                                 // RefCell: same, follows the main pass
+    bloom_pipelines: BloomPipelines,
+    pub meshes: RefCell<Vec<Mesh>>, // RefCell: replaced by load_mesh
+    skeleton: RefCell<Option<Skeleton>>, // RefCell: same, None without skin
+    pub instance_buffer: RefCell<Subbuffer<[InstanceRaw]>>, // RefCell: swapped by set_instance_grid
+    base_instance_buffer: RefCell<Subbuffer<[InstanceRaw]>>, // input of the compute animation
+    instances: RefCell<Vec<InstanceRaw>>, // RefCell: host copy of the base instances, for culling
+    instances_dirty: Cell<bool>,    // host copy not uploaded yet, see update_instances
+    pub compute_descriptor_set: RefCell<Arc<PersistentDescriptorSet>>,
+    upload_futures: RefCell<Vec<Arc<UploadFuture>>>, // RefCell: dropped once the GPU is done
     pub vulkan_context: Rc<RefCell<VulkanContext>>,
-    pub uniform_staging_buffer: Subbuffer<Mvp>,
-    pub uniform_buffer: Subbuffer<Mvp>,
+    ambient_light_buffer: Subbuffer<AmbientLight>,
+    directional_light_buffer: RefCell<Subbuffer<DirectionalLights>>, // RefCell: resized by set_directional_lights
+    spot_light_buffer: RefCell<Subbuffer<SpotLights>>, // RefCell: resized by set_spot_lights
+    sampler: Arc<Sampler>,
 }
 
 impl VulkanDevice {
     pub fn new(
         instance: Arc<VulkanInstance>,
         vulkan_context: Rc<RefCell<VulkanContext>>,
+        model_path: &str,
     ) -> Result<Self> {
         let physical_device = instance.physical_device();
         let queue_family_index = instance.queue_family_index();
         let device_extensions = instance.device_extensions();
+        let transfer_queue_family_index = instance.transfer_queue_family_index();
+
+        // Anisotropic filtering is optional: only enable it when asked for and supported
+        let sampler_anisotropy = vulkan_context.borrow().texture_quality.anisotropic()
+            && physical_device.supported_features().sampler_anisotropy;
 
         // Now initializing the device. This is probably the most important object of Vulkan.
         //
@@ -87,10 +127,14 @@ impl VulkanDevice {
             DeviceCreateInfo {
                 // The list of queues that we are going to use. Here we only use one queue, from the
                 // previously chosen queue family.
-                queue_create_infos: vec![QueueCreateInfo {
-                    queue_family_index,
-                    ..Default::default()
-                }],
+                queue_create_infos: [Some(queue_family_index), transfer_queue_family_index]
+                    .into_iter()
+                    .flatten()
+                    .map(|queue_family_index| QueueCreateInfo {
+                        queue_family_index,
+                        ..Default::default()
+                    })
+                    .collect(),
 
                 // A list of optional features and extensions that our program needs to work correctly.
                 // Some parts of the Vulkan specs are optional and must be enabled manually at device
@@ -106,6 +150,15 @@ impl VulkanDevice {
                 // available, so we don't need to check for support.
                 enabled_features: Features {
                     dynamic_rendering: true,
+                    sampler_anisotropy,
+                    // Wireframe overlay, skipped when not supported
+                    fill_mode_non_solid: physical_device.supported_features().fill_mode_non_solid,
+                    // Point clouds bigger than 1 pixel, when supported
+                    large_points: physical_device.supported_features().large_points,
+                    // Compressed KTX2 textures, refused when not supported
+                    texture_compression_bc: physical_device
+                        .supported_features()
+                        .texture_compression_bc,
                     ..Features::empty()
                 },
 
@@ -117,6 +170,21 @@ impl VulkanDevice {
         // use one queue in this example, so we just retrieve the first and only element of the
         // iterator.
         let queue = queues.next().unwrap();
+        let transfer_queue = queues.next().unwrap_or_else(|| Arc::clone(&queue));
+        info!(
+            "transfer queue family: {}",
+            transfer_queue.queue_family_index()
+        );
+
+        // Limits the renderer depends on: the samples and the point size are clamped to them,
+        // the textures checked against them
+        let limits = DeviceLimits::new(&device);
+        limits.log();
+
+        let samples = limits.clamp_samples(vulkan_context.borrow().samples);
+        vulkan_context.borrow_mut().samples = samples;
+        let point_size = limits.clamp_point_size(vulkan_context.borrow().point_size);
+        vulkan_context.borrow_mut().point_size = point_size;
 
         // Vulkano allocator for both Host and Device
         let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(Arc::clone(&device)));
@@ -139,211 +207,40 @@ impl VulkanDevice {
 
         // ---->
         //
-        let gltf_mesh = MeshBuilder::read_gltf("assets/BoxTextured.gltf")?;
-        let vertices = gltf_mesh.vertices()?;
-        let indices = gltf_mesh.indices();
-        let vertices_length = vertices.len();
-        // let indices_length = indices.len();
-
-        // let indices: Vec<u32> = indices.iter().map(|id| *id as u32).collect();
-
-        // Create a Vertex buffer  : subbuffer<[Vertex]>
-
-        let vertex_buffer = Buffer::new_slice(
-            memory_allocator.clone(),
-            BufferCreateInfo {
-                usage: BufferUsage::VERTEX_BUFFER | BufferUsage::TRANSFER_DST,
-                ..Default::default()
-            },
-            AllocationCreateInfo {
-                memory_type_filter: MemoryTypeFilter {
-                    required_flags: MemoryPropertyFlags::DEVICE_LOCAL, // Make sure this buffer is on the Device=GPU
-                    ..Default::default()
-                },
-                ..Default::default()
-            },
-            vertices_length as DeviceSize,
-        )?;
-
-        // Condition: whether the GTLF contains indices or not?
-        // Option for index staging buffer and index buffer
-        let (index_staging_buffer, index_buffer) =
-            setup_index_buffers(indices, memory_allocator.clone())?;
-
         // Instances for vertex model
         // Create a Vertex buffer  : subbuffer<[InstanceRaw]>
 
-        let instances = Instance::new()
-            .iter()
-            .map(Instance::to_raw)
-            .collect::<Vec<_>>();
+        let instances = Instance::new(
+            vulkan_context.borrow().instances_per_row,
+            vulkan_context.borrow().instance_spacing,
+        )
+        .iter()
+        .map(Instance::to_raw)
+        .collect::<Vec<_>>();
 
         let instances_length = instances.len();
 
         println!("INSTANCES NUMBER: {:}", instances_length);
 
-        let instance_buffer = Buffer::new_slice(
-            memory_allocator.clone(),
-            BufferCreateInfo {
-                usage: BufferUsage::VERTEX_BUFFER | BufferUsage::TRANSFER_DST,
-                ..Default::default()
-            },
-            AllocationCreateInfo {
-                memory_type_filter: MemoryTypeFilter {
-                    required_flags: MemoryPropertyFlags::DEVICE_LOCAL, // Make sure this buffer is on the Device=GPU
-                    ..Default::default()
-                },
-                ..Default::default()
-            },
-            instances_length as DeviceSize,
-        )?;
-
-        // <---  -S T A G I N G  B U F F E R S-
-
-        // Create a Staging Vertex buffer  : subbuffer<[Vertex]>
-
-        // let vertex_staging_buffer = Buffer::from_iter(
-        //     memory_allocator.clone(),
-        //     BufferCreateInfo {
-        //         usage: BufferUsage::TRANSFER_SRC,
-        //         ..Default::default()
-        //     },
-        //     AllocationCreateInfo {
-        //         memory_type_filter: MemoryTypeFilter::PREFER_HOST
-        //             | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
-        //         ..Default::default()
-        //     },
-        //     vertices,
-        // )?;
-
-        let subbuffer_allocator = SubbufferAllocator::new(
-            memory_allocator.clone(),
-            SubbufferAllocatorCreateInfo {
-                arena_size: vertex_buffer.size() + instance_buffer.size(),
-                buffer_usage: BufferUsage::TRANSFER_SRC,
-                memory_type_filter: MemoryTypeFilter::PREFER_HOST
-                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
-                ..Default::default()
-            },
-        );
-
-        let vertex_staging_buffer =
-            subbuffer_allocator.allocate_slice::<Vertex>(vertices_length as DeviceSize)?;
-        let instances_staging_buffer =
-            subbuffer_allocator.allocate_slice::<InstanceRaw>(instances_length as DeviceSize)?;
-
-        {
-            let mut vertex_writer = vertex_staging_buffer.write()?;
-            vertex_writer.copy_from_slice(&vertices);
-            let mut instance_writer = instances_staging_buffer.write()?;
-            instance_writer.copy_from_slice(&instances);
-        }
         // <----
         // Textures
         // ----->
 
-        let mut command_builder = AutoCommandBufferBuilder::primary(
-            &command_allocator,
-            queue_family_index,
-            CommandBufferUsage::OneTimeSubmit,
-        )?;
-
-        let texture = create_texture(
-            "assets/Vulkano_logo.png",
-            &mut command_builder,
-            memory_allocator.clone(),
+        let sampler = create_sampler(
+            Arc::clone(&device),
+            &limits,
+            vulkan_context.borrow().texture_quality,
+            vulkan_context.borrow().sampler_config,
         )?;
 
-        let sampler = create_sampler(Arc::clone(&device))?;
-
-        // <----
-        // Camera
-        // ----->
-
-        let mvp_uniform = vulkan_context.borrow().mvp_uniform().clone();
-
-        // Camera setup
-
-        let uniform_staging_buffer_allocator = SubbufferAllocator::new(
-            memory_allocator.clone(),
-            SubbufferAllocatorCreateInfo {
-                buffer_usage: BufferUsage::TRANSFER_SRC,
-                memory_type_filter: MemoryTypeFilter::PREFER_HOST
-                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
-                ..Default::default()
-            },
-        );
-
-        let uniform_buffer_allocator = SubbufferAllocator::new(
-            memory_allocator.clone(),
-            SubbufferAllocatorCreateInfo {
-                buffer_usage: BufferUsage::UNIFORM_BUFFER | BufferUsage::TRANSFER_DST,
-                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
-                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
-                ..Default::default()
-            },
-        );
-
-        // let uniform_staging_buffer: Subbuffer<CameraUniform> =
-        //     uniform_staging_buffer_allocator.allocate_sized()?;
-        // *uniform_staging_buffer.write()? = *camera_uniform.lock().unwrap();
-
-        // let uniform_buffer: Subbuffer<CameraUniform> =
-        //     uniform_buffer_allocator.allocate_sized().unwrap();
-
-        let uniform_staging_buffer: Subbuffer<Mvp> =
-            uniform_staging_buffer_allocator.allocate_sized()?;
-        *uniform_staging_buffer.write()? = *mvp_uniform.lock().unwrap();
-
-        let uniform_buffer: Subbuffer<Mvp> = uniform_buffer_allocator.allocate_sized().unwrap();
         // ---->
         // Staging buffers to Device buffers
         // <-----
 
-        // command to copy buffer on host to  buffer on device
-        // command builder:
-
-        // build copy command
-        command_builder.copy_buffer(CopyBufferInfo::buffers(
-            vertex_staging_buffer,
-            vertex_buffer.clone(),
-        ))?;
-
-        command_builder.copy_buffer(CopyBufferInfo::buffers(
-            instances_staging_buffer,
-            instance_buffer.clone(),
-        ))?;
-
-        // Condition on index buffer existence
-        // 2 "actions" here
-        // if yes copy_buffer command index staging buffer and index_buffer is Some
-        // otherwise no copy_buffer command and index_buffer option = None
-        let index_buffer = match index_buffer {
-            Some(index_buffer) => match index_staging_buffer {
-                Some(index_staging_buffer) => {
-                    command_builder.copy_buffer(CopyBufferInfo::buffers(
-                        index_staging_buffer,
-                        index_buffer.clone(),
-                    ))?;
-
-                    Some(index_buffer)
-                }
-                None => None,
-            },
-            None => None,
-        };
-
-        command_builder.copy_buffer(CopyBufferInfo::buffers(
-            uniform_staging_buffer.clone(),
-            uniform_buffer.clone(),
-        ))?;
-
-        let command_buffer = command_builder.build()?;
-
-        // submit command
-        let buffers_upload_future = sync::now(Arc::clone(&device))
-            .then_execute(Arc::clone(&queue), command_buffer)?
-            .then_signal_fence_and_flush()?;
+        // Not waited for: the first frame of each renderer joins it, see upload_future
+        let (instance_buffer, base_instance_buffer, upload_future) =
+            upload_instances(&memory_allocator, &command_allocator, &queue, &instances)?;
+        let upload_future = Arc::new(upload_future.then_signal_fence_and_flush()?);
 
         //
 
@@ -359,196 +256,113 @@ impl VulkanDevice {
 
         // Directional Light
 
-        let directional_light = DirectionalLight {
-            position: [1.2, 1.2, 1.9].into(), // Padding for alignment . super tricky to flag. thanks Renderdoc
-            color: [1., 0.2, 0.3],
-        };
-
-        //let directional_light = vec![directional_light.clone()];
+        // Padding for alignment . super tricky to flag. thanks Renderdoc
+        let directional_light = DirectionalLight::new([1.2, 1.2, 1.9], [1., 0.2, 0.3]);
 
-        let directional_lights_subbuffer = DirectionalLight::setup_directional_light_buffers(
-            directional_light,
+        let directional_lights_subbuffer = DirectionalLights::setup_directional_light_buffers(
+            &[directional_light],
             memory_allocator.clone(),
         )?;
 
+        // Spot Lights: none by default, see set_spot_lights
+
+        let spot_lights_subbuffer =
+            SpotLights::setup_spot_light_buffers(&[], memory_allocator.clone())?;
+
         // ---->
         // Graphics Pipeline - Shader
         // ---->
 
-        let graphics_pipeline = {
-            // 👈 scope to make sure shaders are dropped once pipelines are created.
-
-            let vertex_shader = vs::load(Arc::clone(&device))?.entry_point("main").unwrap();
-            let fragment_shader = fs::load(Arc::clone(&device))?.entry_point("main").unwrap();
-
-            // Automatically generate a vertex input state from the vertex shader's input interface,
-            // that takes a single vertex buffer containing `Vertex` structs.
-            let vertex_input_state = [
-                shader::Vertex::per_vertex(),
-                instance_buffer::InstanceRaw::per_instance(),
-            ]
-            .definition(&vertex_shader.info().input_interface)?; // 👈 Don't forget otherwise binding will be missing
-
-            let stages: [PipelineShaderStageCreateInfo; 2] = [
-                PipelineShaderStageCreateInfo::new(vertex_shader),
-                PipelineShaderStageCreateInfo::new(fragment_shader),
-            ];
-
-            // We must now create a **pipeline layout** object, which describes the locations and types of
-            // descriptor sets and push constants used by the shaders in the pipeline.
-            //
-            // Multiple pipelines can share a common layout object, which is more efficient.
-            // The shaders in a pipeline must use a subset of the resources described in its pipeline
-            // layout, but the pipeline layout is allowed to contain resources that are not present in the
-            // shaders; they can be used by shaders in other pipelines that share the same layout.
-            // Thus, it is a good idea to design shaders so that many pipelines have common resource
-            // locations, which allows them to share pipeline layouts.
-            // let layout = PipelineLayout::new(
-            //     Arc::clone(&device),
-            //     // Since we only have one pipeline in this example, and thus one pipeline layout,
-            //     // we automatically generate the creation info for it from the resources used in the
-            //     // shaders. In a real application, you would specify this information manually so that you
-            //     // can re-use one layout in multiple pipelines.
-            //     PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
-            //         .into_pipeline_layout_create_info(Arc::clone(&device))?,
-            // )?;
-
-            let layout = {
-                let mut layout_create_info =
-                    PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages);
-
-                let set_layout = &mut layout_create_info.set_layouts[0];
-                set_layout.bindings.insert(
-                    1,
-                    DescriptorSetLayoutBinding {
-                        descriptor_type: DescriptorType::UniformBuffer,
-                        descriptor_count: 1,
-                        stages: ShaderStages::FRAGMENT,
-                        ..DescriptorSetLayoutBinding::descriptor_type(DescriptorType::UniformBuffer)
-                    },
-                );
-
-                set_layout.bindings.insert(
-                    2,
-                    DescriptorSetLayoutBinding {
-                        descriptor_type: DescriptorType::UniformBuffer,
-                        descriptor_count: 1,
-                        stages: ShaderStages::FRAGMENT,
-                        ..DescriptorSetLayoutBinding::descriptor_type(DescriptorType::UniformBuffer)
-                    },
-                );
-
-                PipelineLayout::new(
-                    Arc::clone(&device),
-                    layout_create_info.into_pipeline_layout_create_info(Arc::clone(&device))?,
-                )?
-            };
-
-            // We describe the formats of attachment images where the colors, depth and/or stencil
-            // information will be written. The pipeline will only be usable with this particular
-            // configuration of the attachment images.
-            let subpass = PipelineRenderingCreateInfo {
-                // We specify a single color attachment that will be rendered to. When we begin
-                // rendering, we will specify a swapchain image to be used as this attachment, so here
-                // we set its format to be the same format as the swapchain.
-                color_attachment_formats: vec![Some(Format::B8G8R8A8_SRGB)], // ⚠ Caution! Hard coded
-                depth_attachment_format: Some(Format::D16_UNORM),
-                ..Default::default()
-            };
-
-            GraphicsPipeline::new(
-                Arc::clone(&device),
-                None,
-                GraphicsPipelineCreateInfo {
-                    stages: stages.into_iter().collect(),
-                    // How vertex data is read from the vertex buffers into the vertex shader.
-                    vertex_input_state: Some(vertex_input_state), // 👈 Do not forget
-                    // How vertices are arranged into primitive shapes.
-                    // The default primitive shape is a triangle.
-                    input_assembly_state: Some(InputAssemblyState::default()),
-                    // How primitives are transformed and clipped to fit the framebuffer.
-                    // We use a resizable viewport, set to draw over the entire window.
-                    viewport_state: Some(ViewportState::default()),
-                    // How polygons are culled and converted into a raster of pixels.
-                    // The default value does not perform any culling.
-                    rasterization_state: Some(RasterizationState {
-                        cull_mode: CullMode::Back,
-                        ..Default::default()
-                    }),
-                    // Depth
-                    depth_stencil_state: Some(DepthStencilState {
-                        // Simple = CompareOp::Less,
-                        depth: Some(DepthState::simple()),
-                        ..Default::default()
-                    }),
-                    // How multiple fragment shader samples are converted to a single pixel value.
-                    // The default value does not perform any multisampling.
-                    //Original without MSAA 👉 multisample_state: Some(MultisampleState::default()),
-                    multisample_state: Some(MultisampleState {
-                        // MSAA
-                        rasterization_samples: vulkan_context.borrow().samples, //SampleCount::Sample4,
-                        ..Default::default()
-                    }),
-                    // How pixel values are combined with the values already present in the framebuffer.
-                    // The default value overwrites the old value with the new one, without any blending.
-                    color_blend_state: Some(ColorBlendState::with_attachment_states(
-                        subpass.color_attachment_formats.len() as u32,
-                        ColorBlendAttachmentState::default(),
-                    )),
-                    // Dynamic states allows us to specify parts of the pipeline settings when
-                    // recording the command buffer, before we perform drawing.
-                    // Here, we specify that the viewport should be dynamic.
-                    dynamic_state: [DynamicState::Viewport].into_iter().collect(),
-                    subpass: Some(subpass.into()),
-                    ..GraphicsPipelineCreateInfo::layout(layout)
-                },
-            )?
-        };
+        let graphics_pipelines =
+            create_graphics_pipelines(&device, &vulkan_context.borrow(), None)?;
+        let wireframe_pipeline = create_wireframe_pipeline(
+            &device,
+            &vulkan_context.borrow(),
+            Arc::clone(graphics_pipelines[&(BlendMode::Opaque, Topology::Triangles)].layout()),
+        )?;
+
+        let depth_view_pipeline = create_depth_view_pipeline(&device, &vulkan_context.borrow())?;
+        let grid = Grid::new(&device, &vulkan_context.borrow())?;
+        let bloom_pipelines = BloomPipelines::new(&device, &vulkan_context.borrow())?;
+
+        // ---->
+        // Compute Pipeline - GPU instance transforms
+        // ---->
+
+        let instance_animation = InstanceAnimation::new(&device)?;
 
-        let descriptor_set = PersistentDescriptorSet::new(
+        let compute_descriptor_set = instance_animation.create_descriptor_set(
             &descriptor_set_allocator,
-            Arc::clone(
-                graphics_pipeline
-                    .layout()
-                    .set_layouts()
-                    .first()
-                    .expect("error getting the layout"),
-            ),
-            [
-                WriteDescriptorSet::buffer(0, uniform_buffer.clone()),
-                WriteDescriptorSet::buffer(1, ambient_light_subbuffer.clone()),
-                WriteDescriptorSet::buffer(2, directional_lights_subbuffer.clone()),
-                WriteDescriptorSet::image_view_sampler(
-                    3,
-                    Arc::clone(&texture),
-                    Arc::clone(&sampler),
-                ),
-            ],
-            [],
+            base_instance_buffer.clone(),
+            instance_buffer.clone(),
         )?;
 
-        buffers_upload_future.wait(None)?; // Not sure this works? Is this needed
-
-        Ok(Self {
+        let vulkan_device = Self {
             device,
             queue,
+            transfer_queue,
+            limits,
             memory_allocator,
             command_allocator,
-            graphics_pipeline,
-            vertex_buffer,
-            index_buffer,
-            instance_buffer,
-            descriptor_set,
+            descriptor_set_allocator,
+            graphics_pipelines: RefCell::new(graphics_pipelines),
+            wireframe_pipeline: RefCell::new(wireframe_pipeline),
+            instance_animation,
+            depth_view_pipeline: RefCell::new(depth_view_pipeline),
+            grid: RefCell::new(grid),
+            bloom_pipelines,
+            meshes: RefCell::new(Vec::new()),
+            skeleton: RefCell::new(None),
+            instance_buffer: RefCell::new(instance_buffer),
+            base_instance_buffer: RefCell::new(base_instance_buffer),
+            instances: RefCell::new(instances),
+            instances_dirty: Cell::new(false),
+            compute_descriptor_set: RefCell::new(compute_descriptor_set),
+            upload_futures: RefCell::new(vec![upload_future]),
             vulkan_context,
-            uniform_staging_buffer,
-            uniform_buffer,
-        })
+            ambient_light_buffer: ambient_light_subbuffer,
+            directional_light_buffer: RefCell::new(directional_lights_subbuffer),
+            spot_light_buffer: RefCell::new(spot_lights_subbuffer),
+            sampler,
+        };
+
+        // ---->
+        // Meshes
+        // ---->
+
+        // Not waited for either: the textures and vertices are the bulk of the startup uploads
+        let (meshes, skeleton, mesh_uploads) = vulkan_device.read_meshes(model_path)?;
+        *vulkan_device.meshes.borrow_mut() = meshes;
+        *vulkan_device.skeleton.borrow_mut() = skeleton;
+        vulkan_device
+            .upload_futures
+            .borrow_mut()
+            .extend(mesh_uploads);
+
+        Ok(vulkan_device)
     }
 
     pub fn queue(&self) -> &Arc<Queue> {
         &self.queue
     }
 
+    // Snapshot taken at startup
+    pub fn limits(&self) -> &DeviceLimits {
+        &self.limits
+    }
+
+    // Queue families accessing the mesh buffers: graphics, plus transfer when separate
+    pub fn queue_family_indices(&self) -> Vec<u32> {
+        let graphics = self.queue.queue_family_index();
+        let transfer = self.transfer_queue.queue_family_index();
+
+        if graphics == transfer {
+            vec![graphics]
+        } else {
+            vec![graphics, transfer]
+        }
+    }
+
     #[allow(unused)]
     pub fn memory_allocator(&self) -> &Arc<StandardMemoryAllocator> {
         &self.memory_allocator
@@ -558,24 +372,444 @@ impl VulkanDevice {
         &self.command_allocator
     }
 
-    pub fn graphics_pipeline(&self) -> &Arc<GraphicsPipeline> {
-        &self.graphics_pipeline
+    pub fn graphics_pipeline(
+        &self,
+        blend_mode: BlendMode,
+        topology: Topology,
+    ) -> Arc<GraphicsPipeline> {
+        Arc::clone(&self.graphics_pipelines.borrow()[&(blend_mode, topology)])
+    }
+
+    pub fn wireframe_pipeline(&self) -> Option<Arc<GraphicsPipeline>> {
+        self.wireframe_pipeline.borrow().clone()
+    }
+
+    // Shared by all the graphics pipelines
+    pub fn graphics_pipeline_layout(&self) -> Arc<PipelineLayout> {
+        Arc::clone(
+            self.graphics_pipeline(BlendMode::Opaque, Topology::Triangles)
+                .layout(),
+        )
     }
 
-    pub fn index_buffer(&self) -> &Option<Subbuffer<[u32]>> {
-        &self.index_buffer
+    pub fn depth_view_pipeline(&self) -> Arc<GraphicsPipeline> {
+        Arc::clone(&self.depth_view_pipeline.borrow())
     }
 
-    pub fn descriptor_set(&self) -> &Arc<PersistentDescriptorSet> {
-        &self.descriptor_set
+    pub fn grid(&self) -> Ref<'_, Grid> {
+        self.grid.borrow()
+    }
+
+    pub fn bloom_pipelines(&self) -> &BloomPipelines {
+        &self.bloom_pipelines
+    }
+
+    pub fn descriptor_set_allocator(&self) -> &Arc<StandardDescriptorSetAllocator> {
+        &self.descriptor_set_allocator
+    }
+
+    pub fn instance_animation(&self) -> &InstanceAnimation {
+        &self.instance_animation
+    }
+
+    pub fn compute_descriptor_set(&self) -> Arc<PersistentDescriptorSet> {
+        Arc::clone(&self.compute_descriptor_set.borrow())
+    }
+
+    // The startup uploads of the instance buffers and meshes, while the GPU may still be running
+    // them. A renderer starts its chain of frames with their join: the first frame waits for the
+    // copies on the GPU, the startup doesn't on the CPU.
+    pub fn upload_future(&self) -> Result<Option<Box<dyn GpuFuture>>> {
+        let mut upload_futures = self.upload_futures.borrow_mut();
+        let mut pending = Vec::with_capacity(upload_futures.len());
+        for future in upload_futures.drain(..) {
+            if !future.is_signaled()? {
+                pending.push(future);
+            }
+        }
+        *upload_futures = pending;
+
+        Ok(upload_futures
+            .iter()
+            .map(|future| Arc::clone(future).boxed())
+            .reduce(|joined, future| joined.join(future).boxed()))
+    }
+
+    pub fn instance_buffer(&self) -> Subbuffer<[InstanceRaw]> {
+        self.instance_buffer.borrow().clone()
+    }
+
+    // Input of the compute animation
+    pub fn base_instance_buffer(&self) -> Subbuffer<[InstanceRaw]> {
+        self.base_instance_buffer.borrow().clone()
+    }
+
+    // The transforms uploaded to the instance buffer, before the compute animation
+    pub fn instances(&self) -> Ref<'_, Vec<InstanceRaw>> {
+        self.instances.borrow()
+    }
+
+    pub fn meshes(&self) -> Ref<'_, Vec<Mesh>> {
+        self.meshes.borrow()
+    }
+
+    // Joint palette of the skinned meshes of the model, see animation.rs
+    pub fn skeleton(&self) -> Ref<'_, Option<Skeleton>> {
+        self.skeleton.borrow()
     }
 /*     pub fn vulkan_context(&self) -> &Arc<VulkanContext> {
         &self.vulkan_context()
     } */
 
-    pub fn update_uniform_buffer(&self) -> Result<()> {
-        *self.uniform_staging_buffer.write()? =
-            *self.vulkan_context.borrow().mvp_uniform().lock().unwrap();
+    // Per heap GPU memory usage, logged via tracing.
+    // Uses ext_memory_budget when available, the static heap sizes otherwise.
+    pub fn memory_report(&self) -> MemoryReport {
+        let report = MemoryReport::new(self.device.physical_device());
+        report.log();
+        report
+    }
+
+    // MSAA sample counts usable for both the color and the depth attachments, 1 (MSAA off) first
+    pub fn supported_sample_counts(&self) -> Vec<SampleCount> {
+        let counts = self.limits.sample_counts;
+
+        [
+            SampleCount::Sample1,
+            SampleCount::Sample2,
+            SampleCount::Sample4,
+            SampleCount::Sample8,
+            SampleCount::Sample16,
+            SampleCount::Sample32,
+            SampleCount::Sample64,
+        ]
+        .into_iter()
+        .filter(|samples| counts.contains_enum(*samples))
+        .collect()
+    }
+
+    // Switch to the next supported MSAA sample count and rebuild the graphics pipeline.
+    // The renderers must recreate their intermediary and depth images afterwards.
+    pub fn cycle_samples(&self) -> Result<SampleCount> {
+        let supported = self.supported_sample_counts();
+        let current = self.vulkan_context.borrow().samples;
+
+        let samples = supported
+            .iter()
+            .position(|samples| *samples == current)
+            .map_or(supported.first(), |index| {
+                supported.get((index + 1) % supported.len())
+            })
+            .copied()
+            .unwrap_or(current);
+
+        self.set_samples(samples)?;
+
+        Ok(samples)
+    }
+
+    // Use `samples` (one of supported_sample_counts) and rebuild the graphics pipeline.
+    // The renderers must recreate their intermediary and depth images afterwards.
+    pub fn set_samples(&self, samples: SampleCount) -> Result<()> {
+        if samples == self.vulkan_context.borrow().samples {
+            return Ok(());
+        }
+
+        // The old pipeline can still be in use by in-flight frames
+        self.queue.with(|mut queue| queue.wait_idle())?;
+
+        self.vulkan_context.borrow_mut().samples = samples;
+        self.rebuild_pipelines()?;
+
+        info!("MSAA: {} samples", samples as u32);
+
+        Ok(())
+    }
+
+    // Next cull mode: Back, Front, None. Handy to spot models with a reversed winding.
+    pub fn cycle_cull_mode(&self) -> Result<CullMode> {
+        let cull_mode = match self.vulkan_context.borrow().cull_mode {
+            CullMode::Back => CullMode::Front,
+            CullMode::Front => CullMode::None,
+            _ => CullMode::Back,
+        };
+
+        self.set_cull_mode(cull_mode)?;
+
+        Ok(cull_mode)
+    }
+
+    // The cull mode is baked in the graphics pipelines: rebuild them
+    pub fn set_cull_mode(&self, cull_mode: CullMode) -> Result<()> {
+        if cull_mode == self.vulkan_context.borrow().cull_mode {
+            return Ok(());
+        }
+
+        self.queue.with(|mut queue| queue.wait_idle())?;
+
+        self.vulkan_context.borrow_mut().cull_mode = cull_mode;
+        self.rebuild_pipelines()?;
+
+        info!("cull mode: {cull_mode:?}");
+
+        Ok(())
+    }
+
+    pub fn toggle_front_face(&self) -> Result<FrontFace> {
+        let front_face = match self.vulkan_context.borrow().front_face {
+            FrontFace::CounterClockwise => FrontFace::Clockwise,
+            _ => FrontFace::CounterClockwise,
+        };
+
+        self.set_front_face(front_face)?;
+
+        Ok(front_face)
+    }
+
+    // Which winding is the front of a triangle, baked in the graphics pipelines as well
+    pub fn set_front_face(&self, front_face: FrontFace) -> Result<()> {
+        if front_face == self.vulkan_context.borrow().front_face {
+            return Ok(());
+        }
+
+        self.queue.with(|mut queue| queue.wait_idle())?;
+
+        self.vulkan_context.borrow_mut().front_face = front_face;
+        self.rebuild_pipelines()?;
+
+        info!("front face: {front_face:?}");
+
+        Ok(())
+    }
+
+    // Read the main pass shaders again from SHADER_DIR and rebuild the graphics pipelines.
+    // On error the current shaders and pipelines are kept.
+    pub fn reload_shaders(&self) -> Result<()> {
+        let shaders = MainShaders::load(&self.device, Path::new(SHADER_DIR))?;
+        if shaders.is_empty() {
+            info!("no shaders in {SHADER_DIR}/, using the built-in ones");
+        }
+
+        self.queue.with(|mut queue| queue.wait_idle())?;
+
+        let previous = std::mem::replace(
+            &mut self.vulkan_context.borrow_mut().reloaded_shaders,
+            shaders,
+        );
+        if let Err(error) = self.rebuild_pipelines() {
+            self.vulkan_context.borrow_mut().reloaded_shaders = previous;
+            return Err(error);
+        }
+
+        info!("shaders reloaded");
+
+        Ok(())
+    }
+
+    // After a change of the pipeline state in the context. The caller waits for the in-flight
+    // frames first. Nothing is replaced when one of the pipelines fails.
+    fn rebuild_pipelines(&self) -> Result<()> {
+        // Same layout: the descriptor sets stay valid
+        let layout = self.graphics_pipeline_layout();
+        let vulkan_context = self.vulkan_context.borrow();
+        let graphics_pipelines =
+            create_graphics_pipelines(&self.device, &vulkan_context, Some(Arc::clone(&layout)))?;
+        let wireframe_pipeline = create_wireframe_pipeline(&self.device, &vulkan_context, layout)?;
+        let depth_view_pipeline = create_depth_view_pipeline(&self.device, &vulkan_context)?;
+        let grid = Grid::new(&self.device, &vulkan_context)?;
+
+        *self.graphics_pipelines.borrow_mut() = graphics_pipelines;
+        *self.wireframe_pipeline.borrow_mut() = wireframe_pipeline;
+        *self.depth_view_pipeline.borrow_mut() = depth_view_pipeline;
+        *self.grid.borrow_mut() = grid;
+
+        Ok(())
+    }
+
+    // Block until the GPU has finished every submission, e.g. before tearing resources down
+    pub fn wait_idle(&self) -> Result<()> {
+        // Safety: single threaded, nothing is submitted to the queues while waiting
+        unsafe { self.device.wait_idle()? };
+
+        Ok(())
+    }
+
+    pub fn ambient_light(&self) -> Result<AmbientLight> {
+        Ok(*self.ambient_light_buffer.read()?)
+    }
+
+    // The light buffers can still be read by in-flight frames
+    pub fn set_ambient_light(&self, ambient_light: AmbientLight) -> Result<()> {
+        self.queue.with(|mut queue| queue.wait_idle())?;
+        *self.ambient_light_buffer.write()? = ambient_light;
+
+        Ok(())
+    }
+
+    pub fn directional_lights(&self) -> Result<Vec<DirectionalLight>> {
+        let buffer = self.directional_light_buffer.borrow();
+        let contents = buffer.read()?;
+
+        Ok(contents.lights[..*contents.count as usize].to_vec())
+    }
+
+    // Written in place when the count is unchanged, otherwise a new buffer (and descriptor sets)
+    pub fn set_directional_lights(&self, directional_lights: &[DirectionalLight]) -> Result<()> {
+        self.queue.with(|mut queue| queue.wait_idle())?;
+
+        {
+            let buffer = self.directional_light_buffer.borrow();
+            let mut contents = buffer.write()?;
+            if contents.lights.len() == directional_lights.len().max(1) {
+                contents.count = (directional_lights.len() as u32).into();
+                contents.lights[..directional_lights.len()].copy_from_slice(directional_lights);
+                return Ok(());
+            }
+        }
+
+        *self.directional_light_buffer.borrow_mut() =
+            DirectionalLights::setup_directional_light_buffers(
+                directional_lights,
+                self.memory_allocator.clone(),
+            )?;
+        self.rebuild_descriptor_set()
+    }
+
+    // Ambient, directional and spot lights of a built-in rig
+    pub fn set_light_preset(&self, preset: LightPreset) -> Result<()> {
+        let (ambient_light, directional_lights) = preset.lights();
+        self.set_ambient_light(ambient_light)?;
+        // Rebuilds the descriptor sets when the number of lights changes
+        self.set_directional_lights(&directional_lights)?;
+        self.set_spot_lights(&preset.spot_lights())
+    }
+
+    // Same as set_directional_lights
+    pub fn set_spot_lights(&self, spot_lights: &[SpotLight]) -> Result<()> {
+        self.queue.with(|mut queue| queue.wait_idle())?;
+
+        {
+            let buffer = self.spot_light_buffer.borrow();
+            let mut contents = buffer.write()?;
+            if contents.lights.len() == spot_lights.len().max(1) {
+                contents.count = (spot_lights.len() as u32).into();
+                contents.lights[..spot_lights.len()].copy_from_slice(spot_lights);
+                return Ok(());
+            }
+        }
+
+        *self.spot_light_buffer.borrow_mut() =
+            SpotLights::setup_spot_light_buffers(spot_lights, self.memory_allocator.clone())?;
+        self.rebuild_descriptor_set()
+    }
+
+    // Blinn-Phong (shininess, specular strength) of the first mesh, see MaterialFactors
+    pub fn specular(&self) -> Result<(f32, f32)> {
+        Ok(self.meshes.borrow().first().map_or((0.0, 0.0), |mesh| {
+            (
+                mesh.material.factors.shininess,
+                mesh.material.factors.specular_strength,
+            )
+        }))
+    }
+
+    // Same highlight for every mesh, a shininess of 0 restores the metallic-roughness shading.
+    // The material buffers can still be read by in-flight frames.
+    pub fn set_specular(&self, shininess: f32, specular_strength: f32) -> Result<()> {
+        self.queue.with(|mut queue| queue.wait_idle())?;
+
+        for mesh in self.meshes.borrow_mut().iter_mut() {
+            let factors = mesh
+                .material
+                .factors
+                .with_specular(shininess, specular_strength);
+            mesh.material.factors = factors;
+            *mesh.material_buffer.write()? = factors;
+        }
+
+        Ok(())
+    }
+
+    // Load a new model at runtime and swap it in place of the current meshes.
+    // One mesh per part (glTF primitive) of the model.
+    pub fn load_mesh(&self, path: &str) -> Result<()> {
+        let (meshes, skeleton, uploads) = self.read_meshes(path)?;
+        for upload in uploads {
+            upload.wait(None)?;
+        }
+
+        // The old buffers can still be in use by in-flight frames.
+        // Wait for the GPU to be idle before releasing them.
+        self.queue.with(|mut queue| queue.wait_idle())?;
+
+        info!("mesh loaded: {path}, {} parts", meshes.len());
+
+        *self.meshes.borrow_mut() = meshes;
+        *self.skeleton.borrow_mut() = skeleton;
+
+        Ok(())
+    }
+
+    // Read a model and submit the uploads of its meshes, without waiting for them
+    fn read_meshes(&self, path: &str) -> Result<ModelUpload> {
+        let default_texture = self.vulkan_context.borrow().default_texture.clone();
+        let mut meshes = Vec::new();
+        let mut uploads = Vec::new();
+        let (builders, skeleton) = MeshBuilder::read(path)?;
+        for mesh in builders {
+            let (mesh, mesh_uploads) = self
+                .create_mesh_from_builder(&mesh.with_default_texture(default_texture.as_deref()))?;
+            meshes.push(mesh);
+            uploads.extend(mesh_uploads);
+        }
+
+        Ok((meshes, skeleton, uploads))
+    }
+
+    // Upload the vertices, indices and material textures of a mesh to the GPU.
+    // The model may or may not have indices. Every mesh is drawn with the instance grid.
+    // Vertex and index copies go through the transfer queue. The textures stay on the graphics
+    // queue: blit_image (mipmaps) needs a graphics capable queue.
+    // Returns the two submitted copies, still running: the mesh can't be drawn before them.
+    pub fn create_mesh_from_builder(
+        &self,
+        mesh: &MeshBuilder,
+    ) -> Result<(Mesh, [Arc<UploadFuture>; 2])> {
+        let queue_family_indices = self.queue_family_indices();
+        let vertices = mesh.vertices()?;
+
+        let mut transfer_builder = AutoCommandBufferBuilder::primary(
+            &self.command_allocator,
+            self.transfer_queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )?;
+
+        let vertex_buffer = upload_to_device_buffer(
+            vertices,
+            BufferUsage::VERTEX_BUFFER,
+            &queue_family_indices,
+            &self.memory_allocator,
+            &mut transfer_builder,
+        )?;
+
+        let indices = mesh.indices();
+        let index_buffer = if indices.is_empty() {
+            None
+        } else {
+            Some(upload_to_device_buffer(
+                indices,
+                BufferUsage::INDEX_BUFFER,
+                &queue_family_indices,
+                &self.memory_allocator,
+                &mut transfer_builder,
+            )?)
+        };
+
+        let transfer_command_buffer = transfer_builder.build()?;
+
+        let transfer_future = sync::now(Arc::clone(&self.device))
+            .then_execute(Arc::clone(&self.transfer_queue), transfer_command_buffer)?
+            .boxed_send_sync()
+            .then_signal_fence_and_flush()?;
 
         let mut command_builder = AutoCommandBufferBuilder::primary(
             &self.command_allocator,
@@ -583,19 +817,697 @@ impl VulkanDevice {
             CommandBufferUsage::OneTimeSubmit,
         )?;
 
-        command_builder.copy_buffer(CopyBufferInfo::buffers(
-            self.uniform_staging_buffer.clone(),
-            self.uniform_buffer.clone(),
-        ))?;
+        let material_textures = MaterialTextures::new(
+            mesh.material(),
+            &mut command_builder,
+            self.memory_allocator.clone(),
+            &self.limits,
+        )?;
+        let material_buffer = mesh
+            .material()
+            .factors
+            .setup_material_buffer(self.memory_allocator.clone())?;
 
         let command_buffer = command_builder.build()?;
 
-        // submit command
-        let buffers_upload_future = sync::now(Arc::clone(&self.device))
+        let graphics_future = sync::now(Arc::clone(&self.device))
             .then_execute(Arc::clone(&self.queue), command_buffer)?
+            .boxed_send_sync()
             .then_signal_fence_and_flush()?;
 
-        buffers_upload_future.wait(None)?;
+        let descriptor_set = create_descriptor_set(
+            &self.descriptor_set_allocator,
+            &self.graphics_pipeline_layout(),
+            self.ambient_light_buffer.clone(),
+            self.directional_light_buffer.borrow().clone(),
+            self.spot_light_buffer.borrow().clone(),
+            material_buffer.clone(),
+            &material_textures,
+            &self.sampler,
+        )?;
+
+        let instance_buffer = self.instance_buffer();
+
+        let gpu_mesh = Mesh {
+            vertex_buffer,
+            index_buffer,
+            first_instance: 0,
+            instance_count: instance_buffer.len() as u32,
+            instance_buffer,
+            material: mesh.material().clone(),
+            material_buffer,
+            material_textures,
+            emissive: mesh.material().emissive_only,
+            topology: mesh.topology(),
+            bounds: mesh.bounding_box(),
+            // Not culled when skinned: the animation moves it out of its bind pose bounds
+            bounding_sphere: mesh
+                .bounding_box()
+                .filter(|_| !mesh.is_skinned())
+                .map(|_| mesh.bounding_sphere()),
+            descriptor_set,
+        };
+
+        Ok((
+            gpu_mesh,
+            [Arc::new(graphics_future), Arc::new(transfer_future)],
+        ))
+    }
+
+    // Re-create the set 1 of every mesh from the current light buffers, material buffers and
+    // textures. For the setters that replace a buffer or a texture instead of writing into it.
+    pub fn rebuild_descriptor_set(&self) -> Result<()> {
+        // The old sets can still be in use by in-flight frames
+        self.queue.with(|mut queue| queue.wait_idle())?;
+
+        let layout = self.graphics_pipeline_layout();
+        for mesh in self.meshes.borrow_mut().iter_mut() {
+            mesh.descriptor_set = create_descriptor_set(
+                &self.descriptor_set_allocator,
+                &layout,
+                self.ambient_light_buffer.clone(),
+                self.directional_light_buffer.borrow().clone(),
+                self.spot_light_buffer.borrow().clone(),
+                mesh.material_buffer.clone(),
+                &mesh.material_textures,
+                &self.sampler,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    // World space bounding boxes of every drawn instance of every mesh, for the camera clamp.
+    // From the uploaded instance transforms (see instances), as the culling: the compute shader
+    // animation is not taken into account.
+    pub fn collision_boxes(&self, model: &Matrix4<f32>) -> Vec<([f32; 3], [f32; 3])> {
+        let instances = self.instances();
+
+        self.meshes()
+            .iter()
+            .filter_map(|mesh| mesh.bounds.map(|bounds| (mesh, bounds)))
+            .flat_map(|(mesh, (min, max))| {
+                let first = (mesh.first_instance as usize).min(instances.len());
+                let last = (first + mesh.instance_count as usize).min(instances.len());
+
+                instances[first..last].iter().map(move |instance| {
+                    let transform = model * instance.matrix();
+
+                    // The 8 corners, transformed, then boxed again
+                    let mut world_min = [f32::MAX; 3];
+                    let mut world_max = [f32::MIN; 3];
+                    for corner in 0..8 {
+                        let point = transform.transform_point(&Point3::new(
+                            if corner & 1 == 0 { min[0] } else { max[0] },
+                            if corner & 2 == 0 { min[1] } else { max[1] },
+                            if corner & 4 == 0 { min[2] } else { max[2] },
+                        ));
+                        for axis in 0..3 {
+                            world_min[axis] = world_min[axis].min(point[axis]);
+                            world_max[axis] = world_max[axis].max(point[axis]);
+                        }
+                    }
+
+                    (world_min, world_max)
+                })
+            })
+            .collect()
+    }
+
+    // World space (min, max) box around every drawn instance of every mesh, to frame the camera.
+    // None without any vertex.
+    pub fn scene_bounds(&self, model: &Matrix4<f32>) -> Option<([f32; 3], [f32; 3])> {
+        self.collision_boxes(model)
+            .into_iter()
+            .reduce(|(min, max), (box_min, box_max)| {
+                (
+                    std::array::from_fn(|axis| min[axis].min(box_min[axis])),
+                    std::array::from_fn(|axis| max[axis].max(box_max[axis])),
+                )
+            })
+    }
+
+    // Overwrite the instance transforms, e.g. every frame to animate them. No GPU wait: the next
+    // recorded frame copies them, see VulkanRenderer::record_instance_upload.
+    // The buffer keeps its size: `instances` must have one entry per instance of the current
+    // grid (see set_instance_grid to change the count). The compute animation, when enabled,
+    // starts from these transforms.
+    pub fn update_instances(&self, instances: &[InstanceRaw]) -> Result<()> {
+        check_instance_count(self.instance_buffer.borrow().len(), instances.len())?;
+
+        *self.instances.borrow_mut() = instances.to_vec();
+        self.instances_dirty.set(true);
+
+        Ok(())
+    }
+
+    // The transforms of update_instances, once: the first frame recorded after the update
+    // uploads them for every window
+    pub fn take_pending_instances(&self) -> Option<Ref<'_, Vec<InstanceRaw>>> {
+        self.instances_dirty
+            .replace(false)
+            .then(|| self.instances.borrow())
+    }
+
+    // Regenerate the instance grid and re-upload the instance buffer.
+    pub fn set_instance_grid(&self, rows: u32, spacing: f32) -> Result<()> {
+        let rows = rows.max(1);
+
+        let instances = Instance::new(rows, spacing)
+            .iter()
+            .map(Instance::to_raw)
+            .collect::<Vec<_>>();
+        let instances_length = instances.len();
+
+        let (instance_buffer, base_instance_buffer, upload_future) = upload_instances(
+            &self.memory_allocator,
+            &self.command_allocator,
+            &self.queue,
+            &instances,
+        )?;
+        upload_future.then_signal_fence_and_flush()?.wait(None)?;
+
+        let compute_descriptor_set = self.instance_animation.create_descriptor_set(
+            &self.descriptor_set_allocator,
+            base_instance_buffer.clone(),
+            instance_buffer.clone(),
+        )?;
+
+        // Wait for in-flight frames before releasing the old instance buffers
+        self.queue.with(|mut queue| queue.wait_idle())?;
+
+        // Every mesh draws the whole new grid
+        for mesh in self.meshes.borrow_mut().iter_mut() {
+            mesh.instance_buffer = instance_buffer.clone();
+            mesh.first_instance = 0;
+            mesh.instance_count = instances_length as u32;
+        }
+
+        *self.instance_buffer.borrow_mut() = instance_buffer;
+        *self.base_instance_buffer.borrow_mut() = base_instance_buffer;
+        *self.instances.borrow_mut() = instances;
+        self.instances_dirty.set(false);
+        *self.compute_descriptor_set.borrow_mut() = compute_descriptor_set;
+
+        let mut vulkan_context = self.vulkan_context.borrow_mut();
+        vulkan_context.instances_per_row = rows;
+        vulkan_context.instance_spacing = spacing;
+
+        info!("INSTANCES NUMBER: {instances_length}");
+
         Ok(())
     }
 }
+
+// The buffers, images and pipelines must not be freed while the GPU still uses them
+impl Drop for VulkanDevice {
+    fn drop(&mut self) {
+        if let Err(e) = self.wait_idle() {
+            error!("failed to wait for the device to be idle: {e}");
+        }
+    }
+}
+
+// Minimums of the Vulkan spec: every device has at least these limits
+const MIN_PUSH_CONSTANTS_SIZE: usize = 128; // maxPushConstantsSize
+const MIN_BOUND_DESCRIPTOR_SETS: u32 = 4; // maxBoundDescriptorSets
+const MIN_PER_STAGE_SAMPLERS: u32 = 16; // maxPerStageDescriptorSamplers and SampledImages
+
+// The push constants and the descriptor sets of the pipelines fit them: no device check needed
+const _: () = {
+    use std::mem::size_of;
+
+    assert!(size_of::<vs::PushConstantData>() <= MIN_PUSH_CONSTANTS_SIZE);
+    assert!(size_of::<cs::PushConstantData>() <= MIN_PUSH_CONSTANTS_SIZE);
+    assert!(size_of::<depth_view_fs::PushConstantData>() <= MIN_PUSH_CONSTANTS_SIZE);
+    assert!(size_of::<grid_vs::PushConstantData>() <= MIN_PUSH_CONSTANTS_SIZE);
+    assert!(size_of::<bloom_bright_fs::PushConstantData>() <= MIN_PUSH_CONSTANTS_SIZE);
+    assert!(size_of::<bloom_composite_fs::PushConstantData>() <= MIN_PUSH_CONSTANTS_SIZE);
+    assert!(size_of::<text_vs::PushConstantData>() <= MIN_PUSH_CONSTANTS_SIZE);
+    assert!(size_of::<gui_vs::PushConstantData>() <= MIN_PUSH_CONSTANTS_SIZE);
+
+    // Set 0: camera. Set 1: lights and the material textures
+    assert!(2 <= MIN_BOUND_DESCRIPTOR_SETS);
+    assert!(MATERIAL_TEXTURE_COUNT <= MIN_PER_STAGE_SAMPLERS);
+    // Bloom composite: scene + blurred levels
+    assert!(BLOOM_LEVELS as u32 + 1 <= MIN_PER_STAGE_SAMPLERS);
+};
+
+// Meshes and skeleton of a model, with the copies still running on the GPU
+type ModelUpload = (Vec<Mesh>, Option<Skeleton>, Vec<Arc<UploadFuture>>);
+
+// Instance buffer and its base copy, the input of the compute animation
+type InstanceUpload = (
+    Subbuffer<[InstanceRaw]>,
+    Subbuffer<[InstanceRaw]>,
+    Box<dyn GpuFuture + Send + Sync>,
+);
+
+// Device local instance buffer (STORAGE_BUFFER: the compute shader writes the transforms in
+// place) and its base copy, both holding `instances`. Submitted, not waited for.
+fn check_instance_count(len: DeviceSize, count: usize) -> Result<()> {
+    if count as DeviceSize != len {
+        return Err(VisualSystemError::InstanceCountMismatch {
+            expected: len,
+            actual: count as DeviceSize,
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+fn upload_instances(
+    memory_allocator: &Arc<StandardMemoryAllocator>,
+    command_allocator: &Arc<StandardCommandBufferAllocator>,
+    queue: &Arc<Queue>,
+    instances: &[InstanceRaw],
+) -> Result<InstanceUpload> {
+    let mut command_builder = AutoCommandBufferBuilder::primary(
+        command_allocator,
+        queue.queue_family_index(),
+        CommandBufferUsage::OneTimeSubmit,
+    )?;
+
+    let instance_buffer = upload_to_device_buffer(
+        instances.iter().copied(),
+        BufferUsage::VERTEX_BUFFER | BufferUsage::STORAGE_BUFFER,
+        &[],
+        memory_allocator,
+        &mut command_builder,
+    )?;
+    // Read only by the compute shader
+    let base_instance_buffer = upload_to_device_buffer(
+        instances.iter().copied(),
+        BufferUsage::STORAGE_BUFFER,
+        &[],
+        memory_allocator,
+        &mut command_builder,
+    )?;
+
+    let command_buffer = command_builder.build()?;
+
+    let future = sync::now(Arc::clone(queue.device()))
+        .then_execute(Arc::clone(queue), command_buffer)?
+        .boxed_send_sync();
+
+    Ok((instance_buffer, base_instance_buffer, future))
+}
+
+// One graphics pipeline per blend mode and topology, all sharing the same layout
+fn create_graphics_pipelines(
+    device: &Arc<Device>,
+    vulkan_context: &VulkanContext,
+    layout: Option<Arc<PipelineLayout>>,
+) -> Result<HashMap<(BlendMode, Topology), Arc<GraphicsPipeline>>> {
+    let opaque = create_graphics_pipeline(
+        device,
+        vulkan_context,
+        BlendMode::Opaque,
+        Topology::Triangles,
+        PolygonMode::Fill,
+        layout,
+    )?;
+    let layout = Arc::clone(opaque.layout());
+
+    let mut graphics_pipelines =
+        HashMap::from([((BlendMode::Opaque, Topology::Triangles), opaque)]);
+    for blend_mode in [
+        BlendMode::Opaque,
+        BlendMode::StraightAlpha,
+        BlendMode::PremultipliedAlpha,
+        BlendMode::Additive,
+    ] {
+        for topology in Topology::ALL {
+            if (blend_mode, topology) == (BlendMode::Opaque, Topology::Triangles) {
+                continue;
+            }
+            graphics_pipelines.insert(
+                (blend_mode, topology),
+                create_graphics_pipeline(
+                    device,
+                    vulkan_context,
+                    blend_mode,
+                    topology,
+                    PolygonMode::Fill,
+                    Some(Arc::clone(&layout)),
+                )?,
+            );
+        }
+    }
+
+    Ok(graphics_pipelines)
+}
+
+// Wireframe overlay pipeline, sharing the layout of the other graphics pipelines.
+// None when the device can't rasterize polygons as lines.
+fn create_wireframe_pipeline(
+    device: &Arc<Device>,
+    vulkan_context: &VulkanContext,
+    layout: Arc<PipelineLayout>,
+) -> Result<Option<Arc<GraphicsPipeline>>> {
+    if !device.enabled_features().fill_mode_non_solid {
+        return Ok(None);
+    }
+
+    let wireframe_pipeline = create_graphics_pipeline(
+        device,
+        vulkan_context,
+        BlendMode::Opaque,
+        Topology::Triangles,
+        PolygonMode::Line,
+        Some(layout),
+    )?;
+
+    Ok(Some(wireframe_pipeline))
+}
+
+// Graphics pipeline for the current MSAA sample count and color output.
+// Reuse `layout` when given, so the descriptor sets created for it stay valid.
+// PolygonMode::Line is the wireframe overlay: flat color, drawn over the shaded mesh.
+fn create_graphics_pipeline(
+    device: &Arc<Device>,
+    vulkan_context: &VulkanContext,
+    blend_mode: BlendMode,
+    topology: Topology,
+    polygon_mode: PolygonMode,
+    layout: Option<Arc<PipelineLayout>>,
+) -> Result<Arc<GraphicsPipeline>> {
+    let wireframe = polygon_mode == PolygonMode::Line;
+
+    // The shaders reloaded with F5 replace the built-in ones
+    let reloaded_shaders = &vulkan_context.reloaded_shaders;
+    let vertex_module = match &reloaded_shaders.vertex {
+        Some(vertex_module) => Arc::clone(vertex_module),
+        None => vs::load(Arc::clone(device))?,
+    };
+    let vertex_shader = vertex_module.entry_point("main").unwrap();
+    // Additive is the unlit emissive pass
+    let fragment_module = match (blend_mode, &reloaded_shaders.fragment) {
+        _ if wireframe => fs_wireframe::load(Arc::clone(device))?,
+        (BlendMode::Additive, _) => fs_emissive::load(Arc::clone(device))?,
+        (_, Some(fragment_module)) => Arc::clone(fragment_module),
+        (_, None) => fs::load(Arc::clone(device))?,
+    };
+    // Specialization constant 0: ENCODE_SRGB
+    let fragment_shader = fragment_module
+        .specialize(
+            [(0, vulkan_context.color_output.encode_in_shader().into())]
+                .into_iter()
+                .collect(),
+        )?
+        .entry_point("main")
+        .unwrap();
+
+    // Automatically generate a vertex input state from the vertex shader's input interface,
+    // that takes a single vertex buffer containing `Vertex` structs.
+    let vertex_input_state = [
+        shader::Vertex::per_vertex(),
+        instance_buffer::InstanceRaw::per_instance(),
+    ]
+    .definition(&vertex_shader.info().input_interface)?; // 👈 Don't forget otherwise binding will be missing
+
+    let stages: [PipelineShaderStageCreateInfo; 2] = [
+        PipelineShaderStageCreateInfo::new(vertex_shader),
+        PipelineShaderStageCreateInfo::new(fragment_shader),
+    ];
+
+    // We must now create a **pipeline layout** object, which describes the locations and types of
+    // descriptor sets and push constants used by the shaders in the pipeline.
+    //
+    // Multiple pipelines can share a common layout object, which is more efficient.
+    // The shaders in a pipeline must use a subset of the resources described in its pipeline
+    // layout, but the pipeline layout is allowed to contain resources that are not present in the
+    // shaders; they can be used by shaders in other pipelines that share the same layout.
+    // Thus, it is a good idea to design shaders so that many pipelines have common resource
+    // locations, which allows them to share pipeline layouts.
+    // let layout = PipelineLayout::new(
+    //     Arc::clone(device),
+    //     // Since we only have one pipeline in this example, and thus one pipeline layout,
+    //     // we automatically generate the creation info for it from the resources used in the
+    //     // shaders. In a real application, you would specify this information manually so that you
+    //     // can re-use one layout in multiple pipelines.
+    //     PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+    //         .into_pipeline_layout_create_info(Arc::clone(device))?,
+    // )?;
+
+    let layout = match layout {
+        Some(layout) => layout,
+        None => {
+            let mut layout_create_info =
+                PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages);
+
+            let set_layout = &mut layout_create_info.set_layouts[1];
+            set_layout.bindings.insert(
+                1,
+                DescriptorSetLayoutBinding {
+                    descriptor_type: DescriptorType::UniformBuffer,
+                    descriptor_count: 1,
+                    stages: ShaderStages::FRAGMENT,
+                    ..DescriptorSetLayoutBinding::descriptor_type(DescriptorType::UniformBuffer)
+                },
+            );
+
+            set_layout.bindings.insert(
+                2,
+                DescriptorSetLayoutBinding {
+                    descriptor_type: DescriptorType::StorageBuffer,
+                    descriptor_count: 1,
+                    stages: ShaderStages::FRAGMENT,
+                    ..DescriptorSetLayoutBinding::descriptor_type(DescriptorType::StorageBuffer)
+                },
+            );
+
+            PipelineLayout::new(
+                Arc::clone(device),
+                layout_create_info.into_pipeline_layout_create_info(Arc::clone(device))?,
+            )?
+        }
+    };
+
+    // We describe the formats of attachment images where the colors, depth and/or stencil
+    // information will be written. The pipeline will only be usable with this particular
+    // configuration of the attachment images.
+    let subpass = PipelineRenderingCreateInfo {
+        // We specify a single color attachment that will be rendered to. When we begin
+        // rendering, we will specify a swapchain image to be used as this attachment, so here
+        // we set its format to be the same format as the swapchain.
+        color_attachment_formats: vec![Some(vulkan_context.color_format())],
+        depth_attachment_format: Some(vulkan_context.depth_format()),
+        ..Default::default()
+    };
+
+    // Straight alpha: color * alpha + dst * (1 - alpha)
+    // Premultiplied alpha: the color is already multiplied by alpha
+    let blend = match blend_mode {
+        BlendMode::Opaque => None,
+        BlendMode::StraightAlpha => Some(AttachmentBlend::alpha()),
+        BlendMode::PremultipliedAlpha => Some(AttachmentBlend {
+            src_color_blend_factor: BlendFactor::One,
+            dst_color_blend_factor: BlendFactor::OneMinusSrcAlpha,
+            color_blend_op: BlendOp::Add,
+            src_alpha_blend_factor: BlendFactor::One,
+            dst_alpha_blend_factor: BlendFactor::OneMinusSrcAlpha,
+            alpha_blend_op: BlendOp::Add,
+        }),
+        BlendMode::Additive => Some(AttachmentBlend::additive()),
+    };
+
+    let graphics_pipeline = GraphicsPipeline::new(
+        Arc::clone(device),
+        None,
+        GraphicsPipelineCreateInfo {
+            stages: stages.into_iter().collect(),
+            // How vertex data is read from the vertex buffers into the vertex shader.
+            vertex_input_state: Some(vertex_input_state), // 👈 Do not forget
+            // How vertices are arranged into primitive shapes: triangles, lines or points.
+            input_assembly_state: Some(InputAssemblyState {
+                topology: topology.primitive_topology(),
+                ..Default::default()
+            }),
+            // How primitives are transformed and clipped to fit the framebuffer.
+            // We use a resizable viewport, set to draw over the entire window.
+            viewport_state: Some(ViewportState::default()),
+            // How polygons are culled and converted into a raster of pixels.
+            // The default value does not perform any culling.
+            rasterization_state: Some(RasterizationState {
+                cull_mode: vulkan_context.cull_mode,
+                front_face: vulkan_context.front_face,
+                polygon_mode,
+                // Pull the lines towards the camera: they pass the depth test of their own surface
+                depth_bias: wireframe.then_some(DepthBiasState {
+                    constant_factor: -1.0,
+                    clamp: 0.0,
+                    slope_factor: -1.0,
+                }),
+                ..Default::default()
+            }),
+            // Depth
+            depth_stencil_state: Some(DepthStencilState {
+                // Simple = CompareOp::Less,
+                // Blended surfaces are depth tested but don't hide what is behind them
+                depth: Some(DepthState {
+                    write_enable: blend_mode == BlendMode::Opaque && !wireframe,
+                    ..DepthState::simple()
+                }),
+                ..Default::default()
+            }),
+            // How multiple fragment shader samples are converted to a single pixel value.
+            // The default value does not perform any multisampling.
+            //Original without MSAA 👉 multisample_state: Some(MultisampleState::default()),
+            multisample_state: Some(MultisampleState {
+                // MSAA
+                rasterization_samples: vulkan_context.samples, //SampleCount::Sample4,
+                ..Default::default()
+            }),
+            // How pixel values are combined with the values already present in the framebuffer.
+            // The default value overwrites the old value with the new one, without any blending.
+            color_blend_state: Some(ColorBlendState::with_attachment_states(
+                subpass.color_attachment_formats.len() as u32,
+                ColorBlendAttachmentState {
+                    blend,
+                    ..Default::default()
+                },
+            )),
+            // Dynamic states allows us to specify parts of the pipeline settings when
+            // recording the command buffer, before we perform drawing.
+            // Here, we specify that the viewport should be dynamic.
+            dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+            subpass: Some(subpass.into()),
+            ..GraphicsPipelineCreateInfo::layout(layout)
+        },
+    )?;
+
+    Ok(graphics_pipeline)
+}
+
+// Fullscreen pass showing the depth buffer. Draws straight into the swapchain image,
+// so no MSAA and no depth attachment.
+fn create_depth_view_pipeline(
+    device: &Arc<Device>,
+    vulkan_context: &VulkanContext,
+) -> Result<Arc<GraphicsPipeline>> {
+    let vertex_shader = fullscreen_vs::load(Arc::clone(device))?
+        .entry_point("main")
+        .unwrap();
+    // The depth attachment is read with texelFetch: texture2DMS or texture2D
+    let fragment_shader = if vulkan_context.samples == SampleCount::Sample1 {
+        depth_view_single_fs::load(Arc::clone(device))?
+    } else {
+        depth_view_fs::load(Arc::clone(device))?
+    }
+    .entry_point("main")
+    .unwrap();
+
+    let stages = [
+        PipelineShaderStageCreateInfo::new(vertex_shader),
+        PipelineShaderStageCreateInfo::new(fragment_shader),
+    ];
+
+    let layout = PipelineLayout::new(
+        Arc::clone(device),
+        PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+            .into_pipeline_layout_create_info(Arc::clone(device))?,
+    )?;
+
+    let subpass = PipelineRenderingCreateInfo {
+        color_attachment_formats: vec![Some(vulkan_context.color_format())],
+        ..Default::default()
+    };
+
+    let depth_view_pipeline = GraphicsPipeline::new(
+        Arc::clone(device),
+        None,
+        GraphicsPipelineCreateInfo {
+            stages: stages.into_iter().collect(),
+            // The fullscreen triangle comes from gl_VertexIndex
+            vertex_input_state: Some(VertexInputState::default()),
+            input_assembly_state: Some(InputAssemblyState::default()),
+            viewport_state: Some(ViewportState::default()),
+            rasterization_state: Some(RasterizationState::default()),
+            multisample_state: Some(MultisampleState::default()),
+            color_blend_state: Some(ColorBlendState::with_attachment_states(
+                subpass.color_attachment_formats.len() as u32,
+                ColorBlendAttachmentState::default(),
+            )),
+            dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+            subpass: Some(subpass.into()),
+            ..GraphicsPipelineCreateInfo::layout(layout)
+        },
+    )?;
+
+    Ok(depth_view_pipeline)
+}
+
+// Set 1 of the graphics pipeline: lights and material. Set 0 (camera) belongs to the renderers.
+#[allow(clippy::too_many_arguments)]
+fn create_descriptor_set(
+    descriptor_set_allocator: &StandardDescriptorSetAllocator,
+    pipeline_layout: &Arc<PipelineLayout>,
+    ambient_light_buffer: Subbuffer<AmbientLight>,
+    directional_light_buffer: Subbuffer<DirectionalLights>,
+    spot_light_buffer: Subbuffer<SpotLights>,
+    material_buffer: Subbuffer<MaterialFactors>,
+    material_textures: &MaterialTextures,
+    sampler: &Arc<Sampler>,
+) -> Result<Arc<PersistentDescriptorSet>> {
+    let descriptor_set = PersistentDescriptorSet::new(
+        descriptor_set_allocator,
+        Arc::clone(
+            pipeline_layout
+                .set_layouts()
+                .get(1)
+                .expect("error getting the layout"),
+        ),
+        [
+            WriteDescriptorSet::buffer(1, ambient_light_buffer),
+            WriteDescriptorSet::buffer(2, directional_light_buffer),
+            WriteDescriptorSet::image_view_sampler(
+                3,
+                Arc::clone(&material_textures.base_color),
+                Arc::clone(sampler),
+            ),
+            WriteDescriptorSet::image_view_sampler(
+                4,
+                Arc::clone(&material_textures.metallic_roughness),
+                Arc::clone(sampler),
+            ),
+            WriteDescriptorSet::image_view_sampler(
+                5,
+                Arc::clone(&material_textures.occlusion),
+                Arc::clone(sampler),
+            ),
+            WriteDescriptorSet::image_view_sampler(
+                6,
+                Arc::clone(&material_textures.emissive),
+                Arc::clone(sampler),
+            ),
+            WriteDescriptorSet::buffer(7, material_buffer),
+            WriteDescriptorSet::buffer(8, spot_light_buffer),
+        ],
+        [],
+    )?;
+
+    Ok(descriptor_set)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    #[test]
+    fn instance_count_must_match_the_buffer() {
+        assert!(check_instance_count(16, 16).is_ok());
+
+        assert!(matches!(
+            check_instance_count(16, 9),
+            Err(Error::VisualSystem(
+                VisualSystemError::InstanceCountMismatch {
+                    expected: 16,
+                    actual: 9
+                }
+            ))
+        ));
+    }
+}
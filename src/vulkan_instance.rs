@@ -1,48 +1,82 @@
 // Note: Physical Instance
 use std::sync::Arc;
 
-use tracing::info;
+use tracing::{info, warn};
 use vulkano::device::physical::{PhysicalDevice, PhysicalDeviceType};
 use vulkano::device::{DeviceExtensions, QueueFlags};
-use vulkano::instance::{Instance, InstanceCreateInfo};
-use vulkano::swapchain::Surface;
+use vulkano::format::{Format, FormatFeatures, NumericFormat};
+use vulkano::instance::debug::DebugUtilsMessenger;
+use vulkano::instance::{Instance, InstanceCreateInfo, InstanceExtensions};
+use vulkano::swapchain::{ColorSpace, Surface};
 use vulkano::{Version, VulkanLibrary};
 use winit::window::Window;
 
-use crate::error::Result;
+use crate::config::ColorOutput;
+use crate::debug_utils::create_debug_messenger;
+use crate::error::{Result, VisualSystemError};
 
 #[derive(Clone)]
 pub struct VulkanInstance {
     pub physical_device: Arc<PhysicalDevice>,
     pub queue_family_index: u32,
+    pub transfer_queue_family_index: Option<u32>, // dedicated transfer (DMA) family, if any
     pub device_extensions: DeviceExtensions,
+    pub surface_formats: Vec<(Format, ColorSpace)>, // offered for the compatible window
+    _debug_messenger: Option<Arc<DebugUtilsMessenger>>, // kept alive, with validation only
 }
 
 impl VulkanInstance {
-    pub fn new(compatible_window: Arc<Window>) -> Result<Self> {
+    // `device_name`: prefer the device whose name contains it, see select_physical_device.
+    // `validation`: enable the Khronos validation layer when installed.
+    pub fn new(
+        compatible_window: Arc<Window>,
+        device_name: Option<&str>,
+        validation: bool,
+    ) -> Result<Self> {
         let library = VulkanLibrary::new()?;
 
-        let  required_extensions = Surface::required_extensions(&compatible_window);
-        // For debugging --> required_extensions.ext_debug_utils = true;
-        let instance = Instance::new(
-            library,
-            InstanceCreateInfo {
-                #[cfg(target_os = "macos")]
-                flags: InstanceCreateFlags::ENUMERATE_PORTABILITY,
-                enabled_extensions: required_extensions,
-                ..Default::default()
-            },
-        )?;
+        let mut required_extensions = Surface::required_extensions(&compatible_window);
+        // Optional: extended (HDR) color spaces in the surface formats
+        required_extensions.ext_swapchain_colorspace =
+            library.supported_extensions().ext_swapchain_colorspace;
 
+        let instance = create_instance(library, required_extensions, validation)?;
+        let debug_messenger = create_debug_messenger_if_enabled(&instance)?;
         let surface = Surface::from_window(Arc::clone(&instance), compatible_window)?;
 
+        Ok(Self {
+            _debug_messenger: debug_messenger,
+            ..Self::select_physical_device(&instance, Some(&surface), device_name)?
+        })
+    }
+
+    // No window: no surface extensions, no swapchain. Any graphics queue will do.
+    pub fn new_headless(device_name: Option<&str>, validation: bool) -> Result<Self> {
+        let library = VulkanLibrary::new()?;
+        let instance = create_instance(library, InstanceExtensions::empty(), validation)?;
+        let debug_messenger = create_debug_messenger_if_enabled(&instance)?;
+
+        Ok(Self {
+            _debug_messenger: debug_messenger,
+            ..Self::select_physical_device(&instance, None, device_name)?
+        })
+    }
+
+    // `surface`: the queue must be able to present to it.
+    // `device_name`: case insensitive substring of the device name. Without it (or when no
+    // suitable device matches), the best device type wins.
+    fn select_physical_device(
+        instance: &Arc<Instance>,
+        surface: Option<&Surface>,
+        device_name: Option<&str>,
+    ) -> Result<Self> {
         // device extension to render to a window
         let mut device_extensions = DeviceExtensions {
-            khr_swapchain: true,
+            khr_swapchain: surface.is_some(),
             ..DeviceExtensions::empty()
         };
 
-        let (physical_device, queue_family_index) = instance
+        let candidates = instance
             .enumerate_physical_devices()?
             .filter(|phys_dev| {
                 phys_dev.api_version() >= Version::V1_3
@@ -56,13 +90,35 @@ impl VulkanInstance {
                     .enumerate()
                     .position(|(idx, queue)| {
                         queue.queue_flags.intersects(QueueFlags::GRAPHICS)
-                            && phys_dev
-                                .surface_support(idx as u32, &surface)
-                                .unwrap_or(false)
+                            && surface.is_none_or(|surface| {
+                                phys_dev
+                                    .surface_support(idx as u32, surface)
+                                    .unwrap_or(false)
+                            })
                     })
                     .map(|idx| (phys_dev, idx as u32))
             })
-            .min_by_key(|(phys_dev, _)| {
+            .collect::<Vec<_>>();
+
+        let by_name = device_name.and_then(|device_name| {
+            let device_name = device_name.to_lowercase();
+            let found = candidates.iter().find(|(phys_dev, _)| {
+                phys_dev
+                    .properties()
+                    .device_name
+                    .to_lowercase()
+                    .contains(&device_name)
+            });
+
+            if found.is_none() {
+                warn!("no suitable device named {device_name}, using the best device type");
+            }
+            found
+        });
+
+        let (physical_device, queue_family_index) = by_name
+            .or_else(|| {
+                candidates.iter().min_by_key(|(phys_dev, _)| {
                 // We assign a lower score to device types that are likely to be faster/better.
                 match phys_dev.properties().device_type {
                     PhysicalDeviceType::DiscreteGpu => 0,
@@ -72,8 +128,10 @@ impl VulkanInstance {
                     PhysicalDeviceType::Other => 4,
                     _ => 5,
                 }
+                })
             })
-            .expect("no suitable physical device found");
+            .cloned()
+            .ok_or(VisualSystemError::NoSuitableDevice)?;
 
         // Some little debug infos.
         info!(
@@ -89,10 +147,35 @@ impl VulkanInstance {
 
         device_extensions.khr_dynamic_rendering = physical_device.api_version() < Version::V1_3;
 
+        // Optional: a transfer only queue family, used for the mesh uploads.
+        // Runs the copies on the DMA engine, alongside the graphics work.
+        let transfer_queue_family_index = physical_device
+            .queue_family_properties()
+            .iter()
+            .position(|queue| {
+                queue.queue_flags.intersects(QueueFlags::TRANSFER)
+                    && !queue
+                        .queue_flags
+                        .intersects(QueueFlags::GRAPHICS | QueueFlags::COMPUTE)
+            })
+            .map(|idx| idx as u32);
+
+        // Optional: per heap memory budget for VulkanDevice::memory_report
+        device_extensions.ext_memory_budget =
+            physical_device.supported_extensions().ext_memory_budget;
+
+        let surface_formats = match surface {
+            Some(surface) => physical_device.surface_formats(surface, Default::default())?,
+            None => Vec::new(),
+        };
+
         Ok(Self {
             physical_device,
             queue_family_index,
+            transfer_queue_family_index,
             device_extensions,
+            surface_formats,
+            _debug_messenger: None,
         })
     }
 
@@ -104,7 +187,136 @@ impl VulkanInstance {
         self.queue_family_index
     }
 
+    pub fn transfer_queue_family_index(&self) -> Option<u32> {
+        self.transfer_queue_family_index
+    }
+
     pub fn device_extensions(&self) -> &DeviceExtensions {
         &self.device_extensions
     }
+
+    pub fn supports_surface_format(&self, format: Format, color_space: ColorSpace) -> bool {
+        self.surface_formats.contains(&(format, color_space))
+    }
+
+    // Most precise depth format usable as an attachment and sampled (depth visualization).
+    // No stencil formats: nothing uses the stencil, and sampling a depth/stencil image needs a
+    // depth only view. D16_UNORM is always supported.
+    pub fn select_depth_format(&self) -> Result<Format> {
+        for format in DEPTH_FORMATS {
+            let supported = self
+                .physical_device
+                .format_properties(format)?
+                .optimal_tiling_features
+                .contains(FormatFeatures::DEPTH_STENCIL_ATTACHMENT | FormatFeatures::SAMPLED_IMAGE);
+            if supported {
+                info!("depth format: {format:?}");
+                return Ok(format);
+            }
+        }
+
+        Ok(Format::D16_UNORM)
+    }
+
+    // Swapchain format for `color_output`: the first of its preferred formats the surface offers.
+    // Falls back to sRGB output, then to linear output, then to the first sRGB color space format
+    // of the surface. Without a surface (headless) the preferred format.
+    pub fn select_surface_format(&self, color_output: ColorOutput) -> (ColorOutput, Format) {
+        if self.surface_formats.is_empty() {
+            return (color_output, color_output.swapchain_format());
+        }
+
+        let offered = |color_output: ColorOutput| {
+            color_output
+                .swapchain_formats()
+                .iter()
+                .copied()
+                .find(|format| self.supports_surface_format(*format, color_output.color_space()))
+        };
+
+        if let Some(format) = offered(color_output) {
+            return (color_output, format);
+        }
+
+        for fallback in [ColorOutput::Srgb, ColorOutput::Linear] {
+            if let Some(format) = offered(fallback) {
+                warn!("{color_output:?} output not supported by the surface, using {fallback:?} ({format:?})");
+                return (fallback, format);
+            }
+        }
+
+        // The shaders encode to sRGB themselves when the format doesn't
+        let (format, _) = self
+            .surface_formats
+            .iter()
+            .find(|(_, color_space)| *color_space == ColorSpace::SrgbNonLinear)
+            .unwrap_or(&self.surface_formats[0]);
+        let fallback = if format.numeric_format_color() == Some(NumericFormat::SRGB) {
+            ColorOutput::Srgb
+        } else {
+            ColorOutput::Linear
+        };
+        warn!("no preferred swapchain format offered by the surface, using {format:?}");
+
+        (fallback, *format)
+    }
+}
+
+// Preferred depth formats, in order, see select_depth_format
+const DEPTH_FORMATS: [Format; 3] = [
+    Format::D32_SFLOAT,
+    Format::X8_D24_UNORM_PACK32,
+    Format::D16_UNORM,
+];
+
+// Optional for debugging: the validation layer reports API misuse. Skipped (with a warning)
+// when it isn't installed.
+const VALIDATION_LAYER: &str = "VK_LAYER_KHRONOS_validation";
+
+fn create_instance(
+    library: Arc<VulkanLibrary>,
+    mut enabled_extensions: InstanceExtensions,
+    validation: bool,
+) -> Result<Arc<Instance>> {
+    // Messages to tracing and command buffer labels, see debug_utils.rs
+    enabled_extensions.ext_debug_utils =
+        validation && library.supported_extensions().ext_debug_utils;
+
+    let enabled_layers = if validation {
+        if library
+            .layer_properties()?
+            .any(|layer| layer.name() == VALIDATION_LAYER)
+        {
+            info!("{VALIDATION_LAYER} enabled");
+            vec![VALIDATION_LAYER.to_string()]
+        } else {
+            warn!("{VALIDATION_LAYER} requested but not installed");
+            Vec::new()
+        }
+    } else {
+        Vec::new()
+    };
+
+    let instance = Instance::new(
+        library,
+        InstanceCreateInfo {
+            #[cfg(target_os = "macos")]
+            flags: InstanceCreateFlags::ENUMERATE_PORTABILITY,
+            enabled_extensions,
+            enabled_layers,
+            ..Default::default()
+        },
+    )?;
+
+    Ok(instance)
+}
+
+fn create_debug_messenger_if_enabled(
+    instance: &Arc<Instance>,
+) -> Result<Option<Arc<DebugUtilsMessenger>>> {
+    if !instance.enabled_extensions().ext_debug_utils {
+        return Ok(None);
+    }
+
+    Ok(Some(create_debug_messenger(Arc::clone(instance))?))
 }
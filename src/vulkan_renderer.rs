@@ -1,37 +1,204 @@
 // Note: Renderer
 
-use std::{rc::Rc, sync::Arc, time::Instant};
+use std::{
+    cmp::Ordering,
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
+use nalgebra::{Point3, Vector3};
 use palette::Srgba;
+use tracing::{error, warn};
 use vulkano::{
+    buffer::{
+        allocator::{SubbufferAllocator, SubbufferAllocatorCreateInfo},
+        BufferUsage, Subbuffer,
+    },
     command_buffer::{
-        AutoCommandBufferBuilder, CommandBufferUsage, RenderingAttachmentInfo,
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, CommandBufferUsage,
+        CopyBufferInfo, PrimaryAutoCommandBuffer, RenderingAttachmentInfo,
         RenderingAttachmentResolveInfo, RenderingInfo,
     },
+    descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet},
     device::DeviceOwned,
     format::{ClearValue, Format},
-    image::{view::ImageView, Image, ImageCreateInfo, ImageType, ImageUsage},
-    memory::allocator::AllocationCreateInfo,
+    image::{view::ImageView, Image, ImageCreateInfo, ImageType, ImageUsage, SampleCount},
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
     pipeline::{graphics::viewport::Viewport, Pipeline, PipelineBindPoint},
     render_pass::{AttachmentLoadOp, AttachmentStoreOp},
     swapchain::{
-        acquire_next_image, Surface, Swapchain, SwapchainCreateInfo, SwapchainPresentInfo,
+        acquire_next_image, PresentMode, Surface, Swapchain, SwapchainCreateInfo,
+        SwapchainPresentInfo,
     },
     sync::{self, GpuFuture},
-    Validated, VulkanError,
+    DeviceSize, Validated, VulkanError,
 };
 use winit::window::Window;
 
-use crate::{error::Result, shader::vs, vulkan_device::VulkanDevice};
+use crate::{
+    animation::{JointMatrix, MAX_JOINTS},
+    bloom::BloomTargets,
+    camera::{Camera, Mvp, Projection},
+    capture::FrameCapture,
+    debug_utils::debug_label,
+    error::{Result, VisualSystemError},
+    frustum::Frustum,
+    gpu_timer::GpuTimer,
+    gui::Gui,
+    instance_buffer::{visible_instances, InstanceRaw},
+    material::BlendMode,
+    mesh::{Mesh, Topology},
+    shader::{depth_view_fs, vs},
+    staging::upload_to_buffer,
+    text::{TextRenderer, FONT_ATLAS},
+    utils::linear_to_srgb,
+    vulkan_device::VulkanDevice,
+};
+
+// The groups of meshes of the main pass, in drawing order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum DrawPass {
+    Opaque,
+    Blended, // straight or premultiplied alpha, back to front
+    Emissive,
+}
+
+fn draw_pass(mesh: &Mesh) -> DrawPass {
+    match mesh.blend_mode() {
+        BlendMode::Opaque => DrawPass::Opaque,
+        BlendMode::StraightAlpha | BlendMode::PremultipliedAlpha => DrawPass::Blended,
+        BlendMode::Additive => DrawPass::Emissive,
+    }
+}
+
+// Instance buffer, first instance and instance count of a mesh draw
+type DrawInstances = (Subbuffer<[InstanceRaw]>, u32, u32);
+
+// The window title shows the frame stats, refreshed at this interval to stay readable
+const TITLE_UPDATE_INTERVAL: Duration = Duration::from_millis(250);
+const TITLE_SEPARATOR: &str = " — ";
+
+// Radians per second of the model spin (R), see VulkanContext::spin_model
+const MODEL_SPIN_SPEED: f32 = 0.5;
+
+// The grid fades out over this fraction of the far plane distance
+const GRID_FADE: f32 = 0.5;
+
+// Smoothed over a few dozen frames, see VulkanRenderer::frame_stats
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameStats {
+    pub fps: f32,
+    pub frame_time: f32, // ms, CPU side: time between two renders
+}
+
+// Where the frames are drawn
+pub enum RenderTarget {
+    // Presented to a window
+    Swapchain {
+        window: Arc<Window>,
+        swapchain: Arc<Swapchain>,
+        images: Vec<Arc<Image>>,
+        image_views: Vec<Arc<ImageView>>,
+    },
+    // Headless: an owned image, read back by render_offscreen
+    Offscreen {
+        image: Arc<Image>,
+        image_view: Arc<ImageView>,
+    },
+}
+
+impl RenderTarget {
+    fn offscreen(vulkan_device: &VulkanDevice, extent: [u32; 2]) -> Result<Self> {
+        let image = Image::new(
+            vulkan_device.memory_allocator.clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: vulkan_device.vulkan_context.borrow().color_format(),
+                extent: [extent[0], extent[1], 1],
+                // Same usage as the swapchain images, TRANSFER_SRC for the read back
+                usage: ImageUsage::COLOR_ATTACHMENT
+                    | ImageUsage::TRANSFER_DST
+                    | ImageUsage::TRANSFER_SRC,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )?;
+
+        Ok(Self::Offscreen {
+            image_view: ImageView::new_default(Arc::clone(&image))?,
+            image,
+        })
+    }
+
+    pub fn extent(&self) -> [u32; 2] {
+        match self {
+            RenderTarget::Swapchain { swapchain, .. } => swapchain.image_extent(),
+            RenderTarget::Offscreen { image, .. } => [image.extent()[0], image.extent()[1]],
+        }
+    }
+
+    pub fn format(&self) -> Format {
+        match self {
+            RenderTarget::Swapchain { swapchain, .. } => swapchain.image_format(),
+            RenderTarget::Offscreen { image, .. } => image.format(),
+        }
+    }
+
+    pub fn image_count(&self) -> usize {
+        match self {
+            RenderTarget::Swapchain { images, .. } => images.len(),
+            RenderTarget::Offscreen { .. } => 1,
+        }
+    }
+
+    pub fn image(&self, index: u32) -> &Arc<Image> {
+        match self {
+            RenderTarget::Swapchain { images, .. } => &images[index as usize],
+            RenderTarget::Offscreen { image, .. } => image,
+        }
+    }
+
+    pub fn image_view(&self, index: u32) -> &Arc<ImageView> {
+        match self {
+            RenderTarget::Swapchain { image_views, .. } => &image_views[index as usize],
+            RenderTarget::Offscreen { image_view, .. } => image_view,
+        }
+    }
+
+    // None when headless
+    pub fn window(&self) -> Option<&Arc<Window>> {
+        match self {
+            RenderTarget::Swapchain { window, .. } => Some(window),
+            RenderTarget::Offscreen { .. } => None,
+        }
+    }
+}
 
 pub struct VulkanRenderer {
     pub vulkan_device: Rc<VulkanDevice>,
-    pub window: Arc<Window>,
-    pub swapchain: Arc<Swapchain>,
-    pub swapchain_images: Vec<Arc<Image>>,
-    pub swapchain_image_views: Vec<Arc<ImageView>>,
-    pub intermediary_image: Arc<ImageView>, // for msaa (multi-sample anti-aliasing)
+    pub target: RenderTarget, // swapchain of the window, or the headless image
+    pub intermediary_image: Option<Arc<ImageView>>, // for msaa (multi-sample anti-aliasing), None with 1 sample
     pub depth_view: Arc<ImageView>,         // Depth
+    pub depth_view_descriptor_set: Arc<PersistentDescriptorSet>, // depth buffer visualization
+    pub bloom_targets: BloomTargets,
+    pub camera: Camera, // each window has its own viewpoint
+    pub mvp_uniform: Mvp,
+    uniform_staging_allocator: SubbufferAllocator, // a staging subbuffer per copy
+    culled_instance_allocator: SubbufferAllocator, // the visible instances of each frame
+    pub instance_counts: (u32, u32),               // (drawn, total) in the last frame
+    frame_uniforms: Vec<FrameUniforms>,            // one per target image
+    text: Option<TextRenderer>,                    // created by the first draw_text
+    pub gui: Option<Gui>,                          // control panel, see toggle_gui
+    last_render: Instant,
+    gpu_timer: Option<GpuTimer>, // None without timestamp support
+    pub gpu_time: Option<f32>,   // ms, of an earlier frame, read by the last render
+    pub fps: f32,                // smoothed
+    frame_time: f32,             // ms, smoothed
+    base_title: String,          // window title without the frame stats
+    last_title_update: Instant,
+    pending_capture: Option<String>, // path, captured at the end of the next frame
+    recreate_pending: bool,          // resized or out of date, recreated by the next render
     pub previous_frame_end: Option<Box<dyn GpuFuture>>, // synchro
     pub start_time: Instant,
 }
@@ -41,6 +208,7 @@ impl VulkanRenderer {
         vulkan_device: Rc<VulkanDevice>,
         window: Arc<Window>,
         image_usage: ImageUsage,
+        camera: Camera,
     ) -> Result<Self> {
         let device = vulkan_device.queue().device();
         let physical_device = device.physical_device();
@@ -59,12 +227,30 @@ impl VulkanRenderer {
         let surface_capabilities =
             physical_device.surface_capabilities(&surface, Default::default())?;
 
-        // Choosing the internal format that the images will have.
-        /*  let image_format = device
-        .physical_device()
-        .surface_formats(&surface, Default::default())
-        .unwrap()[0]
-        .0; */
+        // The format of the images is chosen once for all the windows, from the formats offered
+        // to the primary window: see VulkanInstance::select_surface_format.
+
+        // TRANSFER_SRC is only for the frame capture: drop it when the surface doesn't allow it
+        let image_usage = if surface_capabilities
+            .supported_usage_flags
+            .contains(image_usage)
+        {
+            image_usage
+        } else {
+            image_usage.difference(ImageUsage::TRANSFER_SRC)
+        };
+
+        // Fifo (vsync) is always supported
+        let present_mode = vulkan_device.vulkan_context.borrow().present_mode;
+        let present_mode = if physical_device
+            .surface_present_modes(&surface, Default::default())?
+            .any(|supported| supported == present_mode)
+        {
+            present_mode
+        } else {
+            warn!("{present_mode:?} present mode not supported, using Fifo");
+            PresentMode::Fifo
+        };
 
         // create the swapchain
 
@@ -75,11 +261,17 @@ impl VulkanRenderer {
                 image_extent: surface_capabilities
                     .current_extent
                     .unwrap_or(window.inner_size().into()),
-                image_format: Format::B8G8R8A8_SRGB,
+                image_format: vulkan_device.vulkan_context.borrow().color_format(),
+                image_color_space: vulkan_device
+                    .vulkan_context
+                    .borrow()
+                    .color_output
+                    .color_space(),
                 min_image_count: (surface_capabilities.min_image_count + 1)
                     .min(surface_capabilities.max_image_count.unwrap_or(u32::MAX)),
                 pre_transform: surface_capabilities.current_transform,
                 image_usage,
+                present_mode,
                 ..Default::default()
             },
         )?;
@@ -91,23 +283,43 @@ impl VulkanRenderer {
         // each image.
         let swapchain_image_views = window_size_dependent_setup(&swapchain_images);
 
+        let target = RenderTarget::Swapchain {
+            window,
+            swapchain,
+            images: swapchain_images,
+            image_views: swapchain_image_views,
+        };
+
+        Self::with_target(vulkan_device, target, camera)
+    }
+
+    // No window: the frames go to an image of `extent`, see render_offscreen
+    pub fn new_headless(
+        vulkan_device: Rc<VulkanDevice>,
+        extent: [u32; 2],
+        camera: Camera,
+    ) -> Result<Self> {
+        let target = RenderTarget::offscreen(&vulkan_device, extent)?;
+
+        Self::with_target(vulkan_device, target, camera)
+    }
+
+    // Everything but the target: attachments, camera and per frame state
+    fn with_target(
+        vulkan_device: Rc<VulkanDevice>,
+        target: RenderTarget,
+        mut camera: Camera,
+    ) -> Result<Self> {
+        let extent = target.extent();
+
         // Creating our intermediate multisampled image.
         //
         // MSAA  We pass the same extent and format as for the final
         // image. But we also pass the number of samples-per-pixel, which is 4 here.
-
-        let intermediary_image = ImageView::new_default(Image::new(
-            vulkan_device.memory_allocator.clone(),
-            ImageCreateInfo {
-                image_type: ImageType::Dim2d,
-                format: swapchain.image_format(),
-                extent: [swapchain.image_extent()[0], swapchain.image_extent()[1], 1],
-                usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSIENT_ATTACHMENT, // transient image
-                samples: vulkan_device.vulkan_context.borrow().samples,
-                ..Default::default()
-            },
-            AllocationCreateInfo::default(),
-        )?)?;
+        // With a single sample there is nothing to resolve: the scene is drawn straight into
+        // the swapchain image.
+        let intermediary_image =
+            create_intermediary_image(&vulkan_device, target.format(), extent)?;
 
         // Depth buffer
 
@@ -116,105 +328,395 @@ impl VulkanRenderer {
             vulkan_device.memory_allocator.clone(),
             ImageCreateInfo {
                 image_type: ImageType::Dim2d,
-                format: Format::D16_UNORM,
-                extent: [swapchain.image_extent()[0], swapchain.image_extent()[1], 1],
-                usage: ImageUsage::DEPTH_STENCIL_ATTACHMENT | ImageUsage::TRANSIENT_ATTACHMENT,
+                format: vulkan_device.vulkan_context.borrow().depth_format(),
+                extent: [extent[0], extent[1], 1],
+                // SAMPLED for the depth visualization
+                usage: ImageUsage::DEPTH_STENCIL_ATTACHMENT | ImageUsage::SAMPLED,
                 samples: vulkan_device.vulkan_context.borrow().samples, // Match intermediary
                 ..Default::default()
             },
             AllocationCreateInfo::default(),
         )?)?;
 
+        let depth_view_descriptor_set =
+            create_depth_view_descriptor_set(&vulkan_device, Arc::clone(&depth_view))?;
+
+        let bloom_targets = BloomTargets::new(&vulkan_device, extent)?;
+
+        // Camera of this window
+        camera.update_aspect(extent[0], extent[1]);
+
+        let mut mvp_uniform = Mvp::new();
+        mvp_uniform.update_model_translate(Vector3::new(0.0, 0.0, -1.0));
+        mvp_uniform.update_view_proj(&camera);
+
+        // The staging subbuffer of a frame can still be read by the GPU when the next frame
+        // writes the camera: each copy gets its own
+        let uniform_staging_allocator = SubbufferAllocator::new(
+            vulkan_device.memory_allocator.clone(),
+            SubbufferAllocatorCreateInfo {
+                buffer_usage: BufferUsage::TRANSFER_SRC,
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+        );
+
+        // Written by the CPU each frame, read once by the vertex shader
+        let culled_instance_allocator = SubbufferAllocator::new(
+            vulkan_device.memory_allocator.clone(),
+            SubbufferAllocatorCreateInfo {
+                buffer_usage: BufferUsage::VERTEX_BUFFER,
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+        );
+
+        let frame_uniforms = create_frame_uniforms(&vulkan_device, target.image_count())?;
+
+        let gpu_timer = GpuTimer::new(vulkan_device.queue(), target.image_count())?;
+
         // In the event loop  we are going to submit commands to the GPU. Submitting a command produces
         // an object that implements the `GpuFuture` trait, which holds the resources for as long as
         // they are in use by the GPU.
         //
         // Destroying the `GpuFuture` blocks until the GPU is finished executing it. In order to avoid
         // that, we store the submission of the previous frame here.
-        let previous_frame_end = Some(sync::now(device.clone()).boxed());
+        // The first frame also waits for the startup uploads, when still running
+        let now = sync::now(Arc::clone(vulkan_device.queue().device()));
+        let previous_frame_end = Some(match vulkan_device.upload_future()? {
+            Some(upload_future) => now.join(upload_future).boxed(),
+            None => now.boxed(),
+        });
+
+        // A renderer recreated on resume finds the stats of the previous one in the title
+        let base_title = target.window().map_or_else(String::new, |window| {
+            let title = window.title();
+            title
+                .split(TITLE_SEPARATOR)
+                .next()
+                .unwrap_or_default()
+                .to_string()
+        });
 
         Ok(Self {
             vulkan_device,
-            window,
-            swapchain,
-            swapchain_images,
-            swapchain_image_views,
+            target,
             intermediary_image,
             previous_frame_end,
             start_time: std::time::Instant::now(),
             depth_view,
+            depth_view_descriptor_set,
+            bloom_targets,
+            camera,
+            mvp_uniform,
+            uniform_staging_allocator,
+            culled_instance_allocator,
+            instance_counts: (0, 0),
+            frame_uniforms,
+            text: None,
+            gui: None,
+            last_render: Instant::now(),
+            gpu_timer,
+            gpu_time: None,
+            fps: 0.0,
+            frame_time: 0.0,
+            base_title,
+            last_title_update: Instant::now(),
+            pending_capture: None,
+            recreate_pending: false,
         })
     }
 
+    // Show/hide the egui control panel of this window, see gui.rs
+    pub fn toggle_gui(&mut self) -> Result<()> {
+        let Some(window) = self.target.window() else {
+            warn!("no control panel without a window");
+            return Ok(());
+        };
+
+        self.gui = match self.gui {
+            Some(_) => None,
+            None => Some(Gui::new(&self.vulkan_device, window)?),
+        };
+
+        Ok(())
+    }
+
+    pub fn frame_stats(&self) -> FrameStats {
+        FrameStats {
+            fps: self.fps,
+            frame_time: self.frame_time,
+        }
+    }
+
+    // Save the next frame of this window as a PNG, see capture.rs
+    pub fn capture_frame(&mut self, path: &str) -> Result<()> {
+        if !self
+            .target
+            .image(0)
+            .usage()
+            .intersects(ImageUsage::TRANSFER_SRC)
+        {
+            return Err(VisualSystemError::UnsupportedCaptureFormat(
+                "swapchain without TRANSFER_SRC usage".to_string(),
+            )
+            .into());
+        }
+
+        self.pending_capture = Some(path.to_string());
+        Ok(())
+    }
+
+    // Screen space text for the next frame, see text.rs.
+    // The font atlas is only loaded on the first call.
+    pub fn draw_text(&mut self, text: &str, x: f32, y: f32, scale: f32) -> Result<()> {
+        if self.text.is_none() {
+            self.text = Some(TextRenderer::new(&self.vulkan_device, FONT_ATLAS)?);
+        }
+
+        if let Some(text_renderer) = &mut self.text {
+            text_renderer.draw_text(text, x, y, scale);
+        }
+
+        Ok(())
+    }
+
+    // Recompute the view and projection after `camera` changed. The next frame uploads them.
+    pub fn update_camera(&mut self) -> Result<()> {
+        self.mvp_uniform.update_view_proj(&self.camera);
+        self.frame_uniforms
+            .iter_mut()
+            .for_each(|frame_uniforms| frame_uniforms.dirty = true);
+
+        Ok(())
+    }
+
+    // Copy the MVP to the uniform buffer of the frame, in the frame command buffer, before
+    // the draws that read it (the auto command buffer adds the barrier). Only after a change.
+    fn record_mvp_upload(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<
+            PrimaryAutoCommandBuffer<Arc<StandardCommandBufferAllocator>>,
+            Arc<StandardCommandBufferAllocator>,
+        >,
+        image_index: u32,
+    ) -> Result<()> {
+        let frame_uniforms = &mut self.frame_uniforms[image_index as usize];
+        if !frame_uniforms.dirty {
+            return Ok(());
+        }
+
+        let staging_buffer = self.uniform_staging_allocator.allocate_sized()?;
+        *staging_buffer.write()? = self.mvp_uniform;
+
+        builder.copy_buffer(CopyBufferInfo::buffers(
+            staging_buffer,
+            frame_uniforms.buffer.clone(),
+        ))?;
+        frame_uniforms.dirty = false;
+
+        Ok(())
+    }
+
+    // Same for the joint palette of the skinned meshes, every frame: the animation plays
+    fn record_joint_upload(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<
+            PrimaryAutoCommandBuffer<Arc<StandardCommandBufferAllocator>>,
+            Arc<StandardCommandBufferAllocator>,
+        >,
+        image_index: u32,
+        time: f32,
+    ) -> Result<()> {
+        let joint_matrices = match self.vulkan_device.skeleton().as_ref() {
+            Some(skeleton) if skeleton.joint_count() > 0 => skeleton.joint_matrices(time),
+            _ => return Ok(()),
+        };
+
+        let staging_buffer = self
+            .uniform_staging_allocator
+            .allocate_slice(joint_matrices.len() as DeviceSize)?;
+        staging_buffer.write()?.copy_from_slice(&joint_matrices);
+
+        let frame_joints = self.frame_uniforms[image_index as usize]
+            .joints
+            .clone()
+            .slice(0..joint_matrices.len() as DeviceSize);
+        builder.copy_buffer(CopyBufferInfo::buffers(staging_buffer, frame_joints))?;
+
+        Ok(())
+    }
+
+    // Same for the transforms of VulkanDevice::update_instances, before the compute pass and the
+    // draws that read the instance buffers
+    fn record_instance_upload(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<
+            PrimaryAutoCommandBuffer<Arc<StandardCommandBufferAllocator>>,
+            Arc<StandardCommandBufferAllocator>,
+        >,
+    ) -> Result<()> {
+        let Some(instances) = self.vulkan_device.take_pending_instances() else {
+            return Ok(());
+        };
+
+        for destination in [
+            self.vulkan_device.instance_buffer(),
+            self.vulkan_device.base_instance_buffer(),
+        ] {
+            upload_to_buffer(
+                instances.iter().copied(),
+                destination,
+                &self.vulkan_device.memory_allocator,
+                builder,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    // Recreate at the start of the next render: the resize events of a drag come faster than
+    // the frames, they end up in one recreation
+    pub fn request_recreate(&mut self) {
+        self.recreate_pending = true;
+    }
+
+    // Recreate to the current size of the window
     pub fn recreate(&mut self) -> Result<()> {
-        let surface_capabilities = self
-            .swapchain
+        // Headless: no window to follow
+        let RenderTarget::Swapchain {
+            window, swapchain, ..
+        } = &self.target
+        else {
+            return Ok(());
+        };
+
+        let surface_capabilities = swapchain
             .device()
             .physical_device()
-            .surface_capabilities(self.swapchain.surface(), Default::default())?;
+            .surface_capabilities(swapchain.surface(), Default::default())?;
 
-        self.swapchain_images.clear();
-        self.swapchain_image_views.clear();
+        self.resize(
+            surface_capabilities
+                .current_extent
+                .unwrap_or(window.inner_size().into()),
+        )
+    }
 
-        let (new_swapchain, new_swapchain_images) =
-            self.swapchain.recreate(SwapchainCreateInfo {
-                image_extent: surface_capabilities
-                    .current_extent
-                    .unwrap_or(self.window.inner_size().into()),
-                ..self.swapchain.create_info()
-            })?;
+    // Recreate the swapchain (or the headless image), and the attachment images if needed,
+    // to an explicit extent
+    pub fn resize(&mut self, new_extent: [u32; 2]) -> Result<()> {
+        // Nothing to draw into, e.g. minimized window
+        if new_extent.contains(&0) {
+            return Ok(());
+        }
 
-        let new_swapchain_image_views = window_size_dependent_setup(&new_swapchain_images);
+        if let RenderTarget::Swapchain {
+            swapchain,
+            images,
+            image_views,
+            ..
+        } = &mut self.target
+        {
+            images.clear();
+            image_views.clear();
 
-        self.swapchain = new_swapchain;
-        self.swapchain_images = new_swapchain_images;
-        self.swapchain_image_views = new_swapchain_image_views;
-        self.intermediary_image = ImageView::new_default(Image::new(
-            self.vulkan_device.memory_allocator.clone(),
-            ImageCreateInfo {
-                image_type: ImageType::Dim2d,
-                format: self.swapchain.image_format(),
-                extent: [
-                    self.swapchain.image_extent()[0],
-                    self.swapchain.image_extent()[1],
-                    1,
-                ],
-                usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSIENT_ATTACHMENT, // transient image
-                samples: self.vulkan_device.vulkan_context.borrow().samples,
-                ..Default::default()
-            },
-            AllocationCreateInfo::default(),
-        )?)?;
+            let (new_swapchain, new_swapchain_images) =
+                swapchain.recreate(SwapchainCreateInfo {
+                    image_extent: new_extent,
+                    ..swapchain.create_info()
+                })?;
+
+            if new_swapchain.image_count() != swapchain.image_count() {
+                self.gpu_timer =
+                    GpuTimer::new(self.vulkan_device.queue(), new_swapchain_images.len())?;
+                self.frame_uniforms =
+                    create_frame_uniforms(&self.vulkan_device, new_swapchain_images.len())?;
+            }
+
+            *image_views = window_size_dependent_setup(&new_swapchain_images);
+            *images = new_swapchain_images;
+            *swapchain = new_swapchain;
+        } else if self.target.extent() != new_extent {
+            self.target = RenderTarget::offscreen(&self.vulkan_device, new_extent)?;
+        }
+
+        // Recreate also happens without a size change (out of date, suboptimal).
+        // Keep the intermediary and depth images when their extent and sample count still match.
+        let extent = self.target.extent();
+        let samples = self.vulkan_device.vulkan_context.borrow().samples;
+        let depth_image = self.depth_view.image();
+        if depth_image.extent() == [extent[0], extent[1], 1] && depth_image.samples() == samples {
+            return Ok(());
+        }
+
+        self.intermediary_image =
+            create_intermediary_image(&self.vulkan_device, self.target.format(), extent)?;
 
         self.depth_view = ImageView::new_default(Image::new(
             self.vulkan_device.memory_allocator.clone(),
             ImageCreateInfo {
                 image_type: ImageType::Dim2d,
-                format: Format::D16_UNORM,
-                extent: [
-                    self.swapchain.image_extent()[0],
-                    self.swapchain.image_extent()[1],
-                    1,
-                ],
-                usage: ImageUsage::DEPTH_STENCIL_ATTACHMENT | ImageUsage::TRANSIENT_ATTACHMENT,
+                format: self.vulkan_device.vulkan_context.borrow().depth_format(),
+                extent: [extent[0], extent[1], 1],
+                // SAMPLED for the depth visualization
+                usage: ImageUsage::DEPTH_STENCIL_ATTACHMENT | ImageUsage::SAMPLED,
                 samples: self.vulkan_device.vulkan_context.borrow().samples, // Match intermediary
                 ..Default::default()
             },
             AllocationCreateInfo::default(),
         )?)?;
 
+        self.depth_view_descriptor_set =
+            create_depth_view_descriptor_set(&self.vulkan_device, Arc::clone(&self.depth_view))?;
+
+        self.bloom_targets = BloomTargets::new(&self.vulkan_device, extent)?;
+
         Ok(())
     }
 
     pub fn render(&mut self) -> Result<()> {
+        if self.recreate_pending {
+            self.recreate_pending = false;
+            self.recreate()?;
+        }
+
+        let RenderTarget::Swapchain {
+            window, swapchain, ..
+        } = &self.target
+        else {
+            // Headless: nothing to present
+            return self.render_offscreen().map(drop);
+        };
+        let (window, swapchain) = (Arc::clone(window), Arc::clone(swapchain));
+
         // Do not draw the frame when the screen size is zero. On Windows, this can
         // occur when minimizing the application.
-        let image_extent: [u32; 2] = self.window.inner_size().into();
+        let image_extent: [u32; 2] = window.inner_size().into();
 
         if image_extent.contains(&0) {
             return Ok(());
         }
 
+        // Frame rate, smoothed over a few dozen frames
+        let now = Instant::now();
+        let frame_time = now.duration_since(self.last_render).as_secs_f32();
+        self.last_render = now;
+        if frame_time > 0.0 {
+            self.fps += (1.0 / frame_time - self.fps) * 0.05;
+            self.frame_time += (frame_time * 1000.0 - self.frame_time) * 0.05;
+        }
+
+        if now.duration_since(self.last_title_update) >= TITLE_UPDATE_INTERVAL {
+            self.last_title_update = now;
+            window.set_title(&format!(
+                "{}{TITLE_SEPARATOR}{:.0} fps / {:.1} ms",
+                self.base_title, self.fps, self.frame_time
+            ));
+        }
+
         // It is important to call this function from time to time, otherwise resources
         // will keep accumulating and you will eventually reach an out of memory error.
         // Calling this function polls various fences in order to determine what the GPU
@@ -229,10 +731,11 @@ impl VulkanRenderer {
         // This function can block if no image is available. The parameter is an optional
         // timeout after which the function call will return an error.
         let (image_index, suboptimal, acquire_future) =
-            match acquire_next_image(self.swapchain.clone(), None).map_err(Validated::unwrap) {
+            match acquire_next_image(Arc::clone(&swapchain), None).map_err(Validated::unwrap) {
                 Ok(r) => r,
                 Err(VulkanError::OutOfDate) => {
-                    todo!();
+                    self.request_recreate();
+                    return Ok(());
                 }
                 Err(e) => panic!("failed to acquire next image: {e}"),
             };
@@ -242,7 +745,7 @@ impl VulkanRenderer {
         // drivers this can be when the window resizes, but it may not cause the swapchain
         // to become out of date.
         if suboptimal {
-            todo!();
+            self.request_recreate();
         }
 
         // In order to draw, we have to build a *command buffer*. The command buffer object
@@ -260,6 +763,256 @@ impl VulkanRenderer {
             CommandBufferUsage::OneTimeSubmit,
         )?;
 
+        self.record_frame(&mut builder, image_index)?;
+
+        // Final image, before it goes to the presentation engine
+        let capture = match self.pending_capture.take() {
+            Some(path) => Some((
+                FrameCapture::record(
+                    &mut builder,
+                    Arc::clone(self.vulkan_device.memory_allocator()),
+                    Arc::clone(self.target.image(image_index)),
+                )?,
+                path,
+            )),
+            None => None,
+        };
+
+        let command_buffer = builder.build()?;
+
+        // ------>
+        // Vulkan synchronization
+        // <------
+
+        // we need to wait until the operation is complete. 
+        // To do that, we need to program the GPU to send back a special signal that will make us know it has finished. 
+        // This kind of signal is called a fence, and it lets us know whenever the GPU has reached a certain point of execution.
+        
+        // To do that, let's actually save the future and wait for the operations to finish:
+        let future = self
+            .previous_frame_end
+            .take()
+            .unwrap()
+            .join(acquire_future)
+            .then_execute(Arc::clone(self.vulkan_device.queue()), command_buffer)
+            .unwrap()
+            // The color output is now expected to contain our triangles. But in order to
+            // show then on the screen, we have to *present* the image by calling
+            // `then_swapchain_present`.
+            //
+            // This function does not actually present the image immediately. Instead it
+            // submits a present command at the end of the queue. This means that it will
+            // only be presented once the GPU has finished executing the command buffer
+            // that draws the triangles.
+            .then_swapchain_present(
+                Arc::clone(self.vulkan_device.queue()),
+                SwapchainPresentInfo::swapchain_image_index(swapchain, image_index),
+            )
+            // same as signal fence, and then flush
+            .then_signal_fence_and_flush();
+
+        match future.map_err(Validated::unwrap) {
+            Ok(future) => {
+                // Wait for this frame only when capturing
+                if let Some((capture, path)) = capture {
+                    future.wait(None)?;
+                    if let Err(e) = capture.save(&path) {
+                        error!("failed to save the frame capture: {e}");
+                    }
+                }
+                self.previous_frame_end = Some(future.boxed());
+            }
+            Err(VulkanError::OutOfDate) => {
+                self.request_recreate();
+                self.previous_frame_end =
+                    Some(sync::now(Arc::clone(self.vulkan_device.queue().device())).boxed());
+            }
+            Err(e) => {
+                println!("failed to flush future: {e}");
+                self.previous_frame_end =
+                    Some(sync::now(Arc::clone(self.vulkan_device.queue().device())).boxed());
+            }
+        }
+
+        Ok(())
+    }
+
+    // Headless frame: draw into the target image and read it back.
+    // RGBA8 pixels, rows of width * 4 bytes, top row first.
+    pub fn render_offscreen(&mut self) -> Result<Vec<u8>> {
+        let capture = self.offscreen_frame(FrameCapture::record)?;
+
+        Ok(capture.to_image()?.into_raw())
+    }
+
+    // Headless frame without the read back, see bench.rs
+    pub fn draw_offscreen(&mut self) -> Result<()> {
+        self.offscreen_frame(|_, _, _| Ok(()))
+    }
+
+    // Draw into the target image, `after` records its own commands at the end of the frame.
+    // Waits for the GPU.
+    fn offscreen_frame<T>(
+        &mut self,
+        after: impl FnOnce(
+            &mut AutoCommandBufferBuilder<
+                PrimaryAutoCommandBuffer<Arc<StandardCommandBufferAllocator>>,
+                Arc<StandardCommandBufferAllocator>,
+            >,
+            Arc<StandardMemoryAllocator>,
+            Arc<Image>,
+        ) -> Result<T>,
+    ) -> Result<T> {
+        let RenderTarget::Offscreen { image, .. } = &self.target else {
+            return Err(VisualSystemError::NotHeadless.into());
+        };
+        let image = Arc::clone(image);
+
+        self.previous_frame_end.as_mut().unwrap().cleanup_finished();
+
+        let mut builder = AutoCommandBufferBuilder::primary(
+            self.vulkan_device.command_allocator(),
+            self.vulkan_device.queue().queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )?;
+
+        self.record_frame(&mut builder, 0)?;
+
+        let output = after(
+            &mut builder,
+            Arc::clone(self.vulkan_device.memory_allocator()),
+            image,
+        )?;
+
+        let command_buffer = builder.build()?;
+
+        // No frames in flight: the output is ready right away
+        self.previous_frame_end
+            .take()
+            .unwrap()
+            .then_execute(Arc::clone(self.vulkan_device.queue()), command_buffer)?
+            .then_signal_fence_and_flush()?
+            .wait(None)?;
+        self.previous_frame_end =
+            Some(sync::now(Arc::clone(self.vulkan_device.queue().device())).boxed());
+
+        Ok(output)
+    }
+
+    // The instances to draw each mesh with.
+    // With the frustum culling, the instances in the view of the camera, copied to a subbuffer of
+    // this frame. Not with the compute animation or the model spin: the instances move on the GPU
+    // only.
+    fn mesh_instances(&self, meshes: &[&Mesh]) -> Result<Vec<DrawInstances>> {
+        let culling = {
+            let vulkan_context = self.vulkan_device.vulkan_context.borrow();
+            vulkan_context.frustum_culling
+                && !vulkan_context.animate_instances
+                && !vulkan_context.spin_model
+        };
+        if !culling {
+            return Ok(meshes
+                .iter()
+                .map(|mesh| {
+                    (
+                        mesh.instance_buffer.clone(),
+                        mesh.first_instance,
+                        mesh.instance_count,
+                    )
+                })
+                .collect());
+        }
+
+        let frustum = Frustum::new(&self.camera.build_view_projection_matrix());
+        let model = self.mvp_uniform.model_matrix();
+        let instances = self.vulkan_device.instances();
+        let visible = meshes
+            .iter()
+            .map(|mesh| {
+                let first = (mesh.first_instance as usize).min(instances.len());
+                let last = (first + mesh.instance_count as usize).min(instances.len());
+                match mesh.bounding_sphere {
+                    Some(bounding_sphere) => visible_instances(
+                        &instances[first..last],
+                        bounding_sphere,
+                        &model,
+                        &frustum,
+                    )
+                    .copied()
+                    .collect(),
+                    None => instances[first..last].to_vec(),
+                }
+            })
+            .collect::<Vec<Vec<InstanceRaw>>>();
+
+        // An empty subbuffer can't be allocated
+        let total = visible.iter().map(Vec::len).sum::<usize>();
+        if total == 0 {
+            return Ok(meshes
+                .iter()
+                .map(|mesh| (mesh.instance_buffer.clone(), 0, 0))
+                .collect());
+        }
+
+        let culled_instances = self
+            .culled_instance_allocator
+            .allocate_slice(total as DeviceSize)?;
+        {
+            let mut write = culled_instances.write()?;
+            for (destination, instance) in write.iter_mut().zip(visible.iter().flatten()) {
+                *destination = *instance;
+            }
+        }
+
+        let mut first_instance = 0;
+        Ok(visible
+            .iter()
+            .map(|mesh_visible| {
+                let count = mesh_visible.len() as u32;
+                first_instance += count;
+                (culled_instances.clone(), first_instance - count, count)
+            })
+            .collect())
+    }
+
+    // After the opaque meshes: their depth hides the grid
+    fn record_grid(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<
+            PrimaryAutoCommandBuffer<Arc<StandardCommandBufferAllocator>>,
+            Arc<StandardCommandBufferAllocator>,
+        >,
+    ) -> Result<()> {
+        self.vulkan_device.grid().record(
+            builder,
+            &self.camera.build_view_projection_matrix(),
+            self.camera.zfar * GRID_FADE,
+        )
+    }
+
+    // Every draw of a frame into the target image `image_index`: scene, bloom, depth view,
+    // text and control panel. Acquire and present are left to the caller.
+    fn record_frame(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<
+            PrimaryAutoCommandBuffer<Arc<StandardCommandBufferAllocator>>,
+            Arc<StandardCommandBufferAllocator>,
+        >,
+        image_index: u32,
+    ) -> Result<()> {
+        if let Some(gpu_timer) = &mut self.gpu_timer {
+            self.gpu_time = gpu_timer.read(image_index)?;
+            gpu_timer.begin(builder, image_index)?;
+        }
+
+        let time = (Instant::now() - self.start_time).as_secs_f32();
+
+        self.record_mvp_upload(builder, image_index)?;
+        self.record_joint_upload(builder, image_index, time)?;
+        self.record_instance_upload(builder)?;
+        let camera_descriptor_set =
+            Arc::clone(&self.frame_uniforms[image_index as usize].descriptor_set);
+
         /*         builder.clear_color_image(ClearColorImageInfo {
                     clear_value: ClearColorValue::Float([0.2, 0.2, 0.3, 1.]),
                     ..ClearColorImageInfo::image(Arc::clone(&self.swapchain_images[image_index as usize]))
@@ -268,13 +1021,53 @@ impl VulkanRenderer {
 
         //
 
-        let clear_color_srgba = Srgba::new(0.2, 0.2, 0.3, 1.);
+        let [red, green, blue] = self.vulkan_device.vulkan_context.borrow().clear_color;
+        let clear_color_srgba = Srgba::new(red, green, blue, 1.);
 
-        let extent = self.swapchain.image_extent();
+        // The clear value is written as is: encode it ourselves on a UNORM swapchain
+        let clear_color: [f32; 4] = if self
+            .vulkan_device
+            .vulkan_context
+            .borrow()
+            .color_output
+            .encode_in_shader()
+        {
+            let linear = clear_color_srgba.into_linear();
+            [
+                linear_to_srgb(linear.red as f64) as f32,
+                linear_to_srgb(linear.green as f64) as f32,
+                linear_to_srgb(linear.blue as f64) as f32,
+                linear.alpha,
+            ]
+        } else {
+            clear_color_srgba.into_linear().into()
+        };
+
+        let extent = self.target.extent();
+
+        let show_depth = self.vulkan_device.vulkan_context.borrow().show_depth;
+        let bloom = self.vulkan_device.vulkan_context.borrow().bloom;
+
+        // With bloom, the scene is resolved offscreen and composited into the swapchain image
+        let swapchain_image_view = Arc::clone(self.target.image_view(image_index));
+        let resolve_image_view = if bloom.enabled {
+            Arc::clone(&self.bloom_targets.scene)
+        } else {
+            Arc::clone(&swapchain_image_view)
+        };
 
         // push constant uniform to pass the time to the shader
-        let push_constants = vs::PushConstantData {
-            time: (Instant::now() - self.start_time).as_secs_f32(),
+        let push_constants = {
+            let vulkan_context = self.vulkan_device.vulkan_context.borrow();
+            vs::PushConstantData {
+                time,
+                point_size: vulkan_context.point_size,
+                spin: if vulkan_context.spin_model {
+                    time * MODEL_SPIN_SPEED
+                } else {
+                    0.0
+                },
+            }
         };
 
         //
@@ -291,6 +1084,29 @@ impl VulkanRenderer {
         // Command buffer builder
         // <-----
 
+        // Compute pass: update the instance transforms on the GPU before drawing, see compute.rs
+        if self.vulkan_device.vulkan_context.borrow().animate_instances {
+            self.vulkan_device.instance_animation().record(
+                builder,
+                self.vulkan_device.compute_descriptor_set(),
+                time,
+                self.vulkan_device.instance_buffer().len() as u32,
+            )?;
+        }
+
+        // Labels for RenderDoc and the validation messages, only with VK_EXT_debug_utils
+        let debug_labels = self
+            .vulkan_device
+            .queue()
+            .device()
+            .instance()
+            .enabled_extensions()
+            .ext_debug_utils;
+
+        if debug_labels {
+            builder.begin_debug_utils_label(debug_label!("Main pass"))?;
+        }
+
         // Before we can draw, we have to *enter a render pass*. We specify which
         // attachments we are going to use for rendering here, which needs to match
         // what was previously specified when creating the pipeline.
@@ -310,25 +1126,32 @@ impl VulkanRenderer {
                     //
                     // Only attachments that have `AttachmentLoadOp::Clear` are provided
                     // with clear values, any others should use `None` as the clear value.
-                    clear_value: Some(ClearValue::Float(clear_color_srgba.into_linear().into())),
+                    clear_value: Some(ClearValue::Float(clear_color)),
 
                     // MSAA Resolve
-                    resolve_info: Some(RenderingAttachmentResolveInfo::image_view(Arc::clone(
-                        &self.swapchain_image_views[image_index as usize],
-                    ))),
                     // Instead of rendering directly to the swapchain image rendering to the intermediary image with multi-sample: 4
                     // And then resolving into the swapchain image which only have 1 sample (see above)
-
-                    // intermediary image for MSAA
-                    ..RenderingAttachmentInfo::image_view(
-                        Arc::clone(&self.intermediary_image), // We specify image view corresponding to the currently acquired
-                                                              // swapchain image, to use for this attachment.
-                                                              // Original without MSAA 👉  Arc::clone(&self.swapchain_image_views[image_index as usize]),
-                    )
+                    // Without MSAA the swapchain (or bloom scene) image is the attachment itself.
+                    ..match &self.intermediary_image {
+                        Some(intermediary_image) => RenderingAttachmentInfo {
+                            resolve_info: Some(RenderingAttachmentResolveInfo::image_view(
+                                resolve_image_view,
+                            )),
+                            // intermediary image for MSAA
+                            ..RenderingAttachmentInfo::image_view(Arc::clone(intermediary_image))
+                        },
+                        None => RenderingAttachmentInfo::image_view(resolve_image_view),
+                    }
                 })],
                 // {---- Depth attachment
                 depth_attachment: Some(RenderingAttachmentInfo {
                     load_op: AttachmentLoadOp::Clear,
+                    // Keep the depth when it is drawn afterwards
+                    store_op: if show_depth {
+                        AttachmentStoreOp::Store
+                    } else {
+                        AttachmentStoreOp::DontCare
+                    },
                     clear_value: Some(1.0f32.into()),
                     ..RenderingAttachmentInfo::image_view(Arc::clone(&self.depth_view))
                 }),
@@ -338,99 +1161,310 @@ impl VulkanRenderer {
             // We are now inside the first subpass of the render pass.
             //
             // TODO: Document state setting and how it affects subsequent draw commands.
-            .set_viewport(0, [viewport.clone()].into_iter().collect())?
-            .bind_pipeline_graphics(Arc::clone(self.vulkan_device.graphics_pipeline()))?
-            .bind_vertex_buffers(
-                0,
-                (
-                    self.vulkan_device.vertex_buffer.clone(),
-                    self.vulkan_device.instance_buffer.clone(),
-                ),
-            )?
-            .bind_descriptor_sets(
-                PipelineBindPoint::Graphics,
-                Arc::clone(self.vulkan_device.graphics_pipeline().layout()),
-                0,
-                Arc::clone(self.vulkan_device.descriptor_set()),
-            )?
-            .push_constants(
-                Arc::clone(self.vulkan_device.graphics_pipeline().layout()),
-                0,
-                push_constants,
-            )?;
-        // We add a draw command.
-        // Condition whether index buffers are present or not
-        match self.vulkan_device.index_buffer() {
-            Some(index_buffer) => builder
-                .bind_index_buffer(index_buffer.clone())?
-                .draw_indexed(
-                    index_buffer.len() as u32,
-                    self.vulkan_device.instance_buffer.len() as u32,
-                    0,
-                    0,
-                    0,
-                )?,
-            None => builder.draw(self.vulkan_device.vertex_buffer.len() as u32, 1, 0, 0)?,
-        }
-        // We leave the render pass.
-        .end_rendering()?;
+            .set_viewport(0, [viewport.clone()].into_iter().collect())?;
 
-        let command_buffer = builder.build()?;
+        // One draw per mesh, with the pipeline matching its blend mode.
+        // All the pipelines share the same layout.
+        let pipeline_layout = self.vulkan_device.graphics_pipeline_layout();
+        let wireframe_pipeline = if self.vulkan_device.vulkan_context.borrow().show_wireframe {
+            self.vulkan_device.wireframe_pipeline()
+        } else {
+            None
+        };
 
-        // ------>
-        // Vulkan synchronization
-        // <------
+        // Opaque meshes first: they write the depth the others are tested against. Then the grid,
+        // the blended meshes back to front, and the emissive ones last (additive: any order).
+        // The blended meshes don't write depth: sorted by the distance of their bounds center,
+        // per mesh. The instances of a mesh, and intersecting or nested blended meshes, can
+        // still blend in the wrong order.
+        let meshes = self.vulkan_device.meshes();
+        let mut draw_order = meshes.iter().collect::<Vec<_>>();
+        let model = self.mvp_uniform.model_matrix();
+        let view_distance = |mesh: &Mesh| {
+            mesh.bounds.map_or(0.0, |(min, max)| {
+                let center = nalgebra::center(&Point3::from(min), &Point3::from(max));
+                (model.transform_point(&center) - self.camera.eye).norm_squared()
+            })
+        };
+        draw_order.sort_by(|a, b| {
+            draw_pass(a)
+                .cmp(&draw_pass(b))
+                .then_with(|| match draw_pass(a) {
+                    DrawPass::Blended => view_distance(b).total_cmp(&view_distance(a)),
+                    _ => Ordering::Equal,
+                })
+        });
+        let opaque_count = draw_order
+            .iter()
+            .take_while(|mesh| draw_pass(mesh) == DrawPass::Opaque)
+            .count();
+        let mesh_instances = self.mesh_instances(&draw_order)?;
+        let instance_counts = (
+            mesh_instances.iter().map(|(_, _, count)| count).sum(),
+            draw_order.iter().map(|mesh| mesh.instance_count).sum(),
+        );
 
-        // we need to wait until the operation is complete. 
-        // To do that, we need to program the GPU to send back a special signal that will make us know it has finished. 
-        // This kind of signal is called a fence, and it lets us know whenever the GPU has reached a certain point of execution.
-        
-        // To do that, let's actually save the future and wait for the operations to finish:
-        let future = self
-            .previous_frame_end
-            .take()
-            .unwrap()
-            .join(acquire_future)
-            .then_execute(Arc::clone(self.vulkan_device.queue()), command_buffer)
-            .unwrap()
-            // The color output is now expected to contain our triangles. But in order to
-            // show then on the screen, we have to *present* the image by calling
-            // `then_swapchain_present`.
-            //
-            // This function does not actually present the image immediately. Instead it
-            // submits a present command at the end of the queue. This means that it will
-            // only be presented once the GPU has finished executing the command buffer
-            // that draws the triangles.
-            .then_swapchain_present(
-                Arc::clone(self.vulkan_device.queue()),
-                SwapchainPresentInfo::swapchain_image_index(
-                    Arc::clone(&self.swapchain),
-                    image_index,
-                ),
-            )
-            // same as signal fence, and then flush
-            .then_signal_fence_and_flush();
+        let mut grid_pending = self.vulkan_device.vulkan_context.borrow().show_grid;
+        for (index, (mesh, (instance_buffer, first_instance, instance_count))) in
+            draw_order.into_iter().zip(mesh_instances).enumerate()
+        {
+            if grid_pending && index == opaque_count {
+                self.record_grid(builder)?;
+                grid_pending = false;
+            }
 
-        match future.map_err(Validated::unwrap) {
-            Ok(future) => {
-                self.previous_frame_end = Some(future.boxed());
+            // No (visible) instances: nothing to draw
+            if instance_count == 0 {
+                continue;
             }
-            Err(VulkanError::OutOfDate) => {
-                self.recreate()?;
-                self.previous_frame_end =
-                    Some(sync::now(Arc::clone(self.swapchain.device())).boxed());
+
+            let graphics_pipeline = self
+                .vulkan_device
+                .graphics_pipeline(mesh.blend_mode(), mesh.topology);
+
+            builder
+                .bind_vertex_buffers(0, (mesh.vertex_buffer.clone(), instance_buffer))?
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Graphics,
+                    Arc::clone(&pipeline_layout),
+                    0,
+                    (
+                        Arc::clone(&camera_descriptor_set),
+                        Arc::clone(&mesh.descriptor_set),
+                    ),
+                )?
+                .push_constants(Arc::clone(&pipeline_layout), 0, push_constants)?;
+
+            if let Some(index_buffer) = &mesh.index_buffer {
+                builder.bind_index_buffer(index_buffer.clone())?;
             }
-            Err(e) => {
-                println!("failed to flush future: {e}");
-                self.previous_frame_end =
-                    Some(sync::now(Arc::clone(self.swapchain.device())).boxed());
+
+            // Shaded, then the wireframe on top when enabled. Same layout: the bindings stay.
+            // Lines and points are already their own wireframe.
+            let wireframe_pipeline = wireframe_pipeline
+                .clone()
+                .filter(|_| mesh.topology == Topology::Triangles);
+            for graphics_pipeline in std::iter::once(graphics_pipeline).chain(wireframe_pipeline) {
+                builder.bind_pipeline_graphics(graphics_pipeline)?;
+
+                // We add a draw command.
+                // Condition whether index buffers are present or not
+                match &mesh.index_buffer {
+                    Some(index_buffer) => builder.draw_indexed(
+                        index_buffer.len() as u32,
+                        instance_count,
+                        0,
+                        0,
+                        first_instance,
+                    )?,
+                    None => builder.draw(
+                        mesh.vertex_buffer.len() as u32,
+                        instance_count,
+                        0,
+                        first_instance,
+                    )?,
+                };
+            }
+        }
+        // End the meshes borrow before a possible recreate below
+        drop(meshes);
+        self.instance_counts = instance_counts;
+
+        // Only opaque meshes
+        if grid_pending {
+            self.record_grid(builder)?;
+        }
+
+        // We leave the render pass.
+        builder.end_rendering()?;
+
+        if debug_labels {
+            // Safety: begun above, in the same command buffer
+            unsafe {
+                builder.end_debug_utils_label()?;
             }
         }
 
+        if bloom.enabled {
+            self.bloom_targets.record(
+                builder,
+                self.vulkan_device.bloom_pipelines(),
+                &bloom,
+                Arc::clone(&swapchain_image_view),
+            )?;
+        }
+
+        // Depth visualization: overwrite the resolved image with the depth buffer
+        if show_depth {
+            let (near, far) = (self.camera.znear, self.camera.zfar);
+            let orthographic =
+                matches!(self.camera.projection, Projection::Orthographic { .. }) as u32;
+            let depth_view_pipeline = self.vulkan_device.depth_view_pipeline();
+
+            builder
+                .begin_rendering(RenderingInfo {
+                    color_attachments: vec![Some(RenderingAttachmentInfo {
+                        load_op: AttachmentLoadOp::DontCare,
+                        store_op: AttachmentStoreOp::Store,
+                        ..RenderingAttachmentInfo::image_view(Arc::clone(&swapchain_image_view))
+                    })],
+                    ..Default::default()
+                })?
+                .set_viewport(0, [viewport].into_iter().collect())?
+                .bind_pipeline_graphics(Arc::clone(&depth_view_pipeline))?
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Graphics,
+                    Arc::clone(depth_view_pipeline.layout()),
+                    0,
+                    Arc::clone(&self.depth_view_descriptor_set),
+                )?
+                .push_constants(
+                    Arc::clone(depth_view_pipeline.layout()),
+                    0,
+                    depth_view_fs::PushConstantData {
+                        near,
+                        far,
+                        orthographic,
+                    },
+                )?
+                .draw(3, 1, 0, 0)? // fullscreen triangle
+                .end_rendering()?;
+        }
+
+        // Text on top of everything
+        if let Some(text_renderer) = &mut self.text {
+            text_renderer.record(
+                builder,
+                &self.vulkan_device,
+                Arc::clone(&swapchain_image_view),
+            )?;
+        }
+
+        // The control panel last, over the text
+        if let Some(gui) = &mut self.gui {
+            gui.record(builder, &self.vulkan_device, swapchain_image_view)?;
+        }
+
+        if let Some(gpu_timer) = &self.gpu_timer {
+            gpu_timer.end(builder, image_index)?;
+        }
+
         Ok(())
     }
 }
 
+// Set 0 of the graphics pipeline: the MVP of the window and the joint palette
+fn create_camera_descriptor_set(
+    vulkan_device: &VulkanDevice,
+    uniform_buffer: Subbuffer<Mvp>,
+    joint_buffer: Subbuffer<[JointMatrix]>,
+) -> Result<Arc<PersistentDescriptorSet>> {
+    let descriptor_set = PersistentDescriptorSet::new(
+        vulkan_device.descriptor_set_allocator(),
+        Arc::clone(
+            vulkan_device
+                .graphics_pipeline_layout()
+                .set_layouts()
+                .first()
+                .expect("error getting the camera layout"),
+        ),
+        [
+            WriteDescriptorSet::buffer(0, uniform_buffer),
+            WriteDescriptorSet::buffer(1, joint_buffer),
+        ],
+        [],
+    )?;
+
+    Ok(descriptor_set)
+}
+
+// The MVP of one frame in flight: a frame doesn't overwrite the uniforms an earlier frame is
+// still reading. Indexed by the target image index.
+struct FrameUniforms {
+    buffer: Subbuffer<Mvp>,
+    joints: Subbuffer<[JointMatrix]>, // MAX_JOINTS, see record_joint_upload
+    descriptor_set: Arc<PersistentDescriptorSet>, // set 0 of the graphics pipeline
+    dirty: bool,                      // behind mvp_uniform, see record_mvp_upload
+}
+
+fn create_frame_uniforms(vulkan_device: &VulkanDevice, count: usize) -> Result<Vec<FrameUniforms>> {
+    let uniform_allocator = SubbufferAllocator::new(
+        vulkan_device.memory_allocator.clone(),
+        SubbufferAllocatorCreateInfo {
+            buffer_usage: BufferUsage::UNIFORM_BUFFER
+                | BufferUsage::STORAGE_BUFFER
+                | BufferUsage::TRANSFER_DST,
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+            ..Default::default()
+        },
+    );
+
+    (0..count)
+        .map(|_| {
+            let buffer: Subbuffer<Mvp> = uniform_allocator.allocate_sized()?;
+            let joints = uniform_allocator.allocate_slice(MAX_JOINTS as DeviceSize)?;
+            let descriptor_set =
+                create_camera_descriptor_set(vulkan_device, buffer.clone(), joints.clone())?;
+
+            Ok(FrameUniforms {
+                buffer,
+                joints,
+                descriptor_set,
+                dirty: true,
+            })
+        })
+        .collect()
+}
+
+// The depth image is recreated with the swapchain: so is its descriptor set
+fn create_depth_view_descriptor_set(
+    vulkan_device: &VulkanDevice,
+    depth_view: Arc<ImageView>,
+) -> Result<Arc<PersistentDescriptorSet>> {
+    let descriptor_set = PersistentDescriptorSet::new(
+        vulkan_device.descriptor_set_allocator(),
+        Arc::clone(
+            vulkan_device
+                .depth_view_pipeline()
+                .layout()
+                .set_layouts()
+                .first()
+                .expect("error getting the depth view layout"),
+        ),
+        [WriteDescriptorSet::image_view(0, depth_view)],
+        [],
+    )?;
+
+    Ok(descriptor_set)
+}
+
+// Multisampled color attachment resolved into the target image, None with a single sample
+fn create_intermediary_image(
+    vulkan_device: &VulkanDevice,
+    format: Format,
+    extent: [u32; 2],
+) -> Result<Option<Arc<ImageView>>> {
+    let samples = vulkan_device.vulkan_context.borrow().samples;
+    if samples == SampleCount::Sample1 {
+        return Ok(None);
+    }
+
+    let intermediary_image = ImageView::new_default(Image::new(
+        vulkan_device.memory_allocator.clone(),
+        ImageCreateInfo {
+            image_type: ImageType::Dim2d,
+            format,
+            extent: [extent[0], extent[1], 1],
+            usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSIENT_ATTACHMENT, // transient image
+            samples,
+            ..Default::default()
+        },
+        AllocationCreateInfo::default(),
+    )?)?;
+
+    Ok(Some(intermediary_image))
+}
+
 /// This function is called once during initialization, then again whenever the window is resized.
 fn window_size_dependent_setup(images: &[Arc<Image>]) -> Vec<Arc<ImageView>> {
     images